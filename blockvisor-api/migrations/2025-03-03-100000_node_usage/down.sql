@@ -0,0 +1,3 @@
+drop table node_usage;
+
+drop type enum_usage_state;
@@ -0,0 +1,22 @@
+create type enum_usage_state as enum (
+  'starting',
+  'running',
+  'stopped'
+);
+
+create table node_usage (
+  id uuid primary key default uuid_generate_v4 (),
+  node_id uuid not null references nodes (id),
+  org_id uuid not null references orgs (id),
+  sku text,
+  usage_state enum_usage_state not null,
+  cpu_cores bigint not null,
+  memory_bytes bigint not null,
+  disk_bytes bigint not null,
+  started_at timestamp with time zone default now() not null,
+  ended_at timestamp with time zone
+);
+
+create index idx_node_usage_node_id on node_usage using btree (node_id);
+
+create index idx_node_usage_org_id_started_at on node_usage using btree (org_id, started_at);
@@ -0,0 +1,5 @@
+drop table sso_identities;
+
+drop table sso_sessions;
+
+drop table org_identity_providers;
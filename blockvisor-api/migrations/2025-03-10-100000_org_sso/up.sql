@@ -0,0 +1,37 @@
+create table org_identity_providers (
+  id uuid primary key default uuid_generate_v4 (),
+  org_id uuid not null unique references orgs (id) on delete cascade,
+  issuer text not null,
+  client_id text not null,
+  client_secret text not null,
+  allowed_domains text[] not null,
+  verified_domains text[] not null default '{}',
+  group_roles jsonb not null default '[]',
+  created_at timestamp with time zone default now() not null,
+  updated_at timestamp with time zone
+);
+
+create table sso_sessions (
+  id uuid primary key default uuid_generate_v4 (),
+  org_id uuid not null references orgs (id) on delete cascade,
+  state text not null unique,
+  nonce text not null,
+  code_verifier text not null,
+  redirect_uri text not null,
+  link_user_id uuid references users (id) on delete cascade,
+  created_at timestamp with time zone default now() not null,
+  expires_at timestamp with time zone not null
+);
+
+create index idx_sso_sessions_expires_at on sso_sessions using btree (expires_at);
+
+create table sso_identities (
+  id uuid primary key default uuid_generate_v4 (),
+  user_id uuid not null references users (id) on delete cascade,
+  issuer text not null,
+  subject text not null,
+  created_at timestamp with time zone default now() not null,
+  unique (issuer, subject)
+);
+
+create index idx_sso_identities_user_id on sso_identities using btree (user_id);
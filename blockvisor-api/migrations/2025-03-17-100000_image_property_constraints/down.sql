@@ -0,0 +1,10 @@
+alter table image_properties
+drop column value_type,
+drop column min_value,
+drop column max_value,
+drop column pattern,
+drop column allowed_values,
+drop column max_length,
+drop column is_required;
+
+drop type enum_value_type;
@@ -0,0 +1,10 @@
+create type enum_value_type as enum ('text', 'integer', 'float', 'boolean');
+
+alter table image_properties
+add column value_type enum_value_type not null default 'text',
+add column min_value double precision,
+add column max_value double precision,
+add column pattern text,
+add column allowed_values text[] not null default '{}',
+add column max_length integer,
+add column is_required boolean not null default false;
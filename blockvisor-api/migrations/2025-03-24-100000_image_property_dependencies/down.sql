@@ -0,0 +1,2 @@
+alter table image_properties
+drop column dependencies;
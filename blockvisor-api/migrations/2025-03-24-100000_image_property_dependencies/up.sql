@@ -0,0 +1,2 @@
+alter table image_properties
+add column dependencies jsonb not null default '[]';
@@ -0,0 +1,6 @@
+alter table images
+drop column lifecycle,
+drop column lifecycle_reason,
+drop column sunset_at;
+
+drop type enum_image_lifecycle;
@@ -0,0 +1,6 @@
+create type enum_image_lifecycle as enum ('active', 'deprecated', 'end_of_life');
+
+alter table images
+add column lifecycle enum_image_lifecycle not null default 'active',
+add column lifecycle_reason text,
+add column sunset_at timestamp with time zone;
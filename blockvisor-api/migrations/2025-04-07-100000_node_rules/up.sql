@@ -0,0 +1,13 @@
+create table node_rules (
+  id uuid primary key default uuid_generate_v4 (),
+  node_id uuid not null references nodes (id) on delete cascade,
+  key text not null,
+  description text,
+  protocol enum_firewall_protocol not null,
+  direction enum_firewall_direction not null,
+  action enum_firewall_action not null,
+  ips jsonb,
+  ports jsonb,
+  created_at timestamp with time zone default now() not null,
+  unique (node_id, key)
+);
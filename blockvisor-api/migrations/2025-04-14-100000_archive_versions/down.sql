@@ -0,0 +1,3 @@
+drop table archive_versions;
+
+drop type enum_archive_version_status;
@@ -0,0 +1,18 @@
+create type enum_archive_version_status as enum (
+  'verified',
+  'incomplete'
+);
+
+create table archive_versions (
+  id uuid primary key default uuid_generate_v4 (),
+  store_key text not null,
+  data_version bigint not null,
+  status enum_archive_version_status not null,
+  total_size bigint not null,
+  stored_bytes bigint not null,
+  chunks bigint not null,
+  missing_chunks bigint not null,
+  problem text,
+  verified_at timestamp with time zone default now() not null,
+  unique (store_key, data_version)
+);
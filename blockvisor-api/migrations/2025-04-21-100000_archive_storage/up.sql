@@ -0,0 +1,6 @@
+create table archive_storage (
+  store_key text primary key,
+  data_versions bigint not null,
+  stored_bytes bigint not null,
+  measured_at timestamp with time zone default now() not null
+);
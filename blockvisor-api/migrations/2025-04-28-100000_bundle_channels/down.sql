@@ -0,0 +1,3 @@
+drop table if exists bundle_pins;
+drop table if exists bundles;
+drop type if exists enum_bundle_channel;
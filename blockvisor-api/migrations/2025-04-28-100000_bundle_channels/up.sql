@@ -0,0 +1,22 @@
+create type enum_bundle_channel as enum (
+  'canary',
+  'beta',
+  'stable'
+);
+
+create table bundles (
+  id uuid primary key default uuid_generate_v4 (),
+  version text not null unique,
+  channel enum_bundle_channel not null,
+  created_at timestamp with time zone default now() not null,
+  promoted_at timestamp with time zone
+);
+
+create table bundle_pins (
+  id uuid primary key default uuid_generate_v4 (),
+  host_id uuid unique references hosts on delete cascade,
+  region_id uuid unique references regions on delete cascade,
+  channel enum_bundle_channel not null,
+  created_at timestamp with time zone default now() not null,
+  check ((host_id is null) <> (region_id is null))
+);
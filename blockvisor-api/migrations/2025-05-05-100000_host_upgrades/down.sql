@@ -0,0 +1,7 @@
+drop table host_upgrades;
+drop table host_upgrade_campaigns;
+drop type enum_host_upgrade_status;
+drop type enum_host_upgrade_campaign_status;
+
+-- we cannot drop values from an enum (without creating a new type)
+delete from commands where command_type = 'host_upgrade';
@@ -0,0 +1,46 @@
+alter type enum_command_type add value if not exists 'host_upgrade';
+
+create type enum_host_upgrade_campaign_status as enum (
+  'running',
+  'paused',
+  'completed'
+);
+
+create type enum_host_upgrade_status as enum (
+  'pending',
+  'upgrading',
+  'succeeded',
+  'failed'
+);
+
+create table host_upgrade_campaigns (
+  id uuid primary key default uuid_generate_v4 (),
+  bundle_version text not null,
+  status enum_host_upgrade_campaign_status not null default 'running',
+  batch_size bigint not null check (batch_size > 0),
+  max_failures bigint not null check (max_failures >= 0),
+  paused_reason text,
+  created_at timestamp with time zone default now() not null,
+  updated_at timestamp with time zone,
+  completed_at timestamp with time zone
+);
+
+-- only one campaign may be running or paused at a time
+create unique index idx_host_upgrade_campaigns_active
+  on host_upgrade_campaigns ((true))
+  where status <> 'completed';
+
+create table host_upgrades (
+  id uuid primary key default uuid_generate_v4 (),
+  campaign_id uuid not null references host_upgrade_campaigns on delete cascade,
+  host_id uuid not null references hosts on delete cascade,
+  region_id uuid not null references regions on delete cascade,
+  status enum_host_upgrade_status not null default 'pending',
+  command_id uuid references commands on delete set null,
+  message text,
+  started_at timestamp with time zone,
+  completed_at timestamp with time zone,
+  unique (campaign_id, host_id)
+);
+
+create index idx_host_upgrades_campaign_status on host_upgrades (campaign_id, status);
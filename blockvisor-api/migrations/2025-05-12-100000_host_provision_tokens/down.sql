@@ -0,0 +1 @@
+drop table host_provision_tokens;
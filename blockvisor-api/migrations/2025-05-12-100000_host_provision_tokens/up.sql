@@ -0,0 +1,21 @@
+create table host_provision_tokens (
+  id uuid primary key default uuid_generate_v4 (),
+  org_id uuid not null references orgs on delete cascade,
+  name text not null,
+  token_hash text not null unique,
+  created_by_type enum_resource_type not null,
+  created_by_id uuid not null,
+  expires_at timestamp with time zone,
+  max_uses bigint check (max_uses > 0),
+  use_count bigint not null default 0,
+  region_ids uuid[] not null default '{}',
+  schedule_type enum_schedule_type,
+  tags text[] not null default '{}',
+  created_at timestamp with time zone default now() not null,
+  last_used_at timestamp with time zone,
+  revoked_at timestamp with time zone
+);
+
+create unique index idx_host_provision_tokens_org_name
+  on host_provision_tokens (org_id, lower(name))
+  where revoked_at is null;
@@ -0,0 +1,3 @@
+drop index if exists idx_hosts_last_seen_at;
+
+alter table hosts drop column if exists last_seen_at;
@@ -0,0 +1,6 @@
+alter table hosts add column last_seen_at timestamp with time zone;
+
+update hosts set last_seen_at = now() where deleted_at is null;
+
+create index idx_hosts_last_seen_at on hosts (last_seen_at)
+  where deleted_at is null;
@@ -0,0 +1,5 @@
+drop table if exists node_failovers;
+
+alter table nodes drop column if exists failover_enabled;
+
+alter table orgs drop column if exists failover_enabled;
@@ -0,0 +1,17 @@
+alter type enum_node_event add value if not exists 'failed_over';
+
+alter table orgs add column failover_enabled boolean not null default false;
+
+alter table nodes add column failover_enabled boolean;
+
+create table node_failovers (
+  id uuid primary key default uuid_generate_v4 (),
+  node_id uuid not null unique references nodes on delete cascade,
+  new_node_id uuid not null references nodes on delete cascade,
+  host_id uuid not null references hosts on delete cascade,
+  created_at timestamp with time zone default now() not null,
+  completed_at timestamp with time zone
+);
+
+create index idx_node_failovers_pending on node_failovers (host_id)
+  where completed_at is null;
@@ -0,0 +1,4 @@
+alter table nodes drop column if exists placement;
+
+alter table hosts drop column if exists taints;
+alter table hosts drop column if exists labels;
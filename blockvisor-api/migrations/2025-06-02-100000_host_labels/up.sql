@@ -0,0 +1,4 @@
+alter table hosts add column labels jsonb not null default '{}';
+alter table hosts add column taints jsonb not null default '{}';
+
+alter table nodes add column placement jsonb not null default '{}';
@@ -0,0 +1,9 @@
+alter table hosts drop column if exists disk_overcommit_percent;
+alter table hosts drop column if exists memory_overcommit_percent;
+alter table hosts drop column if exists cpu_overcommit_percent;
+
+alter table regions drop column if exists usage_headroom_percent;
+alter table regions drop column if exists usage_scheduling;
+alter table regions drop column if exists disk_overcommit_percent;
+alter table regions drop column if exists memory_overcommit_percent;
+alter table regions drop column if exists cpu_overcommit_percent;
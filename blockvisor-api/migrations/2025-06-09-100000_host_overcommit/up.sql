@@ -0,0 +1,9 @@
+alter table regions add column cpu_overcommit_percent integer not null default 100 check (cpu_overcommit_percent > 0);
+alter table regions add column memory_overcommit_percent integer not null default 100 check (memory_overcommit_percent > 0);
+alter table regions add column disk_overcommit_percent integer not null default 100 check (disk_overcommit_percent > 0);
+alter table regions add column usage_scheduling boolean not null default false;
+alter table regions add column usage_headroom_percent integer not null default 20 check (usage_headroom_percent between 0 and 100);
+
+alter table hosts add column cpu_overcommit_percent integer check (cpu_overcommit_percent > 0);
+alter table hosts add column memory_overcommit_percent integer check (memory_overcommit_percent > 0);
+alter table hosts add column disk_overcommit_percent integer check (disk_overcommit_percent > 0);
@@ -0,0 +1,11 @@
+drop index if exists idx_nodes_ipv6_address;
+
+alter table nodes drop column if exists ipv6_dns_id;
+alter table nodes drop column if exists ipv6_gateway;
+alter table nodes drop column if exists ipv6_address;
+
+alter table hosts drop column if exists ipv6_gateway;
+
+alter table ip_addresses drop column if exists quarantined_until;
+alter table ip_addresses drop column if exists reserved_reason;
+alter table ip_addresses drop column if exists reserved_at;
@@ -0,0 +1,11 @@
+alter table ip_addresses add column reserved_at timestamp with time zone;
+alter table ip_addresses add column reserved_reason text;
+alter table ip_addresses add column quarantined_until timestamp with time zone;
+
+alter table hosts add column ipv6_gateway inet;
+
+alter table nodes add column ipv6_address inet;
+alter table nodes add column ipv6_gateway inet;
+alter table nodes add column ipv6_dns_id text;
+
+create unique index idx_nodes_ipv6_address on nodes (ipv6_address) where deleted_at is null;
@@ -0,0 +1,5 @@
+drop index if exists idx_hosts_zone_id;
+
+alter table hosts drop column if exists zone_id;
+
+drop table if exists zones;
@@ -0,0 +1,12 @@
+create table zones (
+  id uuid primary key default uuid_generate_v4 (),
+  region_id uuid not null references regions on delete cascade,
+  key text not null,
+  display_name text not null,
+  created_at timestamp with time zone default now() not null,
+  unique (region_id, key)
+);
+
+alter table hosts add column zone_id uuid references zones on delete set null;
+
+create index idx_hosts_zone_id on hosts (zone_id);
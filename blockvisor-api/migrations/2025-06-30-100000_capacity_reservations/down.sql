@@ -0,0 +1,3 @@
+alter table nodes drop column if exists capacity_reservation_id;
+
+drop table if exists capacity_reservations;
@@ -0,0 +1,24 @@
+create table capacity_reservations (
+    id uuid primary key default uuid_generate_v4 (),
+    org_id uuid not null references orgs on delete cascade,
+    region_id uuid not null references regions on delete cascade,
+    protocol_id uuid references protocols on delete cascade,
+    node_slots integer,
+    cpu_cores bigint not null,
+    memory_bytes bigint not null,
+    disk_bytes bigint not null,
+    used_node_slots integer not null default 0,
+    used_cpu_cores bigint not null default 0,
+    used_memory_bytes bigint not null default 0,
+    used_disk_bytes bigint not null default 0,
+    expires_at timestamp with time zone,
+    created_at timestamp with time zone default now() not null,
+    updated_at timestamp with time zone,
+    check ((protocol_id is null) = (node_slots is null))
+);
+
+create index idx_capacity_reservations_org_id on capacity_reservations (org_id);
+
+create index idx_capacity_reservations_region_id on capacity_reservations (region_id);
+
+alter table nodes add column capacity_reservation_id uuid references capacity_reservations on delete set null;
@@ -0,0 +1,3 @@
+drop table if exists node_moves;
+
+-- we cannot drop values from an enum (without creating a new type)
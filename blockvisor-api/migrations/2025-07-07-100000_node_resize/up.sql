@@ -0,0 +1,16 @@
+alter type enum_node_event add value if not exists 'resized';
+
+create table node_moves (
+    id uuid primary key default uuid_generate_v4 (),
+    node_id uuid not null references nodes on delete cascade,
+    new_node_id uuid not null references nodes on delete cascade,
+    created_at timestamp with time zone default now() not null,
+    completed_at timestamp with time zone
+);
+
+-- a node is only moved once at a time
+create unique index idx_node_moves_node_id on node_moves (node_id)
+    where completed_at is null;
+
+create index idx_node_moves_pending on node_moves (new_node_id)
+    where completed_at is null;
@@ -0,0 +1,7 @@
+drop index if exists idx_nodes_failed;
+
+alter table nodes drop column if exists restart_attempted_at;
+alter table nodes drop column if exists restart_attempts;
+alter table nodes drop column if exists restart_policy;
+
+-- we cannot drop values from an enum (without creating a new type)
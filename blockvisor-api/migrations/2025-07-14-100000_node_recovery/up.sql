@@ -0,0 +1,9 @@
+alter type enum_node_event add value if not exists 'recovery_attempted';
+alter type enum_node_event add value if not exists 'recovery_gave_up';
+
+alter table nodes add column restart_policy jsonb not null default '{}';
+alter table nodes add column restart_attempts integer not null default 0;
+alter table nodes add column restart_attempted_at timestamp with time zone;
+
+create index idx_nodes_failed on nodes (host_id)
+  where node_state = 'failed' and deleted_at is null;
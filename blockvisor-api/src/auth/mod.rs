@@ -1,4 +1,5 @@
 pub mod claims;
+pub mod oidc;
 pub mod rbac;
 pub mod resource;
 pub mod token;
@@ -0,0 +1,300 @@
+//! A minimal OpenID Connect relying party for org single sign-on.
+//!
+//! Only the authorization code flow with PKCE is supported. ID tokens are
+//! verified against the provider's JWKS, or against the client secret for
+//! providers that sign with an HMAC algorithm.
+
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use displaydoc::Display;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::grpc::Status;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+const DISCOVERY_PATH: &str = ".well-known/openid-configuration";
+const RANDOM_BYTES: usize = 32;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to build OIDC Client: {0}
+    BuildClient(reqwest::Error),
+    /// Failed to decode ID token header: {0}
+    DecodeHeader(jsonwebtoken::errors::Error),
+    /// Failed to create decoding key from JWK: {0}
+    DecodingKey(jsonwebtoken::errors::Error),
+    /// Failed to fetch provider metadata: {0}
+    Discovery(reqwest::Error),
+    /// Provider metadata issuer `{0}` does not match configured issuer `{1}`.
+    DiscoveryIssuer(String, String),
+    /// Failed to fetch provider JWKS: {0}
+    Jwks(reqwest::Error),
+    /// No JWK found for ID token key id `{0:?}`.
+    MissingKey(Option<String>),
+    /// ID token nonce does not match the session.
+    Nonce,
+    /// Failed to parse issuer url: {0}
+    ParseIssuer(url::ParseError),
+    /// Failed to exchange authorization code: {0}
+    Token(reqwest::Error),
+    /// Error code {0} from token endpoint: {1}
+    TokenStatus(reqwest::StatusCode, String),
+    /// Failed to validate ID token: {0}
+    Validate(jsonwebtoken::errors::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            BuildClient(_) | DecodingKey(_) => Status::internal("Internal error."),
+            Discovery(_) | DiscoveryIssuer(..) | Jwks(_) | ParseIssuer(_) => {
+                Status::failed_precondition("Identity provider unavailable.")
+            }
+            DecodeHeader(_) | MissingKey(_) | Nonce | Token(_) | TokenStatus(..) | Validate(_) => {
+                Status::unauthorized("SSO login failed.")
+            }
+        }
+    }
+}
+
+/// The subset of the provider's discovery document used by the flow.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub jwks_uri: Url,
+}
+
+/// The claims read from a verified ID token.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// A PKCE code verifier and its S256 challenge.
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Self {
+        let verifier = random_string();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Pkce {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// Generate a url-safe random string for use as a `state`, `nonce` or verifier.
+pub fn random_string() -> String {
+    let mut bytes = [0; RANDOM_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The parameters needed to build an authorization request.
+pub struct AuthorizeParams<'a> {
+    pub client_id: &'a str,
+    pub redirect_uri: &'a str,
+    pub state: &'a str,
+    pub nonce: &'a str,
+    pub challenge: &'a str,
+}
+
+/// The parameters needed to exchange an authorization code for an ID token.
+pub struct ExchangeParams<'a> {
+    pub client_id: &'a str,
+    pub client_secret: &'a str,
+    pub redirect_uri: &'a str,
+    pub code: &'a str,
+    pub verifier: &'a str,
+    pub nonce: &'a str,
+}
+
+pub struct Client {
+    inner: reqwest::Client,
+}
+
+impl Client {
+    pub fn new() -> Result<Self, Error> {
+        let inner = reqwest::Client::builder()
+            .timeout(CLIENT_TIMEOUT)
+            .build()
+            .map_err(Error::BuildClient)?;
+
+        Ok(Client { inner })
+    }
+
+    /// Fetch the discovery document for `issuer`.
+    pub async fn discover(&self, issuer: &str) -> Result<ProviderMetadata, Error> {
+        let base = if issuer.ends_with('/') {
+            issuer.to_string()
+        } else {
+            format!("{issuer}/")
+        };
+        let url = Url::parse(&base)
+            .and_then(|url| url.join(DISCOVERY_PATH))
+            .map_err(Error::ParseIssuer)?;
+
+        let metadata: ProviderMetadata = self
+            .inner
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(Error::Discovery)?
+            .json()
+            .await
+            .map_err(Error::Discovery)?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(Error::DiscoveryIssuer(metadata.issuer, issuer.to_string()));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Build the url to redirect the user to for authentication.
+    pub fn authorize_url(metadata: &ProviderMetadata, params: &AuthorizeParams<'_>) -> Url {
+        let mut url = metadata.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("scope", "openid email profile groups")
+            .append_pair("client_id", params.client_id)
+            .append_pair("redirect_uri", params.redirect_uri)
+            .append_pair("state", params.state)
+            .append_pair("nonce", params.nonce)
+            .append_pair("code_challenge", params.challenge)
+            .append_pair("code_challenge_method", "S256");
+        url
+    }
+
+    /// Exchange an authorization code and return the verified ID token claims.
+    pub async fn exchange(
+        &self,
+        metadata: &ProviderMetadata,
+        params: &ExchangeParams<'_>,
+    ) -> Result<IdClaims, Error> {
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", params.code),
+            ("redirect_uri", params.redirect_uri),
+            ("client_id", params.client_id),
+            ("client_secret", params.client_secret),
+            ("code_verifier", params.verifier),
+        ];
+
+        let resp = self
+            .inner
+            .post(metadata.token_endpoint.clone())
+            .form(&form)
+            .send()
+            .await
+            .map_err(Error::Token)?;
+        let status = resp.status();
+        if !status.is_success() {
+            let message = resp.text().await.map_err(Error::Token)?;
+            return Err(Error::TokenStatus(status, message));
+        }
+        let token: TokenResponse = resp.json().await.map_err(Error::Token)?;
+
+        let claims = self
+            .verify(
+                metadata,
+                params.client_id,
+                params.client_secret,
+                &token.id_token,
+            )
+            .await?;
+        if claims.nonce.as_deref() != Some(params.nonce) {
+            return Err(Error::Nonce);
+        }
+
+        Ok(claims)
+    }
+
+    async fn verify(
+        &self,
+        metadata: &ProviderMetadata,
+        client_id: &str,
+        client_secret: &str,
+        id_token: &str,
+    ) -> Result<IdClaims, Error> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(Error::DecodeHeader)?;
+        let key = match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                DecodingKey::from_secret(client_secret.as_bytes())
+            }
+            _ => {
+                let jwks: JwkSet = self
+                    .inner
+                    .get(metadata.jwks_uri.clone())
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .map_err(Error::Jwks)?
+                    .json()
+                    .await
+                    .map_err(Error::Jwks)?;
+
+                let jwk = match header.kid.as_deref() {
+                    Some(kid) => jwks.find(kid),
+                    None => jwks.keys.first(),
+                };
+                let jwk = jwk.ok_or_else(|| Error::MissingKey(header.kid.clone()))?;
+                DecodingKey::from_jwk(jwk).map_err(Error::DecodingKey)?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        jsonwebtoken::decode(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(Error::Validate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkce_challenge_is_s256_of_verifier() {
+        // example from RFC 7636 appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        assert_eq!(challenge, "E9Melhoa2OwvFrEMTJguCQaoV-mRkJrpiZcqEyI8ExM");
+
+        let pkce = Pkce::new();
+        assert_eq!(pkce.verifier.len(), 43);
+        assert_ne!(pkce.verifier, pkce.challenge);
+    }
+}
@@ -56,10 +56,12 @@ define_perms! {
     }
 
     ArchiveAdmin => {
+        CollectGarbage,
         GetDownloadMetadata,
         GetDownloadChunks,
         GetUploadSlots,
         PutDownloadManifest,
+        StorageUsage,
     }
 
     Auth => {
@@ -67,6 +69,7 @@ define_perms! {
         ListPermissions,
         Refresh,
         ResetPassword,
+        SsoLink,
         UpdatePassword,
         UpdateUiPassword,
     }
@@ -80,10 +83,17 @@ define_perms! {
     }
 
     Bundle => {
+        Latest,
         ListVersions,
         Retrieve,
     }
 
+    BundleAdmin => {
+        Pin,
+        Promote,
+        Upload,
+    }
+
     Command => {
         Ack,
         Create,
@@ -120,16 +130,32 @@ define_perms! {
     }
 
     HostAdmin => {
+        AddIps,
         CreateRegion,
+        CreateReservation,
+        CreateZone,
         DeleteHost,
+        ExpireReservation,
+        GetCapacity,
         GetHost,
+        GetUpgrade,
         ListHosts,
+        ListIps,
         ListRegions,
+        ListReservations,
+        ListZones,
+        PauseUpgrade,
+        ReleaseIp,
+        RemoveIp,
+        ReserveIp,
         Restart,
+        ResumeUpgrade,
         Start,
+        StartUpgrade,
         Stop,
         UpdateHost,
         UpdateRegion,
+        UpdateReservation,
         ViewCost,
     }
 
@@ -151,6 +177,7 @@ define_perms! {
         Add,
         Get,
         ListArchives,
+        SetLifecycle,
         UpdateArchive,
         UpdateImage,
     }
@@ -189,6 +216,7 @@ define_perms! {
         List,
         ReportError,
         ReportStatus,
+        Resize,
         Restart,
         Start,
         Stop,
@@ -203,6 +231,7 @@ define_perms! {
         List,
         ReportError,
         ReportStatus,
+        Resize,
         Restart,
         Start,
         Stop,
@@ -220,17 +249,23 @@ define_perms! {
         Delete,
         RemoveMember,
         RemoveSelf,
+        TransferOwnership,
+        UpdateMemberRole,
     }
 
     OrgAdmin => {
         Get,
         List,
         Update,
+        VerifySsoDomain,
     }
 
     OrgProvision => {
+        CreateToken,
         GetToken,
+        ListTokens,
         ResetToken,
+        RevokeToken,
     }
 
     OrgBilling => {
@@ -245,6 +280,12 @@ define_perms! {
         Delete,
     }
 
+    OrgSso => {
+        Get,
+        Set,
+        Delete,
+    }
+
     Protocol => {
         GetPricing,
         GetProtocol,
@@ -260,9 +301,11 @@ define_perms! {
     ProtocolAdmin => {
         AddProtocol,
         AddVersion,
+        ExportCatalog,
         GetPricing,
         GetProtocol,
         GetLatest,
+        ImportCatalog,
         ListProtocols,
         ListVariants,
         ListVersions,
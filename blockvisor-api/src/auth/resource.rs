@@ -352,6 +352,8 @@ pub struct OrgId(Uuid);
     PartialOrd,
     Ord,
     DieselNewType,
+    Serialize,
+    Deserialize,
 )]
 pub struct HostId(Uuid);
 
@@ -369,6 +371,8 @@ pub struct HostId(Uuid);
     PartialOrd,
     Ord,
     DieselNewType,
+    Serialize,
+    Deserialize,
 )]
 pub struct NodeId(Uuid);
 
@@ -1,11 +1,15 @@
 use anyhow::{Context as _, Result, anyhow};
 use diesel::{Connection, PgConnection};
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_migrations::MigrationHarness;
 use tracing::info;
 
 use blockvisor_api::config::{Config, Context};
-use blockvisor_api::database::{self, Database, MIGRATIONS, Pool};
-use blockvisor_api::server;
+use blockvisor_api::database::{self, AdvisoryLock, Database, MIGRATIONS, Pool};
+use blockvisor_api::model::host_heartbeat;
+use blockvisor_api::model::host_upgrade::rollout;
+use blockvisor_api::model::node::{NodeUsage, failover, recovery, resize};
+use blockvisor_api::{server, store, stripe};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,6 +18,16 @@ async fn main() -> Result<()> {
 
     run_migrations(&context.config)?;
     setup_rbac(&context.pool).await?;
+    backfill_usage(&context.pool).await?;
+
+    tokio::spawn(stripe::reconcile::run(context.clone()));
+    tokio::spawn(stripe::usage::run(context.clone()));
+    tokio::spawn(store::gc::run(context.clone()));
+    tokio::spawn(rollout::run(context.clone()));
+    tokio::spawn(host_heartbeat::run(context.clone()));
+    tokio::spawn(failover::run(context.clone()));
+    tokio::spawn(recovery::run(context.clone()));
+    tokio::spawn(resize::run(context.clone()));
 
     info!("Starting server {} ...", env!("CARGO_PKG_VERSION"));
     server::start(context.clone()).await?;
@@ -37,3 +51,12 @@ async fn setup_rbac(pool: &Pool) -> Result<()> {
         .await
         .map_err(Into::into)
 }
+
+async fn backfill_usage(pool: &Pool) -> Result<()> {
+    let mut conn = pool.conn().await?;
+    database::exclusive(AdvisoryLock::UsageBackfill, &mut conn, |conn| {
+        async move { NodeUsage::backfill(conn).await.map_err(anyhow::Error::from) }.scope_boxed()
+    })
+    .await
+    .map(|_nodes| ())
+}
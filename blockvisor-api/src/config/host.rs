@@ -0,0 +1,195 @@
+use displaydoc::Display;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::HumanTime;
+use super::provider::{self, Provider};
+
+const FAILOVER_AFTER_VAR: &str = "HOST_FAILOVER_AFTER";
+const FAILOVER_AFTER_ENTRY: &str = "host.failover_after";
+const FAILOVER_AFTER_DEFAULT: &str = "30m";
+const FAILOVER_DELETE_AFTER_VAR: &str = "HOST_FAILOVER_DELETE_AFTER";
+const FAILOVER_DELETE_AFTER_ENTRY: &str = "host.failover_delete_after";
+const FAILOVER_DELETE_AFTER_DEFAULT: &str = "24h";
+const FAILOVER_INTERVAL_VAR: &str = "HOST_FAILOVER_INTERVAL";
+const FAILOVER_INTERVAL_ENTRY: &str = "host.failover_interval";
+const FAILOVER_INTERVAL_DEFAULT: &str = "1m";
+const HEARTBEAT_INTERVAL_VAR: &str = "HOST_HEARTBEAT_INTERVAL";
+const HEARTBEAT_INTERVAL_ENTRY: &str = "host.heartbeat_interval";
+const HEARTBEAT_INTERVAL_DEFAULT: &str = "30s";
+const HEARTBEAT_TIMEOUT_VAR: &str = "HOST_HEARTBEAT_TIMEOUT";
+const HEARTBEAT_TIMEOUT_ENTRY: &str = "host.heartbeat_timeout";
+const HEARTBEAT_TIMEOUT_DEFAULT: &str = "3m";
+const IP_QUARANTINE_VAR: &str = "HOST_IP_QUARANTINE";
+const IP_QUARANTINE_ENTRY: &str = "host.ip_quarantine";
+const IP_QUARANTINE_DEFAULT: &str = "1h";
+const LEGACY_PROVISION_TOKENS_VAR: &str = "HOST_LEGACY_PROVISION_TOKENS";
+const LEGACY_PROVISION_TOKENS_ENTRY: &str = "host.legacy_provision_tokens";
+const LEGACY_PROVISION_TOKENS_DEFAULT: bool = true;
+const MOVE_DELETE_AFTER_VAR: &str = "HOST_MOVE_DELETE_AFTER";
+const MOVE_DELETE_AFTER_ENTRY: &str = "host.move_delete_after";
+const MOVE_DELETE_AFTER_DEFAULT: &str = "24h";
+const MOVE_INTERVAL_VAR: &str = "HOST_MOVE_INTERVAL";
+const MOVE_INTERVAL_ENTRY: &str = "host.move_interval";
+const MOVE_INTERVAL_DEFAULT: &str = "30s";
+const RECOVERY_INTERVAL_VAR: &str = "HOST_RECOVERY_INTERVAL";
+const RECOVERY_INTERVAL_ENTRY: &str = "host.recovery_interval";
+const RECOVERY_INTERVAL_DEFAULT: &str = "30s";
+const UPGRADE_INTERVAL_VAR: &str = "HOST_UPGRADE_INTERVAL";
+const UPGRADE_INTERVAL_ENTRY: &str = "host.upgrade_interval";
+const UPGRADE_INTERVAL_DEFAULT: &str = "30s";
+const UPGRADE_TIMEOUT_VAR: &str = "HOST_UPGRADE_TIMEOUT";
+const UPGRADE_TIMEOUT_ENTRY: &str = "host.upgrade_timeout";
+const UPGRADE_TIMEOUT_DEFAULT: &str = "30m";
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to read {FAILOVER_AFTER_VAR:?}: {0}
+    ReadFailoverAfter(provider::Error),
+    /// Failed to read {FAILOVER_DELETE_AFTER_VAR:?}: {0}
+    ReadFailoverDeleteAfter(provider::Error),
+    /// Failed to read {FAILOVER_INTERVAL_VAR:?}: {0}
+    ReadFailoverInterval(provider::Error),
+    /// Failed to read {HEARTBEAT_INTERVAL_VAR:?}: {0}
+    ReadHeartbeatInterval(provider::Error),
+    /// Failed to read {HEARTBEAT_TIMEOUT_VAR:?}: {0}
+    ReadHeartbeatTimeout(provider::Error),
+    /// Failed to read {IP_QUARANTINE_VAR:?}: {0}
+    ReadIpQuarantine(provider::Error),
+    /// Failed to read {LEGACY_PROVISION_TOKENS_VAR:?}: {0}
+    ReadLegacyProvisionTokens(provider::Error),
+    /// Failed to read {MOVE_DELETE_AFTER_VAR:?}: {0}
+    ReadMoveDeleteAfter(provider::Error),
+    /// Failed to read {MOVE_INTERVAL_VAR:?}: {0}
+    ReadMoveInterval(provider::Error),
+    /// Failed to read {RECOVERY_INTERVAL_VAR:?}: {0}
+    ReadRecoveryInterval(provider::Error),
+    /// Failed to read {UPGRADE_INTERVAL_VAR:?}: {0}
+    ReadUpgradeInterval(provider::Error),
+    /// Failed to read {UPGRADE_TIMEOUT_VAR:?}: {0}
+    ReadUpgradeTimeout(provider::Error),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// How long a host must be offline before its nodes are failed over.
+    pub failover_after: HumanTime,
+    /// How long a failed over node waits for its host before it is deleted.
+    pub failover_delete_after: HumanTime,
+    /// How often to check for nodes to fail over.
+    pub failover_interval: HumanTime,
+    /// How often to check for hosts that have stopped sending heartbeats.
+    pub heartbeat_interval: HumanTime,
+    /// How long a host may go unseen before it is marked as offline.
+    pub heartbeat_timeout: HumanTime,
+    /// How long a freed IP address is held back before it is reassigned.
+    pub ip_quarantine: HumanTime,
+    /// Whether hosts may still be created with the legacy per-user provision
+    /// tokens. Disable this once every host is provisioned with named tokens.
+    pub legacy_provision_tokens: bool,
+    /// How long a moved node waits for its replacement to run before the
+    /// replacement is deleted.
+    pub move_delete_after: HumanTime,
+    /// How often to check for moved nodes whose replacement is running.
+    pub move_interval: HumanTime,
+    /// How often to check for failed nodes to recover.
+    pub recovery_interval: HumanTime,
+    /// How often to advance running host upgrade campaigns.
+    pub upgrade_interval: HumanTime,
+    /// How long a host may take to report the target version after upgrading.
+    pub upgrade_timeout: HumanTime,
+}
+
+impl TryFrom<&Provider> for Config {
+    type Error = Error;
+
+    fn try_from(provider: &Provider) -> Result<Self, Self::Error> {
+        Ok(Config {
+            failover_after: provider
+                .read_or_else(
+                    || FAILOVER_AFTER_DEFAULT.parse::<HumanTime>(),
+                    FAILOVER_AFTER_VAR,
+                    FAILOVER_AFTER_ENTRY,
+                )
+                .map_err(Error::ReadFailoverAfter)?,
+            failover_delete_after: provider
+                .read_or_else(
+                    || FAILOVER_DELETE_AFTER_DEFAULT.parse::<HumanTime>(),
+                    FAILOVER_DELETE_AFTER_VAR,
+                    FAILOVER_DELETE_AFTER_ENTRY,
+                )
+                .map_err(Error::ReadFailoverDeleteAfter)?,
+            failover_interval: provider
+                .read_or_else(
+                    || FAILOVER_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    FAILOVER_INTERVAL_VAR,
+                    FAILOVER_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadFailoverInterval)?,
+            heartbeat_interval: provider
+                .read_or_else(
+                    || HEARTBEAT_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    HEARTBEAT_INTERVAL_VAR,
+                    HEARTBEAT_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadHeartbeatInterval)?,
+            heartbeat_timeout: provider
+                .read_or_else(
+                    || HEARTBEAT_TIMEOUT_DEFAULT.parse::<HumanTime>(),
+                    HEARTBEAT_TIMEOUT_VAR,
+                    HEARTBEAT_TIMEOUT_ENTRY,
+                )
+                .map_err(Error::ReadHeartbeatTimeout)?,
+            ip_quarantine: provider
+                .read_or_else(
+                    || IP_QUARANTINE_DEFAULT.parse::<HumanTime>(),
+                    IP_QUARANTINE_VAR,
+                    IP_QUARANTINE_ENTRY,
+                )
+                .map_err(Error::ReadIpQuarantine)?,
+            legacy_provision_tokens: provider
+                .read_or(
+                    LEGACY_PROVISION_TOKENS_DEFAULT,
+                    LEGACY_PROVISION_TOKENS_VAR,
+                    LEGACY_PROVISION_TOKENS_ENTRY,
+                )
+                .map_err(Error::ReadLegacyProvisionTokens)?,
+            move_delete_after: provider
+                .read_or_else(
+                    || MOVE_DELETE_AFTER_DEFAULT.parse::<HumanTime>(),
+                    MOVE_DELETE_AFTER_VAR,
+                    MOVE_DELETE_AFTER_ENTRY,
+                )
+                .map_err(Error::ReadMoveDeleteAfter)?,
+            move_interval: provider
+                .read_or_else(
+                    || MOVE_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    MOVE_INTERVAL_VAR,
+                    MOVE_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadMoveInterval)?,
+            recovery_interval: provider
+                .read_or_else(
+                    || RECOVERY_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    RECOVERY_INTERVAL_VAR,
+                    RECOVERY_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadRecoveryInterval)?,
+            upgrade_interval: provider
+                .read_or_else(
+                    || UPGRADE_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    UPGRADE_INTERVAL_VAR,
+                    UPGRADE_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadUpgradeInterval)?,
+            upgrade_timeout: provider
+                .read_or_else(
+                    || UPGRADE_TIMEOUT_DEFAULT.parse::<HumanTime>(),
+                    UPGRADE_TIMEOUT_VAR,
+                    UPGRADE_TIMEOUT_ENTRY,
+                )
+                .map_err(Error::ReadUpgradeTimeout)?,
+        })
+    }
+}
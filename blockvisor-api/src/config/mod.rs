@@ -2,6 +2,7 @@ pub mod cloudflare;
 pub mod database;
 pub mod email;
 pub mod grpc;
+pub mod host;
 pub mod log;
 pub mod mqtt;
 pub mod secret;
@@ -44,6 +45,8 @@ pub enum Error {
     Email(email::Error),
     /// Failed to parse gRPC Config: {0}
     Grpc(grpc::Error),
+    /// Failed to parse host Config: {0}
+    Host(host::Error),
     /// Failed to parse HumanTime: {0}
     HumanTime(serde_json::Error),
     /// Failed to parse Log Config: {0}
@@ -78,6 +81,7 @@ pub struct Config {
     pub database: Arc<database::Config>,
     pub email: Arc<email::Config>,
     pub grpc: Arc<grpc::Config>,
+    pub host: Arc<host::Config>,
     pub log: Arc<log::Config>,
     pub mqtt: Arc<mqtt::Config>,
     pub secret: Arc<secret::Config>,
@@ -132,6 +136,9 @@ impl TryFrom<&Provider> for Config {
         let grpc = grpc::Config::try_from(provider)
             .map(Arc::new)
             .map_err(Error::Grpc)?;
+        let host = host::Config::try_from(provider)
+            .map(Arc::new)
+            .map_err(Error::Host)?;
         let log = log::Config::try_from(provider)
             .map(Arc::new)
             .map_err(Error::Log)?;
@@ -159,6 +166,7 @@ impl TryFrom<&Provider> for Config {
             database,
             email,
             grpc,
+            host,
             log,
             mqtt,
             secret,
@@ -1,5 +1,8 @@
+use std::path::PathBuf;
+
 use displaydoc::Display;
 use serde::Deserialize;
+use strum::EnumString;
 use thiserror::Error;
 use url::Url;
 
@@ -10,8 +13,23 @@ const AWS_ACCESS_KEY_ID_VAR: &str = "AWS_ACCESS_KEY_ID";
 const AWS_ACCESS_KEY_ID_ENTRY: &str = "store.aws_access_key_id";
 const AWS_SECRET_ACCESS_KEY_VAR: &str = "AWS_SECRET_ACCESS_KEY";
 const AWS_SECRET_ACCESS_KEY_ENTRY: &str = "store.aws_secret_access_key";
+const BACKEND_VAR: &str = "STORE_BACKEND";
+const BACKEND_ENTRY: &str = "store.backend";
+const GC_DRY_RUN_VAR: &str = "STORE_GC_DRY_RUN";
+const GC_DRY_RUN_ENTRY: &str = "store.gc.dry_run";
+const GC_INTERVAL_VAR: &str = "STORE_GC_INTERVAL";
+const GC_INTERVAL_ENTRY: &str = "store.gc.interval";
+const GC_KEEP_VERSIONS_VAR: &str = "STORE_GC_KEEP_VERSIONS";
+const GC_KEEP_VERSIONS_ENTRY: &str = "store.gc.keep_versions";
+const GC_KEEP_VERSIONS_DEFAULT: usize = 3;
 const DIR_CHAINS_PREFIX_VAR: &str = "DIR_CHAINS_PREFIX";
 const DIR_CHAINS_PREFIX_ENTRY: &str = "store.prefix";
+const LOCAL_DIR_VAR: &str = "STORE_LOCAL_DIR";
+const LOCAL_DIR_ENTRY: &str = "store.local.dir";
+const LOCAL_SECRET_VAR: &str = "STORE_LOCAL_SECRET";
+const LOCAL_SECRET_ENTRY: &str = "store.local.secret";
+const LOCAL_URL_VAR: &str = "STORE_LOCAL_URL";
+const LOCAL_URL_ENTRY: &str = "store.local.url";
 const PRESIGNED_URL_EXPIRATION_VAR: &str = "PRESIGNED_URL_EXPIRATION";
 const PRESIGNED_URL_EXPIRATION_ENTRY: &str = "store.expiration";
 const REGION_VAR: &str = "AWS_REGION";
@@ -28,12 +46,22 @@ const BUNDLE_BUCKET_ENTRY: &str = "store.bucket.bundle";
 pub enum Error {
     /// Failed to parse BucketConfig: {0}
     Bucket(#[from] BucketError),
+    /// Failed to parse GcConfig: {0}
+    Gc(#[from] GcError),
+    /// Failed to read {BACKEND_VAR:?}: {0}
+    ReadBackend(provider::Error),
     /// Failed to read {PRESIGNED_URL_EXPIRATION_VAR:?}: {0}
     ReadExpiration(provider::Error),
     /// Failed to read {AWS_ACCESS_KEY_ID_VAR:?}: {0}
     ReadKeyId(provider::Error),
     /// Failed to read {AWS_SECRET_ACCESS_KEY_ENTRY:?}: {0}
     ReadKey(provider::Error),
+    /// Failed to read {LOCAL_DIR_VAR:?}: {0}
+    ReadLocalDir(provider::Error),
+    /// Failed to read {LOCAL_SECRET_VAR:?}: {0}
+    ReadLocalSecret(provider::Error),
+    /// Failed to read {LOCAL_URL_VAR:?}: {0}
+    ReadLocalUrl(provider::Error),
     /// Failed to read {DIR_CHAINS_PREFIX_VAR:?}: {0}
     ReadPrefix(provider::Error),
     /// Failed to read {REGION_VAR:?}: {0}
@@ -46,12 +74,10 @@ pub enum Error {
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub bucket: BucketConfig,
-    pub store_url: Url,
-    pub key_id: Redacted<String>,
-    pub key: Redacted<String>,
-    pub region: String,
+    pub backend: BackendConfig,
     pub dir_chains_prefix: String,
     pub presigned_url_expiration: HumanTime,
+    pub gc: GcConfig,
 }
 
 impl TryFrom<&Provider> for Config {
@@ -60,28 +86,95 @@ impl TryFrom<&Provider> for Config {
     fn try_from(provider: &Provider) -> Result<Self, Self::Error> {
         Ok(Config {
             bucket: provider.try_into()?,
-            store_url: provider
-                .read(STORE_URL_VAR, STORE_URL_ENTRY)
-                .map_err(Error::ReadUrl)?,
-            key_id: provider
-                .read(AWS_ACCESS_KEY_ID_VAR, AWS_ACCESS_KEY_ID_ENTRY)
-                .map_err(Error::ReadKeyId)?,
-            key: provider
-                .read(AWS_SECRET_ACCESS_KEY_VAR, AWS_SECRET_ACCESS_KEY_ENTRY)
-                .map_err(Error::ReadKey)?,
-            region: provider
-                .read(REGION_VAR, REGION_ENTRY)
-                .map_err(Error::ReadRegion)?,
+            backend: provider.try_into()?,
             dir_chains_prefix: provider
                 .read(DIR_CHAINS_PREFIX_VAR, DIR_CHAINS_PREFIX_ENTRY)
                 .map_err(Error::ReadPrefix)?,
             presigned_url_expiration: provider
                 .read(PRESIGNED_URL_EXPIRATION_VAR, PRESIGNED_URL_EXPIRATION_ENTRY)
                 .map_err(Error::ReadExpiration)?,
+            gc: provider.try_into()?,
         })
     }
 }
 
+/// Which kind of object storage backs the store.
+#[derive(Clone, Copy, Debug, Default, Deserialize, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    S3,
+    Local,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendConfig {
+    S3(S3Config),
+    Local(LocalConfig),
+}
+
+impl TryFrom<&Provider> for BackendConfig {
+    type Error = Error;
+
+    fn try_from(provider: &Provider) -> Result<Self, Self::Error> {
+        let kind = provider
+            .read_or_default(BACKEND_VAR, BACKEND_ENTRY)
+            .map_err(Error::ReadBackend)?;
+
+        match kind {
+            BackendKind::S3 => Ok(BackendConfig::S3(S3Config {
+                store_url: provider
+                    .read(STORE_URL_VAR, STORE_URL_ENTRY)
+                    .map_err(Error::ReadUrl)?,
+                key_id: provider
+                    .read(AWS_ACCESS_KEY_ID_VAR, AWS_ACCESS_KEY_ID_ENTRY)
+                    .map_err(Error::ReadKeyId)?,
+                key: provider
+                    .read(AWS_SECRET_ACCESS_KEY_VAR, AWS_SECRET_ACCESS_KEY_ENTRY)
+                    .map_err(Error::ReadKey)?,
+                region: provider
+                    .read(REGION_VAR, REGION_ENTRY)
+                    .map_err(Error::ReadRegion)?,
+            })),
+            BackendKind::Local => Ok(BackendConfig::Local(LocalConfig {
+                dir: provider
+                    .read(LOCAL_DIR_VAR, LOCAL_DIR_ENTRY)
+                    .map_err(Error::ReadLocalDir)?,
+                url: provider
+                    .read(LOCAL_URL_VAR, LOCAL_URL_ENTRY)
+                    .map_err(Error::ReadLocalUrl)?,
+                secret: provider
+                    .read(LOCAL_SECRET_VAR, LOCAL_SECRET_ENTRY)
+                    .map_err(Error::ReadLocalSecret)?,
+            })),
+        }
+    }
+}
+
+/// An S3 compatible object storage service.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    pub store_url: Url,
+    pub key_id: Redacted<String>,
+    pub key: Redacted<String>,
+    pub region: String,
+}
+
+/// A local directory served through signed URLs to this API.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalConfig {
+    /// The directory that holds a subdirectory per bucket.
+    pub dir: PathBuf,
+    /// The public base URL of this API, used for download and upload URLs.
+    pub url: Url,
+    /// The secret used to sign download and upload URLs.
+    pub secret: Redacted<String>,
+}
+
 #[derive(Debug, Display, Error)]
 pub enum BucketError {
     /// Failed to read {ARCHIVE_BUCKET_VAR:?}: {0}
@@ -111,3 +204,47 @@ impl TryFrom<&Provider> for BucketConfig {
         })
     }
 }
+
+#[derive(Debug, Display, Error)]
+pub enum GcError {
+    /// Failed to read {GC_DRY_RUN_VAR:?}: {0}
+    ReadDryRun(provider::Error),
+    /// Failed to read {GC_INTERVAL_VAR:?}: {0}
+    ReadInterval(provider::Error),
+    /// Failed to read {GC_KEEP_VERSIONS_VAR:?}: {0}
+    ReadKeepVersions(provider::Error),
+}
+
+/// Garbage collection of old archive data versions.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcConfig {
+    /// How often to collect garbage. Disabled if not set.
+    pub interval: Option<HumanTime>,
+    /// The number of verified data versions to keep per `StoreKey`.
+    pub keep_versions: usize,
+    /// Report what would be deleted without deleting anything.
+    pub dry_run: bool,
+}
+
+impl TryFrom<&Provider> for GcConfig {
+    type Error = GcError;
+
+    fn try_from(provider: &Provider) -> Result<Self, Self::Error> {
+        Ok(GcConfig {
+            interval: provider
+                .maybe_read(GC_INTERVAL_VAR, GC_INTERVAL_ENTRY)
+                .map_err(GcError::ReadInterval)?,
+            keep_versions: provider
+                .read_or(
+                    GC_KEEP_VERSIONS_DEFAULT,
+                    GC_KEEP_VERSIONS_VAR,
+                    GC_KEEP_VERSIONS_ENTRY,
+                )
+                .map_err(GcError::ReadKeepVersions)?,
+            dry_run: provider
+                .read_or_default(GC_DRY_RUN_VAR, GC_DRY_RUN_ENTRY)
+                .map_err(GcError::ReadDryRun)?,
+        })
+    }
+}
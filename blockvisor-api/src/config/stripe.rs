@@ -2,8 +2,8 @@ use displaydoc::Display;
 use serde::Deserialize;
 use thiserror::Error;
 
-use super::Redacted;
 use super::provider;
+use super::{HumanTime, Redacted};
 
 const STRIPE_SECRET_VAR: &str = "STRIPE_SECRET";
 const STRIPE_SECRET_ENTRY: &str = "stripe.secret";
@@ -12,12 +12,27 @@ const STRIPE_URL_VAR: &str = "STRIPE_URL";
 const STRIPE_URL_ENTRY: &str = "stripe.url";
 const STRIPE_URL_DEFAULT: &str = "https://api.stripe.com/v1";
 
+const RECONCILE_INTERVAL_VAR: &str = "STRIPE_RECONCILE_INTERVAL";
+const RECONCILE_INTERVAL_ENTRY: &str = "stripe.reconcile_interval";
+const RECONCILE_INTERVAL_DEFAULT: &str = "1h";
+const RECONCILE_DRY_RUN_VAR: &str = "STRIPE_RECONCILE_DRY_RUN";
+const RECONCILE_DRY_RUN_ENTRY: &str = "stripe.reconcile_dry_run";
+
+const USAGE_INTERVAL_VAR: &str = "STRIPE_USAGE_INTERVAL";
+const USAGE_INTERVAL_ENTRY: &str = "stripe.usage_interval";
+
 #[derive(Debug, Display, Error)]
 pub enum Error {
+    /// Failed to read {RECONCILE_DRY_RUN_VAR:?}: {0}
+    ReadReconcileDryRun(provider::Error),
+    /// Failed to read {RECONCILE_INTERVAL_VAR:?}: {0}
+    ReadReconcileInterval(provider::Error),
     /// Failed to read {STRIPE_SECRET_VAR:?}: {0}
     ReadSecret(provider::Error),
     /// Failed to read {STRIPE_URL_VAR:?}: {0}
     ReadUrl(provider::Error),
+    /// Failed to read {USAGE_INTERVAL_VAR:?}: {0}
+    ReadUsageInterval(provider::Error),
 }
 
 #[derive(Debug, Deserialize)]
@@ -25,6 +40,10 @@ pub enum Error {
 pub struct Config {
     pub secret: Option<Redacted<String>>,
     pub base_url: String,
+    pub reconcile_interval: HumanTime,
+    /// Only log stripe drift instead of repairing it.
+    pub reconcile_dry_run: bool,
+    pub usage_interval: Option<HumanTime>,
 }
 
 impl TryFrom<&provider::Provider> for Config {
@@ -38,6 +57,19 @@ impl TryFrom<&provider::Provider> for Config {
             base_url: provider
                 .read_or(STRIPE_URL_DEFAULT, STRIPE_URL_VAR, STRIPE_URL_ENTRY)
                 .map_err(Error::ReadUrl)?,
+            reconcile_interval: provider
+                .read_or_else(
+                    || RECONCILE_INTERVAL_DEFAULT.parse::<HumanTime>(),
+                    RECONCILE_INTERVAL_VAR,
+                    RECONCILE_INTERVAL_ENTRY,
+                )
+                .map_err(Error::ReadReconcileInterval)?,
+            reconcile_dry_run: provider
+                .read_or_default(RECONCILE_DRY_RUN_VAR, RECONCILE_DRY_RUN_ENTRY)
+                .map_err(Error::ReadReconcileDryRun)?,
+            usage_interval: provider
+                .maybe_read(USAGE_INTERVAL_VAR, USAGE_INTERVAL_ENTRY)
+                .map_err(Error::ReadUsageInterval)?,
         })
     }
 }
@@ -9,7 +9,7 @@ use diesel::{ConnectionError, ConnectionResult};
 use diesel_async::pooled_connection::bb8::{self, PooledConnection};
 use diesel_async::pooled_connection::{AsyncDieselConnectionManager, ManagerConfig};
 use diesel_async::scoped_futures::{ScopedBoxFuture, ScopedFutureExt};
-use diesel_async::{AsyncConnection, AsyncPgConnection};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use diesel_migrations::EmbeddedMigrations;
 use displaydoc::Display;
 use futures_util::FutureExt;
@@ -32,6 +32,7 @@ use crate::config::Context;
 use crate::config::database::Config;
 use crate::grpc::{self, Metadata, ResponseMessage, Status};
 use crate::model::rbac::{RbacPerm, RbacRole};
+use crate::model::sql::pg_try_advisory_xact_lock;
 use crate::mqtt::Message;
 
 pub const MIGRATIONS: EmbeddedMigrations = diesel_migrations::embed_migrations!();
@@ -152,7 +153,8 @@ impl Authorize for ReadConn<'_, '_> {
 /// A `WriteConn` is an open transactional connection to the database.
 ///
 /// Any messages sent over `mqtt_tx` will be forwared to MQTT only after the
-/// database transaction has been committed.
+/// database transaction has been committed, and likewise any tasks sent over
+/// `task_tx` will only run after the commit.
 #[derive(Deref, DerefMut)]
 pub struct WriteConn<'c, 't> {
     #[deref]
@@ -162,6 +164,7 @@ pub struct WriteConn<'c, 't> {
 
     pub meta_tx: UnboundedSender<(&'static str, AsciiMetadataValue)>,
     pub mqtt_tx: UnboundedSender<Message>,
+    pub task_tx: UnboundedSender<BoxFuture<'static, ()>>,
 }
 
 impl Authorize for WriteConn<'_, '_> {
@@ -191,6 +194,16 @@ impl WriteConn<'_, '_> {
         // safety: mqtt_rx is open for the lifetime of WriteConn
         self.mqtt_tx.send(message.into()).expect("mqtt_rx");
     }
+
+    /// Run `task` once the transaction has been committed, so that side
+    /// effects such as emails are skipped if it is rolled back.
+    pub fn after_commit<F>(&mut self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // safety: task_rx is open for the lifetime of WriteConn
+        self.task_tx.send(task.boxed()).expect("task_rx");
+    }
 }
 
 #[derive(Clone, Deref, DerefMut)]
@@ -281,6 +294,7 @@ where
 
         let (meta_tx, mut meta_rx) = mpsc::unbounded_channel();
         let (mqtt_tx, mut mqtt_rx) = mpsc::unbounded_channel();
+        let (task_tx, mut task_rx) = mpsc::unbounded_channel();
 
         let response = conn
             .transaction(|conn| {
@@ -289,6 +303,7 @@ where
                     ctx,
                     meta_tx,
                     mqtt_tx,
+                    task_tx,
                 };
                 f(write).scope_boxed()
             })
@@ -301,6 +316,10 @@ where
             }
         }
 
+        while let Some(task) = task_rx.recv().await {
+            task.await;
+        }
+
         let mut meta = Metadata::new();
         while let Some((key, val)) = meta_rx.recv().await {
             meta.insert_grpc(key, val);
@@ -415,6 +434,55 @@ pub async fn create_roles_and_perms(conn: &mut Conn<'_>) -> Result<(), Error> {
     RbacPerm::create_all(conn).await.map_err(Error::CreatePerms)
 }
 
+/// Background tasks that run on every replica but must only run on one of
+/// them at a time.
+///
+/// Each variant is the key of a Postgres advisory lock, so existing keys must
+/// not change.
+#[derive(Clone, Copy, Debug)]
+#[repr(i64)]
+pub enum AdvisoryLock {
+    StripeReconcile = 1,
+    StripeUsage = 2,
+    UsageBackfill = 3,
+    StoreGc = 4,
+    HostUpgrade = 5,
+    HostHeartbeat = 6,
+    NodeFailover = 7,
+    NodeMove = 8,
+    NodeRecovery = 9,
+}
+
+/// Run `f` in a transaction that holds the advisory `lock`.
+///
+/// Returns None without running `f` if another replica holds the lock. The
+/// lock is released when the transaction ends.
+pub async fn exclusive<'a, 't, F, T, E>(
+    lock: AdvisoryLock,
+    conn: &mut Conn<'t>,
+    f: F,
+) -> Result<Option<T>, E>
+where
+    F: for<'c> FnOnce(&'c mut Conn<'t>) -> ScopedBoxFuture<'a, 'c, Result<T, E>> + Send + 'a,
+    T: Send + 'a,
+    E: From<diesel::result::Error> + Send + 'a,
+{
+    conn.transaction(|conn| {
+        async move {
+            let locked = diesel::select(pg_try_advisory_xact_lock(lock as i64))
+                .get_result(conn)
+                .await?;
+            if locked {
+                f(conn).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
 #[cfg(any(test, feature = "integration-test"))]
 pub mod tests {
     use diesel::migration::MigrationSource;
@@ -517,6 +585,36 @@ pub mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn exclusive_runs_on_one_replica_at_a_time() {
+        let (ctx, _db) = Context::with_mocked().await.unwrap();
+        let mut conn = ctx.conn().await.unwrap();
+        let mut other = ctx.conn().await.unwrap();
+
+        let ran = exclusive(AdvisoryLock::StripeReconcile, &mut conn, |_| {
+            async move {
+                let skipped = exclusive(AdvisoryLock::StripeReconcile, &mut other, |_| {
+                    async { Ok::<_, diesel::result::Error>(()) }.scope_boxed()
+                })
+                .await?;
+                assert!(skipped.is_none());
+                Ok::<_, diesel::result::Error>(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .unwrap();
+        assert!(ran.is_some());
+
+        // the lock is released once the transaction ends
+        let ran = exclusive(AdvisoryLock::StripeReconcile, &mut other, |_| {
+            async { Ok::<_, diesel::result::Error>(()) }.scope_boxed()
+        })
+        .await
+        .unwrap();
+        assert!(ran.is_some());
+    }
+
     impl Drop for TestDb {
         fn drop(&mut self) {
             let test_db_name = self.test_db_name.clone();
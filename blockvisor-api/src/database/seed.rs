@@ -9,7 +9,7 @@ use crate::auth::resource::{NodeId, OrgId, ResourceType, UserId};
 use crate::model::host::{Host, NewHost, ScheduleType};
 use crate::model::image::config::ConfigType;
 use crate::model::image::{Config, Image, ImageId, NewConfig, NodeConfig};
-use crate::model::ip_address::NewIpAddress;
+use crate::model::ip_address::{IpFamily, NewIpAddress};
 use crate::model::node::{Node, NodeState, ResourceAffinity};
 use crate::model::protocol::version::{ProtocolVersion, VersionId};
 use crate::model::protocol::{Protocol, ProtocolId};
@@ -267,6 +267,8 @@ async fn create_hosts(
         bv_version: &bv_version,
         ip_address: "192.168.1.1".parse().unwrap(),
         ip_gateway: "192.168.1.1".parse().unwrap(),
+        ipv6_gateway: None,
+        zone_id: None,
         cpu_cores: 100,
         memory_bytes: 100 * MEMORY_BYTES,
         disk_bytes: 100 * DISK_BYTES,
@@ -290,6 +292,8 @@ async fn create_hosts(
         bv_version: &bv_version,
         ip_address: "192.168.2.1".parse().unwrap(),
         ip_gateway: "192.168.2.1".parse().unwrap(),
+        ipv6_gateway: None,
+        zone_id: None,
         cpu_cores: 1,
         memory_bytes: MEMORY_BYTES,
         disk_bytes: DISK_BYTES,
@@ -302,6 +306,10 @@ async fn create_hosts(
         .await
         .unwrap();
 
+    // both hosts have checked in so nodes can be scheduled on them
+    let host1 = Host::heartbeat(host1.id, conn).await.unwrap();
+    let host2 = Host::heartbeat(host2.id, conn).await.unwrap();
+
     (host1, host2)
 }
 
@@ -312,7 +320,7 @@ async fn create_ip_range(host: &Host, conn: &mut Conn<'_>) -> (IpNetwork, IpNetw
         .collect();
     NewIpAddress::bulk_create(ips, conn).await.unwrap();
 
-    let ip_address = IpAddress::next_for_host(host.id, conn)
+    let ip_address = IpAddress::next_for_host(host.id, IpFamily::V4, conn)
         .await
         .unwrap()
         .unwrap()
@@ -16,7 +16,7 @@ use crate::auth::resource::Resource;
 use crate::auth::token::Cipher;
 use crate::config::Config;
 use crate::config::token::ExpireChrono;
-use crate::model::{Invitation, User};
+use crate::model::{Image, Invitation, ProtocolVersion, User};
 
 const FROM_EMAIL: &str = "no-reply@blockjoy.com";
 const FROM_NAME: &str = "BlockJoy";
@@ -164,6 +164,52 @@ impl Email {
         self.send(Kind::InviteUser, invitee, Some(context)).await
     }
 
+    /// Notifies an org owner that an image build used by their nodes has been
+    /// deprecated or reached end of life.
+    pub async fn image_lifecycle(
+        &self,
+        user: &User,
+        image: &Image,
+        version: &ProtocolVersion,
+        node_names: &[String],
+    ) -> Result<(), Error> {
+        let base = &self.base_url;
+        let context = hashmap! {
+            "protocol" => version.protocol_key.to_string(),
+            "version" => version.semantic_version.to_string(),
+            "build" => image.build_version.to_string(),
+            "lifecycle" => image.lifecycle.description().to_string(),
+            "reason" => image.lifecycle_reason.clone().unwrap_or_else(|| "not given".to_string()),
+            "sunset" => image.sunset_at.map_or_else(
+                || "not scheduled".to_string(),
+                |at| at.format("%Y-%m-%d").to_string(),
+            ),
+            "nodes" => node_names.join(", "),
+            "link" => format!("{base}/nodes"),
+        };
+
+        self.send(Kind::ImageLifecycle, user, Some(context)).await
+    }
+
+    /// Notifies an org owner that the API has given up on recovering a failed
+    /// node.
+    pub async fn node_recovery_failed(
+        &self,
+        user: &User,
+        node_name: &str,
+        attempts: u32,
+    ) -> Result<(), Error> {
+        let base = &self.base_url;
+        let context = hashmap! {
+            "node" => node_name.to_string(),
+            "attempts" => attempts.to_string(),
+            "link" => format!("{base}/nodes"),
+        };
+
+        self.send(Kind::NodeRecoveryFailed, user, Some(context))
+            .await
+    }
+
     /// Sends a password reset email to the specified user containing a JWT that
     /// they can use to authenticate themselves to reset their password.
     pub async fn reset_password(&self, user: &User) -> Result<(), Error> {
@@ -8,8 +8,10 @@ use handlebars::{Context, Handlebars};
 use serde::Deserialize;
 use thiserror::Error;
 
+const IMAGE_LIFECYCLE: &str = "image_lifecycle.toml";
 const INVITE_USER: &str = "invite_user.toml";
 const INVITE_REGISTERED: &str = "invite_registered_user.toml";
+const NODE_RECOVERY_FAILED: &str = "node_recovery_failed.toml";
 const REGISTRATION_CONFIRMATION: &str = "register.toml";
 const RESET_PASSWORD: &str = "reset_password.toml";
 const UPDATE_PASSWORD: &str = "update_password.toml";
@@ -46,8 +48,10 @@ pub enum Language {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Kind {
+    ImageLifecycle,
     InviteUser,
     InviteRegistered,
+    NodeRecoveryFailed,
     RegistrationConfirmation,
     ResetPassword,
     UpdatePassword,
@@ -56,8 +60,10 @@ pub enum Kind {
 impl Kind {
     pub const fn subject(self) -> &'static str {
         match self {
+            Kind::ImageLifecycle => "[BlockJoy] Node Image Lifecycle Update",
             Kind::InviteUser => "[BlockJoy] Organization Invite",
             Kind::InviteRegistered => "[BlockJoy] Organization Invite",
+            Kind::NodeRecoveryFailed => "[BlockJoy] Node Recovery Failed",
             Kind::RegistrationConfirmation => "[BlockJoy] Verify Your Account",
             Kind::ResetPassword => "[BlockJoy] Reset Password",
             Kind::UpdatePassword => "[BlockJoy] Password Updated",
@@ -90,8 +96,10 @@ impl Templates {
         }
 
         let kinds = [
+            (Kind::ImageLifecycle, IMAGE_LIFECYCLE),
             (Kind::InviteUser, INVITE_USER),
             (Kind::InviteRegistered, INVITE_REGISTERED),
+            (Kind::NodeRecoveryFailed, NODE_RECOVERY_FAILED),
             (Kind::RegistrationConfirmation, REGISTRATION_CONFIRMATION),
             (Kind::ResetPassword, RESET_PASSWORD),
             (Kind::UpdatePassword, UPDATE_PASSWORD),
@@ -220,5 +228,9 @@ mod test {
             .await
             .unwrap();
         email.reset_password(&user).await.unwrap();
+        email
+            .node_recovery_failed(&user, "gentle-mouse", 3)
+            .await
+            .unwrap();
     }
 }
@@ -1,18 +1,21 @@
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
 use diesel_async::scoped_futures::ScopedFutureExt;
 use displaydoc::Display;
 use thiserror::Error;
 use tonic::{Request, Response};
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::auth::Authorize;
 use crate::auth::rbac::{ArchiveAdminPerm, ArchivePerm, Perm};
-use crate::database::{ReadConn, Transaction};
+use crate::database::{self, AdvisoryLock, ReadConn, Transaction, WriteConn};
 use crate::grpc::api::archive_service_server::ArchiveService;
 use crate::grpc::{Grpc, Metadata, Status, api};
-use crate::model::image::Archive;
+use crate::model::image::{Archive, ArchiveStorage, ArchiveVersion, NewArchiveVersion};
 use crate::store::manifest::DownloadManifest;
+use crate::store::{StoreKey, gc};
+use crate::util::NanosUtc;
 
 const DEFAULT_EXPIRES: u32 = 7 * 24 * 60 * 60;
 const MAX_CHUNK_INDEXES: usize = 100;
@@ -22,6 +25,10 @@ const MAX_SLOT_INDEXES: usize = 100;
 pub enum Error {
     /// Archive image error: {0}
     Archive(#[from] crate::model::image::archive::Error),
+    /// Archive storage error: {0}
+    ArchiveStorage(#[from] crate::model::image::archive_storage::Error),
+    /// Archive version error: {0}
+    ArchiveVersion(#[from] crate::model::image::archive_version::Error),
     /// Auth check failed: {0}
     Auth(#[from] crate::auth::Error),
     /// Failed to parse chunk index: {0}
@@ -30,6 +37,14 @@ pub enum Error {
     Claims(#[from] crate::auth::claims::Error),
     /// Diesel failure: {0}
     Diesel(#[from] diesel::result::Error),
+    /// Archive garbage collection failed: {0}
+    Gc(#[from] crate::store::gc::Error),
+    /// Data version {0} failed verification: {1}
+    IncompleteVersion(u64, String),
+    /// Invalid download manifest: {0}
+    InvalidManifest(crate::store::manifest::Error),
+    /// No verified data versions found.
+    NoVerifiedVersion,
     /// Failed to parse archive_id: {0}
     ParseArchiveId(uuid::Error),
     /// Failed to parse ArchiveChunk: {0}
@@ -56,6 +71,11 @@ impl From<Error> for Status {
         error!("{err}");
         match err {
             Diesel(_) => Status::internal("Internal error."),
+            IncompleteVersion(version, _) => {
+                Status::failed_precondition(format!("Data version {version} is incomplete."))
+            }
+            InvalidManifest(err) => Status::invalid_argument(format!("manifest: {err}")),
+            NoVerifiedVersion => Status::not_found("No verified data version."),
             ParseArchiveId(_) => Status::invalid_argument("archive_id"),
             ParseChunk(_) => Status::invalid_argument("chunks"),
             ParseCompression(_) => Status::invalid_argument("compression"),
@@ -64,8 +84,11 @@ impl From<Error> for Status {
             ChunkIndex(_) | TooManyChunks => Status::out_of_range("chunk_indexes"),
             SlotIndex(_) | TooManySlots => Status::out_of_range("slot_indexes"),
             Archive(err) => err.into(),
+            ArchiveStorage(err) => err.into(),
+            ArchiveVersion(err) => err.into(),
             Auth(err) => err.into(),
             Claims(err) => err.into(),
+            Gc(err) => err.into(),
             Store(err) => err.into(),
         }
     }
@@ -105,7 +128,35 @@ impl ArchiveService for Grpc {
         req: Request<api::ArchiveServicePutDownloadManifestRequest>,
     ) -> Result<Response<api::ArchiveServicePutDownloadManifestResponse>, tonic::Status> {
         let (meta, _, req) = req.into_parts();
-        self.read(|read| put_download_manifest(req, meta.into(), read).scope_boxed())
+        self.write(|write| put_download_manifest(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn collect_garbage(
+        &self,
+        req: Request<api::ArchiveServiceCollectGarbageRequest>,
+    ) -> Result<Response<api::ArchiveServiceCollectGarbageResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        let resp: Response<gc::Report> = self
+            .write(|write| collect_garbage(req, meta.into(), write).scope_boxed())
+            .await?;
+
+        // objects are only deleted once their archive versions are committed
+        let (meta, mut report, extensions) = resp.into_parts();
+        report
+            .delete_objects(&self.context.store)
+            .await
+            .map_err(|err| Status::from(Error::Gc(err)))?;
+
+        Ok(Response::from_parts(meta, report.into(), extensions))
+    }
+
+    async fn storage_usage(
+        &self,
+        req: Request<api::ArchiveServiceStorageUsageRequest>,
+    ) -> Result<Response<api::ArchiveServiceStorageUsageResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| storage_usage(req, meta.into(), read).scope_boxed())
             .await
     }
 }
@@ -131,10 +182,32 @@ pub async fn get_download_metadata(
 
     let archive_id = req.archive_id.parse().map_err(Error::ParseArchiveId)?;
     let archive = Archive::by_id(archive_id, org_id, &mut read).await?;
+
+    let data_version = if let Some(version) = req.data_version {
+        let archive_version =
+            ArchiveVersion::by_version(&archive.store_key, version, &mut read).await?;
+        match archive_version {
+            Some(archive_version) if !archive_version.is_verified() => {
+                let problem = archive_version.problem.unwrap_or_default();
+                return Err(Error::IncompleteVersion(version, problem));
+            }
+            _ => version,
+        }
+    } else {
+        let incomplete = ArchiveVersion::incomplete(&archive.store_key, &mut read).await?;
+        read.ctx
+            .store
+            .data_versions(&archive.store_key)
+            .await?
+            .into_iter()
+            .find(|version| !incomplete.contains(version))
+            .ok_or(Error::NoVerifiedVersion)?
+    };
+
     let (header, data_version) = read
         .ctx
         .store
-        .download_manifest_header(&archive.store_key, req.data_version)
+        .download_manifest_header(&archive.store_key, Some(data_version))
         .await?;
 
     Ok(api::ArchiveServiceGetDownloadMetadataResponse {
@@ -239,27 +312,32 @@ pub async fn get_upload_slots(
     })
 }
 
+/// Save a download manifest after checking its chunks against the store.
+///
+/// A manifest that is inconsistent is rejected. A manifest with chunks that
+/// are missing or the wrong size in the store is saved, but its data version
+/// is marked incomplete until the manifest is uploaded again and verified.
 pub async fn put_download_manifest(
     req: api::ArchiveServicePutDownloadManifestRequest,
     meta: Metadata,
-    mut read: ReadConn<'_, '_>,
+    mut write: WriteConn<'_, '_>,
 ) -> Result<api::ArchiveServicePutDownloadManifestResponse, Error> {
     let admin_perm: Perm = ArchiveAdminPerm::PutDownloadManifest.into();
     let user_perm: Perm = ArchivePerm::PutDownloadManifest.into();
 
     let (org_id, _authz) = if let Some(ref org_id) = req.org_id {
         let org_id = org_id.parse().map_err(Error::ParseOrgId)?;
-        let authz = read
+        let authz = write
             .auth_or_for(&meta, admin_perm, user_perm, org_id)
             .await?;
         (Some(org_id), authz)
     } else {
-        let authz = read.auth_any(&meta, [admin_perm, user_perm]).await?;
+        let authz = write.auth_any(&meta, [admin_perm, user_perm]).await?;
         (None, authz)
     };
 
     let archive_id = req.archive_id.parse().map_err(Error::ParseArchiveId)?;
-    let archive = Archive::by_id(archive_id, org_id, &mut read).await?;
+    let archive = Archive::by_id(archive_id, org_id, &mut write).await?;
 
     let manifest = DownloadManifest {
         total_size: req.total_size,
@@ -275,10 +353,144 @@ pub async fn put_download_manifest(
             .collect::<Result<Vec<_>, _>>()?,
     };
 
-    read.ctx
-        .store
+    let path = format!("{}/{}/", archive.store_key, req.data_version);
+    manifest.validate(&path).map_err(Error::InvalidManifest)?;
+
+    let store = &write.ctx.store;
+    let verification = store.verify_manifest(&manifest).await?;
+    let archive_version = NewArchiveVersion::new(
+        archive.store_key.clone(),
+        req.data_version,
+        &manifest,
+        &verification,
+    )?;
+
+    store
         .save_download_manifest(&archive.store_key, manifest, req.data_version)
         .await?;
+    let archive_version = archive_version.upsert(&mut write).await?;
+    if let Some(ref problem) = archive_version.problem {
+        warn!(
+            "Data version {} of `{}` is incomplete: {problem}",
+            req.data_version, archive.store_key
+        );
+    }
+
+    Ok(api::ArchiveServicePutDownloadManifestResponse {
+        verified: archive_version.is_verified(),
+        missing_chunks: u32::try_from(archive_version.missing_chunks).unwrap_or(u32::MAX),
+    })
+}
+
+pub async fn collect_garbage(
+    req: api::ArchiveServiceCollectGarbageRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<gc::Report, Error> {
+    let _authz = write.auth(&meta, ArchiveAdminPerm::CollectGarbage).await?;
+
+    let ctx = write.ctx;
+    let keep_versions = req
+        .keep_versions
+        .map_or(ctx.config.store.gc.keep_versions, |keep| {
+            usize::try_from(keep).unwrap_or(usize::MAX)
+        });
+    let dry_run = req.dry_run;
+    let report = database::exclusive(AdvisoryLock::StoreGc, &mut write, |conn| {
+        gc::collect(&ctx.store, keep_versions, dry_run, conn).scope_boxed()
+    })
+    .await?;
+
+    report.ok_or(Error::Gc(gc::Error::Running))
+}
+
+impl From<gc::Report> for api::ArchiveServiceCollectGarbageResponse {
+    fn from(report: gc::Report) -> Self {
+        api::ArchiveServiceCollectGarbageResponse {
+            dry_run: report.dry_run,
+            store_keys: u64::try_from(report.store_keys).unwrap_or(u64::MAX),
+            deleted_versions: report
+                .deleted_versions
+                .into_iter()
+                .map(|(store_key, data_version)| api::DeletedDataVersion {
+                    store_key: store_key.into(),
+                    data_version,
+                })
+                .collect(),
+            deleted_store_keys: report
+                .deleted_store_keys
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            deleted_objects: u64::try_from(report.deleted_objects).unwrap_or(u64::MAX),
+            deleted_bytes: report.deleted_bytes,
+        }
+    }
+}
+
+/// Report the bytes stored per archive, protocol and org.
+///
+/// Archives may share a `StoreKey`, so each is only counted once towards the
+/// totals of a protocol or org.
+pub async fn storage_usage(
+    _: api::ArchiveServiceStorageUsageRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::ArchiveServiceStorageUsageResponse, Error> {
+    let _authz = read.auth(&meta, ArchiveAdminPerm::StorageUsage).await?;
+
+    let storage: HashMap<StoreKey, ArchiveStorage> = ArchiveStorage::all(&mut read)
+        .await?
+        .into_iter()
+        .map(|storage| (storage.store_key.clone(), storage))
+        .collect();
+    let stored_bytes = |store_key: &StoreKey| {
+        storage.get(store_key).map_or(0, |storage| {
+            u64::try_from(storage.stored_bytes).unwrap_or_default()
+        })
+    };
+
+    let archives = Archive::with_protocol(&mut read).await?;
+    let mut protocols: HashMap<_, HashSet<&StoreKey>> = HashMap::new();
+    let mut orgs: HashMap<_, HashSet<&StoreKey>> = HashMap::new();
+    let mut archive_storage = Vec::with_capacity(archives.len());
+
+    for (archive, protocol_id) in &archives {
+        protocols
+            .entry(protocol_id)
+            .or_default()
+            .insert(&archive.store_key);
+        orgs.entry(archive.org_id)
+            .or_default()
+            .insert(&archive.store_key);
+
+        let measured = storage.get(&archive.store_key);
+        archive_storage.push(api::ArchiveStorage {
+            archive_id: archive.id.to_string(),
+            store_key: archive.store_key.to_string(),
+            stored_bytes: stored_bytes(&archive.store_key),
+            data_versions: measured.map_or(0, |storage| {
+                u64::try_from(storage.data_versions).unwrap_or_default()
+            }),
+            measured_at: measured.map(|storage| NanosUtc::from(storage.measured_at).into()),
+        });
+    }
 
-    Ok(api::ArchiveServicePutDownloadManifestResponse {})
+    Ok(api::ArchiveServiceStorageUsageResponse {
+        archives: archive_storage,
+        protocols: protocols
+            .into_iter()
+            .map(|(protocol_id, store_keys)| api::ProtocolStorage {
+                protocol_id: protocol_id.to_string(),
+                stored_bytes: store_keys.into_iter().map(stored_bytes).sum(),
+            })
+            .collect(),
+        orgs: orgs
+            .into_iter()
+            .map(|(org_id, store_keys)| api::OrgStorage {
+                org_id: org_id.map(|id| id.to_string()),
+                stored_bytes: store_keys.into_iter().map(stored_bytes).sum(),
+            })
+            .collect(),
+    })
 }
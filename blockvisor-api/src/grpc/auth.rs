@@ -1,3 +1,4 @@
+use chrono::Utc;
 use diesel_async::scoped_futures::ScopedFutureExt;
 use displaydoc::Display;
 use thiserror::Error;
@@ -6,15 +7,23 @@ use tracing::{error, warn};
 
 use crate::auth::Authorize;
 use crate::auth::claims::{Claims, Expirable, Granted};
-use crate::auth::rbac::{AuthAdminPerm, AuthPerm, GrpcRole, Perm};
+use crate::auth::oidc::{self, AuthorizeParams, ExchangeParams, IdClaims, Pkce};
+use crate::auth::rbac::{AuthAdminPerm, AuthPerm, GrpcRole, OrgRole, Perm, Role};
+use crate::auth::resource::{OrgId, UserId};
 use crate::auth::token::RequestToken;
 use crate::auth::token::refresh::Refresh;
 use crate::database::{Transaction, WriteConn};
-use crate::model::User;
+use crate::model::rbac::RbacUser;
+use crate::model::sso::{IdentityProvider, NewSsoIdentity, NewSsoSession, SsoIdentity, SsoSession};
+use crate::model::user::NewUser;
+use crate::model::{Org, User};
 
 use super::api::auth_service_server::AuthService;
 use super::{Grpc, Metadata, Status, api};
 
+/// How long a user has to complete an SSO login at the identity provider.
+const SSO_SESSION_EXPIRY: chrono::Duration = chrono::Duration::minutes(10);
+
 #[derive(Debug, Display, Error)]
 pub enum Error {
     /// Auth check failed: {0}
@@ -35,8 +44,20 @@ pub enum Error {
     Node(#[from] crate::model::node::Error),
     /// Not JWT Token.
     NotJwt,
+    /// OIDC error: {0}
+    Oidc(#[from] crate::auth::oidc::Error),
     /// Requested to send an email, but no email service is configured.
     NoEmail,
+    /// Email `{0}` is not in a verified SSO domain.
+    SsoDomain(String),
+    /// The SSO account is already linked to a user other than `{0}`.
+    SsoLinked(UserId),
+    /// An account with this email exists and must link SSO before using it.
+    SsoLinkRequired,
+    /// The identity provider did not return an email.
+    SsoNoEmail,
+    /// The identity provider has not verified the email.
+    SsoUnverified,
     /// No Refresh token in cookie or request body.
     NoRefresh,
     /// Org auth error: {0}
@@ -55,6 +76,8 @@ pub enum Error {
     RefreshResource,
     /// Auth resource error: {0}
     Resource(#[from] crate::auth::resource::Error),
+    /// SSO error: {0}
+    Sso(#[from] crate::model::sso::Error),
     /// User auth error: {0}
     User(#[from] crate::model::user::Error),
 }
@@ -69,6 +92,12 @@ impl From<Error> for Status {
             }
             Diesel(_) | Email(_) => Status::internal("Internal error."),
             NoEmail => Status::failed_precondition("No email configured."),
+            SsoDomain(_) | SsoLinked(_) | SsoNoEmail | SsoUnverified => {
+                Status::forbidden("SSO login denied.")
+            }
+            SsoLinkRequired => {
+                Status::failed_precondition("Sign in to link SSO to the existing account.")
+            }
             ClaimsNotUser => Status::forbidden("Access denied."),
             NoRefresh => Status::invalid_argument("No refresh token."),
             ParseOrgId(_) => Status::invalid_argument("org_id"),
@@ -77,10 +106,12 @@ impl From<Error> for Status {
             Claims(err) => err.into(),
             Host(err) => err.into(),
             Node(err) => err.into(),
+            Oidc(err) => err.into(),
             Org(err) => err.into(),
             Rbac(err) => err.into(),
             Refresh(err) => err.into(),
             Resource(err) => err.into(),
+            Sso(err) => err.into(),
             User(err) => err.into(),
         }
     }
@@ -150,6 +181,33 @@ impl AuthService for Grpc {
         self.write(|write| list_permissions(req, meta.into(), write).scope_boxed())
             .await
     }
+
+    async fn sso_authorize(
+        &self,
+        req: Request<api::AuthServiceSsoAuthorizeRequest>,
+    ) -> Result<Response<api::AuthServiceSsoAuthorizeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| sso_authorize(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn sso_callback(
+        &self,
+        req: Request<api::AuthServiceSsoCallbackRequest>,
+    ) -> Result<Response<api::AuthServiceSsoCallbackResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| sso_callback(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn sso_link(
+        &self,
+        req: Request<api::AuthServiceSsoLinkRequest>,
+    ) -> Result<Response<api::AuthServiceSsoLinkResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| sso_link(req, meta.into(), write).scope_boxed())
+            .await
+    }
 }
 
 pub async fn login(
@@ -336,3 +394,175 @@ pub async fn list_permissions(
 
     Ok(api::AuthServiceListPermissionsResponse { permissions })
 }
+
+/// Start an SSO login by returning the identity provider's authorization url.
+pub async fn sso_authorize(
+    req: api::AuthServiceSsoAuthorizeRequest,
+    _: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::AuthServiceSsoAuthorizeResponse, Error> {
+    // No auth claims are required as the identity provider authenticates.
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let url = start_sso(org_id, &req.redirect_uri, None, &mut write).await?;
+
+    Ok(api::AuthServiceSsoAuthorizeResponse {
+        authorization_url: url.into(),
+    })
+}
+
+/// Start an SSO login that links the identity provider account to a signed in
+/// user once the callback completes.
+pub async fn sso_link(
+    req: api::AuthServiceSsoLinkRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::AuthServiceSsoLinkResponse, Error> {
+    let user_id: UserId = req.user_id.parse().map_err(Error::ParseUserId)?;
+    write.auth_for(&meta, AuthPerm::SsoLink, user_id).await?;
+
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let url = start_sso(org_id, &req.redirect_uri, Some(user_id), &mut write).await?;
+
+    Ok(api::AuthServiceSsoLinkResponse {
+        authorization_url: url.into(),
+    })
+}
+
+async fn start_sso(
+    org_id: OrgId,
+    redirect_uri: &str,
+    link_user_id: Option<UserId>,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<url::Url, Error> {
+    let provider = IdentityProvider::by_org(org_id, write).await?;
+    let metadata = oidc::Client::new()?.discover(&provider.issuer).await?;
+
+    let state = oidc::random_string();
+    let nonce = oidc::random_string();
+    let pkce = Pkce::new();
+
+    let session = NewSsoSession {
+        org_id,
+        state: &state,
+        nonce: &nonce,
+        code_verifier: &pkce.verifier,
+        redirect_uri,
+        link_user_id,
+        expires_at: Utc::now() + SSO_SESSION_EXPIRY,
+    };
+    session.create(write).await?;
+
+    let params = AuthorizeParams {
+        client_id: &provider.client_id,
+        redirect_uri,
+        state: &state,
+        nonce: &nonce,
+        challenge: &pkce.challenge,
+    };
+
+    Ok(oidc::Client::authorize_url(&metadata, &params))
+}
+
+/// Complete an SSO login with the code returned by the identity provider.
+///
+/// The provider account is matched to a user by its issuer and subject. An
+/// unknown account either links to the user that started an `sso_link`, or
+/// creates a new user on first login. It is never attached to an existing
+/// user by email, as that would let the provider sign in as anyone.
+///
+/// The user is added to the org with their role derived from the provider's
+/// group claims. Org owners are never demoted by a group mapping.
+pub async fn sso_callback(
+    req: api::AuthServiceSsoCallbackRequest,
+    _: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::AuthServiceSsoCallbackResponse, Error> {
+    let session = SsoSession::take(&req.state, &mut write).await?;
+    let provider = IdentityProvider::by_org(session.org_id, &mut write).await?;
+
+    let client = oidc::Client::new()?;
+    let metadata = client.discover(&provider.issuer).await?;
+    let params = ExchangeParams {
+        client_id: &provider.client_id,
+        client_secret: &provider.client_secret,
+        redirect_uri: &session.redirect_uri,
+        code: &req.code,
+        verifier: &session.code_verifier,
+        nonce: &session.nonce,
+    };
+    let id_claims = client.exchange(&metadata, &params).await?;
+
+    let email = id_claims.email.as_deref().ok_or(Error::SsoNoEmail)?;
+    if id_claims.email_verified != Some(true) {
+        return Err(Error::SsoUnverified);
+    } else if !provider.allows_email(email) {
+        return Err(Error::SsoDomain(email.to_string()));
+    }
+
+    let identity = SsoIdentity::find(&provider.issuer, &id_claims.sub, &mut write).await?;
+    let user = match (identity, session.link_user_id) {
+        (Some(identity), Some(link_user_id)) if identity.user_id != link_user_id => {
+            return Err(Error::SsoLinked(link_user_id));
+        }
+        (Some(identity), _) => User::by_id(identity.user_id, &mut write).await?,
+        (None, Some(link_user_id)) => {
+            let user = User::by_id(link_user_id, &mut write).await?;
+            link_identity(user.id, &provider, &id_claims, &mut write).await?;
+            user
+        }
+        (None, None) => match User::by_email(email, &mut write).await {
+            Ok(_) => return Err(Error::SsoLinkRequired),
+            Err(crate::model::user::Error::FindByEmail(_, diesel::result::Error::NotFound)) => {
+                let first_name = id_claims.given_name.as_deref().unwrap_or_default();
+                let last_name = id_claims.family_name.as_deref().unwrap_or_default();
+                let password = oidc::random_string();
+                let user = NewUser::new(email, first_name, last_name, &password)?
+                    .create(&mut write)
+                    .await?;
+                User::confirm(user.id, &mut write).await?;
+                link_identity(user.id, &provider, &id_claims, &mut write).await?;
+                user
+            }
+            Err(err) => return Err(err.into()),
+        },
+    };
+
+    let org_id = session.org_id;
+    let role = provider.group_roles.role_for(&id_claims.groups);
+    if !Org::has_user(org_id, user.id, &mut write).await? {
+        Org::add_user(user.id, org_id, role, &mut write).await?;
+    } else {
+        let roles = RbacUser::org_roles(user.id, org_id, true, &mut write).await?;
+        if !roles.contains(&Role::Org(OrgRole::Owner)) {
+            Org::set_user_role(user.id, org_id, role, &mut write).await?;
+        }
+    }
+
+    let expires = write.ctx.config.token.expire.token;
+    let claims = Claims::from_now(expires, user.id, GrpcRole::Login);
+
+    let expires = write.ctx.config.token.expire.refresh_user;
+    let refresh = Refresh::from_now(expires, user.id);
+    let cookie = write.ctx.auth.cipher.refresh.cookie(&refresh)?;
+    write.meta("set-cookie", cookie.header()?);
+
+    Ok(api::AuthServiceSsoCallbackResponse {
+        token: write.ctx.auth.cipher.jwt.encode(&claims)?.into(),
+        refresh: write.ctx.auth.cipher.refresh.encode(&refresh)?.into(),
+    })
+}
+
+async fn link_identity(
+    user_id: UserId,
+    provider: &IdentityProvider,
+    id_claims: &IdClaims,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    let identity = NewSsoIdentity {
+        user_id,
+        issuer: &provider.issuer,
+        subject: &id_claims.sub,
+    };
+    identity.create(write).await?;
+    Ok(())
+}
@@ -5,25 +5,47 @@ use tonic::{Request, Response};
 use tracing::{error, warn};
 
 use crate::auth::Authorize;
-use crate::auth::rbac::BundlePerm;
-use crate::database::{ReadConn, Transaction};
+use crate::auth::rbac::{BundleAdminPerm, BundlePerm};
+use crate::auth::resource::{HostId, Resource};
+use crate::database::{ReadConn, Transaction, WriteConn};
 use crate::grpc::api::bundle_service_server::BundleService;
 use crate::grpc::{Grpc, Metadata, Status, api};
+use crate::model::bundle::{BundlePin, NewBundle};
 use crate::model::sql::Version;
+use crate::model::{Bundle, BundleChannel, Host, Region, RegionId};
 use crate::store::BUNDLE_FILE;
+use crate::util::NanosUtc;
 
 #[derive(Debug, Display, Error)]
 pub enum Error {
+    /// Bundle version `{0}` is already stored.
+    AlreadyStored(Version),
     /// Auth check failed: {0}
     Auth(#[from] crate::auth::Error),
+    /// Bundle model error: {0}
+    Bundle(#[from] crate::model::bundle::Error),
     /// Claims check failed: {0}
     Claims(#[from] crate::auth::claims::Error),
     /// Diesel failure: {0}
     Diesel(#[from] diesel::result::Error),
+    /// Bundle host error: {0}
+    Host(#[from] crate::model::host::Error),
     /// Missing bundle identifier.
     MissingId,
+    /// Missing the host or region to pin.
+    MissingPinTarget,
+    /// No bundle is available on channel `{0:?}`.
+    NoBundle(BundleChannel),
+    /// Failed to parse bundle version: {0}
+    ParseBundleVersion(crate::model::sql::Error),
+    /// Failed to parse HostId: {0}
+    ParseHostId(uuid::Error),
+    /// Failed to parse RegionId: {0}
+    ParseRegionId(uuid::Error),
     /// Failed to parse version from key `{0}`: {1}
     ParseVersion(String, crate::model::sql::Error),
+    /// Bundle region error: {0}
+    Region(#[from] crate::model::region::Error),
     /// Store failed: {0}
     Store(#[from] crate::store::Error),
     /// File name should end in `/{BUNDLE_FILE:?}` but is `{0}`.
@@ -35,12 +57,20 @@ impl From<Error> for Status {
         use Error::*;
         error!("{err}");
         match err {
+            AlreadyStored(_) => Status::already_exists("Bundle version already exists."),
             Diesel(_) | ParseVersion(_, _) | Store(_) | Suffix(_) => {
                 Status::internal("Internal error.")
             }
-            MissingId => Status::invalid_argument("bundle_id"),
+            MissingId | ParseBundleVersion(_) => Status::invalid_argument("bundle_id"),
+            MissingPinTarget => Status::invalid_argument("target"),
+            NoBundle(_) => Status::not_found("No bundle available."),
+            ParseHostId(_) => Status::invalid_argument("host_id"),
+            ParseRegionId(_) => Status::invalid_argument("region_id"),
             Auth(err) => err.into(),
+            Bundle(err) => err.into(),
             Claims(err) => err.into(),
+            Host(err) => err.into(),
+            Region(err) => err.into(),
         }
     }
 }
@@ -64,6 +94,42 @@ impl BundleService for Grpc {
         self.read(|read| list_versions(req, meta.into(), read).scope_boxed())
             .await
     }
+
+    async fn latest(
+        &self,
+        req: Request<api::BundleServiceLatestRequest>,
+    ) -> Result<Response<api::BundleServiceLatestResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| latest(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn promote(
+        &self,
+        req: Request<api::BundleServicePromoteRequest>,
+    ) -> Result<Response<api::BundleServicePromoteResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| promote(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn pin(
+        &self,
+        req: Request<api::BundleServicePinRequest>,
+    ) -> Result<Response<api::BundleServicePinResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| pin(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn upload(
+        &self,
+        req: Request<api::BundleServiceUploadRequest>,
+    ) -> Result<Response<api::BundleServiceUploadResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| upload(req, meta.into(), write).scope_boxed())
+            .await
+    }
 }
 
 pub async fn retrieve(
@@ -92,6 +158,165 @@ pub async fn list_versions(
     Ok(api::BundleServiceListVersionsResponse { bundle_ids })
 }
 
+/// Find the newest bundle for the release channel that a host follows.
+pub async fn latest(
+    req: api::BundleServiceLatestRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::BundleServiceLatestResponse, Error> {
+    let host_id: HostId = req.host_id.parse().map_err(Error::ParseHostId)?;
+    let mut resources = vec![Resource::from(host_id)];
+    let org_id = Host::org_id(host_id, &mut read).await?;
+    if let Some(org_id) = org_id {
+        resources.push(Resource::from(org_id));
+    }
+    read.auth_for(&meta, BundlePerm::Latest, &resources).await?;
+
+    let host = Host::by_id(host_id, org_id, &mut read).await?;
+    let channel = BundlePin::channel_for(&host, &mut read).await?;
+
+    let stored = read.ctx.store.list_bundles().await?;
+    let bundles = Bundle::all(&mut read).await?;
+    let (version, bundle_channel) =
+        latest_for(channel, &stored, &bundles).ok_or(Error::NoBundle(channel))?;
+
+    let version = version.to_string();
+    let url = read.ctx.store.download_bundle(&version).await?;
+
+    Ok(api::BundleServiceLatestResponse {
+        bundle_id: Some(api::BundleIdentifier { version }),
+        channel: api::BundleChannel::from(bundle_channel).into(),
+        url: url.to_string(),
+    })
+}
+
+pub async fn promote(
+    req: api::BundleServicePromoteRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::BundleServicePromoteResponse, Error> {
+    write.auth(&meta, BundleAdminPerm::Promote).await?;
+
+    let id = req.bundle_id.ok_or(Error::MissingId)?;
+    let version: Version = id.version.parse().map_err(Error::ParseBundleVersion)?;
+    let channel = BundleChannel::try_from(req.channel())?;
+    let bundle = Bundle::promote(&version, channel, &mut write).await?;
+
+    Ok(api::BundleServicePromoteResponse {
+        bundle: Some(api::Bundle::from(bundle)),
+    })
+}
+
+/// Pin a host or region to a channel, or unpin it if no channel is given.
+pub async fn pin(
+    req: api::BundleServicePinRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::BundleServicePinResponse, Error> {
+    use api::bundle_service_pin_request::Target;
+
+    write.auth(&meta, BundleAdminPerm::Pin).await?;
+
+    let channel = req
+        .channel
+        .map(|_| BundleChannel::try_from(req.channel()))
+        .transpose()?;
+
+    match req.target.ok_or(Error::MissingPinTarget)? {
+        Target::HostId(id) => {
+            let host_id: HostId = id.parse().map_err(Error::ParseHostId)?;
+            let org_id = Host::org_id(host_id, &mut write).await?;
+            let host = Host::by_id(host_id, org_id, &mut write).await?;
+            BundlePin::pin_host(host.id, channel, &mut write).await?;
+        }
+        Target::RegionId(id) => {
+            let region_id: RegionId = id.parse().map_err(Error::ParseRegionId)?;
+            let region = Region::by_id(region_id, &mut write).await?;
+            BundlePin::pin_region(region.id, channel, &mut write).await?;
+        }
+    }
+
+    Ok(api::BundleServicePinResponse {})
+}
+
+/// Reserve a new bundle version and return a URL to upload it to.
+///
+/// Versions already in the store are rejected, since a stored version without
+/// a `Bundle` row is an implicitly `Stable` legacy bundle.
+pub async fn upload(
+    req: api::BundleServiceUploadRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::BundleServiceUploadResponse, Error> {
+    write.auth(&meta, BundleAdminPerm::Upload).await?;
+
+    let id = req.bundle_id.as_ref().ok_or(Error::MissingId)?;
+    let version: Version = id.version.parse().map_err(Error::ParseBundleVersion)?;
+    let channel = req
+        .channel
+        .map(|_| BundleChannel::try_from(req.channel()))
+        .transpose()?
+        .unwrap_or(BundleChannel::Canary);
+
+    let stored = write.ctx.store.list_bundles().await?;
+    let is_stored = |id: &api::BundleIdentifier| {
+        id.version
+            .parse::<Version>()
+            .is_ok_and(|stored| stored == version)
+    };
+    if stored.iter().any(is_stored) {
+        return Err(Error::AlreadyStored(version));
+    }
+
+    let bundle = NewBundle { version, channel }.create(&mut write).await?;
+    let url = write
+        .ctx
+        .store
+        .upload_bundle(&bundle.version.to_string())
+        .await?;
+
+    Ok(api::BundleServiceUploadResponse {
+        bundle: Some(api::Bundle::from(bundle)),
+        url: url.to_string(),
+    })
+}
+
+/// Select the newest stored bundle visible to `channel`.
+///
+/// Stored bundles without a `Bundle` row predate release channels and are
+/// treated as `Stable`.
+fn latest_for(
+    channel: BundleChannel,
+    stored: &[api::BundleIdentifier],
+    bundles: &[Bundle],
+) -> Option<(Version, BundleChannel)> {
+    stored
+        .iter()
+        .filter_map(|id| id.version.parse::<Version>().ok())
+        .map(|version| {
+            let bundle_channel = bundles
+                .iter()
+                .find(|bundle| bundle.version == version)
+                .map_or(BundleChannel::Stable, |bundle| bundle.channel);
+            (version, bundle_channel)
+        })
+        .filter(|(_, bundle_channel)| *bundle_channel >= channel)
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+}
+
+impl From<Bundle> for api::Bundle {
+    fn from(bundle: Bundle) -> Self {
+        api::Bundle {
+            bundle_id: Some(api::BundleIdentifier {
+                version: bundle.version.to_string(),
+            }),
+            channel: api::BundleChannel::from(bundle.channel).into(),
+            created_at: Some(NanosUtc::from(bundle.created_at).into()),
+            promoted_at: bundle.promoted_at.map(NanosUtc::from).map(Into::into),
+        }
+    }
+}
+
 impl api::BundleIdentifier {
     /// Extract the bundle version from a key.
     ///
@@ -144,4 +369,37 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_latest_for_channel() {
+        let stored: Vec<_> = ["0.1.0", "0.2.0", "0.3.0", "0.4.0"]
+            .into_iter()
+            .map(|version| api::BundleIdentifier {
+                version: version.into(),
+            })
+            .collect();
+        let bundle = |version: &str, channel| Bundle {
+            id: uuid::Uuid::new_v4().into(),
+            version: version.parse().unwrap(),
+            channel,
+            created_at: chrono::Utc::now(),
+            promoted_at: None,
+        };
+        // 0.1.0 has no row so is treated as stable
+        let bundles = [
+            bundle("0.2.0", BundleChannel::Stable),
+            bundle("0.3.0", BundleChannel::Beta),
+            bundle("0.4.0", BundleChannel::Canary),
+            // reserved but not yet uploaded
+            bundle("0.5.0", BundleChannel::Canary),
+        ];
+
+        let latest = |channel| {
+            latest_for(channel, &stored, &bundles).map(|(version, _)| version.to_string())
+        };
+        assert_eq!(latest(BundleChannel::Stable).unwrap(), "0.2.0");
+        assert_eq!(latest(BundleChannel::Beta).unwrap(), "0.3.0");
+        assert_eq!(latest(BundleChannel::Canary).unwrap(), "0.4.0");
+        assert!(latest_for(BundleChannel::Stable, &stored[2..], &bundles).is_none());
+    }
 }
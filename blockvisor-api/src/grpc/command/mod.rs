@@ -37,6 +37,10 @@ pub enum Error {
     GrpcHost(Box<crate::grpc::node::Error>),
     /// Command host error: {0}
     Host(#[from] crate::model::host::Error),
+    /// HostUpgrade command is missing expected protobuf bytes.
+    HostUpgradeMissingProtobuf,
+    /// Failed to decode HostUpgrade protobuf: {0}
+    HostUpgradeDecode(prost::DecodeError),
     /// List commands is missing a node_id or host_id.
     ListMissingNodeOrHost,
     /// Missing `command.node_id`.
@@ -88,6 +92,8 @@ impl From<Error> for Status {
         match err {
             Diesel(_)
             | GrpcHost(_)
+            | HostUpgradeMissingProtobuf
+            | HostUpgradeDecode(_)
             | NodeUpdateMissingProtobuf
             | NodeUpdateDecode(_)
             | NotHostCommand(_)
@@ -176,6 +182,10 @@ async fn ack(
         authz
     };
 
+    if authz.resource().host() == Some(command.host_id) {
+        Host::heartbeat(command.host_id, &mut write).await?;
+    }
+
     if command.acked_at.is_none() {
         command.ack(&mut write).await?;
     } else {
@@ -379,7 +389,8 @@ impl api::Command {
             CommandType::HostStart
             | CommandType::HostStop
             | CommandType::HostRestart
-            | CommandType::HostPending => Self::from_host(command),
+            | CommandType::HostPending
+            | CommandType::HostUpgrade => Self::from_host(command),
             CommandType::NodeCreate
             | CommandType::NodeStart
             | CommandType::NodeStop
@@ -396,6 +407,7 @@ impl api::Command {
             CommandType::HostStop => host_stop(command).map(Some),
             CommandType::HostRestart => host_restart(command).map(Some),
             CommandType::HostPending => host_pending(command).map(Some),
+            CommandType::HostUpgrade => host_upgrade(command).map(Some),
             _ => Err(Error::NotHostCommand(command.id)),
         }
     }
@@ -465,6 +477,17 @@ pub fn host_pending(command: &Command) -> Result<api::Command, Error> {
     host_command(command, host_cmd)
 }
 
+fn host_upgrade(command: &Command) -> Result<api::Command, Error> {
+    let bytes = command
+        .protobuf
+        .as_ref()
+        .ok_or(Error::HostUpgradeMissingProtobuf)?;
+    let upgrade: api::HostUpgrade =
+        Message::decode(&bytes[..]).map_err(Error::HostUpgradeDecode)?;
+    let host_cmd = api::host_command::Command::Upgrade(upgrade);
+    host_command(command, host_cmd)
+}
+
 /// Create a new `api::NodeCommand` from a `Command`.
 fn node_command(
     command: &Command,
@@ -9,6 +9,7 @@ use crate::auth::resource::{HostId, OrgId};
 use crate::database::WriteConn;
 use crate::grpc::{Status, api};
 use crate::model::command::NewCommand;
+use crate::model::ip_address::IpFamily;
 use crate::model::node::{LogEvent, NewNodeLog, UpdateNode};
 use crate::model::{Command, CommandType, Host, IpAddress, Node, Protocol};
 
@@ -100,6 +101,18 @@ async fn node_create_failed(
     if let Err(err) = write.ctx.dns.delete(&node.dns_id).await {
         warn!("Failed to remove node dns for node {}: {err}", node.id);
     }
+    if let Some(ref dns_id) = node.ipv6_dns_id {
+        if let Err(err) = write.ctx.dns.delete(dns_id).await {
+            warn!("Failed to remove node IPv6 dns for node {}: {err}", node.id);
+        }
+    }
+
+    // the failed host may still hold the old addresses
+    let old_ips: Vec<_> = std::iter::once(node.ip_address)
+        .chain(node.ipv6_address)
+        .collect();
+    let quarantine = *write.ctx.config.host.ip_quarantine;
+    IpAddress::quarantine(node.host_id, &old_ips, quarantine, write).await?;
 
     // find the next host to assign the node to
     let protocol = Protocol::by_id(node.protocol_id, org_id, authz, write).await?;
@@ -112,9 +125,14 @@ async fn node_create_failed(
     };
 
     // update the node to the new host
-    let ip = IpAddress::next_for_host(host.id, write)
+    let ip = IpAddress::next_for_host(host.id, IpFamily::V4, write)
         .await?
         .ok_or(Error::NoIps(host.id))?;
+    let ipv6 = if host.ipv6_gateway.is_some() {
+        IpAddress::next_for_host(host.id, IpFamily::V6, write).await?
+    } else {
+        None
+    };
     let update = UpdateNode {
         org_id: None,
         host_id: Some(host.id),
@@ -122,9 +140,14 @@ async fn node_create_failed(
         auto_upgrade: None,
         ip_address: Some(ip.ip),
         ip_gateway: Some(host.ip_gateway),
+        ipv6_address: Some(ipv6.as_ref().map(|ip| ip.ip)),
+        ipv6_gateway: Some(ipv6.as_ref().and(host.ipv6_gateway)),
         note: None,
         tags: None,
         cost: None,
+        failover_enabled: None,
+        restart_policy: None,
+        restart_attempts: None,
     };
     let node = update
         .apply(node_id, authz, write)
@@ -133,6 +156,9 @@ async fn node_create_failed(
 
     Host::add_node(&node, write).await?;
     write.ctx.dns.create(&node.dns_name, ip.ip.ip()).await?;
+    if let Some(ipv6) = ipv6 {
+        write.ctx.dns.create(&node.dns_name, ipv6.ip.ip()).await?;
+    }
 
     // notify blockvisor to create the new node
     let mut commands = vec![];
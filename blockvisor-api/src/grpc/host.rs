@@ -1,27 +1,39 @@
 use std::cmp::max;
 use std::collections::{HashMap, HashSet};
 
+use chrono::{DateTime, Utc};
+use diesel::result::Error::NotFound;
 use diesel_async::scoped_futures::ScopedFutureExt;
 use displaydoc::Display;
+use prost_wkt_types::Timestamp;
 use thiserror::Error;
 use tonic::{Request, Response};
 use tracing::error;
 
 use crate::auth::claims::Claims;
 use crate::auth::rbac::{GrpcRole, HostAdminPerm, HostPerm};
-use crate::auth::resource::{HostId, OrgId, Resource};
+use crate::auth::resource::{HostId, NodeId, OrgId, Resource};
 use crate::auth::token::refresh::Refresh;
 use crate::auth::{AuthZ, Authorize};
 use crate::database::{Conn, ReadConn, Transaction, WriteConn};
+use crate::model::capacity_reservation::{
+    CapacityReservation, CapacityReservationFilter, NewCapacityReservation,
+    UpdateCapacityReservation,
+};
 use crate::model::command::NewCommand;
 use crate::model::host::{
     Host, HostFilter, HostRequirements, HostSearch, HostSort, NewHost, UpdateHost,
 };
-use crate::model::node::NodeScheduler;
+use crate::model::host_capacity::{CapacityReport, CapacitySummary, IpCapacity};
+use crate::model::host_upgrade::{HostUpgrade, HostUpgradeCampaign, NewHostUpgradeCampaign};
+use crate::model::ip_address::NewIpAddress;
+use crate::model::node::{NodeScheduler, Placement};
 use crate::model::region::{NewRegion, RegionKey, UpdateRegion};
-use crate::model::sql::{IpNetwork, Tag, Version};
+use crate::model::sql::{IpNetwork, Tag, Tags, Version};
+use crate::model::zone::{NewZone, ZoneKey};
 use crate::model::{
-    CommandType, Image, IpAddress, Node, Org, Protocol, ProtocolVersion, Region, RegionId, Token,
+    CommandType, HostCapacity, HostProvisionToken, Image, IpAddress, Node, Org, Protocol,
+    ProtocolVersion, Region, RegionId, Token, Zone, ZoneId,
 };
 use crate::util::{HashVec, NanosUtc};
 
@@ -34,6 +46,8 @@ pub enum Error {
     Amount(#[from] crate::model::sql::amount::Error),
     /// Auth check failed: {0}
     Auth(#[from] crate::auth::Error),
+    /// Host capacity reservation error: {0}
+    CapacityReservation(#[from] crate::model::capacity_reservation::Error),
     /// Claims check failed: {0}
     Claims(#[from] crate::auth::claims::Error),
     /// Host command error: {0}
@@ -56,30 +70,54 @@ pub enum Error {
     Host(#[from] crate::model::host::Error),
     /// Host token error: {0}
     HostProvisionByToken(crate::model::token::Error),
+    /// Host provision token error: {0}
+    HostProvisionToken(crate::model::host_provision_token::Error),
+    /// Host upgrade error: {0}
+    HostUpgrade(#[from] crate::model::host_upgrade::Error),
     /// Host image error: {0}
     Image(#[from] crate::model::image::Error),
     /// Host ip address error: {0}
     IpAddress(#[from] crate::model::ip_address::Error),
+    /// IPv6 gateway `{0}` is not an IPv6 address.
+    Ipv6GatewayFamily(IpNetwork),
     /// Host JWT failure: {0}
     Jwt(#[from] crate::auth::token::jwt::Error),
+    /// Legacy host provision tokens are disabled.
+    LegacyProvisionToken,
     /// Lookup missing Region. This should not happen.
     LookupMissingRegion,
     /// Failed to parse memory bytes: {0}
     MemoryBytes(std::num::TryFromIntError),
+    /// Missing the bundle to upgrade hosts to.
+    MissingBundleId,
     /// Missing the region to get info for.
     MissingRegion,
     /// Node model error: {0}
     Node(#[from] crate::model::node::Error),
+    /// No host upgrade campaign is active.
+    NoActiveUpgrade,
     /// No visibility of HostRestart command.
     NoHostRestart,
     /// No visibility of HostStart command.
     NoHostStart,
     /// No visibility of HostStop command.
     NoHostStop,
+    /// Failed to parse node slots: {0}
+    NodeSlots(std::num::TryFromIntError),
     /// Host org error: {0}
     Org(#[from] crate::model::org::Error),
+    /// Overcommit percent must be between 1 and i32::MAX: {0}
+    OvercommitPercent(u32),
+    /// Failed to parse bundle version: {0}
+    ParseBundleVersion(crate::model::sql::Error),
     /// Failed to parse bv_version: {0}
     ParseBvVersion(crate::model::sql::Error),
+    /// Failed to parse HostUpgradeCampaignId: {0}
+    ParseCampaignId(uuid::Error),
+    /// Failed to parse CIDR range `{0}`: {1}
+    ParseCidr(String, ipnetwork::IpNetworkError),
+    /// Failed to parse expires_at: {0}
+    ParseExpiresAt(crate::util::timestamp::Error),
     /// Failed to parse HostId: {0}
     ParseId(uuid::Error),
     /// Failed to parse ImageId: {0}
@@ -90,12 +128,20 @@ pub enum Error {
     ParseIpAddress(crate::model::sql::Error),
     /// Failed to parse IP gateway: {0}
     ParseIpGateway(crate::model::sql::Error),
+    /// Failed to parse IPv6 gateway: {0}
+    ParseIpv6Gateway(crate::model::sql::Error),
     /// Failed to parse non-zero host node_count as u64: {0}
     ParseNodeCount(std::num::TryFromIntError),
     /// Failed to parse OrgId: {0}
     ParseOrgId(uuid::Error),
+    /// Failed to parse ProtocolId: {0}
+    ParseProtocolId(uuid::Error),
     /// Failed to parse RegionId: {0}
     ParseRegionId(uuid::Error),
+    /// Failed to parse CapacityReservationId: {0}
+    ParseReservationId(uuid::Error),
+    /// Failed to parse ZoneId: {0}
+    ParseZoneId(uuid::Error),
     /// Host protocol error: {0}
     Protocol(#[from] crate::model::protocol::Error),
     /// Host protocol version error: {0}
@@ -112,8 +158,14 @@ pub enum Error {
     Sql(#[from] crate::model::sql::Error),
     /// Host store error: {0}
     Store(#[from] crate::store::Error),
+    /// Bundle version `{0}` is not in the store.
+    UnknownBundle(Version),
     /// The requested sort field is unknown.
     UnknownSortField,
+    /// Usage headroom percent must be at most 100: {0}
+    UsageHeadroom(u32),
+    /// Host zone error: {0}
+    Zone(#[from] crate::model::zone::Error),
 }
 
 impl From<Error> for Status {
@@ -129,27 +181,46 @@ impl From<Error> for Status {
             FilterLimit(_) => Status::invalid_argument("limit"),
             FilterOffset(_) => Status::invalid_argument("offset"),
             HasNodes => Status::failed_precondition("This host still has nodes."),
-            HostProvisionByToken(_) => Status::forbidden("Invalid token."),
+            Ipv6GatewayFamily(_) | ParseIpv6Gateway(_) => Status::invalid_argument("ipv6_gateway"),
+            HostProvisionByToken(_)
+            | HostProvisionToken(crate::model::host_provision_token::Error::ByToken(NotFound))
+            | LegacyProvisionToken => Status::forbidden("Invalid token."),
             MemoryBytes(_) => Status::out_of_range("memory_bytes"),
+            MissingBundleId => Status::invalid_argument("bundle_id"),
             MissingRegion => Status::out_of_range("region"),
+            NoActiveUpgrade => Status::not_found("No host upgrade campaign is active."),
             NoHostRestart | NoHostStart | NoHostStop => Status::forbidden("Access denied."),
+            NodeSlots(_) => Status::out_of_range("node_slots"),
+            OvercommitPercent(_) => Status::out_of_range("overcommit_percent"),
+            ParseBundleVersion(_) => Status::invalid_argument("bundle_id"),
             ParseBvVersion(_) => Status::invalid_argument("bv_version"),
+            ParseCampaignId(_) => Status::invalid_argument("campaign_id"),
+            ParseCidr(..) => Status::invalid_argument("cidrs"),
+            ParseExpiresAt(_) => Status::invalid_argument("expires_at"),
             ParseId(_) => Status::invalid_argument("host_id"),
             ParseImageId(_) => Status::invalid_argument("image_id"),
             ParseIps(_) => Status::invalid_argument("ips"),
             ParseIpAddress(_) => Status::invalid_argument("ip_address"),
             ParseIpGateway(_) => Status::invalid_argument("ip_gateway"),
             ParseOrgId(_) => Status::invalid_argument("org_id"),
+            ParseProtocolId(_) => Status::invalid_argument("protocol_id"),
             ParseRegionId(_) => Status::invalid_argument("region_id"),
+            ParseReservationId(_) => Status::invalid_argument("reservation_id"),
+            ParseZoneId(_) => Status::invalid_argument("zone_id"),
             SearchOperator(_) => Status::invalid_argument("search.operator"),
             SortOrder(_) => Status::invalid_argument("sort.order"),
+            UnknownBundle(_) => Status::not_found("Bundle not found."),
             UnknownSortField => Status::invalid_argument("sort.field"),
+            UsageHeadroom(_) => Status::out_of_range("usage_headroom_percent"),
             Amount(err) => err.into(),
             Auth(err) => err.into(),
+            CapacityReservation(err) => err.into(),
             Claims(err) => err.into(),
             Command(err) => err.into(),
             CommandApi(err) => err.into(),
             Host(err) => err.into(),
+            HostProvisionToken(err) => err.into(),
+            HostUpgrade(err) => err.into(),
             Image(err) => err.into(),
             IpAddress(err) => err.into(),
             Node(err) => err.into(),
@@ -159,6 +230,7 @@ impl From<Error> for Status {
             Region(err) => err.into(),
             Sql(err) => err.into(),
             Store(err) => err.into(),
+            Zone(err) => err.into(),
         }
     }
 }
@@ -272,6 +344,150 @@ impl HostService for Grpc {
         self.write(|write| restart(req, meta.into(), write).scope_boxed())
             .await
     }
+
+    async fn start_upgrade(
+        &self,
+        req: Request<api::HostServiceStartUpgradeRequest>,
+    ) -> Result<Response<api::HostServiceStartUpgradeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| start_upgrade(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn pause_upgrade(
+        &self,
+        req: Request<api::HostServicePauseUpgradeRequest>,
+    ) -> Result<Response<api::HostServicePauseUpgradeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| pause_upgrade(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn resume_upgrade(
+        &self,
+        req: Request<api::HostServiceResumeUpgradeRequest>,
+    ) -> Result<Response<api::HostServiceResumeUpgradeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| resume_upgrade(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn get_upgrade(
+        &self,
+        req: Request<api::HostServiceGetUpgradeRequest>,
+    ) -> Result<Response<api::HostServiceGetUpgradeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| get_upgrade(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn add_ip_addresses(
+        &self,
+        req: Request<api::HostServiceAddIpAddressesRequest>,
+    ) -> Result<Response<api::HostServiceAddIpAddressesResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| add_ip_addresses(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn list_ip_addresses(
+        &self,
+        req: Request<api::HostServiceListIpAddressesRequest>,
+    ) -> Result<Response<api::HostServiceListIpAddressesResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| list_ip_addresses(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn reserve_ip_address(
+        &self,
+        req: Request<api::HostServiceReserveIpAddressRequest>,
+    ) -> Result<Response<api::HostServiceReserveIpAddressResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| reserve_ip_address(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn release_ip_address(
+        &self,
+        req: Request<api::HostServiceReleaseIpAddressRequest>,
+    ) -> Result<Response<api::HostServiceReleaseIpAddressResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| release_ip_address(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn remove_ip_address(
+        &self,
+        req: Request<api::HostServiceRemoveIpAddressRequest>,
+    ) -> Result<Response<api::HostServiceRemoveIpAddressResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| remove_ip_address(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn create_zone(
+        &self,
+        req: Request<api::HostServiceCreateZoneRequest>,
+    ) -> Result<Response<api::HostServiceCreateZoneResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| create_zone(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn list_zones(
+        &self,
+        req: Request<api::HostServiceListZonesRequest>,
+    ) -> Result<Response<api::HostServiceListZonesResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| list_zones(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn get_region_capacity(
+        &self,
+        req: Request<api::HostServiceGetRegionCapacityRequest>,
+    ) -> Result<Response<api::HostServiceGetRegionCapacityResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| get_region_capacity(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn create_capacity_reservation(
+        &self,
+        req: Request<api::HostServiceCreateCapacityReservationRequest>,
+    ) -> Result<Response<api::HostServiceCreateCapacityReservationResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| create_capacity_reservation(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn list_capacity_reservations(
+        &self,
+        req: Request<api::HostServiceListCapacityReservationsRequest>,
+    ) -> Result<Response<api::HostServiceListCapacityReservationsResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| list_capacity_reservations(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn update_capacity_reservation(
+        &self,
+        req: Request<api::HostServiceUpdateCapacityReservationRequest>,
+    ) -> Result<Response<api::HostServiceUpdateCapacityReservationResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| update_capacity_reservation(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn expire_capacity_reservation(
+        &self,
+        req: Request<api::HostServiceExpireCapacityReservationRequest>,
+    ) -> Result<Response<api::HostServiceExpireCapacityReservationResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| expire_capacity_reservation(req, meta.into(), write).scope_boxed())
+            .await
+    }
 }
 
 pub async fn create_host(
@@ -279,11 +495,42 @@ pub async fn create_host(
     _meta: Metadata,
     mut write: WriteConn<'_, '_>,
 ) -> Result<api::HostServiceCreateHostResponse, Error> {
-    let token = Token::host_provision_by_token(&req.provision_token, &mut write)
-        .await
-        .map_err(Error::HostProvisionByToken)?;
-    let org_id = req.is_private.then_some(token.org_id);
     let region_id = req.region_id.parse().map_err(Error::ParseRegionId)?;
+    let schedule_type = req.schedule_type().try_into()?;
+
+    let (provision_org_id, created_by, default_tags) =
+        match HostProvisionToken::by_token(&req.provision_token, &mut write)
+            .await
+            .map_err(Error::HostProvisionToken)?
+        {
+            Some(token) => {
+                token
+                    .check(region_id, schedule_type, Utc::now())
+                    .map_err(Error::HostProvisionToken)?;
+                let token = token
+                    .claim(&mut write)
+                    .await
+                    .map_err(Error::HostProvisionToken)?;
+                (token.org_id, token.resource(), token.tags)
+            }
+            None if !write.ctx.config.host.legacy_provision_tokens => {
+                return Err(Error::LegacyProvisionToken);
+            }
+            None => {
+                let token = Token::host_provision_by_token(&req.provision_token, &mut write)
+                    .await
+                    .map_err(Error::HostProvisionByToken)?;
+                (token.org_id, token.resource(), Tags::default())
+            }
+        };
+    let org_id = req.is_private.then_some(provision_org_id);
+
+    let zone_id = if let Some(id) = &req.zone_id {
+        let id = id.parse().map_err(Error::ParseZoneId)?;
+        Some(Zone::in_region(id, region_id, &mut write).await?.id)
+    } else {
+        None
+    };
 
     let host_ips: Vec<_> = req
         .ips
@@ -291,33 +538,35 @@ pub async fn create_host(
         .map(|ip| ip.parse().map_err(Error::ParseIps))
         .collect::<Result<_, _>>()?;
 
-    let tags = if let Some(ref tags) = req.tags {
-        tags.tags
-            .iter()
-            .map(|tag| Tag::new(tag.name.clone()).map_err(Into::into))
-            .collect::<Result<Vec<_>, Error>>()
-            .map(Into::into)?
-    } else {
-        Default::default()
-    };
+    let mut tags: Vec<Tag> = default_tags.into_iter().collect();
+    if let Some(ref req_tags) = req.tags {
+        for tag in &req_tags.tags {
+            let tag = Tag::new(tag.name.clone())?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
 
     let new_host = NewHost {
         org_id,
         region_id,
         network_name: &req.network_name,
         display_name: req.display_name.as_deref(),
-        schedule_type: req.schedule_type().try_into()?,
+        schedule_type,
         os: &req.os,
         os_version: &req.os_version,
         bv_version: &req.bv_version.parse().map_err(Error::ParseBvVersion)?,
         ip_address: req.ip_address.parse().map_err(Error::ParseIpAddress)?,
         ip_gateway: req.ip_gateway.parse().map_err(Error::ParseIpGateway)?,
+        ipv6_gateway: req.ipv6_gateway.as_deref().map(ipv6_gateway).transpose()?,
+        zone_id,
         cpu_cores: req.cpu_cores.try_into().map_err(Error::CpuCores)?,
         memory_bytes: req.memory_bytes.try_into().map_err(Error::MemoryBytes)?,
         disk_bytes: req.disk_bytes.try_into().map_err(Error::DiskBytes)?,
-        tags,
-        created_by_type: token.created_by_type,
-        created_by_id: token.created_by_id,
+        tags: tags.into(),
+        created_by_type: created_by.typ(),
+        created_by_id: created_by.id(),
     };
     let host = new_host.create(&host_ips, &mut write).await?;
 
@@ -336,7 +585,7 @@ pub async fn create_host(
         host: Some(host),
         token: jwt.into(),
         refresh: encoded.into(),
-        provision_org_id: token.org_id.to_string(),
+        provision_org_id: provision_org_id.to_string(),
     })
 }
 
@@ -461,10 +710,12 @@ pub async fn list_regions(
     let requirements = HostRequirements {
         scheduler: &NodeScheduler::least_resources(),
         protocol: &protocol,
+        placement: &Placement::default(),
         org_id,
         cpu_cores: image.min_cpu_cores,
         memory_bytes: image.min_memory_bytes,
         disk_bytes: image.min_disk_bytes,
+        exclude_host_id: None,
     };
 
     let mut region_ids = HashSet::new();
@@ -546,6 +797,17 @@ pub async fn update_host(
         .map(|space| space.try_into().map_err(Error::DiskBytes))
         .transpose()?;
 
+    // moving a host to another region takes it out of its zone
+    let zone_id = if let Some(id) = &req.zone_id {
+        let id = id.parse().map_err(Error::ParseZoneId)?;
+        let region_id = region_id.unwrap_or(host.region_id);
+        Some(Some(Zone::in_region(id, region_id, &mut write).await?.id))
+    } else if region_id.is_some_and(|id| id != host.region_id) {
+        Some(None)
+    } else {
+        None
+    };
+
     let update = UpdateHost {
         network_name: req.network_name.as_deref(),
         display_name: req.display_name.as_deref(),
@@ -569,6 +831,22 @@ pub async fn update_host(
             .transpose()?
             .flatten(),
         cost: req.cost.map(TryInto::try_into).transpose()?,
+        labels: req.labels.map(TryInto::try_into).transpose()?,
+        taints: req.taints.map(TryInto::try_into).transpose()?,
+        cpu_overcommit_percent: req
+            .cpu_overcommit_percent
+            .map(overcommit_override)
+            .transpose()?,
+        memory_overcommit_percent: req
+            .memory_overcommit_percent
+            .map(overcommit_override)
+            .transpose()?,
+        disk_overcommit_percent: req
+            .disk_overcommit_percent
+            .map(overcommit_override)
+            .transpose()?,
+        ipv6_gateway: req.ipv6_gateway.as_deref().map(ipv6_gateway).transpose()?,
+        zone_id,
     };
     let host = update.apply(id, &mut write).await?;
     let host = api::Host::from_host(host, Some(&authz), &mut write).await?;
@@ -587,6 +865,19 @@ pub async fn update_region(
         id: req.region_id.parse().map_err(Error::ParseRegionId)?,
         display_name: req.display_name.as_deref(),
         sku_code: req.sku_code.as_deref(),
+        cpu_overcommit_percent: req.cpu_overcommit_percent.map(overcommit).transpose()?,
+        memory_overcommit_percent: req.memory_overcommit_percent.map(overcommit).transpose()?,
+        disk_overcommit_percent: req.disk_overcommit_percent.map(overcommit).transpose()?,
+        usage_scheduling: req.usage_scheduling,
+        usage_headroom_percent: req
+            .usage_headroom_percent
+            .map(|percent| {
+                i32::try_from(percent)
+                    .ok()
+                    .filter(|percent| *percent <= 100)
+                    .ok_or(Error::UsageHeadroom(percent))
+            })
+            .transpose()?,
     };
     let region = update.apply(&mut write).await?;
 
@@ -595,6 +886,277 @@ pub async fn update_region(
     })
 }
 
+pub async fn create_zone(
+    req: api::HostServiceCreateZoneRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceCreateZoneResponse, Error> {
+    write.auth(&meta, HostAdminPerm::CreateZone).await?;
+
+    let new_zone = NewZone {
+        region_id: req.region_id.parse().map_err(Error::ParseRegionId)?,
+        key: ZoneKey::new(req.zone_key.clone())?,
+        display_name: &req.display_name,
+    };
+    let zone = new_zone.create(&mut write).await?;
+
+    Ok(api::HostServiceCreateZoneResponse {
+        zone: Some(zone.into()),
+    })
+}
+
+pub async fn list_zones(
+    req: api::HostServiceListZonesRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::HostServiceListZonesResponse, Error> {
+    read.auth(&meta, HostAdminPerm::ListZones).await?;
+
+    let region_id = req.region_id.parse().map_err(Error::ParseRegionId)?;
+    let zones = Zone::by_regions(&HashSet::from([region_id]), &mut read)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(api::HostServiceListZonesResponse { zones })
+}
+
+/// Report the capacity of regions and their zones, split by public and
+/// org-private hosts.
+pub async fn get_region_capacity(
+    req: api::HostServiceGetRegionCapacityRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::HostServiceGetRegionCapacityResponse, Error> {
+    read.auth(&meta, HostAdminPerm::GetCapacity).await?;
+
+    let regions = if let Some(id) = &req.region_id {
+        let id = id.parse().map_err(Error::ParseRegionId)?;
+        vec![Region::by_id(id, &mut read).await?]
+    } else {
+        Region::all(&mut read).await?
+    };
+    let region_ids = regions.iter().map(|region| region.id).collect();
+    let hosts = Host::by_regions(&region_ids, &mut read).await?;
+    let mut region_zones = Zone::by_regions(&region_ids, &mut read)
+        .await?
+        .to_map_keep_all(|zone| (zone.region_id, zone));
+
+    let host_ids = hosts.iter().map(|host| host.id).collect();
+    let host_ips = IpAddress::for_hosts(&host_ids, &mut read)
+        .await?
+        .to_map_keep_all(|ip| (ip.host_id, ip));
+    let assigned_ips = IpAddress::assigned_for_hosts(&host_ids, &mut read)
+        .await?
+        .to_map_keep_all(|ip| (ip.host_id, ip.ip));
+
+    let now = Utc::now();
+    let no_ips = vec![];
+    let no_assigned = vec![];
+    let mut region_capacity: HashMap<RegionId, CapacityReport> = HashMap::new();
+    let mut zone_capacity: HashMap<ZoneId, CapacityReport> = HashMap::new();
+    for host in &hosts {
+        let region = regions
+            .iter()
+            .find(|region| region.id == host.region_id)
+            .ok_or(Error::LookupMissingRegion)?;
+        let capacity = HostCapacity::new(host, region);
+        let ips = IpCapacity::new(
+            host_ips.get(&host.id).unwrap_or(&no_ips),
+            assigned_ips.get(&host.id).unwrap_or(&no_assigned),
+            now,
+        );
+
+        region_capacity
+            .entry(host.region_id)
+            .or_default()
+            .add(host, &capacity, ips);
+        if let Some(zone_id) = host.zone_id {
+            zone_capacity
+                .entry(zone_id)
+                .or_default()
+                .add(host, &capacity, ips);
+        }
+    }
+
+    let regions = regions
+        .into_iter()
+        .map(|region| {
+            let zones = region_zones
+                .remove(&region.id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|zone| {
+                    let report = zone_capacity.get(&zone.id).copied().unwrap_or_default();
+                    api::ZoneCapacity {
+                        zone: Some(zone.into()),
+                        public: Some(report.public.into()),
+                        private: Some(report.private.into()),
+                    }
+                })
+                .collect();
+            let report = region_capacity.get(&region.id).copied().unwrap_or_default();
+            api::RegionCapacity {
+                region: Some(region.into()),
+                public: Some(report.public.into()),
+                private: Some(report.private.into()),
+                zones,
+            }
+        })
+        .collect();
+
+    Ok(api::HostServiceGetRegionCapacityResponse { regions })
+}
+
+/// Reserve capacity on the public hosts of a region for an org.
+///
+/// With `slots`, the resources are the size of each node slot.
+pub async fn create_capacity_reservation(
+    req: api::HostServiceCreateCapacityReservationRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceCreateCapacityReservationResponse, Error> {
+    write.auth(&meta, HostAdminPerm::CreateReservation).await?;
+
+    let (protocol_id, node_slots) = if let Some(slots) = req.slots {
+        let protocol_id = slots.protocol_id.parse().map_err(Error::ParseProtocolId)?;
+        let node_slots = slots.node_slots.try_into().map_err(Error::NodeSlots)?;
+        (Some(protocol_id), Some(node_slots))
+    } else {
+        (None, None)
+    };
+
+    let new_reservation = NewCapacityReservation {
+        org_id: req.org_id.parse().map_err(Error::ParseOrgId)?,
+        region_id: req.region_id.parse().map_err(Error::ParseRegionId)?,
+        protocol_id,
+        node_slots,
+        cpu_cores: req.cpu_cores.try_into().map_err(Error::CpuCores)?,
+        memory_bytes: req.memory_bytes.try_into().map_err(Error::MemoryBytes)?,
+        disk_bytes: req.disk_bytes.try_into().map_err(Error::DiskBytes)?,
+        expires_at: expires_at(req.expires_at)?,
+    };
+    let reservation = new_reservation.create(&mut write).await?;
+
+    Ok(api::HostServiceCreateCapacityReservationResponse {
+        reservation: Some(reservation.into()),
+    })
+}
+
+pub async fn list_capacity_reservations(
+    req: api::HostServiceListCapacityReservationsRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::HostServiceListCapacityReservationsResponse, Error> {
+    read.auth(&meta, HostAdminPerm::ListReservations).await?;
+
+    let filter = CapacityReservationFilter {
+        org_ids: req
+            .org_ids
+            .iter()
+            .map(|id| id.parse().map_err(Error::ParseOrgId))
+            .collect::<Result<_, _>>()?,
+        region_ids: req
+            .region_ids
+            .iter()
+            .map(|id| id.parse().map_err(Error::ParseRegionId))
+            .collect::<Result<_, _>>()?,
+        include_expired: req.include_expired,
+    };
+    let reservations = filter
+        .query(&mut read)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(api::HostServiceListCapacityReservationsResponse { reservations })
+}
+
+/// Resize a reservation or change when it expires.
+pub async fn update_capacity_reservation(
+    req: api::HostServiceUpdateCapacityReservationRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceUpdateCapacityReservationResponse, Error> {
+    write.auth(&meta, HostAdminPerm::UpdateReservation).await?;
+
+    let update = UpdateCapacityReservation {
+        id: req
+            .reservation_id
+            .parse()
+            .map_err(Error::ParseReservationId)?,
+        node_slots: req
+            .node_slots
+            .map(|slots| slots.try_into().map_err(Error::NodeSlots))
+            .transpose()?,
+        cpu_cores: req
+            .cpu_cores
+            .map(|cores| cores.try_into().map_err(Error::CpuCores))
+            .transpose()?,
+        memory_bytes: req
+            .memory_bytes
+            .map(|bytes| bytes.try_into().map_err(Error::MemoryBytes))
+            .transpose()?,
+        disk_bytes: req
+            .disk_bytes
+            .map(|bytes| bytes.try_into().map_err(Error::DiskBytes))
+            .transpose()?,
+        expires_at: expires_at(req.expires_at)?,
+    };
+    let reservation = update.apply(&mut write).await?;
+
+    Ok(api::HostServiceUpdateCapacityReservationResponse {
+        reservation: Some(reservation.into()),
+    })
+}
+
+/// Release the remaining capacity of a reservation to other orgs.
+pub async fn expire_capacity_reservation(
+    req: api::HostServiceExpireCapacityReservationRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceExpireCapacityReservationResponse, Error> {
+    write.auth(&meta, HostAdminPerm::ExpireReservation).await?;
+
+    let id = req
+        .reservation_id
+        .parse()
+        .map_err(Error::ParseReservationId)?;
+    let reservation = CapacityReservation::expire(id, &mut write).await?;
+
+    Ok(api::HostServiceExpireCapacityReservationResponse {
+        reservation: Some(reservation.into()),
+    })
+}
+
+/// Parse an optional reservation expiry time.
+fn expires_at(timestamp: Option<Timestamp>) -> Result<Option<DateTime<Utc>>, Error> {
+    timestamp
+        .map(NanosUtc::try_from)
+        .transpose()
+        .map_err(Error::ParseExpiresAt)
+        .map(|expires_at| expires_at.map(Into::into))
+}
+
+/// Parse an overcommit ratio as a percentage of raw capacity.
+fn overcommit(percent: u32) -> Result<i32, Error> {
+    i32::try_from(percent)
+        .ok()
+        .filter(|percent| *percent > 0)
+        .ok_or(Error::OvercommitPercent(percent))
+}
+
+/// Parse a host overcommit override, where 0 clears it to the region default.
+fn overcommit_override(percent: u32) -> Result<Option<i32>, Error> {
+    if percent == 0 {
+        Ok(None)
+    } else {
+        overcommit(percent).map(Some)
+    }
+}
+
 pub async fn delete_host(
     req: api::HostServiceDeleteHostRequest,
     meta: Metadata,
@@ -709,6 +1271,223 @@ pub async fn restart(
     Ok(api::HostServiceRestartResponse {})
 }
 
+/// Start a campaign to upgrade blockvisord on every host with an older version.
+pub async fn start_upgrade(
+    req: api::HostServiceStartUpgradeRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceStartUpgradeResponse, Error> {
+    write.auth(&meta, HostAdminPerm::StartUpgrade).await?;
+
+    let bundle_id = req.bundle_id.ok_or(Error::MissingBundleId)?;
+    let version: Version = bundle_id
+        .version
+        .parse()
+        .map_err(Error::ParseBundleVersion)?;
+    let bundles = write.ctx.store.list_bundles().await?;
+    if !bundles.iter().any(|id| id.version == version.to_string()) {
+        return Err(Error::UnknownBundle(version));
+    }
+
+    let region_ids = req
+        .region_ids
+        .iter()
+        .map(|id| id.parse().map_err(Error::ParseRegionId))
+        .collect::<Result<HashSet<RegionId>, _>>()?;
+
+    let campaign = NewHostUpgradeCampaign::new(version, req.batch_size, req.max_failures)?
+        .create(&region_ids, &mut write)
+        .await?;
+    let campaign = api::HostUpgradeCampaign::from_model(campaign, &mut write).await?;
+
+    Ok(api::HostServiceStartUpgradeResponse {
+        campaign: Some(campaign),
+    })
+}
+
+pub async fn pause_upgrade(
+    req: api::HostServicePauseUpgradeRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServicePauseUpgradeResponse, Error> {
+    write.auth(&meta, HostAdminPerm::PauseUpgrade).await?;
+
+    let id = req.campaign_id.parse().map_err(Error::ParseCampaignId)?;
+    let campaign = HostUpgradeCampaign::by_id(id, &mut write)
+        .await?
+        .pause(req.reason.as_deref(), &mut write)
+        .await?;
+    let campaign = api::HostUpgradeCampaign::from_model(campaign, &mut write).await?;
+
+    Ok(api::HostServicePauseUpgradeResponse {
+        campaign: Some(campaign),
+    })
+}
+
+pub async fn resume_upgrade(
+    req: api::HostServiceResumeUpgradeRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceResumeUpgradeResponse, Error> {
+    write.auth(&meta, HostAdminPerm::ResumeUpgrade).await?;
+
+    let id = req.campaign_id.parse().map_err(Error::ParseCampaignId)?;
+    let campaign = HostUpgradeCampaign::by_id(id, &mut write)
+        .await?
+        .resume(req.max_failures, &mut write)
+        .await?;
+    let campaign = api::HostUpgradeCampaign::from_model(campaign, &mut write).await?;
+
+    Ok(api::HostServiceResumeUpgradeResponse {
+        campaign: Some(campaign),
+    })
+}
+
+/// Inspect a host upgrade campaign, or the active one if no id is given.
+pub async fn get_upgrade(
+    req: api::HostServiceGetUpgradeRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::HostServiceGetUpgradeResponse, Error> {
+    read.auth(&meta, HostAdminPerm::GetUpgrade).await?;
+
+    let campaign = if let Some(id) = req.campaign_id {
+        let id = id.parse().map_err(Error::ParseCampaignId)?;
+        HostUpgradeCampaign::by_id(id, &mut read).await?
+    } else {
+        HostUpgradeCampaign::active(&mut read)
+            .await?
+            .ok_or(Error::NoActiveUpgrade)?
+    };
+
+    let hosts = campaign
+        .hosts(&mut read)
+        .await?
+        .into_iter()
+        .map(api::HostUpgradeState::from)
+        .collect();
+    let campaign = api::HostUpgradeCampaign::from_model(campaign, &mut read).await?;
+
+    Ok(api::HostServiceGetUpgradeResponse {
+        campaign: Some(campaign),
+        hosts,
+    })
+}
+
+/// Add every address in some CIDR ranges to the IP pool of a host.
+pub async fn add_ip_addresses(
+    req: api::HostServiceAddIpAddressesRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceAddIpAddressesResponse, Error> {
+    write.auth(&meta, HostAdminPerm::AddIps).await?;
+
+    let id: HostId = req.host_id.parse().map_err(Error::ParseId)?;
+    let org_id = Host::org_id(id, &mut write).await?;
+    let host = Host::by_id(id, org_id, &mut write).await?;
+
+    // never hand out the host's own address or its gateways
+    let excluded: Vec<_> = [
+        Some(host.ip_address),
+        Some(host.ip_gateway),
+        host.ipv6_gateway,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|ip| ip.ip())
+    .collect();
+
+    let mut new_ips = vec![];
+    for cidr in &req.cidrs {
+        let range = cidr
+            .parse::<ipnetwork::IpNetwork>()
+            .map_err(|err| Error::ParseCidr(cidr.clone(), err))?;
+        new_ips.extend(NewIpAddress::from_range(range, &excluded, host.id)?);
+    }
+    let ip_addresses = NewIpAddress::bulk_create(new_ips, &mut write)
+        .await?
+        .into_iter()
+        .map(|ip| api::IpAddress::from((ip, None)))
+        .collect();
+
+    Ok(api::HostServiceAddIpAddressesResponse { ip_addresses })
+}
+
+/// List the IP pool of a host, with the node holding each address.
+pub async fn list_ip_addresses(
+    req: api::HostServiceListIpAddressesRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::HostServiceListIpAddressesResponse, Error> {
+    read.auth(&meta, HostAdminPerm::ListIps).await?;
+
+    let id: HostId = req.host_id.parse().map_err(Error::ParseId)?;
+    let ip_addresses = IpAddress::by_host(id, &mut read)
+        .await?
+        .into_iter()
+        .map(api::IpAddress::from)
+        .collect();
+
+    Ok(api::HostServiceListIpAddressesResponse { ip_addresses })
+}
+
+/// Hold back an address from new nodes until it is released.
+pub async fn reserve_ip_address(
+    req: api::HostServiceReserveIpAddressRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceReserveIpAddressResponse, Error> {
+    write.auth(&meta, HostAdminPerm::ReserveIp).await?;
+
+    let ip = req.ip.parse().map_err(Error::ParseIpAddress)?;
+    let ip_address = IpAddress::reserve(ip, req.reason.as_deref(), &mut write).await?;
+
+    Ok(api::HostServiceReserveIpAddressResponse {
+        ip_address: Some(api::IpAddress::from((ip_address, None))),
+    })
+}
+
+/// Make a reserved or quarantined address available to new nodes.
+pub async fn release_ip_address(
+    req: api::HostServiceReleaseIpAddressRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceReleaseIpAddressResponse, Error> {
+    write.auth(&meta, HostAdminPerm::ReleaseIp).await?;
+
+    let ip = req.ip.parse().map_err(Error::ParseIpAddress)?;
+    IpAddress::release(ip, &mut write).await?;
+    let ip_address = IpAddress::by_ip(ip, &mut write).await?;
+
+    Ok(api::HostServiceReleaseIpAddressResponse {
+        ip_address: Some(ip_address.into()),
+    })
+}
+
+/// Remove an address that is not held by a node from the IP pool of its host.
+pub async fn remove_ip_address(
+    req: api::HostServiceRemoveIpAddressRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::HostServiceRemoveIpAddressResponse, Error> {
+    write.auth(&meta, HostAdminPerm::RemoveIp).await?;
+
+    let ip = req.ip.parse().map_err(Error::ParseIpAddress)?;
+    IpAddress::delete(ip, &mut write).await?;
+
+    Ok(api::HostServiceRemoveIpAddressResponse {})
+}
+
+/// Parse the IPv6 gateway of a dual-stack host.
+fn ipv6_gateway(ip: &str) -> Result<IpNetwork, Error> {
+    let gateway: IpNetwork = ip.parse().map_err(Error::ParseIpv6Gateway)?;
+    if gateway.is_ipv6() {
+        Ok(gateway)
+    } else {
+        Err(Error::Ipv6GatewayFamily(gateway))
+    }
+}
+
 impl api::Host {
     pub async fn from_host(
         host: Host,
@@ -741,6 +1520,7 @@ impl api::Host {
             .get(&host.region_id)
             .ok_or(Error::LookupMissingRegion)?;
         let cost = authz.and_then(|authz| common::BillingAmount::from_host(&host, authz));
+        let capacity = HostCapacity::new(&host, region);
 
         let no_ips = vec![];
         let ips = lookup.host_ips.get(&host.id).unwrap_or(&no_ips);
@@ -766,20 +1546,78 @@ impl api::Host {
             bv_version: host.bv_version.to_string(),
             ip_address: host.ip_address.to_string(),
             ip_gateway: host.ip_gateway.to_string(),
+            ipv6_gateway: host.ipv6_gateway.map(|ip| ip.to_string()),
+            zone_id: host.zone_id.map(|id| id.to_string()),
             ip_addresses,
             cpu_cores: host.cpu_cores.try_into().map_err(Error::CpuCores)?,
             memory_bytes: host.memory_bytes.try_into().map_err(Error::MemoryBytes)?,
             disk_bytes: host.disk_bytes.try_into().map_err(Error::DiskBytes)?,
+            allocated_cpu_cores: capacity
+                .allocated
+                .cpu_cores
+                .try_into()
+                .map_err(Error::CpuCores)?,
+            allocated_memory_bytes: capacity
+                .allocated
+                .memory_bytes
+                .try_into()
+                .map_err(Error::MemoryBytes)?,
+            allocated_disk_bytes: capacity
+                .allocated
+                .disk_bytes
+                .try_into()
+                .map_err(Error::DiskBytes)?,
+            effective_cpu_cores: capacity
+                .effective
+                .cpu_cores
+                .try_into()
+                .map_err(Error::CpuCores)?,
+            effective_memory_bytes: capacity
+                .effective
+                .memory_bytes
+                .try_into()
+                .map_err(Error::MemoryBytes)?,
+            effective_disk_bytes: capacity
+                .effective
+                .disk_bytes
+                .try_into()
+                .map_err(Error::DiskBytes)?,
+            cpu_overcommit_percent: host.cpu_overcommit_percent.map(i32::unsigned_abs),
+            memory_overcommit_percent: host.memory_overcommit_percent.map(i32::unsigned_abs),
+            disk_overcommit_percent: host.disk_overcommit_percent.map(i32::unsigned_abs),
             node_count: u64::try_from(max(0, host.node_count)).map_err(Error::ParseNodeCount)?,
             tags: Some(host.tags.into()),
             created_by: Some(common::Resource::from(created_by)),
             created_at: Some(NanosUtc::from(host.created_at).into()),
             updated_at: host.updated_at.map(|at| NanosUtc::from(at).into()),
+            last_seen_at: host.last_seen_at.map(|at| NanosUtc::from(at).into()),
+            labels: host.labels.into(),
+            taints: host.taints.into(),
             cost,
         })
     }
 }
 
+impl From<CapacitySummary> for api::Capacity {
+    fn from(summary: CapacitySummary) -> Self {
+        api::Capacity {
+            hosts: summary.hosts,
+            total_cpu_cores: summary.total.cpu_cores.unsigned_abs(),
+            total_memory_bytes: summary.total.memory_bytes.unsigned_abs(),
+            total_disk_bytes: summary.total.disk_bytes.unsigned_abs(),
+            allocated_cpu_cores: summary.allocated.cpu_cores.unsigned_abs(),
+            allocated_memory_bytes: summary.allocated.memory_bytes.unsigned_abs(),
+            allocated_disk_bytes: summary.allocated.disk_bytes.unsigned_abs(),
+            free_cpu_cores: summary.free.cpu_cores.unsigned_abs(),
+            free_memory_bytes: summary.free.memory_bytes.unsigned_abs(),
+            free_disk_bytes: summary.free.disk_bytes.unsigned_abs(),
+            total_ips: summary.ips.total,
+            allocated_ips: summary.ips.allocated,
+            free_ips: summary.ips.free,
+        }
+    }
+}
+
 struct Lookup {
     orgs: HashMap<OrgId, Org>,
     regions: HashMap<RegionId, Region>,
@@ -885,3 +1723,56 @@ impl api::HostServiceListHostsRequest {
         })
     }
 }
+
+impl api::HostUpgradeCampaign {
+    async fn from_model(campaign: HostUpgradeCampaign, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let counts = campaign.counts(conn).await?;
+        let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+
+        Ok(api::HostUpgradeCampaign {
+            campaign_id: campaign.id.to_string(),
+            bundle_id: Some(api::BundleIdentifier {
+                version: campaign.bundle_version.to_string(),
+            }),
+            status: api::HostUpgradeCampaignStatus::from(campaign.status).into(),
+            batch_size: u32::try_from(campaign.batch_size).unwrap_or(u32::MAX),
+            max_failures: u32::try_from(campaign.max_failures).unwrap_or(u32::MAX),
+            paused_reason: campaign.paused_reason,
+            pending: count(counts.pending),
+            upgrading: count(counts.upgrading),
+            succeeded: count(counts.succeeded),
+            failed: count(counts.failed),
+            created_at: Some(NanosUtc::from(campaign.created_at).into()),
+            updated_at: campaign.updated_at.map(NanosUtc::from).map(Into::into),
+            completed_at: campaign.completed_at.map(NanosUtc::from).map(Into::into),
+        })
+    }
+}
+
+impl From<(IpAddress, Option<NodeId>)> for api::IpAddress {
+    fn from((ip, node_id): (IpAddress, Option<NodeId>)) -> Self {
+        api::IpAddress {
+            ip: ip.ip.to_string(),
+            host_id: ip.host_id.to_string(),
+            node_id: node_id.map(|id| id.to_string()),
+            reserved_at: ip.reserved_at.map(NanosUtc::from).map(Into::into),
+            reserved_reason: ip.reserved_reason,
+            quarantined_until: ip.quarantined_until.map(NanosUtc::from).map(Into::into),
+        }
+    }
+}
+
+impl From<(HostUpgrade, Host)> for api::HostUpgradeState {
+    fn from((upgrade, host): (HostUpgrade, Host)) -> Self {
+        api::HostUpgradeState {
+            host_id: upgrade.host_id.to_string(),
+            region_id: upgrade.region_id.to_string(),
+            bv_version: host.bv_version.to_string(),
+            status: api::HostUpgradeStatus::from(upgrade.status).into(),
+            command_id: upgrade.command_id.map(|id| id.to_string()),
+            message: upgrade.message,
+            started_at: upgrade.started_at.map(NanosUtc::from).map(Into::into),
+            completed_at: upgrade.completed_at.map(NanosUtc::from).map(Into::into),
+        }
+    }
+}
@@ -1,23 +1,28 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use diesel_async::scoped_futures::ScopedFutureExt;
 use displaydoc::Display;
 use prost_wkt_types::Empty;
 use thiserror::Error;
 use tonic::{Request, Response};
-use tracing::error;
+use tracing::{error, warn};
 
-use crate::auth::Authorize;
 use crate::auth::rbac::{ImageAdminPerm, ImagePerm, Perm};
+use crate::auth::resource::OrgId;
+use crate::auth::{AuthZ, Authorize};
 use crate::database::{ReadConn, Transaction, WriteConn};
 use crate::model::image::archive::{NewArchive, UpdateArchive};
 use crate::model::image::config::Ramdisks;
 use crate::model::image::property::ImagePropertyKey;
 use crate::model::image::rule::{ImageRule, NewImageRule};
-use crate::model::image::{Archive, Image, ImageProperty, NewImage, NewProperty, UpdateImage};
+use crate::model::image::{
+    Archive, Image, ImageLifecycle, ImageProperty, NewImage, NewProperty, UpdateImage,
+    UpdateLifecycle,
+};
 use crate::model::protocol::VersionKey;
+use crate::model::rbac::RbacUser;
 use crate::model::sql::Version;
-use crate::model::{Node, ProtocolVersion};
+use crate::model::{Node, ProtocolVersion, User};
 use crate::store::StoreKey;
 use crate::util::{HashVec, NanosUtc};
 
@@ -76,16 +81,22 @@ pub enum Error {
     ParseProtocolId(uuid::Error),
     /// Failed to parse protocol version: {0}
     ParseVersion(crate::model::sql::Error),
+    /// Failed to parse sunset_at: {0}
+    ParseSunsetAt(crate::util::timestamp::Error),
     /// Failed to parse VersionId: {0}
     ParseVersionId(uuid::Error),
     /// Image property error: {0}
     Property(#[from] crate::model::image::property::Error),
     /// Image protocol error: {0}
     Protocol(#[from] crate::model::protocol::Error),
+    /// Image rbac error: {0}
+    Rbac(#[from] crate::model::rbac::Error),
     /// Image firewall rule error: {0}
     Rule(#[from] crate::model::image::rule::Error),
     /// Image store error: {0}
     Store(#[from] crate::store::Error),
+    /// Image user error: {0}
+    User(#[from] crate::model::user::Error),
     /// Image protocol version error: {0}
     Version(#[from] crate::model::protocol::version::Error),
 }
@@ -121,6 +132,7 @@ impl From<Error> for Status {
             ParseImageId(_) => Status::invalid_argument("image_id"),
             ParseOrgId(_) => Status::invalid_argument("org_id"),
             ParseProtocolId(_) => Status::invalid_argument("protocol_id"),
+            ParseSunsetAt(_) => Status::invalid_argument("sunset_at"),
             ParseVersion(_) => Status::invalid_argument("protocol_version"),
             ParseVersionId(_) => Status::invalid_argument("protocol_version_id"),
             Archive(err) => err.into(),
@@ -130,8 +142,10 @@ impl From<Error> for Status {
             Node(err) => err.into(),
             Property(err) => err.into(),
             Protocol(err) => err.into(),
+            Rbac(err) => err.into(),
             Rule(err) => err.into(),
             Store(err) => err.into(),
+            User(err) => err.into(),
             Version(err) => err.into(),
         }
     }
@@ -183,6 +197,15 @@ impl ImageService for Grpc {
         self.write(|write| update_image(req, meta.into(), write).scope_boxed())
             .await
     }
+
+    async fn set_lifecycle(
+        &self,
+        req: Request<api::ImageServiceSetLifecycleRequest>,
+    ) -> Result<Response<api::ImageServiceSetLifecycleResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| set_lifecycle(req, meta.into(), write).scope_boxed())
+            .await
+    }
 }
 
 async fn add_image(
@@ -203,7 +226,25 @@ async fn add_image(
         .transpose()?;
 
     let version = ProtocolVersion::by_id(version_id, org_id, &authz, &mut write).await?;
-    let latest = Image::latest_build(version_id, org_id, &authz, &mut write).await?;
+    let (image, properties, rules, archives) =
+        create_image(req, &version, org_id, &authz, &mut write).await?;
+
+    Ok(api::ImageServiceAddImageResponse {
+        image: Some(api::Image::from(image, properties, rules)?),
+        archives: archives.into_iter().map(Into::into).collect(),
+    })
+}
+
+/// Create the next image build of `version` with its firewall rules,
+/// properties and archive pointers.
+pub(crate) async fn create_image(
+    req: api::ImageServiceAddImageRequest,
+    version: &ProtocolVersion,
+    org_id: Option<OrgId>,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(Image, Vec<ImageProperty>, Vec<ImageRule>, Vec<Archive>), Error> {
+    let latest = Image::latest_build(version.id, org_id, authz, write).await?;
 
     let firewall = req.firewall.ok_or(Error::MissingFirewallConfig)?;
     let new_image = NewImage {
@@ -221,21 +262,21 @@ async fn add_image(
         default_firewall_out: firewall.default_out().try_into()?,
         dns_scheme: req.dns_scheme,
     };
-    let image = new_image.create(&mut write).await?;
+    let image = new_image.create(write).await?;
 
     let new_rules = firewall
         .rules
         .into_iter()
         .map(|rule| NewImageRule::from_api(image.id, rule))
         .collect::<Result<_, _>>()?;
-    let rules = NewImageRule::bulk_create(new_rules, &mut write).await?;
+    let rules = NewImageRule::bulk_create(new_rules, write).await?;
 
     let new_properties = req
         .properties
         .into_iter()
         .map(|prop| NewProperty::from(image.id, prop))
         .collect::<Result<_, _>>()?;
-    let properties = NewProperty::bulk_create(new_properties, &mut write).await?;
+    let properties = NewProperty::bulk_create(new_properties, write).await?;
     let key_to_property_id = properties
         .iter()
         .to_map_keep_last(|prop| (prop.key.clone(), prop.id));
@@ -304,17 +345,14 @@ async fn add_image(
 
     // ensure all possible new_archive key combinations are provided
     let archives = if new_archive_powerset.is_empty() {
-        NewArchive::bulk_create(new_archives, &mut write).await?
+        NewArchive::bulk_create(new_archives, write).await?
     } else {
         return Err(Error::MissingKeyCombos(new_archive_powerset));
     };
 
-    Node::notify_auto_upgrades(&image, &version, org_id, &authz, &mut write).await?;
+    Node::notify_auto_upgrades(&image, version, org_id, authz, write).await?;
 
-    Ok(api::ImageServiceAddImageResponse {
-        image: Some(api::Image::from(image, properties, rules)?),
-        archives: archives.into_iter().map(Into::into).collect(),
-    })
+    Ok((image, properties, rules, archives))
 }
 
 async fn get_image(
@@ -352,7 +390,7 @@ async fn get_image(
     let build = if let Some(build) = req.build_version {
         i64::try_from(build).map_err(Error::BuildVersion)?
     } else {
-        Image::latest_build(version.id, org_id, &authz, &mut read)
+        Image::latest_active_build(version.id, org_id, &authz, &mut read)
             .await?
             .ok_or(Error::NoBuilds)?
             .build_version
@@ -437,6 +475,95 @@ async fn update_image(
     })
 }
 
+async fn set_lifecycle(
+    req: api::ImageServiceSetLifecycleRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::ImageServiceSetLifecycleResponse, Error> {
+    let authz = write.auth(&meta, ImageAdminPerm::SetLifecycle).await?;
+
+    let id = req.image_id.parse().map_err(Error::ParseImageId)?;
+    let lifecycle = ImageLifecycle::try_from(req.lifecycle())?;
+    let sunset_at = req
+        .sunset_at
+        .map(|at| NanosUtc::try_from(at).map_err(Error::ParseSunsetAt))
+        .transpose()?
+        .map(Into::into);
+
+    let update = UpdateLifecycle {
+        id,
+        lifecycle,
+        lifecycle_reason: req.reason,
+        sunset_at,
+    };
+    let image = update.update(&mut write).await?;
+
+    if image.lifecycle != ImageLifecycle::Active {
+        notify_lifecycle(&image, &authz, &mut write).await?;
+    }
+
+    let properties = ImageProperty::by_image_id(image.id, &mut write).await?;
+    let rules = ImageRule::by_image_id(image.id, &mut write).await?;
+
+    Ok(api::ImageServiceSetLifecycleResponse {
+        image: Some(api::Image::from(image, properties, rules)?),
+    })
+}
+
+/// Email the owners of each org with nodes still running `image`, once the
+/// lifecycle change has been committed.
+async fn notify_lifecycle(
+    image: &Image,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    let ctx = write.ctx;
+    let Some(email) = ctx.email.clone() else {
+        return Ok(());
+    };
+
+    let nodes = Node::by_image_id(image.id, write).await?;
+    if nodes.is_empty() {
+        return Ok(());
+    }
+    let version =
+        ProtocolVersion::by_id(image.protocol_version_id, image.org_id, authz, write).await?;
+
+    let mut org_nodes: HashMap<OrgId, Vec<String>> = HashMap::new();
+    for node in nodes {
+        org_nodes
+            .entry(node.org_id)
+            .or_default()
+            .push(node.node_name);
+    }
+
+    let mut emails = Vec::new();
+    for (org_id, node_names) in org_nodes {
+        let owner_ids = RbacUser::org_owners(org_id, write).await?;
+        let owners = User::by_ids(&owner_ids.into_iter().collect(), write).await?;
+        emails.push((owners, node_names));
+    }
+
+    let image = image.clone();
+    write.after_commit(async move {
+        for (owners, node_names) in emails {
+            for owner in owners {
+                if let Err(err) = email
+                    .image_lifecycle(&owner, &image, &version, &node_names)
+                    .await
+                {
+                    warn!(
+                        "Failed to send image lifecycle email to {}: {err}",
+                        owner.id
+                    );
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
 impl api::Image {
     pub fn from(
         image: Image,
@@ -465,6 +592,9 @@ impl api::Image {
             created_at: Some(NanosUtc::from(image.created_at).into()),
             updated_at: image.updated_at.map(NanosUtc::from).map(Into::into),
             dns_scheme: image.dns_scheme,
+            lifecycle: common::ImageLifecycle::from(image.lifecycle).into(),
+            lifecycle_reason: image.lifecycle_reason,
+            sunset_at: image.sunset_at.map(NanosUtc::from).map(Into::into),
         })
     }
 }
@@ -156,7 +156,10 @@ pub async fn host(
         return Err(Error::NotHostToken);
     }
 
-    let host = UpdateHostMetrics::apply(&update, &mut write).await?;
+    let mut host = UpdateHostMetrics::apply(&update, &mut write).await?;
+    if authz.resource().host() == Some(host.id) {
+        host = Host::heartbeat(host.id, &mut write).await?;
+    }
     let host = api::Host::from_host(host, Some(&authz), &mut write).await?;
 
     let updated_by = common::Resource::from(&authz);
@@ -12,11 +12,14 @@ use crate::auth::resource::{NodeId, OrgId, Resource};
 use crate::auth::{AuthZ, Authorize};
 use crate::database::{Conn, ReadConn, Transaction, WriteConn};
 use crate::model::command::NewCommand;
-use crate::model::image::ConfigId;
-use crate::model::image::config::{Config, ConfigType, NewConfig, NodeConfig};
+use crate::model::image::config::{Config, ConfigType, FirewallConfig, NewConfig, NodeConfig};
+use crate::model::image::rule::{FirewallRuleKey, ImageRule};
+use crate::model::image::{ConfigId, ImageId, ImageLifecycle};
+use crate::model::node::rule::{self as node_rule, NewNodeRule, NodeRule};
 use crate::model::node::{
-    HostCount, Launch, NewNode, NextState, Node, NodeFilter, NodeReport, NodeSearch, NodeSort,
-    NodeState, NodeStatus, RegionCount, UpdateNode, UpdateNodeConfig, UpdateNodeState,
+    HostCount, Launch, NewNode, NextState, Node, NodeFilter, NodeReport, NodeResize, NodeSearch,
+    NodeSort, NodeState, NodeStatus, Placement, RegionCount, Resize, RestartPolicy, UpdateNode,
+    UpdateNodeConfig, UpdateNodeState,
 };
 use crate::model::protocol::ProtocolVersion;
 use crate::model::sql::Tag;
@@ -52,12 +55,18 @@ pub enum Error {
     FilterLimit(std::num::TryFromIntError),
     /// Failed to parse filter offset as i64: {0}
     FilterOffset(std::num::TryFromIntError),
+    /// Can't replace the firewall config and change node rules together.
+    FirewallAndRules,
     /// Node host error: {0}
     Host(#[from] crate::model::host::Error),
     /// Node image error: {0}
     Image(#[from] crate::model::image::Error),
     /// Node image config error: {0}
     ImageConfig(#[from] crate::model::image::config::Error),
+    /// Image `{0}` is end of life, so the node must be upgraded first.
+    ImageEndOfLife(ImageId),
+    /// Image `{0}` is {1:?} and can't be used for new nodes.
+    ImageNotActive(ImageId, ImageLifecycle),
     /// Node image property error: {0}
     ImageProperty(#[from] crate::model::image::property::Error),
     /// Node ip address error: {0}
@@ -72,6 +81,8 @@ pub enum Error {
     MissingLauncher,
     /// Node model error: {0}
     Node(#[from] crate::model::node::Error),
+    /// Node rule override error: {0}
+    NodeRule(#[from] crate::model::node::rule::Error),
     /// Node model status error: {0}
     NodeStatus(#[from] crate::model::node::status::Error),
     /// No visiblity of NodeCreate command.
@@ -104,14 +115,20 @@ pub enum Error {
     ParseRegionId(uuid::Error),
     /// Failed to parse UserId: {0}
     ParseUserId(uuid::Error),
+    /// Node placement error: {0}
+    Placement(#[from] crate::model::node::placement::Error),
     /// Node protocol error: {0}
     Protocol(#[from] crate::model::protocol::Error),
     /// Node protocol version error: {0}
     ProtocolVersion(#[from] crate::model::protocol::version::Error),
+    /// Node recovery error: {0}
+    Recovery(#[from] crate::model::node::recovery::Error),
     /// Node region error: {0}
     Region(#[from] crate::model::region::Error),
     /// Node report error: {0}
     Report(#[from] crate::model::node::report::Error),
+    /// Node resize error: {0}
+    Resize(#[from] crate::model::node::resize::Error),
     /// Report config id `{0}` does not match node config id `{1}`.
     ReportConfigId(ConfigId, ConfigId),
     /// Report status has next_state which is only set by the server.
@@ -144,6 +161,14 @@ impl From<Error> for Status {
             BlockHeight(_) => Status::invalid_argument("block_height"),
             FilterLimit(_) => Status::invalid_argument("limit"),
             FilterOffset(_) => Status::invalid_argument("offset"),
+            FirewallAndRules => Status::invalid_argument("new_firewall"),
+            ImageEndOfLife(_) => Status::failed_precondition(
+                "The node image is end of life. Upgrade the node to change its config.",
+            ),
+            ImageNotActive(_, lifecycle) => Status::failed_precondition(format!(
+                "The image is {} and can't be used for new nodes.",
+                lifecycle.description()
+            )),
             MissingIds => Status::invalid_argument("ids"),
             MissingLaunch => Status::invalid_argument("launch"),
             MissingLauncher => Status::invalid_argument("launcher"),
@@ -177,12 +202,16 @@ impl From<Error> for Status {
             IpAddress(err) => err.into(),
             Launch(err) => err.into(),
             Node(err) => err.into(),
+            NodeRule(err) => err.into(),
             NodeStatus(err) => err.into(),
             Org(err) => err.into(),
+            Placement(err) => err.into(),
             Protocol(err) => err.into(),
             ProtocolVersion(err) => err.into(),
+            Recovery(err) => err.into(),
             Region(err) => err.into(),
             Report(err) => err.into(),
+            Resize(err) => err.into(),
             Resource(err) => err.into(),
             Rule(err) => err.into(),
             Sql(err) => err.into(),
@@ -256,6 +285,15 @@ impl NodeService for Grpc {
             .await
     }
 
+    async fn resize(
+        &self,
+        req: Request<api::NodeServiceResizeRequest>,
+    ) -> Result<Response<api::NodeServiceResizeResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| resize(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
     async fn start(
         &self,
         req: Request<api::NodeServiceStartRequest>,
@@ -349,6 +387,9 @@ pub async fn create(
 
     let image_id = req.image_id.parse().map_err(Error::ParseImageId)?;
     let image = Image::by_id(image_id, Some(org_id), &authz, &mut write).await?;
+    if image.lifecycle != ImageLifecycle::Active {
+        return Err(Error::ImageNotActive(image.id, image.lifecycle));
+    }
 
     let version =
         ProtocolVersion::by_id(image.protocol_version_id, Some(org_id), &authz, &mut write).await?;
@@ -363,7 +404,16 @@ pub async fn create(
         .into_iter()
         .map(TryFrom::try_from)
         .collect::<Result<Vec<_>, _>>()?;
-    let config = NodeConfig::new(image, Some(org_id), new_values, add_rules, &mut write).await?;
+    let image_rules = ImageRule::by_image_id(image.id, &mut write).await?;
+    node_rule::validate(&add_rules, &image_rules)?;
+    let config = NodeConfig::new(
+        image,
+        Some(org_id),
+        new_values,
+        add_rules.clone(),
+        &mut write,
+    )
+    .await?;
 
     let new_config = NewConfig {
         image_id,
@@ -382,6 +432,11 @@ pub async fn create(
     } else {
         Default::default()
     };
+    let placement = req
+        .placement
+        .map(Placement::try_from)
+        .transpose()?
+        .unwrap_or_default();
 
     let dns_base = &write.ctx.config.cloudflare.dns.base;
     let new_node = NewNode {
@@ -394,6 +449,7 @@ pub async fn create(
         semantic_version: version.semantic_version,
         auto_upgrade: true,
         tags,
+        placement,
     };
 
     let created = new_node
@@ -404,6 +460,13 @@ pub async fn create(
     for node in created {
         let created_by = common::Resource::from(node.created_by());
 
+        let node_rules = add_rules
+            .iter()
+            .cloned()
+            .map(|rule| NewNodeRule::new(node.id, rule))
+            .collect();
+        NewNodeRule::bulk_create(node_rules, &mut write).await?;
+
         let create_cmd = NewCommand::node(&node, CommandType::NodeCreate)?
             .create(&mut write)
             .await?;
@@ -568,6 +631,15 @@ pub async fn update_config(
     };
 
     let node = Node::by_id(node_id, &mut write).await?;
+    // existing nodes keep their image even if it's no longer visible to them
+    if Image::lifecycle(node.image_id, &mut write).await? == ImageLifecycle::EndOfLife {
+        return Err(Error::ImageEndOfLife(node.image_id));
+    }
+
+    let restart_policy = req
+        .restart_policy
+        .map(RestartPolicy::try_from)
+        .transpose()?;
     let update = UpdateNode {
         org_id: new_org_id,
         host_id: None,
@@ -575,6 +647,8 @@ pub async fn update_config(
         auto_upgrade: req.auto_upgrade,
         ip_address: None,
         ip_gateway: None,
+        ipv6_address: None,
+        ipv6_gateway: None,
         note: req.new_note.as_deref(),
         tags: req
             .update_tags
@@ -582,22 +656,59 @@ pub async fn update_config(
             .transpose()?
             .flatten(),
         cost: req.cost.map(common::BillingAmount::try_into).transpose()?,
+        failover_enabled: req.failover_enabled,
+        restart_policy,
+        restart_attempts: restart_policy.map(|_| 0),
     };
     update.apply(node_id, &authz, &mut write).await?;
 
+    let rules_updated = !req.add_rules.is_empty() || !req.remove_rules.is_empty();
+    let new_firewall = if rules_updated {
+        if req.new_firewall.is_some() {
+            return Err(Error::FirewallAndRules);
+        }
+
+        let add_rules = req
+            .add_rules
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let remove_keys: Vec<_> = req.remove_rules.into_iter().map(FirewallRuleKey).collect();
+        let image_rules = ImageRule::by_image_id(node.image_id, &mut write).await?;
+        let node_rules = NodeRule::replace(
+            node_id,
+            add_rules,
+            remove_keys.clone(),
+            &image_rules,
+            &mut write,
+        )
+        .await?;
+
+        // start from the current rules to keep any earlier `new_firewall` changes
+        let config = Config::by_id(node.config_id, &mut write).await?;
+        let firewall = config.node_config()?.firewall;
+        let current_rules = firewall
+            .rules
+            .into_iter()
+            .filter(|rule| !remove_keys.contains(&rule.key));
+        Some(common::FirewallConfig::from(FirewallConfig {
+            default_in: firewall.default_in,
+            default_out: firewall.default_out,
+            rules: node_rule::merge_rules(current_rules, node_rules.into_iter().map(Into::into)),
+        }))
+    } else {
+        req.new_firewall
+    };
+
     let values_updated = !req.new_values.is_empty();
-    if values_updated || req.new_firewall.is_some() {
+    if values_updated || new_firewall.is_some() {
         let update = UpdateNodeConfig {
             new_values: req
                 .new_values
                 .into_iter()
                 .map(TryInto::try_into)
                 .collect::<Result<Vec<_>, _>>()?,
-            new_firewall: req
-                .new_firewall
-                .clone()
-                .map(TryInto::try_into)
-                .transpose()?,
+            new_firewall: new_firewall.clone().map(TryInto::try_into).transpose()?,
         };
         update.apply(node_id, &authz, &mut write).await?;
     }
@@ -625,7 +736,8 @@ pub async fn update_config(
         new_display_name: req.new_display_name,
         new_note: req.new_note,
         new_values,
-        new_firewall: req.new_firewall,
+        new_firewall,
+        new_vm: None,
     };
     let node_cmd = NewCommand::node(&node, CommandType::NodeUpdate)?
         .with_protobuf(&api_update)
@@ -674,6 +786,9 @@ pub async fn upgrade_image(
     let nodes = Node::by_ids(&ids, &mut write).await?;
 
     let image = Image::by_id(image_id, org_id, &authz, &mut write).await?;
+    if image.lifecycle != ImageLifecycle::Active {
+        return Err(Error::ImageNotActive(image.id, image.lifecycle));
+    }
     let version =
         ProtocolVersion::by_id(image.protocol_version_id, org_id, &authz, &mut write).await?;
     for node in nodes {
@@ -684,6 +799,61 @@ pub async fn upgrade_image(
     Ok(api::NodeServiceUpgradeImageResponse {})
 }
 
+pub async fn resize(
+    req: api::NodeServiceResizeRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::NodeServiceResizeResponse, Error> {
+    let node_id = req.node_id.parse().map_err(Error::ParseId)?;
+    let authz = write
+        .auth_or_for(&meta, NodeAdminPerm::Resize, NodePerm::Resize, node_id)
+        .await?;
+
+    let node = Node::by_id(node_id, &mut write).await?;
+    if Image::lifecycle(node.image_id, &mut write).await? == ImageLifecycle::EndOfLife {
+        return Err(Error::ImageEndOfLife(node.image_id));
+    }
+
+    let resize = NodeResize {
+        cpu_cores: req.cpu_cores,
+        memory_bytes: req.memory_bytes,
+        disk_bytes: req.disk_bytes,
+    };
+    let node = match resize.apply(node, &authz, &mut write).await? {
+        Resize::InPlace(node) => {
+            let config = Config::by_id(node.config_id, &mut write).await?;
+            let api_update = api::NodeUpdate {
+                node_id: node.id.to_string(),
+                config_id: node.config_id.to_string(),
+                auto_upgrade: None,
+                new_org_id: None,
+                new_org_name: None,
+                new_display_name: None,
+                new_note: None,
+                new_values: vec![],
+                new_firewall: None,
+                new_vm: Some(config.node_config()?.vm.into()),
+            };
+            let node_cmd = NewCommand::node(&node, CommandType::NodeUpdate)?
+                .with_protobuf(&api_update)
+                .create(&mut write)
+                .await?;
+            let update_cmd = node_update(&node_cmd, &mut write).await?;
+            write.mqtt(update_cmd);
+
+            let api_node = api::Node::from_model(node, &authz, &mut write).await?;
+            let updated_by = common::Resource::from(&authz);
+            write.mqtt(api::NodeMessage::updated(api_node.clone(), updated_by));
+            api_node
+        }
+
+        // the original node is deleted once the new node is running
+        Resize::Moved(new) => api::Node::from_model(new, &authz, &mut write).await?,
+    };
+
+    Ok(api::NodeServiceResizeResponse { node: Some(node) })
+}
+
 pub async fn start(
     req: api::NodeServiceStartRequest,
     meta: Metadata,
@@ -940,8 +1110,13 @@ impl api::Node {
             }),
             semantic_version: node.semantic_version.to_string(),
             auto_upgrade: node.auto_upgrade,
+            failover_enabled: node.failover_enabled,
+            placement: Some(node.placement.into()),
+            restart_policy: Some(node.restart_policy.into()),
             ip_address: node.ip_address.to_string(),
             ip_gateway: node.ip_gateway.to_string(),
+            ipv6_address: node.ipv6_address.map(|ip| ip.to_string()),
+            ipv6_gateway: node.ipv6_gateway.map(|ip| ip.to_string()),
             dns_name: node.dns_name,
             p2p_address: node.p2p_address,
             dns_url: node.dns_url,
@@ -976,6 +1151,10 @@ impl api::NodeServiceListRequest {
             .next_states()
             .map(NextState::try_from)
             .collect::<Result<_, _>>()?;
+        let image_lifecycles = self
+            .image_lifecycles()
+            .map(ImageLifecycle::try_from)
+            .collect::<Result<_, _>>()?;
 
         let protocol_ids = self
             .protocol_ids
@@ -1056,6 +1235,7 @@ impl api::NodeServiceListRequest {
             ip_addresses,
             node_states,
             next_states,
+            image_lifecycles,
             search,
             sort,
             limit: i64::try_from(self.limit).map_err(Error::FilterLimit)?,
@@ -9,13 +9,22 @@ use tonic::{Request, Response};
 use tracing::error;
 
 use crate::auth::Authorize;
-use crate::auth::rbac::{OrgAddressPerm, OrgAdminPerm, OrgBillingPerm, OrgPerm, OrgProvisionPerm};
+use crate::auth::oidc;
+use crate::auth::rbac::{
+    HostAdminPerm, NodeAdminPerm, OrgAddressPerm, OrgAdminPerm, OrgBillingPerm, OrgPerm,
+    OrgProvisionPerm, OrgRole, OrgSsoPerm, Perm, Perms, Role,
+};
 use crate::auth::resource::{OrgId, UserId};
 use crate::database::{Conn, ReadConn, Transaction, WriteConn};
 use crate::model::address::NewAddress;
+use crate::model::cost::{CostGroupBy, CostReport};
+use crate::model::host_provision_token::NewHostProvisionToken;
+use crate::model::node::{NodeUsage, UsageSummary};
 use crate::model::org::{NewOrg, OrgFilter, OrgSearch, OrgSort, UpdateOrg};
 use crate::model::rbac::{OrgUsers, RbacUser};
-use crate::model::{Address, Invitation, Org, Token, User};
+use crate::model::sql::Tag;
+use crate::model::sso::{GroupRoles, NewIdentityProvider};
+use crate::model::{Address, HostProvisionToken, IdentityProvider, Invitation, Org, Token, User};
 use crate::util::{HashVec, NanosUtc};
 
 use super::api::org_service_server::OrgService;
@@ -35,6 +44,8 @@ pub enum Error {
     ClaimsNotUser,
     /// Can't delete personal org.
     DeletePersonal,
+    /// Org cost report error: {0}
+    CostReport(#[from] crate::model::cost::Error),
     /// Diesel failure: {0}
     Diesel(#[from] diesel::result::Error),
     /// Failed to parse filter limit as i64: {0}
@@ -45,20 +56,46 @@ pub enum Error {
     Invitation(#[from] crate::model::invitation::Error),
     /// The request is missing the `address` fields.
     MissingAddress,
+    /// A `client_secret` is required for a new identity provider.
+    MissingClientSecret,
+    /// The request is missing a `start` time.
+    MissingStart,
     /// Stripe is not configured.
     NoStripe,
     /// No customer exists in stripe for org `{0}`.
     NoStripeCustomer(OrgId),
     /// No subscription exists in stripe for org `{0}`.
     NoStripeSubscription(OrgId),
+    /// Identity provider check failed: {0}
+    Oidc(#[from] crate::auth::oidc::Error),
+    /// User `{0}` is not a member of this org.
+    NotMember(UserId),
     /// Org model error: {0}
     Org(#[from] crate::model::org::Error),
+    /// Org host provision token error: {0}
+    HostProvisionToken(#[from] crate::model::host_provision_token::Error),
     /// Failed to parse `id` as OrgId: {0}
     ParseId(uuid::Error),
     /// Failed to parse non-zero count as u64: {0}
     ParseMax(std::num::TryFromIntError),
+    /// Failed to parse period end: {0}
+    ParseEnd(crate::util::timestamp::Error),
+    /// Failed to parse token expiry: {0}
+    ParseExpiresAt(crate::util::timestamp::Error),
     /// Failed to parse OrgId: {0}
     ParseOrgId(uuid::Error),
+    /// Failed to parse RegionId: {0}
+    ParseRegionId(uuid::Error),
+    /// Failed to parse org role: {0}
+    ParseRole(String),
+    /// Failed to parse schedule type: {0}
+    ParseScheduleType(crate::model::host::Error),
+    /// Failed to parse period start: {0}
+    ParseStart(crate::util::timestamp::Error),
+    /// Failed to parse tag: {0}
+    ParseTag(crate::model::sql::Error),
+    /// Failed to parse token id: {0}
+    ParseTokenId(uuid::Error),
     /// Failed to parse UserId: {0}
     ParseUserId(uuid::Error),
     /// Org rbac error: {0}
@@ -67,12 +104,16 @@ pub enum Error {
     Resource(#[from] crate::auth::resource::Error),
     /// Cannot remove last owner from an org.
     RemoveLastOwner,
+    /// Role `{0}` can't be assigned to an org member.
+    RoleNotAssignable(Role),
     /// User to remove is not self.
     RemoveNotSelf,
     /// Org search failed: {0}
     SearchOperator(crate::util::search::Error),
     /// Sort order: {0}
     SortOrder(crate::util::search::Error),
+    /// Org SSO error: {0}
+    Sso(#[from] crate::model::sso::Error),
     /// Stripe error: {0}
     Stripe(#[from] crate::stripe::Error),
     /// Stripe Currency error: {0}
@@ -81,6 +122,8 @@ pub enum Error {
     StripeInvoice(#[from] crate::stripe::api::invoice::Error),
     /// Org token error: {0}
     Token(#[from] crate::model::token::Error),
+    /// Org usage error: {0}
+    Usage(#[from] crate::model::node::usage::Error),
     /// The requested sort field is unknown.
     UnknownSortField,
     /// Org user error: {0}
@@ -93,16 +136,26 @@ impl From<Error> for Status {
         error!("{err}");
         match err {
             ClaimsNotUser | DeletePersonal | RemoveNotSelf => Status::forbidden("Access denied."),
+            NotMember(_) => Status::failed_precondition("User is not an org member."),
             ConvertNoOrg | Diesel(_) | ParseMax(_) | Stripe(_) | StripeCurrency(_)
             | StripeInvoice(_) => Status::internal("Internal error."),
             FilterLimit(_) => Status::invalid_argument("limit"),
             FilterOffset(_) => Status::invalid_argument("offset"),
             MissingAddress => Status::failed_precondition("User has no address."),
+            MissingClientSecret => Status::invalid_argument("client_secret"),
+            MissingStart | ParseStart(_) => Status::invalid_argument("start"),
             NoStripe => Status::failed_precondition("Stripe is not configured."),
             NoStripeCustomer(_) => Status::failed_precondition("No customer for that org."),
             NoStripeSubscription(_) => Status::failed_precondition("No subscription for that org."),
             ParseId(_) => Status::invalid_argument("id"),
+            ParseEnd(_) => Status::invalid_argument("end"),
+            ParseExpiresAt(_) => Status::invalid_argument("expires_at"),
             ParseOrgId(_) => Status::invalid_argument("org_id"),
+            ParseRegionId(_) => Status::invalid_argument("region_ids"),
+            ParseRole(_) | RoleNotAssignable(_) => Status::invalid_argument("role"),
+            ParseScheduleType(_) => Status::invalid_argument("schedule_type"),
+            ParseTag(_) => Status::invalid_argument("tags"),
+            ParseTokenId(_) => Status::invalid_argument("token_id"),
             ParseUserId(_) => Status::invalid_argument("user_id"),
             RemoveLastOwner => Status::failed_precondition("Can't remove last org owner."),
             SearchOperator(_) => Status::invalid_argument("search.operator"),
@@ -111,11 +164,16 @@ impl From<Error> for Status {
             Address(err) => err.into(),
             Auth(err) => err.into(),
             Claims(err) => err.into(),
+            CostReport(err) => err.into(),
+            HostProvisionToken(err) => err.into(),
             Invitation(err) => err.into(),
+            Oidc(err) => err.into(),
             Org(err) => err.into(),
             Rbac(err) => err.into(),
             Resource(err) => err.into(),
+            Sso(err) => err.into(),
             Token(err) => err.into(),
+            Usage(err) => err.into(),
             User(err) => err.into(),
         }
     }
@@ -177,6 +235,24 @@ impl OrgService for Grpc {
             .await
     }
 
+    async fn update_member_role(
+        &self,
+        req: Request<api::OrgServiceUpdateMemberRoleRequest>,
+    ) -> Result<Response<api::OrgServiceUpdateMemberRoleResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| update_member_role(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn transfer_ownership(
+        &self,
+        req: Request<api::OrgServiceTransferOwnershipRequest>,
+    ) -> Result<Response<api::OrgServiceTransferOwnershipResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| transfer_ownership(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
     async fn get_provision_token(
         &self,
         req: Request<api::OrgServiceGetProvisionTokenRequest>,
@@ -195,6 +271,33 @@ impl OrgService for Grpc {
             .await
     }
 
+    async fn create_provision_token(
+        &self,
+        req: Request<api::OrgServiceCreateProvisionTokenRequest>,
+    ) -> Result<Response<api::OrgServiceCreateProvisionTokenResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| create_provision_token(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn list_provision_tokens(
+        &self,
+        req: Request<api::OrgServiceListProvisionTokensRequest>,
+    ) -> Result<Response<api::OrgServiceListProvisionTokensResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| list_provision_tokens(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn revoke_provision_token(
+        &self,
+        req: Request<api::OrgServiceRevokeProvisionTokenRequest>,
+    ) -> Result<Response<api::OrgServiceRevokeProvisionTokenResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| revoke_provision_token(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
     async fn init_card(
         &self,
         req: Request<api::OrgServiceInitCardRequest>,
@@ -257,6 +360,60 @@ impl OrgService for Grpc {
         self.read(|read| get_invoices(req, meta.into(), read).scope_boxed())
             .await
     }
+
+    async fn cost_report(
+        &self,
+        req: Request<api::OrgServiceCostReportRequest>,
+    ) -> Result<Response<api::OrgServiceCostReportResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| cost_report(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn get_usage(
+        &self,
+        req: Request<api::OrgServiceGetUsageRequest>,
+    ) -> Result<Response<api::OrgServiceGetUsageResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| get_usage(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn get_identity_provider(
+        &self,
+        req: Request<api::OrgServiceGetIdentityProviderRequest>,
+    ) -> Result<Response<api::OrgServiceGetIdentityProviderResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| get_identity_provider(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
+    async fn set_identity_provider(
+        &self,
+        req: Request<api::OrgServiceSetIdentityProviderRequest>,
+    ) -> Result<Response<api::OrgServiceSetIdentityProviderResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| set_identity_provider(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn verify_sso_domain(
+        &self,
+        req: Request<api::OrgServiceVerifySsoDomainRequest>,
+    ) -> Result<Response<api::OrgServiceVerifySsoDomainResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| verify_sso_domain(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
+    async fn delete_identity_provider(
+        &self,
+        req: Request<api::OrgServiceDeleteIdentityProviderRequest>,
+    ) -> Result<Response<api::OrgServiceDeleteIdentityProviderResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| delete_identity_provider(req, meta.into(), write).scope_boxed())
+            .await
+    }
 }
 
 pub async fn create(
@@ -329,6 +486,7 @@ pub async fn update(
         id: org_id,
         name: req.name.as_deref(),
         address_id: None,
+        failover_enabled: req.failover_enabled,
     };
     let org = update.update(&mut write).await?;
     let org = api::Org::from_model(&org, &mut write).await?;
@@ -408,6 +566,94 @@ pub async fn remove_member(
     Ok(api::OrgServiceRemoveMemberResponse {})
 }
 
+/// Change an org member's role to either `org-admin` or `org-member`.
+///
+/// Demoting an owner requires permission to transfer ownership, and the last
+/// owner can't be demoted. Permissions are resolved from the member's roles on
+/// each request, so the change applies from their next request or refresh.
+pub async fn update_member_role(
+    req: api::OrgServiceUpdateMemberRoleRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceUpdateMemberRoleResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let authz = write
+        .auth_for(&meta, OrgPerm::UpdateMemberRole, org_id)
+        .await?;
+
+    let user_id: UserId = req.user_id.parse().map_err(Error::ParseUserId)?;
+    let role = match req.role.parse().map_err(Error::ParseRole)? {
+        Role::Org(role @ (OrgRole::Admin | OrgRole::Member)) => role,
+        role => return Err(Error::RoleNotAssignable(role)),
+    };
+
+    let org = Org::by_id(org_id, &mut write).await?;
+    if org.is_personal {
+        return Err(Error::DeletePersonal);
+    }
+
+    let roles = RbacUser::org_roles(user_id, org_id, false, &mut write).await?;
+    if roles.is_empty() {
+        return Err(Error::NotMember(user_id));
+    }
+
+    if roles.contains(&Role::Org(OrgRole::Owner)) {
+        write
+            .auth_for(&meta, OrgPerm::TransferOwnership, org_id)
+            .await?;
+
+        let owners = RbacUser::org_owners(org_id, &mut write).await?;
+        if owners.len() == 1 && owners[0] == user_id {
+            return Err(Error::RemoveLastOwner);
+        }
+    }
+
+    Org::set_user_role(user_id, org_id, role, &mut write).await?;
+
+    let org = api::Org::from_model(&org, &mut write).await?;
+    let updated_by = common::Resource::from(&authz);
+    let msg = api::OrgMessage::updated(org.clone(), updated_by);
+    write.mqtt(msg);
+
+    Ok(api::OrgServiceUpdateMemberRoleResponse { org: Some(org) })
+}
+
+/// Make an org member the sole owner of the org.
+///
+/// All previous owners are demoted to `org-admin`.
+pub async fn transfer_ownership(
+    req: api::OrgServiceTransferOwnershipRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceTransferOwnershipResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let authz = write
+        .auth_for(&meta, OrgPerm::TransferOwnership, org_id)
+        .await?;
+
+    let user_id: UserId = req.user_id.parse().map_err(Error::ParseUserId)?;
+    let org = Org::by_id(org_id, &mut write).await?;
+    if org.is_personal {
+        return Err(Error::DeletePersonal);
+    }
+    if !Org::has_user(org_id, user_id, &mut write).await? {
+        return Err(Error::NotMember(user_id));
+    }
+
+    let owners = RbacUser::org_owners(org_id, &mut write).await?;
+    for owner_id in owners.into_iter().filter(|id| *id != user_id) {
+        Org::set_user_role(owner_id, org_id, OrgRole::Admin, &mut write).await?;
+    }
+    Org::set_user_role(user_id, org_id, OrgRole::Owner, &mut write).await?;
+
+    let org = api::Org::from_model(&org, &mut write).await?;
+    let updated_by = common::Resource::from(&authz);
+    let msg = api::OrgMessage::updated(org.clone(), updated_by);
+    write.mqtt(msg);
+
+    Ok(api::OrgServiceTransferOwnershipResponse { org: Some(org) })
+}
+
 pub async fn get_provision_token(
     req: api::OrgServiceGetProvisionTokenRequest,
     meta: Metadata,
@@ -443,6 +689,104 @@ pub async fn reset_provision_token(
     })
 }
 
+/// Create a named host provision token.
+///
+/// The plaintext token is only returned here, as only its hash is stored.
+pub async fn create_provision_token(
+    req: api::OrgServiceCreateProvisionTokenRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceCreateProvisionTokenResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let authz = write
+        .auth_for(&meta, OrgProvisionPerm::CreateToken, org_id)
+        .await?;
+
+    let expires_at = req
+        .expires_at
+        .map(NanosUtc::try_from)
+        .transpose()
+        .map_err(Error::ParseExpiresAt)?
+        .map(Into::into);
+    let max_uses = req.max_uses.map(i64::from).filter(|max_uses| *max_uses > 0);
+    let region_ids = req
+        .region_ids
+        .iter()
+        .map(|id| id.parse().map_err(Error::ParseRegionId))
+        .collect::<Result<_, _>>()?;
+    let schedule_type = if req.schedule_type.is_some() {
+        Some(
+            req.schedule_type()
+                .try_into()
+                .map_err(Error::ParseScheduleType)?,
+        )
+    } else {
+        None
+    };
+    let tags = req
+        .tags
+        .map(|tags| {
+            tags.tags
+                .into_iter()
+                .map(|tag| Tag::new(tag.name))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(Error::ParseTag)?
+        .unwrap_or_default();
+
+    let created_by = authz.resource();
+    let new_token = NewHostProvisionToken {
+        org_id,
+        name: req.name,
+        created_by_type: created_by.typ(),
+        created_by_id: created_by.id(),
+        expires_at,
+        max_uses,
+        region_ids,
+        schedule_type,
+        tags: tags.into(),
+    };
+    let (token, secret) = new_token.create(&mut write).await?;
+
+    Ok(api::OrgServiceCreateProvisionTokenResponse {
+        token: Some(token.into()),
+        secret: secret.take(),
+    })
+}
+
+pub async fn list_provision_tokens(
+    req: api::OrgServiceListProvisionTokensRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::OrgServiceListProvisionTokensResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    read.auth_for(&meta, OrgProvisionPerm::ListTokens, org_id)
+        .await?;
+
+    let tokens = HostProvisionToken::by_org(org_id, &mut read).await?;
+
+    Ok(api::OrgServiceListProvisionTokensResponse {
+        tokens: tokens.into_iter().map(Into::into).collect(),
+    })
+}
+
+pub async fn revoke_provision_token(
+    req: api::OrgServiceRevokeProvisionTokenRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceRevokeProvisionTokenResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    write
+        .auth_for(&meta, OrgProvisionPerm::RevokeToken, org_id)
+        .await?;
+
+    let token_id = req.token_id.parse().map_err(Error::ParseTokenId)?;
+    HostProvisionToken::revoke(token_id, org_id, &mut write).await?;
+
+    Ok(api::OrgServiceRevokeProvisionTokenResponse {})
+}
+
 pub async fn init_card(
     req: api::OrgServiceInitCardRequest,
     meta: Metadata,
@@ -636,6 +980,7 @@ pub async fn set_address(
                 id: org.id,
                 name: None,
                 address_id: Some(address.id),
+                failover_enabled: None,
             };
             update_org.update(&mut write).await?;
         }
@@ -700,6 +1045,170 @@ pub async fn get_invoices(
     Ok(api::OrgServiceGetInvoicesResponse { invoices })
 }
 
+pub async fn cost_report(
+    req: api::OrgServiceCostReportRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::OrgServiceCostReportResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let perms = Perms::Any(hashset! {
+        Perm::from(NodeAdminPerm::ViewCost),
+        Perm::from(HostAdminPerm::ViewCost),
+    });
+    let authz = read.auth_for(&meta, perms, org_id).await?;
+
+    let group_by = CostGroupBy::try_from(req.group_by())?;
+    let start = req
+        .start
+        .ok_or(Error::MissingStart)
+        .and_then(|start| NanosUtc::try_from(start).map_err(Error::ParseStart))?;
+    let end = req
+        .end
+        .map(NanosUtc::try_from)
+        .transpose()
+        .map_err(Error::ParseEnd)?
+        .map_or_else(chrono::Utc::now, Into::into);
+
+    // only include the costs that the caller is permitted to view
+    let report = CostReport::new(
+        org_id,
+        start.into(),
+        end,
+        group_by,
+        authz.has_perm(NodeAdminPerm::ViewCost),
+        authz.has_perm(HostAdminPerm::ViewCost),
+        &mut read,
+    )
+    .await?;
+
+    Ok(report.into())
+}
+
+pub async fn get_usage(
+    req: api::OrgServiceGetUsageRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::OrgServiceGetUsageResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    read.auth_for(&meta, OrgBillingPerm::GetBillingDetails, org_id)
+        .await?;
+
+    let start = req
+        .start
+        .ok_or(Error::MissingStart)
+        .and_then(|start| NanosUtc::try_from(start).map_err(Error::ParseStart))?;
+    let now = chrono::Utc::now();
+    let end = req
+        .end
+        .map(NanosUtc::try_from)
+        .transpose()
+        .map_err(Error::ParseEnd)?
+        .map_or(now, Into::into);
+
+    let usage = NodeUsage::for_org(org_id, start.into(), end, &mut read).await?;
+    let summary = UsageSummary::new(&usage, start.into(), end, now)?;
+
+    Ok(api::OrgServiceGetUsageResponse {
+        org_id: org_id.to_string(),
+        start: Some(start.into()),
+        end: Some(NanosUtc::from(end).into()),
+        usage: summary.into_iter().map(Into::into).collect(),
+    })
+}
+
+pub async fn get_identity_provider(
+    req: api::OrgServiceGetIdentityProviderRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::OrgServiceGetIdentityProviderResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    read.auth_for(&meta, OrgSsoPerm::Get, org_id).await?;
+
+    let provider = IdentityProvider::by_org(org_id, &mut read).await?;
+
+    Ok(api::OrgServiceGetIdentityProviderResponse {
+        provider: Some(provider.into()),
+    })
+}
+
+/// Configure single sign-on for an org.
+///
+/// The issuer's discovery document is fetched up front so that a bad issuer
+/// is rejected here rather than on the first login attempt. The existing
+/// client secret is kept if none is provided, as are the verified domains that
+/// remain allowed.
+pub async fn set_identity_provider(
+    req: api::OrgServiceSetIdentityProviderRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceSetIdentityProviderResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    write.auth_for(&meta, OrgSsoPerm::Set, org_id).await?;
+
+    let existing = match IdentityProvider::by_org(org_id, &mut write).await {
+        Ok(existing) => Some(existing),
+        Err(crate::model::sso::Error::FindByOrg(_, diesel::result::Error::NotFound)) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let client_secret = match (req.client_secret, &existing) {
+        (Some(secret), _) => secret,
+        (None, Some(existing)) => existing.client_secret.clone(),
+        (None, None) => return Err(Error::MissingClientSecret),
+    };
+
+    oidc::Client::new()?.discover(&req.issuer).await?;
+
+    let group_roles = GroupRoles::from_api(req.group_roles)?;
+    let mut provider = NewIdentityProvider::new(
+        org_id,
+        req.issuer,
+        req.client_id,
+        client_secret,
+        req.allowed_domains,
+        group_roles,
+    )?;
+    if let Some(existing) = &existing {
+        provider = provider.keep_verified(existing);
+    }
+    let provider = provider.upsert(&mut write).await?;
+
+    Ok(api::OrgServiceSetIdentityProviderResponse {
+        provider: Some(provider.into()),
+    })
+}
+
+/// Mark an allowed SSO domain as owned by the org.
+///
+/// Only verified domains are trusted for SSO logins. Ownership is checked out
+/// of band, so only blockjoy admins may verify a domain.
+pub async fn verify_sso_domain(
+    req: api::OrgServiceVerifySsoDomainRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceVerifySsoDomainResponse, Error> {
+    write.auth(&meta, OrgAdminPerm::VerifySsoDomain).await?;
+
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    let provider = IdentityProvider::verify_domain(org_id, &req.domain, &mut write).await?;
+
+    Ok(api::OrgServiceVerifySsoDomainResponse {
+        provider: Some(provider.into()),
+    })
+}
+
+pub async fn delete_identity_provider(
+    req: api::OrgServiceDeleteIdentityProviderRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::OrgServiceDeleteIdentityProviderResponse, Error> {
+    let org_id: OrgId = req.org_id.parse().map_err(Error::ParseOrgId)?;
+    write.auth_for(&meta, OrgSsoPerm::Delete, org_id).await?;
+
+    IdentityProvider::delete(org_id, &mut write).await?;
+
+    Ok(api::OrgServiceDeleteIdentityProviderResponse {})
+}
+
 impl api::Org {
     /// Converts a list of `orgs` into a list of `api::Org`.
     ///
@@ -770,6 +1279,7 @@ impl api::Org {
                     member_count: u64::try_from(max(0, org.member_count))
                         .map_err(Error::ParseMax)?,
                     members,
+                    failover_enabled: org.failover_enabled,
                 })
             })
             .collect()
@@ -783,6 +1293,35 @@ impl api::Org {
     }
 }
 
+impl From<HostProvisionToken> for api::HostProvisionToken {
+    fn from(token: HostProvisionToken) -> Self {
+        let created_by = token.resource();
+        api::HostProvisionToken {
+            token_id: token.id.to_string(),
+            org_id: token.org_id.to_string(),
+            name: token.name,
+            created_by: Some(common::Resource::from(created_by)),
+            expires_at: token.expires_at.map(|time| NanosUtc::from(time).into()),
+            max_uses: token
+                .max_uses
+                .map(|max_uses| u64::try_from(max_uses).unwrap_or_default()),
+            use_count: u64::try_from(token.use_count).unwrap_or_default(),
+            region_ids: token
+                .region_ids
+                .into_iter()
+                .flatten()
+                .map(|id| id.to_string())
+                .collect(),
+            schedule_type: token
+                .schedule_type
+                .map(|schedule_type| common::ScheduleType::from(schedule_type).into()),
+            tags: Some(token.tags.into()),
+            created_at: Some(NanosUtc::from(token.created_at).into()),
+            last_used_at: token.last_used_at.map(|time| NanosUtc::from(time).into()),
+        }
+    }
+}
+
 impl api::OrgServiceListRequest {
     fn into_filter(self) -> Result<OrgFilter, Error> {
         let member_id = self
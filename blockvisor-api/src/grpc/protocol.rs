@@ -10,9 +10,12 @@ use tracing::error;
 use crate::auth::rbac::{Perm, ProtocolAdminPerm, ProtocolPerm};
 use crate::auth::{AuthZ, Authorize};
 use crate::database::{Conn, ReadConn, Transaction, WriteConn};
+use crate::model::image::UpdateImage;
+use crate::model::protocol::catalog::{self, Catalog, CatalogFormat, Change};
 use crate::model::protocol::stats::NodeStats;
 use crate::model::protocol::version::{
-    NewVersion, ProtocolKey, ProtocolVersion, UpdateVersion, VersionKey, VersionMetadata,
+    NewVersion, ProtocolKey, ProtocolVersion, UpdateVersion, VariantKey, VersionKey,
+    VersionMetadata,
 };
 use crate::model::protocol::{
     NewProtocol, Protocol, ProtocolFilter, ProtocolSearch, ProtocolSort, UpdateProtocol,
@@ -27,6 +30,10 @@ use super::{Grpc, Metadata, Status, api, common};
 pub enum Error {
     /// Auth check failed: {0}
     Auth(#[from] crate::auth::Error),
+    /// Protocol catalog error: {0}
+    Catalog(#[from] crate::model::protocol::catalog::Error),
+    /// The catalog has {0} conflicting changes.
+    CatalogConflicts(usize),
     /// Claims check failed: {0}
     Claims(#[from] crate::auth::claims::Error),
     /// Protocol command failed: {0}
@@ -35,6 +42,10 @@ pub enum Error {
     CommandGrpc(#[from] crate::grpc::command::Error),
     /// Diesel failure: {0}
     Diesel(#[from] diesel::result::Error),
+    /// Protocol image error: {0}
+    Image(#[from] crate::grpc::image::Error),
+    /// Protocol image model error: {0}
+    ImageModel(#[from] crate::model::image::Error),
     /// Failed to parse filter limit as i64: {0}
     FilterLimit(std::num::TryFromIntError),
     /// Failed to parse filter offset as i64: {0}
@@ -97,6 +108,9 @@ impl From<Error> for Status {
             Diesel(_) | MissingModel | Store(_) | Stripe(_) | StripePrice(_) => {
                 Status::internal("Internal error.")
             }
+            CatalogConflicts(count) => Status::failed_precondition(format!(
+                "The catalog has {count} conflicting changes. Run with dry_run to list them."
+            )),
             FilterLimit(_) => Status::invalid_argument("limit"),
             FilterOffset(_) => Status::invalid_argument("offset"),
             MissingProtocol => Status::invalid_argument("protocol"),
@@ -113,9 +127,12 @@ impl From<Error> for Status {
             SortOrder(_) => Status::invalid_argument("sort.order"),
             UnknownSortField => Status::invalid_argument("sort.field"),
             Auth(err) => err.into(),
+            Catalog(err) => err.into(),
             Claims(err) => err.into(),
             Command(err) => err.into(),
             CommandGrpc(err) => err.into(),
+            Image(err) => err.into(),
+            ImageModel(err) => err.into(),
             Node(err) => err.into(),
             NodeLog(err) => err.into(),
             Protocol(err) => err.into(),
@@ -146,6 +163,15 @@ impl ProtocolService for Grpc {
             .await
     }
 
+    async fn export_catalog(
+        &self,
+        req: Request<api::ProtocolServiceExportCatalogRequest>,
+    ) -> Result<Response<api::ProtocolServiceExportCatalogResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.read(|read| export_catalog(req, meta.into(), read).scope_boxed())
+            .await
+    }
+
     async fn get_latest(
         &self,
         req: Request<api::ProtocolServiceGetLatestRequest>,
@@ -182,6 +208,15 @@ impl ProtocolService for Grpc {
             .await
     }
 
+    async fn import_catalog(
+        &self,
+        req: Request<api::ProtocolServiceImportCatalogRequest>,
+    ) -> Result<Response<api::ProtocolServiceImportCatalogResponse>, tonic::Status> {
+        let (meta, _, req) = req.into_parts();
+        self.write(|write| import_catalog(req, meta.into(), write).scope_boxed())
+            .await
+    }
+
     async fn list_protocols(
         &self,
         req: Request<api::ProtocolServiceListProtocolsRequest>,
@@ -296,6 +331,144 @@ pub async fn add_version(
     })
 }
 
+pub async fn export_catalog(
+    req: api::ProtocolServiceExportCatalogRequest,
+    meta: Metadata,
+    mut read: ReadConn<'_, '_>,
+) -> Result<api::ProtocolServiceExportCatalogResponse, Error> {
+    read.auth(&meta, ProtocolAdminPerm::ExportCatalog).await?;
+
+    let format = CatalogFormat::try_from(req.format())?;
+    let catalog = Catalog::load(&mut read).await?;
+
+    Ok(api::ProtocolServiceExportCatalogResponse {
+        catalog: catalog.serialize(format)?,
+    })
+}
+
+/// Apply a declarative catalog of public protocols.
+///
+/// The planned changes are always returned, but are only applied when this is
+/// not a `dry_run` and none of them conflict with the current catalog.
+pub async fn import_catalog(
+    req: api::ProtocolServiceImportCatalogRequest,
+    meta: Metadata,
+    mut write: WriteConn<'_, '_>,
+) -> Result<api::ProtocolServiceImportCatalogResponse, Error> {
+    let authz = write.auth(&meta, ProtocolAdminPerm::ImportCatalog).await?;
+
+    let format = CatalogFormat::try_from(req.format())?;
+    let desired = Catalog::parse(&req.catalog, format)?;
+    let current = Catalog::load(&mut write).await?;
+    let changes = desired.plan(&current);
+
+    let conflicts = changes.iter().filter(|change| change.is_conflict()).count();
+    if !req.dry_run {
+        if conflicts > 0 {
+            return Err(Error::CatalogConflicts(conflicts));
+        }
+        for change in &changes {
+            apply_catalog_change(change, &authz, &mut write).await?;
+        }
+    }
+
+    Ok(api::ProtocolServiceImportCatalogResponse {
+        changes: changes.iter().map(Into::into).collect(),
+        applied: !req.dry_run,
+    })
+}
+
+async fn apply_catalog_change(
+    change: &Change<'_>,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    match change {
+        Change::CreateProtocol(protocol) => {
+            let new_protocol = NewProtocol {
+                org_id: None,
+                key: ProtocolKey::new(protocol.key.clone())?,
+                name: protocol.name.clone(),
+                description: protocol.description.clone(),
+                ticker: protocol.ticker.clone(),
+            };
+            let created = new_protocol.create(write).await?;
+            let update = UpdateProtocol {
+                id: created.id,
+                name: None,
+                description: None,
+                visibility: Some(protocol.visibility),
+            };
+            update.apply(write).await?;
+        }
+        Change::UpdateProtocol(protocol) => {
+            let existing = catalog::find_protocol(&protocol.key, write).await?;
+            let update = UpdateProtocol {
+                id: existing.id,
+                name: Some(protocol.name.as_str()),
+                description: protocol.description.as_deref(),
+                visibility: Some(protocol.visibility),
+            };
+            update.apply(write).await?;
+        }
+        Change::CreateVersion(path, version) => {
+            let protocol = catalog::find_protocol(&path.protocol_key, write).await?;
+            let new_version = NewVersion {
+                org_id: None,
+                protocol_id: protocol.id,
+                protocol_key: &protocol.key,
+                variant_key: &VariantKey::new(path.variant_key.clone())?,
+                metadata: version.metadata.clone().into(),
+                semantic_version: &path.semantic_version.parse().map_err(Error::ParseVersion)?,
+                sku_code: &version.sku_code,
+                description: version.description.clone(),
+            };
+            let created = new_version.create(write).await?;
+            let update = UpdateVersion {
+                id: created.id,
+                sku_code: None,
+                description: None,
+                visibility: Some(version.visibility),
+            };
+            update.apply(write).await?;
+        }
+        Change::UpdateVersion(path, version) => {
+            let existing = catalog::find_version(path, write).await?;
+            let update = UpdateVersion {
+                id: existing.id,
+                sku_code: Some(version.sku_code.as_str()),
+                description: version.description.as_deref(),
+                visibility: Some(version.visibility),
+            };
+            update.apply(write).await?;
+        }
+        Change::CreateImage(path, image) => {
+            let version = catalog::find_version(path, write).await?;
+            let req = image.to_request(version.id.to_string());
+            let (created, _, _, _) =
+                crate::grpc::image::create_image(req, &version, None, authz, write).await?;
+            let update = UpdateImage {
+                id: created.id,
+                visibility: Some(image.visibility),
+            };
+            update.update(write).await?;
+        }
+        Change::UpdateImage(path, image) => {
+            let version = catalog::find_version(path, write).await?;
+            let existing =
+                catalog::find_image(path, version.id, image.build_version, write).await?;
+            let update = UpdateImage {
+                id: existing.id,
+                visibility: Some(image.visibility),
+            };
+            update.update(write).await?;
+        }
+        Change::Conflict(..) => (),
+    }
+
+    Ok(())
+}
+
 pub async fn get_latest(
     req: api::ProtocolServiceGetLatestRequest,
     meta: Metadata,
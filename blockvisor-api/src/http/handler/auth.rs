@@ -22,6 +22,9 @@ where
         .route("/password", routing::put(update_password))
         .route("/ui_password", routing::put(update_ui_password))
         .route("/permissions", routing::get(list_permissions))
+        .route("/sso/authorize", routing::post(sso_authorize))
+        .route("/sso/callback", routing::post(sso_callback))
+        .route("/sso/link", routing::post(sso_link))
         .with_state(context)
 }
 
@@ -87,3 +90,30 @@ async fn list_permissions(
     ctx.write(|write| grpc::auth::list_permissions(req, headers.into(), write).scope_boxed())
         .await
 }
+
+async fn sso_authorize(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Json(req): Json<api::AuthServiceSsoAuthorizeRequest>,
+) -> Result<Json<api::AuthServiceSsoAuthorizeResponse>, super::Error> {
+    ctx.write(|write| grpc::auth::sso_authorize(req, headers.into(), write).scope_boxed())
+        .await
+}
+
+async fn sso_callback(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Json(req): Json<api::AuthServiceSsoCallbackRequest>,
+) -> Result<Json<api::AuthServiceSsoCallbackResponse>, super::Error> {
+    ctx.write(|write| grpc::auth::sso_callback(req, headers.into(), write).scope_boxed())
+        .await
+}
+
+async fn sso_link(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Json(req): Json<api::AuthServiceSsoLinkRequest>,
+) -> Result<Json<api::AuthServiceSsoLinkResponse>, super::Error> {
+    ctx.write(|write| grpc::auth::sso_link(req, headers.into(), write).scope_boxed())
+        .await
+}
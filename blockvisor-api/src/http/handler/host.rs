@@ -107,6 +107,13 @@ struct HostServiceUpdateHostRequest {
     schedule_type: Option<i32>,
     update_tags: Option<common::UpdateTags>,
     cost: Option<common::BillingAmount>,
+    labels: Option<common::Labels>,
+    taints: Option<common::Labels>,
+    cpu_overcommit_percent: Option<u32>,
+    memory_overcommit_percent: Option<u32>,
+    disk_overcommit_percent: Option<u32>,
+    ipv6_gateway: Option<String>,
+    zone_id: Option<String>,
 }
 
 async fn update_host(
@@ -129,6 +136,13 @@ async fn update_host(
         schedule_type: req.schedule_type,
         update_tags: req.update_tags,
         cost: req.cost,
+        labels: req.labels,
+        taints: req.taints,
+        cpu_overcommit_percent: req.cpu_overcommit_percent,
+        memory_overcommit_percent: req.memory_overcommit_percent,
+        disk_overcommit_percent: req.disk_overcommit_percent,
+        ipv6_gateway: req.ipv6_gateway,
+        zone_id: req.zone_id,
     };
     ctx.write(|write| grpc::host::update_host(req, headers.into(), write).scope_boxed())
         .await
@@ -139,6 +153,11 @@ async fn update_host(
 struct HostServiceUpdateRegionRequest {
     display_name: Option<String>,
     sku_code: Option<String>,
+    cpu_overcommit_percent: Option<u32>,
+    memory_overcommit_percent: Option<u32>,
+    disk_overcommit_percent: Option<u32>,
+    usage_scheduling: Option<bool>,
+    usage_headroom_percent: Option<u32>,
 }
 
 async fn update_region(
@@ -151,6 +170,11 @@ async fn update_region(
         region_id,
         display_name: req.display_name,
         sku_code: req.sku_code,
+        cpu_overcommit_percent: req.cpu_overcommit_percent,
+        memory_overcommit_percent: req.memory_overcommit_percent,
+        disk_overcommit_percent: req.disk_overcommit_percent,
+        usage_scheduling: req.usage_scheduling,
+        usage_headroom_percent: req.usage_headroom_percent,
     };
     ctx.write(|write| grpc::host::update_region(req, headers.into(), write).scope_boxed())
         .await
@@ -18,6 +18,7 @@ pub mod mqtt;
 pub mod node;
 pub mod org;
 pub mod protocol;
+pub mod store;
 pub mod stripe;
 pub mod user;
 
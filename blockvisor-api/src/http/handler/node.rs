@@ -24,6 +24,7 @@ where
         .route("/status", routing::post(report_status))
         .route("/config", routing::put(update_config))
         .route("/image", routing::put(upgrade_image))
+        .route("/resize", routing::put(resize))
         .route("/{id}/start", routing::put(start))
         .route("/{id}/stop", routing::put(stop))
         .route("/{id}/restart", routing::put(restart))
@@ -107,6 +108,15 @@ async fn upgrade_image(
         .await
 }
 
+async fn resize(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Json(req): Json<api::NodeServiceResizeRequest>,
+) -> Result<Json<api::NodeServiceResizeResponse>, Error> {
+    ctx.write(|write| grpc::node::resize(req, headers.into(), write).scope_boxed())
+        .await
+}
+
 async fn start(
     State(ctx): State<Arc<Context>>,
     headers: HeaderMap,
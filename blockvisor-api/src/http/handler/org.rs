@@ -2,7 +2,8 @@ use std::sync::Arc;
 
 use axum::Json;
 use axum::extract::{Path, Query, State};
-use axum::http::header::HeaderMap;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, HeaderMap};
+use axum::response::IntoResponse;
 use axum::routing::{self, Router};
 use diesel_async::scoped_futures::ScopedFutureExt;
 
@@ -23,6 +24,8 @@ where
         .route("/{id}", routing::put(update))
         .route("/{id}", routing::delete(delete))
         .route("/{id}/member", routing::delete(remove_member))
+        .route("/{id}/member/role", routing::put(update_member_role))
+        .route("/{id}/owner", routing::put(transfer_ownership))
         .route("/{id}/provision-token", routing::get(get_provision_token))
         .route(
             "/{id}/provision-token",
@@ -35,6 +38,25 @@ where
         .route("/{id}/address", routing::post(set_address))
         .route("/{id}/address", routing::delete(delete_address))
         .route("/{id}/invoices", routing::get(get_invoices))
+        .route("/{id}/cost-report", routing::get(cost_report))
+        .route("/{id}/cost-report/csv", routing::get(cost_report_csv))
+        .route("/{id}/usage", routing::get(get_usage))
+        .route(
+            "/{id}/identity-provider",
+            routing::get(get_identity_provider),
+        )
+        .route(
+            "/{id}/identity-provider",
+            routing::put(set_identity_provider),
+        )
+        .route(
+            "/{id}/identity-provider",
+            routing::delete(delete_identity_provider),
+        )
+        .route(
+            "/{id}/identity-provider/domains/{domain}/verify",
+            routing::post(verify_sso_domain),
+        )
         .with_state(context)
 }
 
@@ -70,6 +92,7 @@ async fn list(
 #[serde(deny_unknown_fields)]
 struct OrgServiceUpdateRequest {
     name: Option<String>,
+    failover_enabled: Option<bool>,
 }
 
 async fn update(
@@ -81,6 +104,7 @@ async fn update(
     let req = api::OrgServiceUpdateRequest {
         org_id,
         name: req.name,
+        failover_enabled: req.failover_enabled,
     };
     ctx.write(|write| grpc::org::update(req, headers.into(), write).scope_boxed())
         .await
@@ -116,6 +140,48 @@ async fn remove_member(
         .await
 }
 
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrgServiceUpdateMemberRoleRequest {
+    user_id: String,
+    role: String,
+}
+
+async fn update_member_role(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Json(req): Json<OrgServiceUpdateMemberRoleRequest>,
+) -> Result<Json<api::OrgServiceUpdateMemberRoleResponse>, Error> {
+    let req = api::OrgServiceUpdateMemberRoleRequest {
+        org_id,
+        user_id: req.user_id,
+        role: req.role,
+    };
+    ctx.write(|write| grpc::org::update_member_role(req, headers.into(), write).scope_boxed())
+        .await
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrgServiceTransferOwnershipRequest {
+    user_id: String,
+}
+
+async fn transfer_ownership(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Json(req): Json<OrgServiceTransferOwnershipRequest>,
+) -> Result<Json<api::OrgServiceTransferOwnershipResponse>, Error> {
+    let req = api::OrgServiceTransferOwnershipRequest {
+        org_id,
+        user_id: req.user_id,
+    };
+    ctx.write(|write| grpc::org::transfer_ownership(req, headers.into(), write).scope_boxed())
+        .await
+}
+
 #[derive(serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct OrgServiceGetProvisionTokenRequest {
@@ -245,3 +311,135 @@ async fn get_invoices(
     ctx.read(|read| grpc::org::get_invoices(req, headers.into(), read).scope_boxed())
         .await
 }
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrgServiceCostReportRequest {
+    start: prost_wkt_types::Timestamp,
+    end: Option<prost_wkt_types::Timestamp>,
+    group_by: i32,
+}
+
+impl OrgServiceCostReportRequest {
+    fn into_api(self, org_id: String) -> api::OrgServiceCostReportRequest {
+        api::OrgServiceCostReportRequest {
+            org_id,
+            start: Some(self.start),
+            end: self.end,
+            group_by: self.group_by,
+        }
+    }
+}
+
+async fn cost_report(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Query(req): Query<OrgServiceCostReportRequest>,
+) -> Result<Json<api::OrgServiceCostReportResponse>, Error> {
+    let req = req.into_api(org_id);
+    ctx.read(|read| grpc::org::cost_report(req, headers.into(), read).scope_boxed())
+        .await
+}
+
+async fn cost_report_csv(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Query(req): Query<OrgServiceCostReportRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let req = req.into_api(org_id);
+    let Json(report): Json<api::OrgServiceCostReportResponse> = ctx
+        .read(|read| grpc::org::cost_report(req, headers.into(), read).scope_boxed())
+        .await?;
+
+    let filename = format!("attachment; filename=\"cost-report-{}.csv\"", report.org_id);
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv".to_string()),
+            (CONTENT_DISPOSITION, filename),
+        ],
+        report.to_csv(),
+    ))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrgServiceGetUsageRequest {
+    start: prost_wkt_types::Timestamp,
+    end: Option<prost_wkt_types::Timestamp>,
+}
+
+async fn get_usage(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Query(req): Query<OrgServiceGetUsageRequest>,
+) -> Result<Json<api::OrgServiceGetUsageResponse>, Error> {
+    let req = api::OrgServiceGetUsageRequest {
+        org_id,
+        start: Some(req.start),
+        end: req.end,
+    };
+    ctx.read(|read| grpc::org::get_usage(req, headers.into(), read).scope_boxed())
+        .await
+}
+
+async fn get_identity_provider(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+) -> Result<Json<api::OrgServiceGetIdentityProviderResponse>, Error> {
+    let req = api::OrgServiceGetIdentityProviderRequest { org_id };
+    ctx.read(|read| grpc::org::get_identity_provider(req, headers.into(), read).scope_boxed())
+        .await
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrgServiceSetIdentityProviderRequest {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    allowed_domains: Vec<String>,
+    #[serde(default)]
+    group_roles: Vec<api::SsoGroupRole>,
+}
+
+async fn set_identity_provider(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+    Json(req): Json<OrgServiceSetIdentityProviderRequest>,
+) -> Result<Json<api::OrgServiceSetIdentityProviderResponse>, Error> {
+    let req = api::OrgServiceSetIdentityProviderRequest {
+        org_id,
+        issuer: req.issuer,
+        client_id: req.client_id,
+        client_secret: req.client_secret,
+        allowed_domains: req.allowed_domains,
+        group_roles: req.group_roles,
+    };
+    ctx.write(|write| grpc::org::set_identity_provider(req, headers.into(), write).scope_boxed())
+        .await
+}
+
+async fn verify_sso_domain(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id, domain)): Path<(String, String)>,
+) -> Result<Json<api::OrgServiceVerifySsoDomainResponse>, Error> {
+    let req = api::OrgServiceVerifySsoDomainRequest { org_id, domain };
+    ctx.write(|write| grpc::org::verify_sso_domain(req, headers.into(), write).scope_boxed())
+        .await
+}
+
+async fn delete_identity_provider(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Path((org_id,)): Path<(String,)>,
+) -> Result<Json<api::OrgServiceDeleteIdentityProviderResponse>, Error> {
+    let req = api::OrgServiceDeleteIdentityProviderRequest { org_id };
+    ctx.write(|write| grpc::org::delete_identity_provider(req, headers.into(), write).scope_boxed())
+        .await
+}
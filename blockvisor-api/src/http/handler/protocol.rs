@@ -29,6 +29,8 @@ where
         .route("/latest", routing::get(get_latest))
         .route("/pricing", routing::get(get_pricing))
         .route("/stats", routing::get(get_stats))
+        .route("/catalog", routing::get(export_catalog))
+        .route("/catalog", routing::post(import_catalog))
         .with_state(context)
 }
 
@@ -50,6 +52,15 @@ async fn add_version(
         .await
 }
 
+async fn export_catalog(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Query(req): Query<api::ProtocolServiceExportCatalogRequest>,
+) -> Result<Json<api::ProtocolServiceExportCatalogResponse>, Error> {
+    ctx.read(|read| grpc::protocol::export_catalog(req, headers.into(), read).scope_boxed())
+        .await
+}
+
 async fn get_latest(
     State(ctx): State<Arc<Context>>,
     headers: HeaderMap,
@@ -113,6 +124,15 @@ async fn get_stats(
         .await
 }
 
+async fn import_catalog(
+    State(ctx): State<Arc<Context>>,
+    headers: HeaderMap,
+    Json(req): Json<api::ProtocolServiceImportCatalogRequest>,
+) -> Result<Json<api::ProtocolServiceImportCatalogResponse>, Error> {
+    ctx.write(|write| grpc::protocol::import_catalog(req, headers.into(), write).scope_boxed())
+        .await
+}
+
 async fn list_protocols(
     State(ctx): State<Arc<Context>>,
     headers: HeaderMap,
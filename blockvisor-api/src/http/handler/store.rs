@@ -0,0 +1,95 @@
+//! Download and upload routes for the local directory store backend.
+//!
+//! These serve the signed URLs created by `store::LocalDir`, so they only
+//! respond when the store is configured with the local backend.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{Router, get};
+use displaydoc::Display;
+use thiserror::Error;
+use tracing::error;
+
+use crate::config::Context;
+use crate::grpc::Status;
+use crate::http::response;
+use crate::store::local::{self, Access, Signature};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Local store error: {0}
+    Local(#[from] local::Error),
+    /// The store is not using the local backend.
+    NotLocal,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        use local::Error::{Expired, InvalidPath, InvalidSignature, NotFound};
+        match err {
+            Local(Expired | InvalidSignature) => Status::forbidden("Access denied."),
+            Local(InvalidPath(_)) => Status::invalid_argument("key"),
+            Local(NotFound(_)) | NotLocal => Status::not_found("Not found."),
+            Local(_) => {
+                error!("{err}");
+                Status::internal("Internal error.")
+            }
+        }
+    }
+}
+
+pub fn router<S>(context: Arc<Context>) -> Router<S>
+where
+    S: Clone + Send + Sync,
+{
+    Router::new()
+        .route("/{bucket}/{*key}", get(download).put(upload))
+        .with_state(context)
+}
+
+async fn download(
+    State(ctx): State<Arc<Context>>,
+    Path((bucket, key)): Path<(String, String)>,
+    Query(signature): Query<Signature>,
+) -> Result<Response, super::Error> {
+    let local = ctx.store.backend.as_local().ok_or(Error::NotLocal)?;
+    let path = local
+        .verify(Access::Download, &bucket, &key, &signature)
+        .map_err(Error::Local)?;
+    let (size, contents) = local.read_stream(&path).await.map_err(Error::Local)?;
+
+    let headers = [
+        (CONTENT_LENGTH, size.to_string()),
+        (CONTENT_TYPE, "application/octet-stream".to_string()),
+    ];
+    Ok((headers, Body::from_stream(contents)).into_response())
+}
+
+async fn upload(
+    State(ctx): State<Arc<Context>>,
+    Path((bucket, key)): Path<(String, String)>,
+    Query(signature): Query<Signature>,
+    body: Body,
+) -> Result<Response, super::Error> {
+    let local = ctx.store.backend.as_local().ok_or(Error::NotLocal)?;
+    let path = local
+        .verify(Access::Upload, &bucket, &key, &signature)
+        .map_err(Error::Local)?;
+    local
+        .write_stream(&path, body.into_data_stream())
+        .await
+        .map_err(Error::Local)?;
+
+    Ok(response::ok())
+}
+
+impl From<Error> for super::Error {
+    fn from(err: Error) -> Self {
+        Status::from(err).into()
+    }
+}
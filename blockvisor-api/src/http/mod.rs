@@ -13,7 +13,7 @@ use crate::config::Context;
 
 use self::handler::{
     api_key, archive, auth, bundle, discovery, health, host, invitation, metrics, mqtt, node, org,
-    protocol, stripe, user,
+    protocol, store, stripe, user,
 };
 
 pub fn router(context: &Arc<Context>) -> Router {
@@ -41,6 +41,7 @@ pub fn router(context: &Arc<Context>) -> Router {
         .nest("/v1/protocol", protocol::router(context.clone()))
         .nest("/v1/user", user::router(context.clone()))
         // These are utility endpoints that are not accessible through the gRPC API
+        .nest("/v1/store", store::router(context.clone()))
         .nest("/v1/stripe", stripe::router(context.clone()))
         .nest("/mqtt", mqtt::router(context.clone()))
         .merge(health::router(context.clone()))
@@ -0,0 +1,80 @@
+//! Helpers for the background tasks that run on every replica.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use futures_util::future::BoxFuture;
+use tracing::{error, warn};
+
+use crate::config::Context;
+use crate::database::{self, AdvisoryLock, Conn, Database};
+use crate::mqtt::Message;
+
+/// Run `f` forever at `interval` while holding the advisory `lock`.
+///
+/// A run is skipped while another replica holds the lock. The messages
+/// returned by `f` are sent once the lock is released, and errors are logged.
+pub async fn run_exclusive<'a, F, T, M, E>(
+    ctx: &'a Context,
+    lock: AdvisoryLock,
+    interval: Duration,
+    mut f: F,
+) where
+    F: for<'c> FnMut(&'c mut Conn<'a>) -> BoxFuture<'c, Result<T, E>> + Send,
+    T: IntoIterator<Item = M> + Send,
+    M: Into<Message> + Send,
+    E: std::fmt::Display + From<diesel::result::Error> + Send,
+{
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match ctx.conn().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("Failed to run {lock:?}: {err}");
+                continue;
+            }
+        };
+
+        let result = database::exclusive(lock, &mut conn, |conn| f(conn).scope_boxed()).await;
+        let messages = match result {
+            Ok(messages) => messages,
+            Err(err) => {
+                error!("Failed to run {lock:?}: {err}");
+                continue;
+            }
+        };
+        drop(conn);
+
+        for message in messages.into_iter().flatten() {
+            if let Err(err) = ctx.notifier.send(message).await {
+                warn!("Failed to send {lock:?} message: {err}");
+            }
+        }
+    }
+}
+
+/// Anything last seen before the returned time is older than `after`.
+pub fn cutoff(now: DateTime<Utc>, after: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(after)
+        .ok()
+        .and_then(|after| now.checked_sub_signed(after))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cutoff_saturates_for_large_durations() {
+        let now = Utc::now();
+        assert_eq!(
+            cutoff(now, Duration::from_secs(180)),
+            now - chrono::Duration::seconds(180)
+        );
+        assert_eq!(cutoff(now, Duration::MAX), DateTime::<Utc>::MIN_UTC);
+    }
+}
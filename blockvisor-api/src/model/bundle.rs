@@ -0,0 +1,368 @@
+//! Release channels for blockvisord bundles.
+//!
+//! Each bundle version is released on a channel and may later be promoted to
+//! a more stable one. A host follows the channel pinned to it, or else the one
+//! pinned to its region, or else `Stable`. Bundles in the store that predate
+//! channels have no row and are treated as `Stable`.
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::UniqueViolation;
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel::upsert::excluded;
+use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::HostId;
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+use crate::model::Host;
+use crate::model::region::RegionId;
+use crate::model::schema::{bundle_pins, bundles, sql_types};
+use crate::model::sql::Version;
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to find all bundles: {0}
+    All(diesel::result::Error),
+    /// Failed to find bundle version `{0}`: {1}
+    ByVersion(Version, diesel::result::Error),
+    /// Failed to find bundle channel pins for host `{0}`: {1}
+    ChannelFor(HostId, diesel::result::Error),
+    /// Failed to reserve bundle version `{0}`: {1}
+    Create(Version, diesel::result::Error),
+    /// Bundle version `{0}` is already on channel `{1:?}`.
+    NotPromoted(Version, BundleChannel),
+    /// Failed to pin bundle channel: {0}
+    Pin(diesel::result::Error),
+    /// Failed to promote bundle version `{0}`: {1}
+    Promote(Version, diesel::result::Error),
+    /// Failed to unpin bundle channel: {0}
+    Unpin(diesel::result::Error),
+    /// Unknown BundleChannel.
+    UnknownChannel,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            ByVersion(_, NotFound) | Promote(_, NotFound) => Status::not_found("Bundle not found."),
+            Create(_, DatabaseError(UniqueViolation, _)) => {
+                Status::already_exists("Bundle version already exists.")
+            }
+            NotPromoted(_, channel) => Status::failed_precondition(format!(
+                "Bundle is already on the {} channel.",
+                channel.description()
+            )),
+            UnknownChannel => Status::invalid_argument("channel"),
+            All(_) | ByVersion(..) | ChannelFor(..) | Create(..) | Pin(_) | Promote(..)
+            | Unpin(_) => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
+pub struct BundleId(Uuid);
+
+/// A release channel, ordered from least to most stable.
+///
+/// A host on a channel may run bundles from that channel or any more stable
+/// one, so `Canary` hosts also receive `Beta` and `Stable` bundles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumBundleChannel"]
+pub enum BundleChannel {
+    Canary,
+    Beta,
+    #[default]
+    Stable,
+}
+
+impl BundleChannel {
+    pub const fn description(self) -> &'static str {
+        match self {
+            BundleChannel::Canary => "canary",
+            BundleChannel::Beta => "beta",
+            BundleChannel::Stable => "stable",
+        }
+    }
+}
+
+impl From<BundleChannel> for api::BundleChannel {
+    fn from(channel: BundleChannel) -> Self {
+        match channel {
+            BundleChannel::Canary => api::BundleChannel::Canary,
+            BundleChannel::Beta => api::BundleChannel::Beta,
+            BundleChannel::Stable => api::BundleChannel::Stable,
+        }
+    }
+}
+
+impl TryFrom<api::BundleChannel> for BundleChannel {
+    type Error = Error;
+
+    fn try_from(channel: api::BundleChannel) -> Result<Self, Self::Error> {
+        match channel {
+            api::BundleChannel::Unspecified => Err(Error::UnknownChannel),
+            api::BundleChannel::Canary => Ok(BundleChannel::Canary),
+            api::BundleChannel::Beta => Ok(BundleChannel::Beta),
+            api::BundleChannel::Stable => Ok(BundleChannel::Stable),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = bundles)]
+pub struct Bundle {
+    pub id: BundleId,
+    pub version: Version,
+    pub channel: BundleChannel,
+    pub created_at: DateTime<Utc>,
+    pub promoted_at: Option<DateTime<Utc>>,
+}
+
+impl Bundle {
+    pub async fn all(conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        bundles::table.get_results(conn).await.map_err(Error::All)
+    }
+
+    pub async fn by_version(version: &Version, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        bundles::table
+            .filter(bundles::version.eq(version))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ByVersion(version.clone(), err))
+    }
+
+    /// Move a bundle to a more stable channel.
+    pub async fn promote(
+        version: &Version,
+        channel: BundleChannel,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        let bundle = Self::by_version(version, conn).await?;
+        if channel <= bundle.channel {
+            return Err(Error::NotPromoted(version.clone(), bundle.channel));
+        }
+
+        diesel::update(bundles::table.find(bundle.id))
+            .set((
+                bundles::channel.eq(channel),
+                bundles::promoted_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Promote(version.clone(), err))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = bundles)]
+pub struct NewBundle {
+    pub version: Version,
+    pub channel: BundleChannel,
+}
+
+impl NewBundle {
+    /// Reserve a new bundle version, failing if it already exists.
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<Bundle, Error> {
+        let version = self.version.clone();
+        diesel::insert_into(bundles::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Create(version, err))
+    }
+}
+
+/// The channel a host or region is pinned to.
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = bundle_pins)]
+pub struct BundlePin {
+    pub id: Uuid,
+    pub host_id: Option<HostId>,
+    pub region_id: Option<RegionId>,
+    pub channel: BundleChannel,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BundlePin {
+    /// The channel that a host follows.
+    pub async fn channel_for(host: &Host, conn: &mut Conn<'_>) -> Result<BundleChannel, Error> {
+        let pins: Vec<Self> = bundle_pins::table
+            .filter(
+                bundle_pins::host_id
+                    .eq(host.id)
+                    .or(bundle_pins::region_id.eq(host.region_id)),
+            )
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ChannelFor(host.id, err))?;
+
+        let host_pin = pins.iter().find(|pin| pin.host_id.is_some());
+        let region_pin = pins.iter().find(|pin| pin.region_id.is_some());
+
+        Ok(host_pin
+            .or(region_pin)
+            .map(|pin| pin.channel)
+            .unwrap_or_default())
+    }
+
+    /// Pin a host to a channel, or unpin it if `channel` is None.
+    pub async fn pin_host(
+        host_id: HostId,
+        channel: Option<BundleChannel>,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        let Some(channel) = channel else {
+            return diesel::delete(bundle_pins::table.filter(bundle_pins::host_id.eq(host_id)))
+                .execute(conn)
+                .await
+                .map(|_| ())
+                .map_err(Error::Unpin);
+        };
+
+        diesel::insert_into(bundle_pins::table)
+            .values((
+                bundle_pins::host_id.eq(host_id),
+                bundle_pins::channel.eq(channel),
+            ))
+            .on_conflict(bundle_pins::host_id)
+            .do_update()
+            .set(bundle_pins::channel.eq(excluded(bundle_pins::channel)))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(Error::Pin)
+    }
+
+    /// Pin a region to a channel, or unpin it if `channel` is None.
+    pub async fn pin_region(
+        region_id: RegionId,
+        channel: Option<BundleChannel>,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        let Some(channel) = channel else {
+            return diesel::delete(bundle_pins::table.filter(bundle_pins::region_id.eq(region_id)))
+                .execute(conn)
+                .await
+                .map(|_| ())
+                .map_err(Error::Unpin);
+        };
+
+        diesel::insert_into(bundle_pins::table)
+            .values((
+                bundle_pins::region_id.eq(region_id),
+                bundle_pins::channel.eq(channel),
+            ))
+            .on_conflict(bundle_pins::region_id)
+            .do_update()
+            .set(bundle_pins::channel.eq(excluded(bundle_pins::channel)))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(Error::Pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Context;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn host_pins_take_precedence_over_region_pins() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let mut conn = db.conn().await;
+        let host1 = &db.seed.host1;
+        let host2 = &db.seed.host2;
+        assert_eq!(host1.region_id, host2.region_id);
+
+        // an unpinned host follows the stable channel
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Stable);
+
+        // every host in a pinned region follows its channel
+        BundlePin::pin_region(host1.region_id, Some(BundleChannel::Beta), &mut conn)
+            .await
+            .unwrap();
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Beta);
+
+        // unless the host has its own pin
+        BundlePin::pin_host(host1.id, Some(BundleChannel::Canary), &mut conn)
+            .await
+            .unwrap();
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Canary);
+        let channel = BundlePin::channel_for(host2, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Beta);
+
+        // a host pin applies even when it is more stable than the region
+        BundlePin::pin_host(host1.id, Some(BundleChannel::Stable), &mut conn)
+            .await
+            .unwrap();
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Stable);
+
+        // unpinning the host falls back to its region
+        BundlePin::pin_host(host1.id, None, &mut conn)
+            .await
+            .unwrap();
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Beta);
+
+        BundlePin::pin_region(host1.region_id, None, &mut conn)
+            .await
+            .unwrap();
+        let channel = BundlePin::channel_for(host1, &mut conn).await.unwrap();
+        assert_eq!(channel, BundleChannel::Stable);
+    }
+
+    #[tokio::test]
+    async fn promote_moves_a_bundle_to_a_more_stable_channel() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let mut conn = db.conn().await;
+        let version: Version = "0.1.0".parse().unwrap();
+
+        let new_bundle = NewBundle {
+            version: version.clone(),
+            channel: BundleChannel::Canary,
+        };
+        let bundle = new_bundle.create(&mut conn).await.unwrap();
+        assert_eq!(bundle.channel, BundleChannel::Canary);
+        assert!(bundle.promoted_at.is_none());
+
+        let bundle = Bundle::promote(&version, BundleChannel::Beta, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(bundle.channel, BundleChannel::Beta);
+        assert!(bundle.promoted_at.is_some());
+        let bundle = Bundle::by_version(&version, &mut conn).await.unwrap();
+        assert_eq!(bundle.channel, BundleChannel::Beta);
+
+        // a bundle can't be moved back to a less stable channel
+        for channel in [BundleChannel::Canary, BundleChannel::Beta] {
+            let result = Bundle::promote(&version, channel, &mut conn).await;
+            assert!(matches!(
+                result,
+                Err(Error::NotPromoted(_, BundleChannel::Beta))
+            ));
+        }
+
+        let bundle = Bundle::promote(&version, BundleChannel::Stable, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(bundle.channel, BundleChannel::Stable);
+
+        let missing: Version = "0.2.0".parse().unwrap();
+        let result = Bundle::promote(&missing, BundleChannel::Stable, &mut conn).await;
+        assert!(matches!(result, Err(Error::ByVersion(_, NotFound))));
+    }
+}
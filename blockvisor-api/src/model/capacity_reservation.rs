@@ -0,0 +1,480 @@
+//! Capacity reserved for an org on the public hosts of a region.
+//!
+//! A reservation holds back CPU, memory and disk from other orgs, either as a
+//! total amount of resources or as a number of node slots of a fixed size for
+//! some protocol. The scheduler only places the nodes of other orgs on public
+//! hosts in the region while their free capacity still fits the node after the
+//! remaining reservations.
+//!
+//! Nodes that the org launches on public hosts in the region consume its
+//! reservations, using a slot reservation for the node protocol first when the
+//! node fits into its slot size. The consumed reservation is recorded on the
+//! node and handed back once the node is deleted, which includes the original
+//! of a moved node.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From, FromStr};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::ForeignKeyViolation;
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel_async::RunQueryDsl;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::{NodeId, OrgId};
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+use crate::util::NanosUtc;
+
+use super::host_capacity::Resources;
+use super::schema::{capacity_reservations, nodes};
+use super::{Node, ProtocolId, RegionId};
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to get active capacity reservations: {0}
+    Active(diesel::result::Error),
+    /// Failed to get capacity reservation `{0}`: {1}
+    ById(CapacityReservationId, diesel::result::Error),
+    /// Failed to consume capacity reservation `{0}`: {1}
+    Consume(CapacityReservationId, diesel::result::Error),
+    /// Failed to create capacity reservation: {0}
+    Create(diesel::result::Error),
+    /// Failed to expire capacity reservation `{0}`: {1}
+    Expire(CapacityReservationId, diesel::result::Error),
+    /// Failed to filter capacity reservations: {0}
+    Filter(diesel::result::Error),
+    /// Failed to lock capacity reservations: {0}
+    Lock(diesel::result::Error),
+    /// Node slots can only be set for a slot reservation.
+    NodeSlots,
+    /// Failed to release capacity reservation `{0}`: {1}
+    Release(CapacityReservationId, diesel::result::Error),
+    /// Failed to set the capacity reservation of node `{0}`: {1}
+    SetNode(NodeId, diesel::result::Error),
+    /// Failed to update capacity reservation `{0}`: {1}
+    Update(CapacityReservationId, diesel::result::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            Create(DatabaseError(ForeignKeyViolation, _)) => {
+                Status::not_found("Org, region or protocol not found.")
+            }
+            ById(_, NotFound) | Expire(_, NotFound) | Update(_, NotFound) => {
+                Status::not_found("Capacity reservation not found.")
+            }
+            NodeSlots => Status::failed_precondition("Not a slot reservation."),
+            _ => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From, FromStr)]
+pub struct CapacityReservationId(Uuid);
+
+#[derive(Clone, Debug, Queryable)]
+pub struct CapacityReservation {
+    pub id: CapacityReservationId,
+    pub org_id: OrgId,
+    pub region_id: RegionId,
+    /// The protocol of a slot reservation.
+    pub protocol_id: Option<ProtocolId>,
+    /// The number of slots of a slot reservation.
+    pub node_slots: Option<i32>,
+    /// The total resources reserved, or the size of each slot.
+    pub cpu_cores: i64,
+    pub memory_bytes: i64,
+    pub disk_bytes: i64,
+    pub used_node_slots: i32,
+    pub used_cpu_cores: i64,
+    pub used_memory_bytes: i64,
+    pub used_disk_bytes: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl CapacityReservation {
+    pub async fn by_id(id: CapacityReservationId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        capacity_reservations::table
+            .find(id)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ById(id, err))
+    }
+
+    /// The reservations that have not expired by `now`.
+    async fn active(now: DateTime<Utc>, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        capacity_reservations::table
+            .filter(
+                capacity_reservations::expires_at
+                    .is_null()
+                    .or(capacity_reservations::expires_at.gt(now)),
+            )
+            .order_by(capacity_reservations::created_at)
+            .get_results(conn)
+            .await
+            .map_err(Error::Active)
+    }
+
+    /// The remaining capacity per region that is reserved for orgs other than
+    /// `org_id`.
+    pub async fn held_for_others(
+        org_id: Option<OrgId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<HashMap<RegionId, Resources>, Error> {
+        let mut held: HashMap<RegionId, Resources> = HashMap::new();
+        for reservation in Self::active(Utc::now(), conn).await? {
+            if Some(reservation.org_id) == org_id {
+                continue;
+            }
+
+            let remaining = reservation.remaining();
+            let total = held.entry(reservation.region_id).or_default();
+            total.cpu_cores = total.cpu_cores.saturating_add(remaining.cpu_cores);
+            total.memory_bytes = total.memory_bytes.saturating_add(remaining.memory_bytes);
+            total.disk_bytes = total.disk_bytes.saturating_add(remaining.disk_bytes);
+        }
+        Ok(held)
+    }
+
+    /// Consume a reservation of the node org for a node launched on a public
+    /// host in `region_id`, and record it on the node.
+    ///
+    /// A slot reservation is only used when the node fits into its slot size.
+    /// The reservations of the org are locked so that concurrent launches
+    /// can't both take the last of one.
+    ///
+    /// Returns the consumed reservation, if the org had one with capacity left.
+    pub async fn consume(
+        node: &Node,
+        region_id: RegionId,
+        conn: &mut Conn<'_>,
+    ) -> Result<Option<Self>, Error> {
+        let reservations: Vec<Self> = capacity_reservations::table
+            .filter(capacity_reservations::org_id.eq(node.org_id))
+            .filter(capacity_reservations::region_id.eq(region_id))
+            .filter(
+                capacity_reservations::expires_at
+                    .is_null()
+                    .or(capacity_reservations::expires_at.gt(Utc::now())),
+            )
+            .order_by(capacity_reservations::created_at)
+            .for_update()
+            .get_results(conn)
+            .await
+            .map_err(Error::Lock)?
+            .into_iter()
+            .filter(|reservation| reservation.remaining() != Resources::default())
+            .collect();
+
+        let slot = reservations.iter().find(|reservation| {
+            reservation.protocol_id == Some(node.protocol_id) && reservation.fits_slot(node)
+        });
+        let resources = reservations
+            .iter()
+            .find(|reservation| reservation.node_slots.is_none());
+
+        let row = capacity_reservations::table;
+        let reservation: Option<Self> = if let Some(slot) = slot {
+            diesel::update(row.find(slot.id))
+                .set(
+                    capacity_reservations::used_node_slots
+                        .eq(capacity_reservations::used_node_slots + 1),
+                )
+                .get_result(conn)
+                .await
+                .map(Some)
+                .map_err(|err| Error::Consume(slot.id, err))?
+        } else if let Some(resources) = resources {
+            diesel::update(row.find(resources.id))
+                .set((
+                    capacity_reservations::used_cpu_cores
+                        .eq(capacity_reservations::used_cpu_cores + node.cpu_cores),
+                    capacity_reservations::used_memory_bytes
+                        .eq(capacity_reservations::used_memory_bytes + node.memory_bytes),
+                    capacity_reservations::used_disk_bytes
+                        .eq(capacity_reservations::used_disk_bytes + node.disk_bytes),
+                ))
+                .get_result(conn)
+                .await
+                .map(Some)
+                .map_err(|err| Error::Consume(resources.id, err))?
+        } else {
+            None
+        };
+
+        let reservation_id = reservation.as_ref().map(|reservation| reservation.id);
+        if node.capacity_reservation_id != reservation_id {
+            diesel::update(nodes::table.find(node.id))
+                .set(nodes::capacity_reservation_id.eq(reservation_id))
+                .execute(conn)
+                .await
+                .map_err(|err| Error::SetNode(node.id, err))?;
+        }
+
+        Ok(reservation)
+    }
+
+    /// Hand back the reservation consumed by a node that is deleted, moved or
+    /// resized.
+    ///
+    /// Returns the released reservation, if the node had consumed one.
+    pub async fn release(node: &Node, conn: &mut Conn<'_>) -> Result<Option<Self>, Error> {
+        let Some(id) = node.capacity_reservation_id else {
+            return Ok(None);
+        };
+
+        let row = capacity_reservations::table.find(id);
+        let Some(reservation) = row
+            .for_update()
+            .get_result::<Self>(conn)
+            .await
+            .optional()
+            .map_err(|err| Error::Release(id, err))?
+        else {
+            return Ok(None);
+        };
+
+        let release = |used: i64, size: i64| used.saturating_sub(size).max(0);
+        let released = if reservation.node_slots.is_some() {
+            let used_node_slots = reservation.used_node_slots.saturating_sub(1).max(0);
+            diesel::update(row)
+                .set(capacity_reservations::used_node_slots.eq(used_node_slots))
+                .get_result(conn)
+                .await
+        } else {
+            diesel::update(row)
+                .set((
+                    capacity_reservations::used_cpu_cores
+                        .eq(release(reservation.used_cpu_cores, node.cpu_cores)),
+                    capacity_reservations::used_memory_bytes
+                        .eq(release(reservation.used_memory_bytes, node.memory_bytes)),
+                    capacity_reservations::used_disk_bytes
+                        .eq(release(reservation.used_disk_bytes, node.disk_bytes)),
+                ))
+                .get_result(conn)
+                .await
+        };
+
+        released.map(Some).map_err(|err| Error::Release(id, err))
+    }
+
+    /// Expire a reservation now, unless it has already expired.
+    pub async fn expire(id: CapacityReservationId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let now = Utc::now();
+        let reservation = Self::by_id(id, conn).await?;
+        if reservation
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return Ok(reservation);
+        }
+
+        diesel::update(capacity_reservations::table.find(id))
+            .set((
+                capacity_reservations::expires_at.eq(now),
+                capacity_reservations::updated_at.eq(now),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Expire(id, err))
+    }
+
+    /// Whether `node` fits into the slot size of a slot reservation.
+    fn fits_slot(&self, node: &Node) -> bool {
+        node.cpu_cores <= self.cpu_cores
+            && node.memory_bytes <= self.memory_bytes
+            && node.disk_bytes <= self.disk_bytes
+    }
+
+    /// The reserved capacity that has not been consumed yet.
+    pub fn remaining(&self) -> Resources {
+        let remaining = |reserved: i64, used: i64| reserved.saturating_sub(used).max(0);
+
+        if let Some(slots) = self.node_slots {
+            let free_slots = remaining(i64::from(slots), i64::from(self.used_node_slots));
+            Resources {
+                cpu_cores: self.cpu_cores.saturating_mul(free_slots),
+                memory_bytes: self.memory_bytes.saturating_mul(free_slots),
+                disk_bytes: self.disk_bytes.saturating_mul(free_slots),
+            }
+        } else {
+            Resources {
+                cpu_cores: remaining(self.cpu_cores, self.used_cpu_cores),
+                memory_bytes: remaining(self.memory_bytes, self.used_memory_bytes),
+                disk_bytes: remaining(self.disk_bytes, self.used_disk_bytes),
+            }
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+#[derive(Debug)]
+pub struct CapacityReservationFilter {
+    pub org_ids: HashSet<OrgId>,
+    pub region_ids: HashSet<RegionId>,
+    pub include_expired: bool,
+}
+
+impl CapacityReservationFilter {
+    pub async fn query(self, conn: &mut Conn<'_>) -> Result<Vec<CapacityReservation>, Error> {
+        let mut query = capacity_reservations::table.into_boxed();
+
+        if !self.org_ids.is_empty() {
+            query = query.filter(capacity_reservations::org_id.eq_any(self.org_ids));
+        }
+        if !self.region_ids.is_empty() {
+            query = query.filter(capacity_reservations::region_id.eq_any(self.region_ids));
+        }
+        if !self.include_expired {
+            query = query.filter(
+                capacity_reservations::expires_at
+                    .is_null()
+                    .or(capacity_reservations::expires_at.gt(Utc::now())),
+            );
+        }
+
+        query
+            .order_by(capacity_reservations::created_at.desc())
+            .get_results(conn)
+            .await
+            .map_err(Error::Filter)
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = capacity_reservations)]
+pub struct NewCapacityReservation {
+    pub org_id: OrgId,
+    pub region_id: RegionId,
+    pub protocol_id: Option<ProtocolId>,
+    pub node_slots: Option<i32>,
+    pub cpu_cores: i64,
+    pub memory_bytes: i64,
+    pub disk_bytes: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl NewCapacityReservation {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<CapacityReservation, Error> {
+        diesel::insert_into(capacity_reservations::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(Error::Create)
+    }
+}
+
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = capacity_reservations)]
+pub struct UpdateCapacityReservation {
+    pub id: CapacityReservationId,
+    pub node_slots: Option<i32>,
+    pub cpu_cores: Option<i64>,
+    pub memory_bytes: Option<i64>,
+    pub disk_bytes: Option<i64>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl UpdateCapacityReservation {
+    pub async fn apply(self, conn: &mut Conn<'_>) -> Result<CapacityReservation, Error> {
+        let id = self.id;
+        if self.node_slots.is_some() {
+            let reservation = CapacityReservation::by_id(id, conn).await?;
+            if reservation.node_slots.is_none() {
+                return Err(Error::NodeSlots);
+            }
+        }
+
+        diesel::update(capacity_reservations::table.find(id))
+            .set((self, capacity_reservations::updated_at.eq(Utc::now())))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Update(id, err))
+    }
+}
+
+impl From<CapacityReservation> for api::CapacityReservation {
+    fn from(reservation: CapacityReservation) -> Self {
+        let remaining = reservation.remaining();
+        let active = reservation.is_active(Utc::now());
+
+        api::CapacityReservation {
+            reservation_id: reservation.id.to_string(),
+            org_id: reservation.org_id.to_string(),
+            region_id: reservation.region_id.to_string(),
+            protocol_id: reservation.protocol_id.map(|id| id.to_string()),
+            node_slots: reservation.node_slots.map(i32::unsigned_abs),
+            used_node_slots: reservation.used_node_slots.unsigned_abs(),
+            cpu_cores: reservation.cpu_cores.unsigned_abs(),
+            memory_bytes: reservation.memory_bytes.unsigned_abs(),
+            disk_bytes: reservation.disk_bytes.unsigned_abs(),
+            remaining_cpu_cores: remaining.cpu_cores.unsigned_abs(),
+            remaining_memory_bytes: remaining.memory_bytes.unsigned_abs(),
+            remaining_disk_bytes: remaining.disk_bytes.unsigned_abs(),
+            active,
+            expires_at: reservation.expires_at.map(|at| NanosUtc::from(at).into()),
+            created_at: Some(NanosUtc::from(reservation.created_at).into()),
+            updated_at: reservation.updated_at.map(|at| NanosUtc::from(at).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_capacity_of_reservations() {
+        let now = Utc::now();
+        let mut reservation = CapacityReservation {
+            id: Uuid::new_v4().into(),
+            org_id: Uuid::new_v4().into(),
+            region_id: Uuid::new_v4().into(),
+            protocol_id: None,
+            node_slots: None,
+            cpu_cores: 16,
+            memory_bytes: 64,
+            disk_bytes: 1000,
+            used_node_slots: 0,
+            used_cpu_cores: 4,
+            used_memory_bytes: 80,
+            used_disk_bytes: 0,
+            expires_at: None,
+            created_at: now,
+            updated_at: None,
+        };
+
+        // consumed resources never go negative
+        let remaining = reservation.remaining();
+        assert_eq!(remaining.cpu_cores, 12);
+        assert_eq!(remaining.memory_bytes, 0);
+        assert_eq!(remaining.disk_bytes, 1000);
+
+        // each unused slot holds the slot size
+        reservation.protocol_id = Some(Uuid::new_v4().into());
+        reservation.node_slots = Some(3);
+        reservation.used_node_slots = 1;
+        let remaining = reservation.remaining();
+        assert_eq!(remaining.cpu_cores, 32);
+        assert_eq!(remaining.memory_bytes, 128);
+
+        reservation.used_node_slots = 4;
+        assert_eq!(reservation.remaining(), Resources::default());
+
+        assert!(reservation.is_active(now));
+        reservation.expires_at = Some(now);
+        assert!(!reservation.is_active(now));
+    }
+}
@@ -76,6 +76,7 @@ pub enum CommandType {
     HostStop,
     HostRestart,
     HostPending,
+    HostUpgrade,
     NodeCreate,
     NodeStart,
     NodeStop,
@@ -88,7 +89,10 @@ pub enum CommandType {
 impl CommandType {
     const fn is_host(self) -> bool {
         use CommandType::*;
-        matches!(self, HostStart | HostStop | HostRestart | HostPending)
+        matches!(
+            self,
+            HostStart | HostStop | HostRestart | HostPending | HostUpgrade
+        )
     }
 
     const fn is_node(self) -> bool {
@@ -0,0 +1,462 @@
+//! Aggregation of node and host costs for org reporting.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use displaydoc::Display;
+use thiserror::Error;
+
+use crate::auth::resource::{HostId, OrgId};
+use crate::database::Conn;
+use crate::grpc::{Status, api, common};
+use crate::model::sql::{Amount, Currency, Tags};
+use crate::util::NanosUtc;
+
+use super::schema::{hosts, nodes, protocols, regions};
+
+/// The average length of a month in seconds (365.2425 days / 12).
+const SECONDS_PER_MONTH: i64 = 2_629_746;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to find host costs for org `{0}`: {1}
+    HostCosts(OrgId, diesel::result::Error),
+    /// Cost report start `{0}` is not before end `{1}`.
+    InvalidRange(DateTime<Utc>, DateTime<Utc>),
+    /// Failed to find node costs for org `{0}`: {1}
+    NodeCosts(OrgId, diesel::result::Error),
+    /// Unknown cost grouping.
+    UnknownGroupBy,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            InvalidRange(_, _) => Status::invalid_argument("end"),
+            UnknownGroupBy => Status::invalid_argument("group_by"),
+            HostCosts(_, _) | NodeCosts(_, _) => Status::internal("Internal error."),
+        }
+    }
+}
+
+/// How the costs in a `CostReport` are grouped together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostGroupBy {
+    Protocol,
+    Region,
+    Host,
+    Tag,
+}
+
+impl TryFrom<api::CostGroupBy> for CostGroupBy {
+    type Error = Error;
+
+    fn try_from(group_by: api::CostGroupBy) -> Result<Self, Self::Error> {
+        match group_by {
+            api::CostGroupBy::Unspecified => Err(Error::UnknownGroupBy),
+            api::CostGroupBy::Protocol => Ok(CostGroupBy::Protocol),
+            api::CostGroupBy::Region => Ok(CostGroupBy::Region),
+            api::CostGroupBy::Host => Ok(CostGroupBy::Host),
+            api::CostGroupBy::Tag => Ok(CostGroupBy::Tag),
+        }
+    }
+}
+
+/// A single node or host with a cost.
+struct CostEntry {
+    is_node: bool,
+    host_id: HostId,
+    protocol: Option<String>,
+    region: String,
+    tags: Tags,
+    cost: Amount,
+    created_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl CostEntry {
+    /// The group keys this entry contributes to.
+    ///
+    /// An entry without a key (such as a host grouped by protocol or an
+    /// untagged node) is reported under an empty key.
+    fn keys(&self, group_by: CostGroupBy) -> Vec<String> {
+        match group_by {
+            CostGroupBy::Protocol => vec![self.protocol.clone().unwrap_or_default()],
+            CostGroupBy::Region => vec![self.region.clone()],
+            CostGroupBy::Host => vec![self.host_id.to_string()],
+            CostGroupBy::Tag => {
+                let tags: Vec<_> = self
+                    .tags
+                    .clone()
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect();
+                if tags.is_empty() {
+                    vec![String::new()]
+                } else {
+                    tags
+                }
+            }
+        }
+    }
+
+    /// The cost of this entry prorated over the time it was live between
+    /// `start` and `end`.
+    fn prorated(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> i64 {
+        let from = self.created_at.max(start);
+        let to = self.deleted_at.map_or(end, |deleted| deleted.min(end));
+        let seconds = i128::from((to - from).num_seconds().max(0));
+        let amount =
+            i128::from(self.cost.monthly_amount()) * seconds / i128::from(SECONDS_PER_MONTH);
+        i64::try_from(amount).unwrap_or(i64::MAX)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CostGroup {
+    pub key: String,
+    pub node_count: u64,
+    pub host_count: u64,
+    /// The sum of monthly costs of each node or host in this group.
+    pub monthly_amount: i64,
+    /// The cost incurred over the report period.
+    pub period_amount: i64,
+}
+
+#[derive(Debug)]
+pub struct CostReport {
+    pub org_id: OrgId,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub currency: Currency,
+    pub groups: Vec<CostGroup>,
+    /// The sum of monthly costs of each node or host, counted once even when
+    /// it is in several groups.
+    pub total_monthly_amount: i64,
+    /// The cost incurred over the report period, counted once per entry.
+    pub total_period_amount: i64,
+}
+
+impl CostReport {
+    /// Build a report of all org node and host costs between `start` and `end`.
+    pub async fn new(
+        org_id: OrgId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        group_by: CostGroupBy,
+        with_nodes: bool,
+        with_hosts: bool,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        if start >= end {
+            return Err(Error::InvalidRange(start, end));
+        }
+
+        let mut entries = Vec::new();
+        if with_nodes {
+            entries.extend(Self::node_entries(org_id, start, end, conn).await?);
+        }
+        if with_hosts {
+            entries.extend(Self::host_entries(org_id, start, end, conn).await?);
+        }
+
+        Ok(Self::from_entries(org_id, start, end, group_by, &entries))
+    }
+
+    fn from_entries(
+        org_id: OrgId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        group_by: CostGroupBy,
+        entries: &[CostEntry],
+    ) -> Self {
+        let mut groups: BTreeMap<String, CostGroup> = BTreeMap::new();
+        let mut total_monthly_amount = 0;
+        let mut total_period_amount = 0;
+        for entry in entries {
+            let monthly_amount = entry.cost.monthly_amount();
+            let period_amount = entry.prorated(start, end);
+            total_monthly_amount += monthly_amount;
+            total_period_amount += period_amount;
+
+            for key in entry.keys(group_by) {
+                let group = groups.entry(key.clone()).or_insert_with(|| CostGroup {
+                    key,
+                    ..Default::default()
+                });
+                if entry.is_node {
+                    group.node_count += 1;
+                } else {
+                    group.host_count += 1;
+                }
+                group.monthly_amount += monthly_amount;
+                group.period_amount += period_amount;
+            }
+        }
+
+        CostReport {
+            org_id,
+            start,
+            end,
+            currency: Currency::Usd,
+            groups: groups.into_values().collect(),
+            total_monthly_amount,
+            total_period_amount,
+        }
+    }
+
+    async fn node_entries(
+        org_id: OrgId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<CostEntry>, Error> {
+        let rows: Vec<(
+            HostId,
+            String,
+            String,
+            Tags,
+            Option<Amount>,
+            DateTime<Utc>,
+            Option<DateTime<Utc>>,
+        )> = nodes::table
+            .inner_join(protocols::table)
+            .inner_join(hosts::table.inner_join(regions::table))
+            .filter(nodes::org_id.eq(org_id))
+            .filter(nodes::cost.is_not_null())
+            .filter(nodes::created_at.lt(end))
+            .filter(nodes::deleted_at.is_null().or(nodes::deleted_at.gt(start)))
+            .select((
+                nodes::host_id,
+                protocols::key,
+                regions::key,
+                nodes::tags,
+                nodes::cost,
+                nodes::created_at,
+                nodes::deleted_at,
+            ))
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::NodeCosts(org_id, err))?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(
+                |(host_id, protocol, region, tags, cost, created_at, deleted_at)| {
+                    Some(CostEntry {
+                        is_node: true,
+                        host_id,
+                        protocol: Some(protocol),
+                        region,
+                        tags,
+                        cost: cost?,
+                        created_at,
+                        deleted_at,
+                    })
+                },
+            )
+            .collect())
+    }
+
+    async fn host_entries(
+        org_id: OrgId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<CostEntry>, Error> {
+        let rows: Vec<(
+            HostId,
+            String,
+            Tags,
+            Option<Amount>,
+            DateTime<Utc>,
+            Option<DateTime<Utc>>,
+        )> = hosts::table
+            .inner_join(regions::table)
+            .filter(hosts::org_id.eq(org_id))
+            .filter(hosts::cost.is_not_null())
+            .filter(hosts::created_at.lt(end))
+            .filter(hosts::deleted_at.is_null().or(hosts::deleted_at.gt(start)))
+            .select((
+                hosts::id,
+                regions::key,
+                hosts::tags,
+                hosts::cost,
+                hosts::created_at,
+                hosts::deleted_at,
+            ))
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::HostCosts(org_id, err))?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(host_id, region, tags, cost, created_at, deleted_at)| {
+                Some(CostEntry {
+                    is_node: false,
+                    host_id,
+                    protocol: None,
+                    region,
+                    tags,
+                    cost: cost?,
+                    created_at,
+                    deleted_at,
+                })
+            })
+            .collect())
+    }
+}
+
+impl From<CostReport> for api::OrgServiceCostReportResponse {
+    fn from(report: CostReport) -> Self {
+        let currency = common::Currency::from(report.currency);
+        let amount = |amount_minor_units| {
+            Some(common::Amount {
+                currency: currency.into(),
+                amount_minor_units,
+            })
+        };
+
+        api::OrgServiceCostReportResponse {
+            org_id: report.org_id.to_string(),
+            start: Some(NanosUtc::from(report.start).into()),
+            end: Some(NanosUtc::from(report.end).into()),
+            total_monthly_amount: amount(report.total_monthly_amount),
+            total_period_amount: amount(report.total_period_amount),
+            groups: report
+                .groups
+                .into_iter()
+                .map(|group| api::CostGroup {
+                    key: group.key,
+                    node_count: group.node_count,
+                    host_count: group.host_count,
+                    monthly_amount: amount(group.monthly_amount),
+                    period_amount: amount(group.period_amount),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl api::OrgServiceCostReportResponse {
+    /// Render the report as CSV with one row per group.
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("key,node_count,host_count,currency,monthly_amount,period_amount\n");
+        for group in &self.groups {
+            let (currency, monthly_amount) = csv_amount(group.monthly_amount.as_ref());
+            let (_, period_amount) = csv_amount(group.period_amount.as_ref());
+            let row = [
+                csv_escape(&group.key),
+                group.node_count.to_string(),
+                group.host_count.to_string(),
+                currency,
+                monthly_amount,
+                period_amount,
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn csv_amount(amount: Option<&common::Amount>) -> (String, String) {
+    amount.map_or_else(Default::default, |amount| {
+        (
+            amount
+                .currency()
+                .as_str_name()
+                .trim_start_matches("CURRENCY_")
+                .to_string(),
+            amount.amount_minor_units.to_string(),
+        )
+    })
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or newline.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::model::sql::{Period, Tag};
+
+    use super::*;
+
+    fn entry(created_at: DateTime<Utc>, deleted_at: Option<DateTime<Utc>>) -> CostEntry {
+        CostEntry {
+            is_node: true,
+            host_id: uuid::Uuid::new_v4().into(),
+            protocol: Some("ethereum".into()),
+            region: "eu-1".into(),
+            tags: Tags::default(),
+            cost: Amount {
+                amount: 10_000,
+                currency: Currency::Usd,
+                period: Period::Monthly,
+            },
+            created_at,
+            deleted_at,
+        }
+    }
+
+    #[test]
+    fn prorates_cost_over_live_period() {
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let end = start + chrono::Duration::seconds(SECONDS_PER_MONTH);
+
+        let full = entry(start - chrono::Duration::days(7), None);
+        assert_eq!(full.prorated(start, end), 10_000);
+
+        let half = entry(
+            start,
+            Some(start + chrono::Duration::seconds(SECONDS_PER_MONTH / 2)),
+        );
+        assert_eq!(half.prorated(start, end), 5_000);
+
+        let before = entry(start - chrono::Duration::days(7), Some(start));
+        assert_eq!(before.prorated(start, end), 0);
+    }
+
+    #[test]
+    fn totals_count_tagged_entries_once() {
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let end = start + chrono::Duration::seconds(SECONDS_PER_MONTH);
+
+        let mut tagged = entry(start - chrono::Duration::days(7), None);
+        tagged.tags = vec![
+            Tag::new("blue".to_string()).unwrap(),
+            Tag::new("green".to_string()).unwrap(),
+        ]
+        .into();
+        let untagged = entry(start - chrono::Duration::days(7), None);
+
+        let org_id = uuid::Uuid::new_v4().into();
+        let entries = [tagged, untagged];
+        let report = CostReport::from_entries(org_id, start, end, CostGroupBy::Tag, &entries);
+
+        assert_eq!(report.groups.len(), 3);
+        let group_sum: i64 = report.groups.iter().map(|g| g.monthly_amount).sum();
+        assert_eq!(group_sum, 30_000);
+        assert_eq!(report.total_monthly_amount, 20_000);
+        assert_eq!(report.total_period_amount, 20_000);
+    }
+
+    #[test]
+    fn escapes_csv_fields() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
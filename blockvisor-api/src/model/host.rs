@@ -1,4 +1,4 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use chrono::{DateTime, Utc};
 use diesel::dsl::{count, exists, not, sql};
@@ -7,7 +7,7 @@ use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::result::DatabaseErrorKind::UniqueViolation;
 use diesel::result::Error::{DatabaseError, NotFound};
-use diesel::sql_types::{Bool, Nullable};
+use diesel::sql_types::{BigInt, Bool, Nullable};
 use diesel_async::RunQueryDsl;
 use diesel_derive_enum::DbEnum;
 use displaydoc::Display;
@@ -16,13 +16,15 @@ use thiserror::Error;
 use crate::auth::resource::{HostId, OrgId, Resource, ResourceId, ResourceType};
 use crate::database::Conn;
 use crate::grpc::{Status, common};
-use crate::model::sql::{self, Amount, IpNetwork, Tags, Version, greatest};
+use crate::model::sql::{self, Amount, IpNetwork, Labels, Tags, Version, greatest};
 use crate::util::{SearchOperator, SortOrder};
 
+use super::capacity_reservation::CapacityReservation;
+use super::host_capacity::{FREE_CPU_SQL, FREE_DISK_SQL, FREE_MEMORY_SQL, Resources};
 use super::ip_address::NewIpAddress;
-use super::node::{NodeScheduler, ResourceAffinity, SimilarNodeAffinity};
-use super::schema::{hosts, ip_addresses, nodes, sql_types};
-use super::{Command, Node, Org, Paginate, Protocol, RegionId};
+use super::node::{NodeScheduler, Placement, ResourceAffinity, SimilarNodeAffinity};
+use super::schema::{hosts, ip_addresses, nodes, regions, sql_types};
+use super::{Command, Node, Org, Paginate, Protocol, RegionId, ZoneId};
 
 #[derive(Debug, Display, Error)]
 pub enum Error {
@@ -34,6 +36,8 @@ pub enum Error {
     BillingCurrencyUnknown,
     /// Unknown BillingAmount Period.
     BillingPeriodUnknown,
+    /// Host capacity reservation error: {0}
+    CapacityReservation(#[from] crate::model::capacity_reservation::Error),
     /// Host Command error: {0}
     Command(Box<super::command::Error>),
     /// Failed to parse cpu cores as i64: {0}
@@ -48,12 +52,16 @@ pub enum Error {
     FindById(HostId, diesel::result::Error),
     /// Failed to find hosts by id `{0:?}`: {1}
     FindByIds(HashSet<HostId>, diesel::result::Error),
+    /// Failed to find hosts in regions `{0:?}`: {1}
+    FindByRegions(HashSet<RegionId>, diesel::result::Error),
     /// Failed to find org id for possibly deleted host id `{0}`: {1}
     FindDeletedOrgId(HostId, diesel::result::Error),
     /// Failed to find org id for host id `{0}`: {1}
     FindOrgId(HostId, diesel::result::Error),
     /// Failed to parse free_ips as u32: {0}
     FreeIps(std::num::TryFromIntError),
+    /// Failed to record heartbeat for host `{0}`: {1}
+    Heartbeat(HostId, diesel::result::Error),
     /// Failed to get host candidates: {0}
     HostCandidates(diesel::result::Error),
     /// Host ip address error: {0}
@@ -70,8 +78,14 @@ pub enum Error {
     Paginate(#[from] crate::model::paginate::Error),
     /// Failed to parse host ip address: {0}
     ParseIp(std::net::AddrParseError),
+    /// Failed to get the free capacity of public hosts: {0}
+    PublicFree(diesel::result::Error),
     /// Failed to decrement node count for host `{0}`: {1}
     RemoveNode(HostId, diesel::result::Error),
+    /// Failed to find stale hosts: {0}
+    Stale(diesel::result::Error),
+    /// Failed to mark stale host `{0}` as offline: {1}
+    StaleOffline(HostId, diesel::result::Error),
     /// Unknown ConnectionStatus.
     UnknownConnectionStatus,
     /// Unknown ScheduleType.
@@ -104,6 +118,7 @@ impl From<Error> for Status {
             ParseIp(_) => Status::invalid_argument("ip_addr"),
             UnknownConnectionStatus => Status::invalid_argument("connection_status"),
             UnknownScheduleType => Status::invalid_argument("schedule_type"),
+            CapacityReservation(err) => err.into(),
             Paginate(err) => err.into(),
             IpAddress(err) => err.into(),
             Org(err) => err.into(),
@@ -150,6 +165,14 @@ pub struct Host {
     pub updated_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub cost: Option<Amount>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub labels: Labels,
+    pub taints: Labels,
+    pub cpu_overcommit_percent: Option<i32>,
+    pub memory_overcommit_percent: Option<i32>,
+    pub disk_overcommit_percent: Option<i32>,
+    pub ipv6_gateway: Option<IpNetwork>,
+    pub zone_id: Option<ZoneId>,
 }
 
 impl Host {
@@ -181,6 +204,18 @@ impl Host {
             .map_err(|err| Error::FindByIds(ids.clone(), err))
     }
 
+    pub async fn by_regions(
+        region_ids: &HashSet<RegionId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<Self>, Error> {
+        hosts::table
+            .filter(hosts::region_id.eq_any(region_ids))
+            .filter(hosts::deleted_at.is_null())
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::FindByRegions(region_ids.clone(), err))
+    }
+
     pub async fn org_id(id: HostId, conn: &mut Conn<'_>) -> Result<Option<OrgId>, Error> {
         hosts::table
             .find(id)
@@ -200,6 +235,50 @@ impl Host {
             .map_err(|err| Error::FindDeletedOrgId(id, err))
     }
 
+    /// Record that a host has been heard from, which also means it is online.
+    pub async fn heartbeat(id: HostId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let row = hosts::table.find(id).filter(hosts::deleted_at.is_null());
+        diesel::update(row)
+            .set((
+                hosts::last_seen_at.eq(Utc::now()),
+                hosts::connection_status.eq(ConnectionStatus::Online),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Heartbeat(id, err))
+    }
+
+    /// Online hosts that have not been heard from since `cutoff`.
+    pub async fn stale(cutoff: DateTime<Utc>, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        hosts::table
+            .filter(hosts::deleted_at.is_null())
+            .filter(hosts::connection_status.eq(ConnectionStatus::Online))
+            .filter(hosts::last_seen_at.lt(cutoff))
+            .get_results(conn)
+            .await
+            .map_err(Error::Stale)
+    }
+
+    /// Mark a stale host as offline.
+    ///
+    /// Returns None if the host was heard from again since `cutoff`.
+    pub async fn stale_offline(
+        id: HostId,
+        cutoff: DateTime<Utc>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Option<Self>, Error> {
+        let row = hosts::table
+            .find(id)
+            .filter(hosts::deleted_at.is_null())
+            .filter(hosts::last_seen_at.lt(cutoff));
+        diesel::update(row)
+            .set(hosts::connection_status.eq(ConnectionStatus::Offline))
+            .get_result(conn)
+            .await
+            .optional()
+            .map_err(|err| Error::StaleOffline(id, err))
+    }
+
     pub async fn add_node(node: &Node, conn: &mut Conn<'_>) -> Result<Self, Error> {
         diesel::update(hosts::table.find(node.host_id))
             .set((
@@ -254,16 +333,28 @@ impl Host {
     }
 
     /// List suitable hosts for a node to be scheduled on.
+    ///
+    /// Hosts that have been seen before but are now offline are skipped, so
+    /// hosts that stop sending heartbeats are no longer scheduled on.
     pub async fn candidates(
         require: HostRequirements<'_>,
         limit: Option<i64>,
         conn: &mut Conn<'_>,
     ) -> Result<Vec<HostCandidate>, Error> {
-        let free_cpu = hosts::cpu_cores - hosts::node_cpu_cores;
-        let free_memory = hosts::memory_bytes - hosts::node_memory_bytes;
-        let free_disk = hosts::disk_bytes - hosts::node_disk_bytes;
+        // effective free capacity after overcommit and usage (see `host_capacity`)
+        let free_cpu = sql::<BigInt>(FREE_CPU_SQL);
+        let free_memory = sql::<BigInt>(FREE_MEMORY_SQL);
+        let free_disk = sql::<BigInt>(FREE_DISK_SQL);
+        // nodes always need a free IPv4 address
         let free_ips = ip_addresses::table
             .filter(ip_addresses::host_id.eq(hosts::id))
+            .filter(sql::family(ip_addresses::ip).eq(4))
+            .filter(ip_addresses::reserved_at.is_null())
+            .filter(
+                ip_addresses::quarantined_until
+                    .is_null()
+                    .or(ip_addresses::quarantined_until.lt(Utc::now())),
+            )
             .filter(not(exists(
                 nodes::table
                     .filter(nodes::ip_address.eq(ip_addresses::ip))
@@ -279,11 +370,13 @@ impl Host {
         let tag_order = format!("'{tag}' = ANY(tags)");
 
         let mut query = hosts::table
+            .inner_join(regions::table)
             .filter(hosts::deleted_at.is_null())
             .filter(hosts::schedule_type.eq(ScheduleType::Automatic))
-            .filter(free_cpu.gt(require.cpu_cores))
-            .filter(free_memory.gt(require.memory_bytes))
-            .filter(free_disk.gt(require.disk_bytes))
+            .filter(hosts::connection_status.eq(ConnectionStatus::Online))
+            .filter(free_cpu.clone().gt(require.cpu_cores))
+            .filter(free_memory.clone().gt(require.memory_bytes))
+            .filter(free_disk.clone().gt(require.disk_bytes))
             .filter(free_ips.gt(0))
             .filter(sql::<Bool>(&tag_filter))
             .order_by(sql::<Bool>(&tag_order).desc())
@@ -299,6 +392,54 @@ impl Host {
             query = query.filter(hosts::region_id.eq(region_id));
         }
 
+        if let Some(host_id) = require.exclude_host_id {
+            query = query.filter(hosts::id.ne(host_id));
+        }
+
+        // skip public hosts in regions where the node only fits into capacity
+        // that is reserved for other orgs
+        let unreserved = Self::unreserved_public_free(require.org_id, conn).await?;
+        let full: Vec<_> = unreserved
+            .into_iter()
+            .filter(|(_, free)| {
+                free.cpu_cores < require.cpu_cores
+                    || free.memory_bytes < require.memory_bytes
+                    || free.disk_bytes < require.disk_bytes
+            })
+            .map(|(region_id, _)| region_id)
+            .collect();
+        if !full.is_empty() {
+            query = query.filter(
+                hosts::org_id
+                    .is_not_null()
+                    .or(not(hosts::region_id.eq_any(full))),
+            );
+        }
+
+        // a host must have every required label, and each taint must be tolerated
+        let placement = require.placement;
+        if !placement.required.is_empty() {
+            query = query.filter(hosts::labels.contains(&placement.required));
+        }
+        query = query.filter(hosts::taints.is_contained_by(&placement.tolerations));
+
+        if placement.has_anti_affinity() {
+            let avoided = nodes::table
+                .filter(nodes::host_id.eq(hosts::id))
+                .filter(nodes::deleted_at.is_null())
+                .filter(
+                    nodes::id
+                        .eq_any(&placement.anti_affinity_nodes)
+                        .or(nodes::tags.overlaps_with(&placement.anti_affinity_tags)),
+                )
+                .select(nodes::id);
+            query = query.filter(not(exists(avoided)));
+        }
+
+        if !placement.preferred.is_empty() {
+            query = query.then_order_by(hosts::labels.contains(&placement.preferred).desc());
+        }
+
         if let Some(similarity) = require.scheduler.similarity {
             let similar = nodes::table
                 .filter(nodes::host_id.eq(hosts::id))
@@ -309,7 +450,21 @@ impl Host {
 
             query = match similarity {
                 SimilarNodeAffinity::Cluster => query.then_order_by(similar.desc()),
-                SimilarNodeAffinity::Spread => query.then_order_by(similar),
+                SimilarNodeAffinity::Spread => {
+                    // spread over zones first, treating a host without a zone
+                    // as its own zone, then over the hosts within a zone
+                    let protocol_id = require.protocol.id;
+                    let zone_similar = format!(
+                        "(select count(*) from nodes n \
+                            inner join hosts h on h.id = n.host_id \
+                            where n.protocol_id = '{protocol_id}' \
+                            and n.deleted_at is null \
+                            and (h.zone_id = hosts.zone_id or h.id = hosts.id))"
+                    );
+                    query
+                        .then_order_by(sql::<BigInt>(&zone_similar))
+                        .then_order_by(similar)
+                }
             };
         }
 
@@ -342,6 +497,73 @@ impl Host {
             .collect()
     }
 
+    /// The free public capacity left for `org_id` in each region where other
+    /// orgs hold capacity reservations.
+    ///
+    /// Regions without reservations for other orgs are not returned.
+    pub async fn unreserved_public_free(
+        org_id: Option<OrgId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<HashMap<RegionId, Resources>, Error> {
+        let held = CapacityReservation::held_for_others(org_id, conn).await?;
+        if held.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let region_ids = held.keys().copied().collect();
+        let free = Self::public_free(&region_ids, conn).await?;
+        Ok(held
+            .into_iter()
+            .map(|(region_id, held)| {
+                let free = free.get(&region_id).copied().unwrap_or_default();
+                let unreserved = Resources {
+                    cpu_cores: free.cpu_cores - held.cpu_cores,
+                    memory_bytes: free.memory_bytes - held.memory_bytes,
+                    disk_bytes: free.disk_bytes - held.disk_bytes,
+                };
+                (region_id, unreserved)
+            })
+            .collect())
+    }
+
+    /// The free capacity of the schedulable public hosts in each region.
+    async fn public_free(
+        region_ids: &HashSet<RegionId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<HashMap<RegionId, Resources>, Error> {
+        let sum_free = |free: &str| sql::<BigInt>(&format!("sum(greatest({free}, 0))::bigint"));
+
+        let rows: Vec<(RegionId, i64, i64, i64)> = hosts::table
+            .inner_join(regions::table)
+            .filter(hosts::region_id.eq_any(region_ids))
+            .filter(hosts::org_id.is_null())
+            .filter(hosts::deleted_at.is_null())
+            .filter(hosts::schedule_type.eq(ScheduleType::Automatic))
+            .filter(hosts::connection_status.eq(ConnectionStatus::Online))
+            .group_by(hosts::region_id)
+            .select((
+                hosts::region_id,
+                sum_free(FREE_CPU_SQL),
+                sum_free(FREE_MEMORY_SQL),
+                sum_free(FREE_DISK_SQL),
+            ))
+            .get_results(conn)
+            .await
+            .map_err(Error::PublicFree)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(region_id, cpu_cores, memory_bytes, disk_bytes)| {
+                let free = Resources {
+                    cpu_cores,
+                    memory_bytes,
+                    disk_bytes,
+                };
+                (region_id, free)
+            })
+            .collect())
+    }
+
     pub fn created_by(&self) -> Resource {
         Resource::new(self.created_by_type, self.created_by_id)
     }
@@ -350,10 +572,13 @@ impl Host {
 pub struct HostRequirements<'r> {
     pub scheduler: &'r NodeScheduler,
     pub protocol: &'r Protocol,
+    pub placement: &'r Placement,
     pub org_id: Option<OrgId>,
     pub cpu_cores: i64,
     pub memory_bytes: i64,
     pub disk_bytes: i64,
+    /// A host that may not be a candidate, such as the host of a failed node.
+    pub exclude_host_id: Option<HostId>,
 }
 
 pub struct HostCandidate {
@@ -374,6 +599,8 @@ pub struct NewHost<'a> {
     pub bv_version: &'a Version,
     pub ip_address: IpNetwork,
     pub ip_gateway: IpNetwork,
+    pub ipv6_gateway: Option<IpNetwork>,
+    pub zone_id: Option<ZoneId>,
     pub cpu_cores: i64,
     pub memory_bytes: i64,
     pub disk_bytes: i64,
@@ -422,6 +649,13 @@ pub struct UpdateHost<'a> {
     pub disk_bytes: Option<i64>,
     pub tags: Option<Tags>,
     pub cost: Option<Amount>,
+    pub labels: Option<Labels>,
+    pub taints: Option<Labels>,
+    pub cpu_overcommit_percent: Option<Option<i32>>,
+    pub memory_overcommit_percent: Option<Option<i32>>,
+    pub disk_overcommit_percent: Option<Option<i32>>,
+    pub ipv6_gateway: Option<IpNetwork>,
+    pub zone_id: Option<Option<ZoneId>>,
 }
 
 impl UpdateHost<'_> {
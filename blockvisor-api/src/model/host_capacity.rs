@@ -0,0 +1,268 @@
+//! The capacity of a host that is available for scheduling nodes.
+//!
+//! The allocated capacity of a host is the sum of the resources requested by
+//! its nodes, which may exceed the raw capacity of the host by its overcommit
+//! ratios. Each ratio is a percentage of the raw capacity, set per host or
+//! falling back to the region of the host.
+//!
+//! A region may also schedule on the observed usage of its hosts, in which
+//! case the free CPU and memory of a host is further limited to its unused
+//! capacity after keeping back `usage_headroom_percent` of the raw capacity.
+//! Hosts that have not reported any usage are only limited by allocation.
+//!
+//! `Host::candidates` applies the same rules in SQL, which must be kept in sync
+//! with `HostCapacity::new`.
+//!
+//! The capacity of a region or zone is summed over its hosts, separately for
+//! public and org-private hosts.
+
+use chrono::{DateTime, Utc};
+
+use crate::model::sql::IpNetwork;
+use crate::model::{Host, IpAddress, Region};
+
+/// Free CPU cores of a host in `Host::candidates`.
+pub(crate) const FREE_CPU_SQL: &str = "least(\
+    hosts.cpu_cores \
+        * coalesce(hosts.cpu_overcommit_percent, regions.cpu_overcommit_percent) / 100 \
+        - hosts.node_cpu_cores, \
+    case when regions.usage_scheduling then \
+        (hosts.cpu_cores * (100 - regions.usage_headroom_percent) - hosts.used_cpu_hundreths) \
+        / 100 \
+    end)";
+
+/// Free memory bytes of a host in `Host::candidates`.
+pub(crate) const FREE_MEMORY_SQL: &str = "least(\
+    hosts.memory_bytes \
+        * coalesce(hosts.memory_overcommit_percent, regions.memory_overcommit_percent) / 100 \
+        - hosts.node_memory_bytes, \
+    case when regions.usage_scheduling then \
+        hosts.memory_bytes * (100 - regions.usage_headroom_percent) / 100 \
+        - hosts.used_memory_bytes \
+    end)";
+
+/// Free disk bytes of a host in `Host::candidates`.
+pub(crate) const FREE_DISK_SQL: &str = "hosts.disk_bytes \
+    * coalesce(hosts.disk_overcommit_percent, regions.disk_overcommit_percent) / 100 \
+    - hosts.node_disk_bytes";
+
+/// The CPU, memory and disk capacity of a host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Resources {
+    pub cpu_cores: i64,
+    pub memory_bytes: i64,
+    pub disk_bytes: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HostCapacity {
+    /// The resources allocated to nodes on the host.
+    pub allocated: Resources,
+    /// The raw capacity of the host after applying the overcommit ratios.
+    pub effective: Resources,
+    /// The capacity left for new nodes.
+    pub free: Resources,
+}
+
+impl HostCapacity {
+    pub fn new(host: &Host, region: &Region) -> Self {
+        let allocated = Resources {
+            cpu_cores: host.node_cpu_cores,
+            memory_bytes: host.node_memory_bytes,
+            disk_bytes: host.node_disk_bytes,
+        };
+        let effective = Resources {
+            cpu_cores: overcommit(
+                host.cpu_cores,
+                host.cpu_overcommit_percent
+                    .unwrap_or(region.cpu_overcommit_percent),
+            ),
+            memory_bytes: overcommit(
+                host.memory_bytes,
+                host.memory_overcommit_percent
+                    .unwrap_or(region.memory_overcommit_percent),
+            ),
+            disk_bytes: overcommit(
+                host.disk_bytes,
+                host.disk_overcommit_percent
+                    .unwrap_or(region.disk_overcommit_percent),
+            ),
+        };
+
+        let headroom = region
+            .usage_scheduling
+            .then_some(region.usage_headroom_percent);
+        // used CPU is reported in hundredths of a core
+        let unused_cpu = headroom
+            .zip(host.used_cpu_hundreths)
+            .map(|(headroom, used)| {
+                (usable(host.cpu_cores.saturating_mul(100), headroom) - used) / 100
+            });
+        let unused_memory = headroom
+            .zip(host.used_memory_bytes)
+            .map(|(headroom, used)| usable(host.memory_bytes, headroom) - used);
+
+        let free = Resources {
+            cpu_cores: free(effective.cpu_cores, allocated.cpu_cores, unused_cpu),
+            memory_bytes: free(
+                effective.memory_bytes,
+                allocated.memory_bytes,
+                unused_memory,
+            ),
+            disk_bytes: free(effective.disk_bytes, allocated.disk_bytes, None),
+        };
+
+        HostCapacity {
+            allocated,
+            effective,
+            free,
+        }
+    }
+}
+
+/// The IPv4 pool of a host, which every node needs an address from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IpCapacity {
+    pub total: u64,
+    /// Addresses held by nodes.
+    pub allocated: u64,
+    /// Addresses that are not held, reserved or quarantined.
+    pub free: u64,
+}
+
+impl IpCapacity {
+    pub fn new(ips: &[IpAddress], assigned: &[IpNetwork], now: DateTime<Utc>) -> Self {
+        let mut capacity = IpCapacity::default();
+        for ip in ips.iter().filter(|ip| ip.ip.is_ipv4()) {
+            capacity.total += 1;
+            if assigned.contains(&ip.ip) {
+                capacity.allocated += 1;
+            } else if ip.reserved_at.is_none()
+                && ip.quarantined_until.is_none_or(|until| until < now)
+            {
+                capacity.free += 1;
+            }
+        }
+        capacity
+    }
+}
+
+/// The combined capacity of a group of hosts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapacitySummary {
+    pub hosts: u32,
+    /// The effective capacity of the hosts after overcommit.
+    pub total: Resources,
+    pub allocated: Resources,
+    /// The free capacity, where overcommitted hosts count as full.
+    pub free: Resources,
+    pub ips: IpCapacity,
+}
+
+impl CapacitySummary {
+    pub fn add(&mut self, capacity: &HostCapacity, ips: IpCapacity) {
+        let sum = |total: &mut Resources, add: Resources| {
+            total.cpu_cores = total.cpu_cores.saturating_add(add.cpu_cores.max(0));
+            total.memory_bytes = total.memory_bytes.saturating_add(add.memory_bytes.max(0));
+            total.disk_bytes = total.disk_bytes.saturating_add(add.disk_bytes.max(0));
+        };
+
+        self.hosts = self.hosts.saturating_add(1);
+        sum(&mut self.total, capacity.effective);
+        sum(&mut self.allocated, capacity.allocated);
+        sum(&mut self.free, capacity.free);
+        self.ips.total += ips.total;
+        self.ips.allocated += ips.allocated;
+        self.ips.free += ips.free;
+    }
+}
+
+/// The capacity of a region or zone, split by host visibility.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapacityReport {
+    pub public: CapacitySummary,
+    pub private: CapacitySummary,
+}
+
+impl CapacityReport {
+    pub fn add(&mut self, host: &Host, capacity: &HostCapacity, ips: IpCapacity) {
+        if host.org_id.is_some() {
+            self.private.add(capacity, ips);
+        } else {
+            self.public.add(capacity, ips);
+        }
+    }
+}
+
+/// Apply an overcommit percentage to some raw capacity.
+fn overcommit(capacity: i64, percent: i32) -> i64 {
+    capacity.saturating_mul(i64::from(percent)) / 100
+}
+
+/// The capacity left after keeping back `headroom` percent.
+fn usable(capacity: i64, headroom: i32) -> i64 {
+    capacity.saturating_mul(100 - i64::from(headroom)) / 100
+}
+
+/// The free capacity after allocation, limited by any unused capacity.
+fn free(effective: i64, allocated: i64, unused: Option<i64>) -> i64 {
+    let free = effective - allocated;
+    unused.map_or(free, |unused| free.min(unused))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_capacity_with_overcommit_and_usage() {
+        // 8 cores overcommitted by 150% with 10 cores allocated
+        let effective = overcommit(8, 150);
+        assert_eq!(effective, 12);
+        assert_eq!(free(effective, 10, None), 2);
+
+        // 20% headroom of 100 GB leaves 80 GB, of which 50 GB is used
+        let gb = 1024 * 1024 * 1024;
+        let unused = usable(100 * gb, 20) - 50 * gb;
+        assert_eq!(free(150 * gb, 60 * gb, Some(unused)), 30 * gb);
+        assert_eq!(free(150 * gb, 130 * gb, Some(unused)), 20 * gb);
+    }
+
+    #[test]
+    fn summary_counts_overcommitted_hosts_as_full() {
+        let resources = |n| Resources {
+            cpu_cores: n,
+            memory_bytes: n,
+            disk_bytes: n,
+        };
+        let ips = IpCapacity {
+            total: 4,
+            allocated: 1,
+            free: 2,
+        };
+
+        let mut summary = CapacitySummary::default();
+        summary.add(
+            &HostCapacity {
+                allocated: resources(6),
+                effective: resources(10),
+                free: resources(4),
+            },
+            ips,
+        );
+        summary.add(
+            &HostCapacity {
+                allocated: resources(12),
+                effective: resources(10),
+                free: resources(-2),
+            },
+            ips,
+        );
+
+        assert_eq!(summary.hosts, 2);
+        assert_eq!(summary.total, resources(20));
+        assert_eq!(summary.allocated, resources(18));
+        assert_eq!(summary.free, resources(4));
+        assert_eq!(summary.ips.free, 4);
+    }
+}
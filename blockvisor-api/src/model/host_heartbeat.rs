@@ -0,0 +1,93 @@
+//! Detect hosts that have stopped sending heartbeats.
+//!
+//! A host is seen whenever it publishes an online status, sends host metrics or
+//! acks a command. A host that dies without publishing a last-will message
+//! would otherwise stay online forever, so online hosts that have not been seen
+//! within the configured heartbeat timeout are marked as offline. The health of
+//! their nodes becomes unknown, and they are no longer scheduled on.
+//!
+//! Each sweep runs in a transaction holding an advisory lock, so only one
+//! replica marks a stale host as offline.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use displaydoc::Display;
+use futures_util::FutureExt;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::auth::resource::Resource;
+use crate::config::Context;
+use crate::database::{AdvisoryLock, Conn};
+use crate::grpc::{api, common};
+use crate::model::background::{self, cutoff};
+use crate::model::node::relaunch::authz;
+use crate::model::{Host, Node};
+use crate::mqtt::Message;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Heartbeat claims error: {0}
+    Claims(#[from] crate::auth::claims::Error),
+    /// Heartbeat database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Heartbeat host error: {0}
+    Host(#[from] crate::model::host::Error),
+    /// Heartbeat host API error: {0}
+    HostApi(#[from] crate::grpc::host::Error),
+    /// Heartbeat node error: {0}
+    Node(#[from] crate::model::node::Error),
+    /// Heartbeat node API error: {0}
+    NodeApi(#[from] crate::grpc::node::Error),
+    /// Heartbeat transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+}
+
+/// Run `sweep` forever at the configured interval.
+pub async fn run(ctx: Arc<Context>) {
+    let timeout = *ctx.config.host.heartbeat_timeout;
+    let interval = *ctx.config.host.heartbeat_interval;
+    background::run_exclusive(&ctx, AdvisoryLock::HostHeartbeat, interval, |conn| {
+        sweep(timeout, conn).boxed()
+    })
+    .await;
+}
+
+/// Mark online hosts that have not been seen within `timeout` as offline.
+///
+/// Returns the `HostMessage` and `NodeMessage` updates to send for each stale
+/// host and its nodes.
+pub async fn sweep(timeout: Duration, conn: &mut Conn<'_>) -> Result<Vec<Message>, Error> {
+    let cutoff = cutoff(Utc::now(), timeout);
+
+    let mut messages = Vec::new();
+    for stale in Host::stale(cutoff, conn).await? {
+        // skip hosts that were seen again since the lookup
+        let Some(host) = Host::stale_offline(stale.id, cutoff, conn).await? else {
+            continue;
+        };
+
+        let nodes = Node::clear_health(host.id, conn).await?;
+        warn!(
+            "Marking host {} as offline after no heartbeat since {} ({} nodes affected).",
+            host.id,
+            host.last_seen_at.unwrap_or(cutoff),
+            nodes.len()
+        );
+
+        let updated_by = common::Resource::from(host.id);
+        let authz = authz(Resource::from(host.id), conn).await?;
+        let nodes = api::Node::from_models(nodes, &authz, conn).await?;
+        let host = api::Host::from_host(host, None, conn).await?;
+        messages.push(api::HostMessage::updated(host, updated_by.clone()).into());
+        messages.extend(
+            api::NodeMessage::updated_many(nodes, &updated_by)
+                .into_iter()
+                .map(Into::into),
+        );
+    }
+
+    Ok(messages)
+}
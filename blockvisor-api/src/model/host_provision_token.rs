@@ -0,0 +1,349 @@
+//! Named host provision tokens.
+//!
+//! Unlike the single per-user token in `model::token`, an org may have many
+//! provision tokens, and each one may be restricted to a set of regions or a
+//! schedule type, limited to a number of uses, or set to expire. Only a hash
+//! of the token is stored, so the plaintext is returned once on creation.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::UniqueViolation;
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel_async::RunQueryDsl;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::{OrgId, Resource, ResourceId, ResourceType};
+use crate::database::Conn;
+use crate::grpc::Status;
+use crate::model::host::ScheduleType;
+use crate::model::region::RegionId;
+use crate::model::schema::host_provision_tokens;
+use crate::model::sql::Tags;
+
+/// Distinguishes these tokens from the legacy per-user provision tokens.
+const TOKEN_PREFIX: &str = "bvp_";
+const TOKEN_LEN: usize = 32;
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to find host provision tokens for org `{0}`: {1}
+    ByOrg(OrgId, diesel::result::Error),
+    /// Failed to find host provision token: {0}
+    ByToken(diesel::result::Error),
+    /// Failed to claim host provision token `{0}`: {1}
+    Claim(HostProvisionTokenId, diesel::result::Error),
+    /// Failed to create host provision token: {0}
+    Create(diesel::result::Error),
+    /// Host provision token `{0}` has no uses left.
+    Exhausted(HostProvisionTokenId),
+    /// Host provision token `{0}` has expired.
+    Expired(HostProvisionTokenId),
+    /// Host provision token `{0}` can't provision hosts in region `{1}`.
+    RegionNotAllowed(HostProvisionTokenId, RegionId),
+    /// Failed to revoke host provision token `{0}`: {1}
+    Revoke(HostProvisionTokenId, diesel::result::Error),
+    /// Host provision token `{0}` can't provision hosts with schedule type `{1:?}`.
+    ScheduleTypeNotAllowed(HostProvisionTokenId, ScheduleType),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            ByToken(NotFound) | Revoke(_, NotFound) => Status::not_found("Token not found."),
+            Create(DatabaseError(UniqueViolation, _)) => {
+                Status::already_exists("A token with that name already exists.")
+            }
+            Claim(_, NotFound) | Exhausted(_) => {
+                Status::failed_precondition("Token has no uses left.")
+            }
+            Expired(_) => Status::failed_precondition("Token has expired."),
+            RegionNotAllowed(..) => Status::forbidden("Token is not valid for that region."),
+            ScheduleTypeNotAllowed(..) => {
+                Status::forbidden("Token is not valid for that schedule type.")
+            }
+            ByOrg(..) | ByToken(_) | Claim(..) | Create(_) | Revoke(..) => {
+                Status::internal("Internal error.")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
+pub struct HostProvisionTokenId(Uuid);
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = host_provision_tokens)]
+pub struct HostProvisionToken {
+    pub id: HostProvisionTokenId,
+    pub org_id: OrgId,
+    pub name: String,
+    pub token_hash: String,
+    pub created_by_type: ResourceType,
+    pub created_by_id: ResourceId,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i64>,
+    pub use_count: i64,
+    pub region_ids: Vec<Option<RegionId>>,
+    pub schedule_type: Option<ScheduleType>,
+    pub tags: Tags,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl HostProvisionToken {
+    /// All unrevoked tokens for an org.
+    pub async fn by_org(org_id: OrgId, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        host_provision_tokens::table
+            .filter(host_provision_tokens::org_id.eq(org_id))
+            .filter(host_provision_tokens::revoked_at.is_null())
+            .order_by(host_provision_tokens::created_at)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ByOrg(org_id, err))
+    }
+
+    /// Find an unrevoked token from its plaintext value.
+    ///
+    /// Returns None for values that are not in the format of these tokens, so
+    /// that the caller may fall back to the legacy provision tokens.
+    pub async fn by_token(token: &str, conn: &mut Conn<'_>) -> Result<Option<Self>, Error> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return Ok(None);
+        }
+
+        host_provision_tokens::table
+            .filter(host_provision_tokens::token_hash.eq(TokenHash::from(token).0))
+            .filter(host_provision_tokens::revoked_at.is_null())
+            .get_result(conn)
+            .await
+            .map(Some)
+            .map_err(Error::ByToken)
+    }
+
+    /// Check that this token may provision a host with the given constraints.
+    pub fn check(
+        &self,
+        region_id: RegionId,
+        schedule_type: ScheduleType,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(Error::Expired(self.id));
+        }
+        if self
+            .max_uses
+            .is_some_and(|max_uses| self.use_count >= max_uses)
+        {
+            return Err(Error::Exhausted(self.id));
+        }
+        if !self.region_ids.is_empty() && !self.region_ids.contains(&Some(region_id)) {
+            return Err(Error::RegionNotAllowed(self.id, region_id));
+        }
+        if self
+            .schedule_type
+            .is_some_and(|allowed| allowed != schedule_type)
+        {
+            return Err(Error::ScheduleTypeNotAllowed(self.id, schedule_type));
+        }
+
+        Ok(())
+    }
+
+    /// Use up one of the remaining uses of this token.
+    ///
+    /// The use count is checked in the update, so concurrent claims can't
+    /// exceed `max_uses`.
+    pub async fn claim(&self, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let remaining =
+            host_provision_tokens::max_uses
+                .is_null()
+                .or(host_provision_tokens::use_count
+                    .nullable()
+                    .lt(host_provision_tokens::max_uses));
+
+        diesel::update(host_provision_tokens::table.find(self.id))
+            .filter(host_provision_tokens::revoked_at.is_null())
+            .filter(remaining)
+            .set((
+                host_provision_tokens::use_count.eq(host_provision_tokens::use_count + 1),
+                host_provision_tokens::last_used_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Claim(self.id, err))
+    }
+
+    pub async fn revoke(
+        id: HostProvisionTokenId,
+        org_id: OrgId,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        let filter = host_provision_tokens::table
+            .find(id)
+            .filter(host_provision_tokens::org_id.eq(org_id))
+            .filter(host_provision_tokens::revoked_at.is_null());
+
+        diesel::update(filter)
+            .set(host_provision_tokens::revoked_at.eq(Utc::now()))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Revoke(id, err))
+    }
+
+    pub fn resource(&self) -> Resource {
+        Resource::new(self.created_by_type, self.created_by_id)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = host_provision_tokens)]
+pub struct NewHostProvisionToken {
+    pub org_id: OrgId,
+    pub name: String,
+    pub created_by_type: ResourceType,
+    pub created_by_id: ResourceId,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i64>,
+    pub region_ids: Vec<RegionId>,
+    pub schedule_type: Option<ScheduleType>,
+    pub tags: Tags,
+}
+
+impl NewHostProvisionToken {
+    /// Create a new token, returning it along with its plaintext value.
+    pub async fn create(
+        self,
+        conn: &mut Conn<'_>,
+    ) -> Result<(HostProvisionToken, TokenSecret), Error> {
+        let secret = TokenSecret::new();
+
+        let token = diesel::insert_into(host_provision_tokens::table)
+            .values((
+                self,
+                host_provision_tokens::token_hash.eq(TokenHash::from(secret.0.as_str()).0),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(Error::Create)?;
+
+        Ok((token, secret))
+    }
+}
+
+/// The plaintext value of a provision token.
+pub struct TokenSecret(String);
+
+impl TokenSecret {
+    fn new() -> Self {
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LEN)
+            .map(char::from)
+            .collect();
+        TokenSecret(format!("{TOKEN_PREFIX}{random}"))
+    }
+
+    pub fn take(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Debug for TokenSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<Redacted TokenSecret>")
+    }
+}
+
+/// A hex-encoded blake3 hash of a token.
+struct TokenHash(String);
+
+impl From<&str> for TokenHash {
+    fn from(token: &str) -> Self {
+        TokenHash(blake3::hash(token.as_bytes()).to_hex().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn token() -> HostProvisionToken {
+        HostProvisionToken {
+            id: Uuid::new_v4().into(),
+            org_id: Uuid::new_v4().into(),
+            name: "test".into(),
+            token_hash: TokenHash::from("bvp_test").0,
+            created_by_type: ResourceType::User,
+            created_by_id: Uuid::new_v4().into(),
+            expires_at: None,
+            max_uses: None,
+            use_count: 0,
+            region_ids: vec![],
+            schedule_type: None,
+            tags: Tags::default(),
+            created_at: Utc::now(),
+            last_used_at: None,
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn check_token_constraints() {
+        let now = Utc::now();
+        let region_a = RegionId::from(Uuid::new_v4());
+        let region_b = RegionId::from(Uuid::new_v4());
+
+        let unrestricted = token();
+        assert!(
+            unrestricted
+                .check(region_a, ScheduleType::Manual, now)
+                .is_ok()
+        );
+
+        let expired = HostProvisionToken {
+            expires_at: Some(now - Duration::minutes(1)),
+            ..token()
+        };
+        assert!(matches!(
+            expired.check(region_a, ScheduleType::Automatic, now),
+            Err(Error::Expired(_))
+        ));
+
+        let exhausted = HostProvisionToken {
+            max_uses: Some(1),
+            use_count: 1,
+            ..token()
+        };
+        assert!(matches!(
+            exhausted.check(region_a, ScheduleType::Automatic, now),
+            Err(Error::Exhausted(_))
+        ));
+
+        let scoped = HostProvisionToken {
+            region_ids: vec![Some(region_a)],
+            schedule_type: Some(ScheduleType::Automatic),
+            ..token()
+        };
+        assert!(scoped.check(region_a, ScheduleType::Automatic, now).is_ok());
+        assert!(matches!(
+            scoped.check(region_b, ScheduleType::Automatic, now),
+            Err(Error::RegionNotAllowed(..))
+        ));
+        assert!(matches!(
+            scoped.check(region_a, ScheduleType::Manual, now),
+            Err(Error::ScheduleTypeNotAllowed(..))
+        ));
+    }
+}
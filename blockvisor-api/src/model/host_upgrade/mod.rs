@@ -0,0 +1,380 @@
+//! Fleet-wide blockvisord upgrade campaigns.
+//!
+//! A campaign upgrades every host running an older blockvisord to a target
+//! bundle version. Hosts are tracked individually so that the rollout in
+//! `rollout` can advance each region in batches and stop on failures.
+
+pub mod rollout;
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From, FromStr};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::UniqueViolation;
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::HostId;
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+use crate::model::Host;
+use crate::model::command::CommandId;
+use crate::model::region::RegionId;
+use crate::model::sql::Version;
+
+use super::schema::{host_upgrade_campaigns, host_upgrades, hosts, sql_types};
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to find the active host upgrade campaign: {0}
+    Active(diesel::result::Error),
+    /// Failed to find host upgrade campaign `{0}`: {1}
+    ById(HostUpgradeCampaignId, diesel::result::Error),
+    /// Failed to create host upgrade campaign: {0}
+    Create(diesel::result::Error),
+    /// Failed to create host upgrades: {0}
+    CreateUpgrades(diesel::result::Error),
+    /// Failed to find hosts to upgrade: {0}
+    FindHosts(diesel::result::Error),
+    /// Failed to find upgrades for campaign `{0}`: {1}
+    Hosts(HostUpgradeCampaignId, diesel::result::Error),
+    /// Host upgrade campaign is not {0:?}.
+    NotStatus(CampaignStatus),
+    /// Failed to update host upgrade `{0}`: {1}
+    Update(HostUpgradeId, diesel::result::Error),
+    /// Failed to update host upgrade campaign `{0}`: {1}
+    UpdateCampaign(HostUpgradeCampaignId, diesel::result::Error),
+    /// Batch size must be at least 1.
+    ZeroBatchSize,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            Create(DatabaseError(UniqueViolation, _)) => {
+                Status::already_exists("A host upgrade campaign is already active.")
+            }
+            ById(_, NotFound) => Status::not_found("Host upgrade campaign not found."),
+            ZeroBatchSize => Status::invalid_argument("batch_size"),
+            NotStatus(CampaignStatus::Running) => {
+                Status::failed_precondition("Host upgrade campaign is not running.")
+            }
+            NotStatus(CampaignStatus::Paused) => {
+                Status::failed_precondition("Host upgrade campaign is not paused.")
+            }
+            _ => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From, FromStr)]
+pub struct HostUpgradeCampaignId(Uuid);
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From, FromStr)]
+pub struct HostUpgradeId(Uuid);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumHostUpgradeCampaignStatus"]
+pub enum CampaignStatus {
+    Running,
+    Paused,
+    Completed,
+}
+
+impl From<CampaignStatus> for api::HostUpgradeCampaignStatus {
+    fn from(status: CampaignStatus) -> Self {
+        match status {
+            CampaignStatus::Running => api::HostUpgradeCampaignStatus::Running,
+            CampaignStatus::Paused => api::HostUpgradeCampaignStatus::Paused,
+            CampaignStatus::Completed => api::HostUpgradeCampaignStatus::Completed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumHostUpgradeStatus"]
+pub enum UpgradeStatus {
+    Pending,
+    Upgrading,
+    Succeeded,
+    Failed,
+}
+
+impl From<UpgradeStatus> for api::HostUpgradeStatus {
+    fn from(status: UpgradeStatus) -> Self {
+        match status {
+            UpgradeStatus::Pending => api::HostUpgradeStatus::Pending,
+            UpgradeStatus::Upgrading => api::HostUpgradeStatus::Upgrading,
+            UpgradeStatus::Succeeded => api::HostUpgradeStatus::Succeeded,
+            UpgradeStatus::Failed => api::HostUpgradeStatus::Failed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = host_upgrade_campaigns)]
+pub struct HostUpgradeCampaign {
+    pub id: HostUpgradeCampaignId,
+    pub bundle_version: Version,
+    pub status: CampaignStatus,
+    pub batch_size: i64,
+    pub max_failures: i64,
+    pub paused_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl HostUpgradeCampaign {
+    pub async fn by_id(id: HostUpgradeCampaignId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        host_upgrade_campaigns::table
+            .find(id)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ById(id, err))
+    }
+
+    /// The campaign that is currently running or paused, if any.
+    pub async fn active(conn: &mut Conn<'_>) -> Result<Option<Self>, Error> {
+        host_upgrade_campaigns::table
+            .filter(host_upgrade_campaigns::status.ne(CampaignStatus::Completed))
+            .get_result(conn)
+            .await
+            .optional()
+            .map_err(Error::Active)
+    }
+
+    /// Pause a running campaign so that no more hosts are upgraded.
+    ///
+    /// Hosts that are already upgrading will still have their outcome
+    /// recorded.
+    pub async fn pause(self, reason: Option<&str>, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        if self.status != CampaignStatus::Running {
+            return Err(Error::NotStatus(CampaignStatus::Running));
+        }
+
+        diesel::update(host_upgrade_campaigns::table.find(self.id))
+            .set((
+                host_upgrade_campaigns::status.eq(CampaignStatus::Paused),
+                host_upgrade_campaigns::paused_reason.eq(reason),
+                host_upgrade_campaigns::updated_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::UpdateCampaign(self.id, err))
+    }
+
+    /// Resume a paused campaign.
+    ///
+    /// A campaign that paused itself after too many failures will pause again
+    /// unless `max_failures` is raised above the current number of failures.
+    pub async fn resume(
+        self,
+        max_failures: Option<u32>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        if self.status != CampaignStatus::Paused {
+            return Err(Error::NotStatus(CampaignStatus::Paused));
+        }
+
+        let max_failures = max_failures.map_or(self.max_failures, i64::from);
+        diesel::update(host_upgrade_campaigns::table.find(self.id))
+            .set((
+                host_upgrade_campaigns::status.eq(CampaignStatus::Running),
+                host_upgrade_campaigns::max_failures.eq(max_failures),
+                host_upgrade_campaigns::paused_reason.eq(None::<String>),
+                host_upgrade_campaigns::updated_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::UpdateCampaign(self.id, err))
+    }
+
+    pub async fn complete(self, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        diesel::update(host_upgrade_campaigns::table.find(self.id))
+            .set((
+                host_upgrade_campaigns::status.eq(CampaignStatus::Completed),
+                host_upgrade_campaigns::updated_at.eq(Utc::now()),
+                host_upgrade_campaigns::completed_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::UpdateCampaign(self.id, err))
+    }
+
+    /// Every host in this campaign along with its current state.
+    ///
+    /// Hosts deleted since the campaign started are left out.
+    pub async fn hosts(&self, conn: &mut Conn<'_>) -> Result<Vec<(HostUpgrade, Host)>, Error> {
+        host_upgrades::table
+            .inner_join(hosts::table)
+            .filter(host_upgrades::campaign_id.eq(self.id))
+            .filter(hosts::deleted_at.is_null())
+            .select((host_upgrades::all_columns, hosts::all_columns))
+            .order_by(host_upgrades::id)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::Hosts(self.id, err))
+    }
+
+    /// The number of hosts in each `UpgradeStatus`.
+    pub async fn counts(&self, conn: &mut Conn<'_>) -> Result<UpgradeCounts, Error> {
+        let statuses: Vec<UpgradeStatus> = host_upgrades::table
+            .inner_join(hosts::table)
+            .filter(host_upgrades::campaign_id.eq(self.id))
+            .filter(hosts::deleted_at.is_null())
+            .select(host_upgrades::status)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::Hosts(self.id, err))?;
+
+        Ok(UpgradeCounts::from_iter(statuses))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UpgradeCounts {
+    pub pending: usize,
+    pub upgrading: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl FromIterator<UpgradeStatus> for UpgradeCounts {
+    fn from_iter<I: IntoIterator<Item = UpgradeStatus>>(iter: I) -> Self {
+        let mut counts = UpgradeCounts::default();
+        for status in iter {
+            match status {
+                UpgradeStatus::Pending => counts.pending += 1,
+                UpgradeStatus::Upgrading => counts.upgrading += 1,
+                UpgradeStatus::Succeeded => counts.succeeded += 1,
+                UpgradeStatus::Failed => counts.failed += 1,
+            }
+        }
+        counts
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = host_upgrade_campaigns)]
+pub struct NewHostUpgradeCampaign {
+    pub bundle_version: Version,
+    pub batch_size: i64,
+    pub max_failures: i64,
+}
+
+impl NewHostUpgradeCampaign {
+    pub fn new(bundle_version: Version, batch_size: u32, max_failures: u32) -> Result<Self, Error> {
+        if batch_size == 0 {
+            return Err(Error::ZeroBatchSize);
+        }
+
+        Ok(NewHostUpgradeCampaign {
+            bundle_version,
+            batch_size: batch_size.into(),
+            max_failures: max_failures.into(),
+        })
+    }
+
+    /// Create a campaign for every host that is running an older version.
+    ///
+    /// If `region_ids` is not empty then only hosts in those regions are
+    /// upgraded.
+    pub async fn create(
+        self,
+        region_ids: &HashSet<RegionId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<HostUpgradeCampaign, Error> {
+        let target = self.bundle_version.clone();
+        let campaign: HostUpgradeCampaign = diesel::insert_into(host_upgrade_campaigns::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(Error::Create)?;
+
+        let mut query = hosts::table
+            .filter(hosts::deleted_at.is_null())
+            .select((hosts::id, hosts::region_id, hosts::bv_version))
+            .into_boxed();
+        if !region_ids.is_empty() {
+            query = query.filter(hosts::region_id.eq_any(region_ids));
+        }
+        let hosts: Vec<(HostId, RegionId, Version)> =
+            query.get_results(conn).await.map_err(Error::FindHosts)?;
+
+        let upgrades: Vec<_> = hosts
+            .into_iter()
+            .filter(|(_, _, version)| *version < target)
+            .map(|(host_id, region_id, _)| {
+                (
+                    host_upgrades::campaign_id.eq(campaign.id),
+                    host_upgrades::host_id.eq(host_id),
+                    host_upgrades::region_id.eq(region_id),
+                )
+            })
+            .collect();
+        diesel::insert_into(host_upgrades::table)
+            .values(upgrades)
+            .execute(conn)
+            .await
+            .map_err(Error::CreateUpgrades)?;
+
+        Ok(campaign)
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = host_upgrades)]
+pub struct HostUpgrade {
+    pub id: HostUpgradeId,
+    pub campaign_id: HostUpgradeCampaignId,
+    pub host_id: HostId,
+    pub region_id: RegionId,
+    pub status: UpgradeStatus,
+    pub command_id: Option<CommandId>,
+    pub message: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl HostUpgrade {
+    /// Mark this host as upgrading with `command_id`.
+    pub async fn start(&self, command_id: CommandId, conn: &mut Conn<'_>) -> Result<(), Error> {
+        diesel::update(host_upgrades::table.find(self.id))
+            .set((
+                host_upgrades::status.eq(UpgradeStatus::Upgrading),
+                host_upgrades::command_id.eq(command_id),
+                host_upgrades::started_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Update(self.id, err))
+    }
+
+    /// Record the outcome of upgrading this host.
+    pub async fn finish(
+        &self,
+        status: UpgradeStatus,
+        message: Option<&str>,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        diesel::update(host_upgrades::table.find(self.id))
+            .set((
+                host_upgrades::status.eq(status),
+                host_upgrades::message.eq(message),
+                host_upgrades::completed_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Update(self.id, err))
+    }
+}
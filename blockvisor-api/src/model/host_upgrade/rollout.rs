@@ -0,0 +1,433 @@
+//! Advance the active host upgrade campaign.
+//!
+//! Each region is upgraded in batches of `batch_size` hosts, and the next batch
+//! in a region is only started once every host in the current batch has
+//! finished. Only online hosts are started, so offline hosts wait until they
+//! reconnect.
+//!
+//! A host has finished upgrading once it is online and reports a `bv_version`
+//! of at least the target version. It has failed if its `HostUpgrade` command
+//! fails, or if it does not report the target version within the configured
+//! upgrade timeout. The campaign pauses itself once more than `max_failures`
+//! hosts have failed, and completes once every host has finished.
+//!
+//! Every replica runs the rollout, so each pass holds an advisory lock and is
+//! skipped while another replica is advancing the campaign.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use displaydoc::Display;
+use futures_util::FutureExt;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::Context;
+use crate::database::{AdvisoryLock, Conn};
+use crate::grpc::api;
+use crate::model::Host;
+use crate::model::background;
+use crate::model::command::{Command, CommandType, ExitCode, NewCommand};
+use crate::model::host::ConnectionStatus;
+use crate::model::region::RegionId;
+use crate::model::sql::Version;
+
+use super::{CampaignStatus, HostUpgradeCampaign, UpgradeCounts, UpgradeStatus};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Rollout command error: {0}
+    Command(#[from] crate::model::command::Error),
+    /// Rollout command API error: {0}
+    CommandApi(#[from] crate::grpc::command::Error),
+    /// Rollout database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Rollout host upgrade error: {0}
+    HostUpgrade(#[from] super::Error),
+    /// Rollout transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+}
+
+/// Run `advance` forever at the configured interval.
+pub async fn run(ctx: Arc<Context>) {
+    let timeout = *ctx.config.host.upgrade_timeout;
+    let interval = *ctx.config.host.upgrade_interval;
+    background::run_exclusive(&ctx, AdvisoryLock::HostUpgrade, interval, |conn| {
+        advance(timeout, conn).boxed()
+    })
+    .await;
+}
+
+/// Record the outcome of upgrading hosts and start the next batches.
+///
+/// Returns the `HostUpgrade` commands to send to the newly started hosts.
+pub async fn advance(timeout: Duration, conn: &mut Conn<'_>) -> Result<Vec<api::Command>, Error> {
+    let Some(campaign) = HostUpgradeCampaign::active(conn).await? else {
+        return Ok(vec![]);
+    };
+    let target = campaign.bundle_version.clone();
+    let mut hosts = campaign.hosts(conn).await?;
+    let now = Utc::now();
+
+    for (upgrade, host) in &mut hosts {
+        let finished = match upgrade.status {
+            UpgradeStatus::Upgrading => {
+                let exit = match upgrade.command_id {
+                    Some(id) => {
+                        let command = Command::by_id(id, conn).await?;
+                        command.exit_code.map(|code| (code, command.exit_message))
+                    }
+                    None => None,
+                };
+                check(&target, host, exit, upgrade.started_at, timeout, now)
+            }
+            // already upgraded outside of this campaign
+            UpgradeStatus::Pending if is_upgraded(&target, host) => {
+                Some((UpgradeStatus::Succeeded, None))
+            }
+            UpgradeStatus::Pending | UpgradeStatus::Succeeded | UpgradeStatus::Failed => None,
+        };
+
+        if let Some((status, message)) = finished {
+            upgrade.finish(status, message.as_deref(), conn).await?;
+            if status == UpgradeStatus::Failed {
+                warn!(
+                    "Failed to upgrade host {} to {target}: {}",
+                    host.id,
+                    message.as_deref().unwrap_or_default()
+                );
+            }
+            upgrade.status = status;
+        }
+    }
+
+    let counts = hosts
+        .iter()
+        .map(|(upgrade, _)| upgrade.status)
+        .collect::<UpgradeCounts>();
+    let failed = i64::try_from(counts.failed).unwrap_or(i64::MAX);
+
+    if campaign.status == CampaignStatus::Running && failed > campaign.max_failures {
+        let reason = format!("{failed} hosts failed to upgrade.");
+        warn!("Pausing upgrade to {target}: {reason}");
+        campaign.pause(Some(&reason), conn).await?;
+        return Ok(vec![]);
+    } else if counts.pending == 0 && counts.upgrading == 0 {
+        info!("Completed upgrade to {target}: {counts:?}");
+        campaign.complete(conn).await?;
+        return Ok(vec![]);
+    } else if campaign.status != CampaignStatus::Running {
+        return Ok(vec![]);
+    }
+
+    let states: Vec<_> = hosts
+        .iter()
+        .map(|(upgrade, host)| {
+            let online = host.connection_status == ConnectionStatus::Online;
+            (upgrade.region_id, upgrade.status, online)
+        })
+        .collect();
+    let batch_size = usize::try_from(campaign.batch_size).unwrap_or(usize::MAX);
+
+    let upgrade = api::HostUpgrade {
+        bundle_id: Some(api::BundleIdentifier {
+            version: target.to_string(),
+        }),
+    };
+    let mut commands = Vec::new();
+    for index in next_batches(&states, batch_size) {
+        let (upgrade_row, host) = &hosts[index];
+        let command = NewCommand::host(host.id, CommandType::HostUpgrade)?
+            .with_protobuf(&upgrade)
+            .create(conn)
+            .await?;
+        upgrade_row.start(command.id, conn).await?;
+        if let Some(command) = api::Command::from_host(&command)? {
+            commands.push(command);
+        }
+    }
+
+    if !commands.is_empty() {
+        info!("Upgrading {} hosts to {target}.", commands.len());
+    }
+
+    Ok(commands)
+}
+
+fn is_upgraded(target: &Version, host: &Host) -> bool {
+    host.connection_status == ConnectionStatus::Online && host.bv_version >= *target
+}
+
+/// Check whether an upgrading host has finished.
+///
+/// Returns None while the host is still upgrading.
+fn check(
+    target: &Version,
+    host: &Host,
+    exit: Option<(ExitCode, Option<String>)>,
+    started_at: Option<DateTime<Utc>>,
+    timeout: Duration,
+    now: DateTime<Utc>,
+) -> Option<(UpgradeStatus, Option<String>)> {
+    if is_upgraded(target, host) {
+        return Some((UpgradeStatus::Succeeded, None));
+    }
+
+    match exit {
+        Some((ExitCode::Ok, _)) | None => (),
+        Some((code, message)) => {
+            let message =
+                message.map_or_else(|| format!("{code:?}"), |msg| format!("{code:?}: {msg}"));
+            return Some((UpgradeStatus::Failed, Some(message)));
+        }
+    }
+
+    let timeout = chrono::Duration::from_std(timeout).unwrap_or(chrono::Duration::MAX);
+    let started_at = started_at.unwrap_or(now);
+    if now - started_at > timeout {
+        let message = format!("Timed out waiting for host to report version {target}.");
+        return Some((UpgradeStatus::Failed, Some(message)));
+    }
+
+    None
+}
+
+/// Select the hosts to start upgrading next.
+///
+/// Each host is given as its region, upgrade status and whether it is online.
+/// A region with hosts still upgrading is left alone, otherwise up to
+/// `batch_size` of its pending online hosts are returned as indexes into
+/// `hosts`.
+fn next_batches(hosts: &[(RegionId, UpgradeStatus, bool)], batch_size: usize) -> Vec<usize> {
+    let busy: HashSet<RegionId> = hosts
+        .iter()
+        .filter(|(_, status, _)| *status == UpgradeStatus::Upgrading)
+        .map(|(region_id, _, _)| *region_id)
+        .collect();
+
+    let mut started: HashMap<RegionId, usize> = HashMap::new();
+    let mut batch = Vec::new();
+    for (index, (region_id, status, online)) in hosts.iter().enumerate() {
+        if *status != UpgradeStatus::Pending || !online || busy.contains(region_id) {
+            continue;
+        }
+
+        let count = started.entry(*region_id).or_default();
+        if *count < batch_size {
+            *count += 1;
+            batch.push(index);
+        }
+    }
+
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+    use diesel_async::RunQueryDsl;
+    use uuid::Uuid;
+
+    use crate::auth::resource::HostId;
+    use crate::model::command::UpdateCommand;
+    use crate::model::host_upgrade::{HostUpgrade, NewHostUpgradeCampaign};
+    use crate::model::schema::{host_upgrades, hosts};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn next_batches_waits_for_each_region() {
+        let region_a = RegionId::from(Uuid::new_v4());
+        let region_b = RegionId::from(Uuid::new_v4());
+        let hosts = [
+            (region_a, UpgradeStatus::Succeeded, true),
+            (region_a, UpgradeStatus::Pending, true),
+            (region_a, UpgradeStatus::Pending, false),
+            (region_a, UpgradeStatus::Pending, true),
+            (region_a, UpgradeStatus::Pending, true),
+            (region_b, UpgradeStatus::Upgrading, true),
+            (region_b, UpgradeStatus::Pending, true),
+        ];
+
+        // region b still has a host upgrading, and offline hosts are skipped
+        assert_eq!(next_batches(&hosts, 2), vec![1, 3]);
+        assert_eq!(next_batches(&hosts, 5), vec![1, 3, 4]);
+    }
+
+    async fn start_campaign(max_failures: u32, conn: &mut Conn<'_>) -> HostUpgradeCampaign {
+        let target = "0.2.0".parse().unwrap();
+        let campaign = NewHostUpgradeCampaign::new(target, 1, max_failures)
+            .unwrap()
+            .create(&HashSet::new(), conn)
+            .await
+            .unwrap();
+
+        diesel::update(hosts::table)
+            .set(hosts::connection_status.eq(ConnectionStatus::Online))
+            .execute(conn)
+            .await
+            .unwrap();
+
+        campaign
+    }
+
+    async fn report_version(host_id: HostId, version: &str, conn: &mut Conn<'_>) {
+        let version: Version = version.parse().unwrap();
+        diesel::update(hosts::table.find(host_id))
+            .set(hosts::bv_version.eq(version))
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    async fn upgrading(campaign: &HostUpgradeCampaign, conn: &mut Conn<'_>) -> Vec<HostUpgrade> {
+        campaign
+            .hosts(conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|(upgrade, _)| upgrade.status == UpgradeStatus::Upgrading)
+            .map(|(upgrade, _)| upgrade)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn finished_batches_advance_the_campaign() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let mut conn = db.conn().await;
+        let campaign = start_campaign(0, &mut conn).await;
+
+        // both hosts share a region so only one is started at a time
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert_eq!(commands.len(), 1);
+        let first = upgrading(&campaign, &mut conn).await;
+        assert_eq!(first.len(), 1);
+
+        // nothing changes until the host reports the target version
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+
+        report_version(first[0].host_id, "0.2.0", &mut conn).await;
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert_eq!(commands.len(), 1);
+        let second = upgrading(&campaign, &mut conn).await;
+        assert_eq!(second.len(), 1);
+        assert_ne!(second[0].host_id, first[0].host_id);
+
+        report_version(second[0].host_id, "0.2.1", &mut conn).await;
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+
+        let campaign = HostUpgradeCampaign::by_id(campaign.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Completed);
+        let counts = campaign.counts(&mut conn).await.unwrap();
+        assert_eq!(counts.succeeded, 2);
+    }
+
+    #[tokio::test]
+    async fn failed_batch_pauses_until_resumed() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let mut conn = db.conn().await;
+        let campaign = start_campaign(0, &mut conn).await;
+
+        advance(TIMEOUT, &mut conn).await.unwrap();
+        let first = upgrading(&campaign, &mut conn).await;
+        let update = UpdateCommand {
+            exit_code: Some(ExitCode::InternalError),
+            exit_message: Some("disk full".into()),
+            retry_hint_seconds: None,
+            completed_at: Some(Utc::now()),
+        };
+        update
+            .apply(first[0].command_id.unwrap(), &mut conn)
+            .await
+            .unwrap();
+
+        // one failure is more than `max_failures`
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+        let campaign = HostUpgradeCampaign::by_id(campaign.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Paused);
+        assert!(campaign.paused_reason.is_some());
+        let counts = campaign.counts(&mut conn).await.unwrap();
+        assert_eq!(counts.failed, 1);
+        assert_eq!(counts.pending, 1);
+
+        let failed: HostUpgrade = host_upgrades::table
+            .find(first[0].id)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(failed.status, UpgradeStatus::Failed);
+        assert_eq!(failed.message.as_deref(), Some("InternalError: disk full"));
+
+        // a paused campaign starts no more hosts
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+
+        // resuming without raising `max_failures` pauses again
+        campaign.resume(None, &mut conn).await.unwrap();
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+        let campaign = HostUpgradeCampaign::by_id(campaign.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Paused);
+
+        // raising it starts the next host
+        let campaign = campaign.resume(Some(1), &mut conn).await.unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Running);
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert_eq!(commands.len(), 1);
+        let second = upgrading(&campaign, &mut conn).await;
+        assert_eq!(second.len(), 1);
+        assert_ne!(second[0].host_id, first[0].host_id);
+    }
+
+    #[tokio::test]
+    async fn upgrade_fails_after_timeout() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let mut conn = db.conn().await;
+        let campaign = start_campaign(1, &mut conn).await;
+
+        advance(TIMEOUT, &mut conn).await.unwrap();
+        let first = upgrading(&campaign, &mut conn).await;
+
+        // still within the timeout
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert!(commands.is_empty());
+        assert_eq!(upgrading(&campaign, &mut conn).await.len(), 1);
+
+        let started_at = Utc::now() - chrono::Duration::hours(2);
+        diesel::update(host_upgrades::table.find(first[0].id))
+            .set(host_upgrades::started_at.eq(started_at))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let commands = advance(TIMEOUT, &mut conn).await.unwrap();
+        assert_eq!(commands.len(), 1);
+
+        let timed_out: HostUpgrade = host_upgrades::table
+            .find(first[0].id)
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(timed_out.status, UpgradeStatus::Failed);
+        assert!(timed_out.message.unwrap().starts_with("Timed out"));
+
+        // one failure is within `max_failures` so the campaign keeps running
+        let campaign = HostUpgradeCampaign::by_id(campaign.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(campaign.status, CampaignStatus::Running);
+    }
+}
@@ -12,10 +12,11 @@ use thiserror::Error;
 use crate::auth::resource::OrgId;
 use crate::database::Conn;
 use crate::grpc::{Status, api};
-use crate::model::schema::archives;
+use crate::model::protocol::ProtocolId;
+use crate::model::schema::{archives, configs, images, nodes, protocol_versions};
 use crate::store::StoreKey;
 
-use super::{ImageId, ImagePropertyId};
+use super::{ImageId, ImageLifecycle, ImagePropertyId};
 
 #[derive(Debug, DisplayDoc, Error)]
 pub enum Error {
@@ -27,8 +28,14 @@ pub enum Error {
     ByImageId(ImageId, diesel::result::Error),
     /// Failed to find archives for image id `{0:?}` and property ids `{1:?}`: {2}
     ByPropertyIds(ImageId, Vec<ImagePropertyId>, diesel::result::Error),
+    /// Failed to find archives referenced by nodes: {0}
+    Referenced(diesel::result::Error),
+    /// Failed to find archive store keys: {0}
+    StoreKeys(diesel::result::Error),
     /// Failed to update archive {0}: {1}
     Update(ArchiveId, diesel::result::Error),
+    /// Failed to find archives with protocols: {0}
+    WithProtocol(diesel::result::Error),
 }
 
 impl From<Error> for Status {
@@ -98,6 +105,57 @@ impl Archive {
             .await
             .map_err(|err| Error::ByPropertyIds(image_id, property_ids, err))
     }
+
+    /// Every archive along with the protocol of its image.
+    pub async fn with_protocol(conn: &mut Conn<'_>) -> Result<Vec<(Self, ProtocolId)>, Error> {
+        archives::table
+            .inner_join(images::table.inner_join(protocol_versions::table))
+            .select((archives::all_columns, protocol_versions::protocol_id))
+            .get_results(conn)
+            .await
+            .map_err(Error::WithProtocol)
+    }
+
+    /// Partition every `StoreKey` by whether it may still be downloaded.
+    ///
+    /// An archive is unreferenced once no live node config uses it and its
+    /// image has reached end of life, so no new node can use it either. A
+    /// `StoreKey` is returned in the second set only when every archive that
+    /// shares it is unreferenced.
+    pub async fn store_keys(
+        conn: &mut Conn<'_>,
+    ) -> Result<(HashSet<StoreKey>, HashSet<StoreKey>), Error> {
+        let referenced: HashSet<ArchiveId> = configs::table
+            .inner_join(nodes::table)
+            .filter(nodes::deleted_at.is_null())
+            .select(configs::archive_id)
+            .distinct()
+            .get_results(conn)
+            .await
+            .map_err(Error::Referenced)?
+            .into_iter()
+            .collect();
+
+        let archives: Vec<(ArchiveId, StoreKey, ImageLifecycle)> = archives::table
+            .inner_join(images::table)
+            .select((archives::id, archives::store_key, images::lifecycle))
+            .get_results(conn)
+            .await
+            .map_err(Error::StoreKeys)?;
+
+        let mut live = HashSet::new();
+        let mut unreferenced = HashSet::new();
+        for (id, store_key, lifecycle) in archives {
+            if referenced.contains(&id) || lifecycle != ImageLifecycle::EndOfLife {
+                unreferenced.remove(&store_key);
+                live.insert(store_key);
+            } else if !live.contains(&store_key) {
+                unreferenced.insert(store_key);
+            }
+        }
+
+        Ok((live, unreferenced))
+    }
 }
 
 impl From<Archive> for api::Archive {
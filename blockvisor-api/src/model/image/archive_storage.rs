@@ -0,0 +1,106 @@
+//! The storage used by each `StoreKey` in the archive bucket.
+//!
+//! Rows are measured from the bucket by each garbage collection pass, so they
+//! also account for data versions that predate manifest verification.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_async::RunQueryDsl;
+use displaydoc::Display;
+use thiserror::Error;
+
+use crate::database::Conn;
+use crate::grpc::Status;
+use crate::model::schema::archive_storage;
+use crate::store::StoreKey;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to find all archive storage: {0}
+    All(diesel::result::Error),
+    /// Failed to parse data version count: {0}
+    DataVersions(std::num::TryFromIntError),
+    /// Failed to delete archive storage for store key `{0}`: {1}
+    Delete(StoreKey, diesel::result::Error),
+    /// Failed to parse stored bytes: {0}
+    StoredBytes(std::num::TryFromIntError),
+    /// Failed to upsert archive storage for store key `{0}`: {1}
+    Upsert(StoreKey, diesel::result::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            All(_) | DataVersions(_) | Delete(..) | StoredBytes(_) | Upsert(..) => {
+                Status::internal("Internal error.")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = archive_storage)]
+pub struct ArchiveStorage {
+    pub store_key: StoreKey,
+    pub data_versions: i64,
+    pub stored_bytes: i64,
+    pub measured_at: DateTime<Utc>,
+}
+
+impl ArchiveStorage {
+    pub async fn all(conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        archive_storage::table
+            .get_results(conn)
+            .await
+            .map_err(Error::All)
+    }
+
+    pub async fn delete(store_key: &StoreKey, conn: &mut Conn<'_>) -> Result<(), Error> {
+        diesel::delete(archive_storage::table.find(store_key))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Delete(store_key.clone(), err))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = archive_storage)]
+pub struct NewArchiveStorage {
+    pub store_key: StoreKey,
+    pub data_versions: i64,
+    pub stored_bytes: i64,
+}
+
+impl NewArchiveStorage {
+    pub fn new(
+        store_key: StoreKey,
+        data_versions: usize,
+        stored_bytes: u64,
+    ) -> Result<Self, Error> {
+        Ok(NewArchiveStorage {
+            store_key,
+            data_versions: i64::try_from(data_versions).map_err(Error::DataVersions)?,
+            stored_bytes: i64::try_from(stored_bytes).map_err(Error::StoredBytes)?,
+        })
+    }
+
+    pub async fn upsert(self, conn: &mut Conn<'_>) -> Result<ArchiveStorage, Error> {
+        let store_key = self.store_key.clone();
+
+        diesel::insert_into(archive_storage::table)
+            .values(self)
+            .on_conflict(archive_storage::store_key)
+            .do_update()
+            .set((
+                archive_storage::data_versions.eq(excluded(archive_storage::data_versions)),
+                archive_storage::stored_bytes.eq(excluded(archive_storage::stored_bytes)),
+                archive_storage::measured_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Upsert(store_key, err))
+    }
+}
@@ -0,0 +1,214 @@
+//! Verification state of the data versions stored under a `StoreKey`.
+//!
+//! A row is written each time a download manifest is uploaded. Data versions
+//! without a row predate verification and are treated as complete.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::database::Conn;
+use crate::grpc::Status;
+use crate::model::schema::{archive_versions, sql_types};
+use crate::store::manifest::DownloadManifest;
+use crate::store::{StoreKey, Verification};
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to find archive version {1} for store key `{0}`: {2}
+    ByVersion(StoreKey, u64, diesel::result::Error),
+    /// Failed to parse manifest chunk count: {0}
+    Chunks(std::num::TryFromIntError),
+    /// Data version {0} does not fit in an i64: {1}
+    DataVersion(u64, std::num::TryFromIntError),
+    /// Failed to delete archive versions for store key `{0}`: {1}
+    Delete(StoreKey, diesel::result::Error),
+    /// Failed to find incomplete versions for store key `{0}`: {1}
+    Incomplete(StoreKey, diesel::result::Error),
+    /// Failed to parse stored bytes: {0}
+    StoredBytes(std::num::TryFromIntError),
+    /// Failed to parse manifest total size: {0}
+    TotalSize(std::num::TryFromIntError),
+    /// Failed to upsert archive version {1} for store key `{0}`: {2}
+    Upsert(StoreKey, i64, diesel::result::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            DataVersion(..) => Status::invalid_argument("data_version"),
+            ByVersion(..) | Chunks(_) | Delete(..) | Incomplete(..) | StoredBytes(_)
+            | TotalSize(_) | Upsert(..) => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
+pub struct ArchiveVersionId(Uuid);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumArchiveVersionStatus"]
+pub enum ArchiveVersionStatus {
+    Verified,
+    Incomplete,
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = archive_versions)]
+pub struct ArchiveVersion {
+    pub id: ArchiveVersionId,
+    pub store_key: StoreKey,
+    pub data_version: i64,
+    pub status: ArchiveVersionStatus,
+    pub total_size: i64,
+    pub stored_bytes: i64,
+    pub chunks: i64,
+    pub missing_chunks: i64,
+    pub problem: Option<String>,
+    pub verified_at: DateTime<Utc>,
+}
+
+impl ArchiveVersion {
+    pub async fn by_version(
+        store_key: &StoreKey,
+        data_version: u64,
+        conn: &mut Conn<'_>,
+    ) -> Result<Option<Self>, Error> {
+        let version =
+            i64::try_from(data_version).map_err(|err| Error::DataVersion(data_version, err))?;
+
+        archive_versions::table
+            .filter(archive_versions::store_key.eq(store_key))
+            .filter(archive_versions::data_version.eq(version))
+            .get_result(conn)
+            .await
+            .optional()
+            .map_err(|err| Error::ByVersion(store_key.clone(), data_version, err))
+    }
+
+    /// The set of data versions for a `StoreKey` that failed verification.
+    pub async fn incomplete(
+        store_key: &StoreKey,
+        conn: &mut Conn<'_>,
+    ) -> Result<HashSet<u64>, Error> {
+        let versions: Vec<i64> = archive_versions::table
+            .filter(archive_versions::store_key.eq(store_key))
+            .filter(archive_versions::status.eq(ArchiveVersionStatus::Incomplete))
+            .select(archive_versions::data_version)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::Incomplete(store_key.clone(), err))?;
+
+        Ok(versions
+            .into_iter()
+            .filter_map(|version| u64::try_from(version).ok())
+            .collect())
+    }
+
+    /// Delete the verification state of data versions removed from the store.
+    ///
+    /// If `data_versions` is None then all versions for the `StoreKey` are
+    /// deleted.
+    pub async fn delete(
+        store_key: &StoreKey,
+        data_versions: Option<&[u64]>,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        let mut query = diesel::delete(archive_versions::table)
+            .filter(archive_versions::store_key.eq(store_key))
+            .into_boxed();
+
+        if let Some(versions) = data_versions {
+            let versions = versions
+                .iter()
+                .map(|&version| {
+                    i64::try_from(version).map_err(|err| Error::DataVersion(version, err))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            query = query.filter(archive_versions::data_version.eq_any(versions));
+        }
+
+        query
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Delete(store_key.clone(), err))
+    }
+
+    pub const fn is_verified(&self) -> bool {
+        matches!(self.status, ArchiveVersionStatus::Verified)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = archive_versions)]
+pub struct NewArchiveVersion {
+    pub store_key: StoreKey,
+    pub data_version: i64,
+    pub status: ArchiveVersionStatus,
+    pub total_size: i64,
+    pub stored_bytes: i64,
+    pub chunks: i64,
+    pub missing_chunks: i64,
+    pub problem: Option<String>,
+}
+
+impl NewArchiveVersion {
+    pub fn new(
+        store_key: StoreKey,
+        data_version: u64,
+        manifest: &DownloadManifest,
+        verification: &Verification,
+    ) -> Result<Self, Error> {
+        let missing = verification.missing.len() + verification.mismatched.len();
+
+        Ok(NewArchiveVersion {
+            store_key,
+            data_version: i64::try_from(data_version)
+                .map_err(|err| Error::DataVersion(data_version, err))?,
+            status: if verification.is_complete() {
+                ArchiveVersionStatus::Verified
+            } else {
+                ArchiveVersionStatus::Incomplete
+            },
+            total_size: i64::try_from(manifest.total_size).map_err(Error::TotalSize)?,
+            stored_bytes: i64::try_from(verification.stored_bytes).map_err(Error::StoredBytes)?,
+            chunks: i64::try_from(manifest.chunks.len()).map_err(Error::Chunks)?,
+            missing_chunks: i64::try_from(missing).map_err(Error::Chunks)?,
+            problem: verification.problem(),
+        })
+    }
+
+    /// Insert or replace the verification state of this data version.
+    pub async fn upsert(self, conn: &mut Conn<'_>) -> Result<ArchiveVersion, Error> {
+        let store_key = self.store_key.clone();
+        let data_version = self.data_version;
+
+        diesel::insert_into(archive_versions::table)
+            .values(self)
+            .on_conflict((archive_versions::store_key, archive_versions::data_version))
+            .do_update()
+            .set((
+                archive_versions::status.eq(excluded(archive_versions::status)),
+                archive_versions::total_size.eq(excluded(archive_versions::total_size)),
+                archive_versions::stored_bytes.eq(excluded(archive_versions::stored_bytes)),
+                archive_versions::chunks.eq(excluded(archive_versions::chunks)),
+                archive_versions::missing_chunks.eq(excluded(archive_versions::missing_chunks)),
+                archive_versions::problem.eq(excluded(archive_versions::problem)),
+                archive_versions::verified_at.eq(Utc::now()),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Upsert(store_key, data_version, err))
+    }
+}
@@ -17,6 +17,7 @@ use displaydoc::Display as DisplayDoc;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::auth::AuthZ;
@@ -25,6 +26,7 @@ use crate::database::Conn;
 use crate::grpc::{Status, common};
 use crate::model::image::Image;
 use crate::model::image::property::{ImageProperty, ImagePropertyKey};
+use crate::model::node::rule as node_rule;
 use crate::model::schema::{configs, sql_types};
 use crate::model::sql::Version;
 use crate::store::StoreKey;
@@ -213,7 +215,7 @@ impl NodeConfig {
         conn: &mut Conn<'_>,
     ) -> Result<Self, Error> {
         let properties = ImageProperty::by_image_id(image.id, conn).await?;
-        let values = PropertyMap::new(properties).apply_overrides(new_values);
+        let values = PropertyMap::new(properties).apply_overrides(new_values)?;
 
         let mut rules = ImageRule::by_image_id(image.id, conn)
             .await?
@@ -227,13 +229,20 @@ impl NodeConfig {
         Self::generate_from(image, org_id, values, rules, conn).await
     }
 
+    /// Upgrade to a new image, keeping changed property values and firewall
+    /// rules, with `node_rules` applied on top of the new image rules.
     pub async fn upgrade(
         self,
         image: Image,
         org_id: Option<OrgId>,
+        node_rules: Vec<FirewallRule>,
         conn: &mut Conn<'_>,
     ) -> Result<Self, Error> {
         let old_properties = ImageProperty::by_image_id(self.image.image_id, conn).await?;
+        let was_required: HashSet<_> = old_properties
+            .iter()
+            .filter_map(|property| property.is_required.then(|| property.key.clone()))
+            .collect();
         let old_defaults = old_properties
             .into_iter()
             .to_map_keep_last(|property| (property.key, property.default_value));
@@ -251,7 +260,13 @@ impl NodeConfig {
             })
             .collect();
         let new_properties = ImageProperty::by_image_id(image.id, conn).await?;
-        let new_values = PropertyMap::new(new_properties).apply_overrides(changed_values);
+        let newly_required = new_properties
+            .iter()
+            .filter(|property| property.is_required && !was_required.contains(&property.key))
+            .map(|property| property.key.clone())
+            .collect();
+        let new_values =
+            PropertyMap::new(new_properties).carry_overrides(changed_values, &newly_required)?;
 
         let old_rules = ImageRule::by_image_id(self.image.image_id, conn)
             .await?
@@ -264,11 +279,25 @@ impl NodeConfig {
                 true
             }
         });
-        let mut new_rules = ImageRule::by_image_id(image.id, conn)
-            .await?
-            .into_iter()
+        let image_rules = ImageRule::by_image_id(image.id, conn).await?;
+        // node rules that clash with the new image rules are dropped
+        let node_rules = node_rules.into_iter().filter(|rule| {
+            match node_rule::validate(std::slice::from_ref(rule), &image_rules) {
+                Ok(()) => true,
+                Err(err) => {
+                    warn!(
+                        "Dropping node rule `{}` for image {}: {err}",
+                        rule.key, image.id
+                    );
+                    false
+                }
+            }
+        });
+        let mut new_rules = image_rules
+            .iter()
+            .cloned()
             .to_map_keep_last(|rule| (rule.key.clone(), FirewallRule::from(rule)));
-        for rule in changed_rules {
+        for rule in changed_rules.chain(node_rules) {
             new_rules.insert(rule.key.clone(), rule);
         }
         let new_rules = new_rules.into_values().collect();
@@ -384,7 +413,7 @@ impl NodeConfig {
                 image_uri: self.image.image_uri,
                 archive_id: self.image.archive_id,
                 store_key: self.image.store_key,
-                values: property_map.apply_overrides(overrides),
+                values: property_map.apply_overrides(overrides)?,
                 min_babel_version: self.image.min_babel_version,
             },
             firewall: if let Some(config) = new_firewall {
@@ -508,7 +537,7 @@ impl ToSql<Jsonb, Pg> for Ramdisks {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RamdiskConfig {
     pub mount: String,
     pub size_bytes: u64,
@@ -2,11 +2,17 @@ pub mod archive;
 
 pub use archive::{Archive, ArchiveId};
 
+pub mod archive_storage;
+pub use archive_storage::{ArchiveStorage, NewArchiveStorage};
+
+pub mod archive_version;
+pub use archive_version::{ArchiveVersion, ArchiveVersionStatus, NewArchiveVersion};
+
 pub mod config;
 pub use config::{Config, ConfigId, NewConfig, NodeConfig};
 
 pub mod property;
-pub use property::{ImageProperty, ImagePropertyId, NewProperty, UiType};
+pub use property::{ImageProperty, ImagePropertyId, NewProperty, UiType, ValueType};
 
 pub mod rule;
 pub use rule::{FirewallRule, ImageRule, ImageRuleId, NewImageRule};
@@ -18,6 +24,7 @@ use derive_more::{Deref, Display, From, FromStr};
 use diesel::prelude::*;
 use diesel::result::Error::NotFound;
 use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
 use diesel_derive_newtype::DieselNewType;
 use displaydoc::Display as DisplayDoc;
 use serde::{Deserialize, Serialize};
@@ -27,9 +34,9 @@ use uuid::Uuid;
 use crate::auth::AuthZ;
 use crate::auth::resource::OrgId;
 use crate::database::Conn;
-use crate::grpc::Status;
+use crate::grpc::{Status, common};
 use crate::model::protocol::{VersionId, Visibility};
-use crate::model::schema::images;
+use crate::model::schema::{images, sql_types};
 use crate::model::sql::Version;
 
 use self::config::Ramdisks;
@@ -49,6 +56,8 @@ pub enum Error {
     Create(diesel::result::Error),
     /// Failed to get the last build for protocol version `{0}`: {1}
     LatestBuild(VersionId, diesel::result::Error),
+    /// Unknown ImageLifecycle.
+    UnknownLifecycle,
     /// Failed to update image id {0}: {1}
     Update(ImageId, diesel::result::Error),
 }
@@ -60,6 +69,7 @@ impl From<Error> for Status {
             ById(_, NotFound) => Status::not_found("Image not found."),
             ByBuild(_, _, _, NotFound) => Status::not_found("No image for that build."),
             Update(_, NotFound) => Status::not_found("No image updated."),
+            UnknownLifecycle => Status::invalid_argument("lifecycle"),
             _ => Status::internal("Internal error."),
         }
     }
@@ -101,6 +111,9 @@ pub struct Image {
     pub updated_at: Option<DateTime<Utc>>,
     pub min_babel_version: Version,
     pub dns_scheme: Option<String>,
+    pub lifecycle: ImageLifecycle,
+    pub lifecycle_reason: Option<String>,
+    pub sunset_at: Option<DateTime<Utc>>,
 }
 
 impl Image {
@@ -119,6 +132,16 @@ impl Image {
             .map_err(|err| Error::ById(id, err))
     }
 
+    /// The lifecycle of an image, regardless of its visibility.
+    pub async fn lifecycle(id: ImageId, conn: &mut Conn<'_>) -> Result<ImageLifecycle, Error> {
+        images::table
+            .find(id)
+            .select(images::lifecycle)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ById(id, err))
+    }
+
     pub async fn by_version(
         version_id: VersionId,
         org_id: Option<OrgId>,
@@ -152,6 +175,25 @@ impl Image {
             .map_err(|err| Error::LatestBuild(version_id, err))
     }
 
+    /// The latest build of a version that may still be used for new nodes.
+    pub async fn latest_active_build(
+        version_id: VersionId,
+        org_id: Option<OrgId>,
+        authz: &AuthZ,
+        conn: &mut Conn<'_>,
+    ) -> Result<Option<Self>, Error> {
+        images::table
+            .filter(images::protocol_version_id.eq(version_id))
+            .filter(images::org_id.eq(org_id).or(images::org_id.is_null()))
+            .filter(images::visibility.eq_any(<&[Visibility]>::from(authz)))
+            .filter(images::lifecycle.eq(ImageLifecycle::Active))
+            .order_by(images::build_version.desc())
+            .first(conn)
+            .await
+            .optional()
+            .map_err(|err| Error::LatestBuild(version_id, err))
+    }
+
     pub async fn by_versions(
         version_ids: &HashSet<VersionId>,
         org_id: Option<OrgId>,
@@ -230,3 +272,69 @@ impl UpdateImage {
             .map_err(|err| Error::Update(id, err))
     }
 }
+
+/// Replace the lifecycle state of an image, along with its reason and sunset.
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = images, treat_none_as_null = true)]
+pub struct UpdateLifecycle {
+    pub id: ImageId,
+    pub lifecycle: ImageLifecycle,
+    pub lifecycle_reason: Option<String>,
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl UpdateLifecycle {
+    pub async fn update(self, conn: &mut Conn<'_>) -> Result<Image, Error> {
+        let id = self.id;
+        diesel::update(images::table.find(id))
+            .set((self, images::updated_at.eq(Utc::now())))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Update(id, err))
+    }
+}
+
+/// Whether an image build may be used.
+///
+/// Deprecated builds keep running but can't be used for new nodes, and nodes
+/// on end-of-life builds must be upgraded before their config can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumImageLifecycle"]
+pub enum ImageLifecycle {
+    Active,
+    Deprecated,
+    EndOfLife,
+}
+
+impl ImageLifecycle {
+    pub const fn description(self) -> &'static str {
+        match self {
+            ImageLifecycle::Active => "active",
+            ImageLifecycle::Deprecated => "deprecated",
+            ImageLifecycle::EndOfLife => "end of life",
+        }
+    }
+}
+
+impl From<ImageLifecycle> for common::ImageLifecycle {
+    fn from(lifecycle: ImageLifecycle) -> Self {
+        match lifecycle {
+            ImageLifecycle::Active => common::ImageLifecycle::Active,
+            ImageLifecycle::Deprecated => common::ImageLifecycle::Deprecated,
+            ImageLifecycle::EndOfLife => common::ImageLifecycle::EndOfLife,
+        }
+    }
+}
+
+impl TryFrom<common::ImageLifecycle> for ImageLifecycle {
+    type Error = Error;
+
+    fn try_from(lifecycle: common::ImageLifecycle) -> Result<Self, Self::Error> {
+        match lifecycle {
+            common::ImageLifecycle::Unspecified => Err(Error::UnknownLifecycle),
+            common::ImageLifecycle::Active => Ok(ImageLifecycle::Active),
+            common::ImageLifecycle::Deprecated => Ok(ImageLifecycle::Deprecated),
+            common::ImageLifecycle::EndOfLife => Ok(ImageLifecycle::EndOfLife),
+        }
+    }
+}
@@ -1,13 +1,21 @@
 use std::collections::{HashMap, HashSet};
 
 use derive_more::{Deref, Display, From, Into};
+use diesel::deserialize::{FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::sql_types::Jsonb;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
 use diesel::result::Error::NotFound;
+use diesel::serialize::{Output, ToSql};
 use diesel_async::RunQueryDsl;
 use diesel_derive_enum::DbEnum;
 use diesel_derive_newtype::DieselNewType;
 use displaydoc::Display as DisplayDoc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::database::Conn;
@@ -29,10 +37,28 @@ pub enum Error {
     ByImageId(ImageId, diesel::result::Error),
     /// Failed to find image properties for image ids `{0:?}`: {1}
     ByImageIds(HashSet<ImageId>, diesel::result::Error),
+    /// Constraint `max_length` must be positive: {0}
+    ConstraintMaxLength(u32),
+    /// Constraint `pattern` is not a valid regex: {0}
+    ConstraintPattern(regex::Error),
+    /// Constraint `min_value` {0} is greater than `max_value` {1}.
+    ConstraintRange(f64, f64),
+    /// Constraints `min_value` and `max_value` are only valid for numeric values.
+    ConstraintValueType,
+    /// Property `{0}` can't depend on itself.
+    DependencySelf(ImagePropertyKey),
+    /// Property `{0}` depends on unknown property `{1}`.
+    DependencyTarget(ImagePropertyKey, ImagePropertyKey),
+    /// Value for `{0}` {1}.
+    DependencyViolation(ImagePropertyKey, DependencyViolation),
     /// Multiple defaults set for image property key group `{0}`.
     GroupMultipleDefaults(ImagePropertyGroup),
     /// No default set for image property key group `{0}`.
     GroupNoDefault(ImagePropertyGroup),
+    /// Default value for `{0}` {1}.
+    InvalidDefault(ImagePropertyKey, Violation),
+    /// Value for `{0}` {1}.
+    InvalidValue(ImagePropertyKey, Violation),
     /// ImagePropertyGroup is not lower-kebab-case: {0}
     PropertyGroupChars(String),
     /// ImagePropertyGroup must be at least 3 characters: {0}
@@ -41,6 +67,8 @@ pub enum Error {
     PropertyKeyChars(String),
     /// ImagePropertyKey must be at least 3 characters: {0}
     PropertyKeyLen(String),
+    /// Unknown DependencyKind.
+    UnknownDependencyKind,
     /// Unknown UiType.
     UnknownUiType,
 }
@@ -50,9 +78,26 @@ impl From<Error> for Status {
         use Error::*;
         match err {
             ById(_, NotFound) => Status::not_found("Image property ot found."),
+            ConstraintMaxLength(_) => Status::invalid_argument("constraints.max_length"),
+            ConstraintPattern(_) => Status::invalid_argument("constraints.pattern"),
+            ConstraintRange(..) => Status::invalid_argument("constraints.min_value"),
+            ConstraintValueType => Status::invalid_argument("constraints.value_type"),
+            DependencySelf(key) | DependencyTarget(key, _) => {
+                Status::invalid_argument(format!("dependencies: {key}"))
+            }
+            DependencyViolation(key, violation) => {
+                Status::invalid_argument(format!("property.{key}: {violation}"))
+            }
             GroupMultipleDefaults(_) | GroupNoDefault(_) => {
                 Status::failed_precondition("is_group_default")
             }
+            InvalidDefault(key, violation) => {
+                Status::invalid_argument(format!("default_value: {key} {violation}"))
+            }
+            InvalidValue(key, violation) => {
+                Status::invalid_argument(format!("property.{key}: {violation}"))
+            }
+            UnknownDependencyKind => Status::invalid_argument("dependencies.kind"),
             UnknownUiType => Status::invalid_argument("ui_type"),
             _ => Status::internal("Internal error."),
         }
@@ -62,10 +107,31 @@ impl From<Error> for Status {
 #[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
 pub struct ImagePropertyId(Uuid);
 
-#[derive(Clone, derive_more::Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, Into)]
+#[derive(
+    Clone,
+    derive_more::Debug,
+    Display,
+    Hash,
+    PartialEq,
+    Eq,
+    DieselNewType,
+    Deref,
+    Into,
+    Serialize,
+    Deserialize,
+)]
 #[debug("{_0}")]
+#[serde(try_from = "String", into = "String")]
 pub struct ImagePropertyKey(String);
 
+impl TryFrom<String> for ImagePropertyKey {
+    type Error = Error;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        Self::new(key)
+    }
+}
+
 impl ImagePropertyKey {
     pub fn new(key: String) -> Result<Self, Error> {
         if key.len() < 3 {
@@ -112,6 +178,14 @@ pub struct ImageProperty {
     pub add_disk_bytes: Option<i64>,
     pub display_name: Option<String>,
     pub display_group: Option<String>,
+    pub value_type: ValueType,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub pattern: Option<String>,
+    pub allowed_values: Vec<Option<String>>,
+    pub max_length: Option<i32>,
+    pub is_required: bool,
+    pub dependencies: Dependencies,
 }
 
 impl ImageProperty {
@@ -152,10 +226,23 @@ impl ImageProperty {
             .await
             .map_err(|err| Error::ByImageIds(image_ids.clone(), err))
     }
+
+    pub fn constraints(&self) -> PropertyConstraints {
+        PropertyConstraints {
+            value_type: self.value_type,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            pattern: self.pattern.clone(),
+            allowed_values: self.allowed_values.iter().flatten().cloned().collect(),
+            max_length: self.max_length,
+            is_required: self.is_required,
+        }
+    }
 }
 
 impl From<ImageProperty> for api::ImageProperty {
     fn from(property: ImageProperty) -> Self {
+        let constraints = property.constraints().into();
         api::ImageProperty {
             image_property_id: property.id.to_string(),
             image_id: property.image_id.to_string(),
@@ -172,6 +259,13 @@ impl From<ImageProperty> for api::ImageProperty {
             add_cpu_cores: property.add_cpu_cores,
             add_memory_bytes: property.add_memory_bytes,
             add_disk_bytes: property.add_disk_bytes,
+            constraints: Some(constraints),
+            dependencies: property
+                .dependencies
+                .0
+                .into_iter()
+                .map(Into::into)
+                .collect(),
         }
     }
 }
@@ -193,6 +287,14 @@ pub struct NewProperty {
     pub add_disk_bytes: Option<i64>,
     pub display_name: Option<String>,
     pub display_group: Option<String>,
+    pub value_type: ValueType,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub pattern: Option<String>,
+    pub allowed_values: Vec<Option<String>>,
+    pub max_length: Option<i32>,
+    pub is_required: bool,
+    pub dependencies: Dependencies,
 }
 
 impl NewProperty {
@@ -212,15 +314,42 @@ impl NewProperty {
             add_disk_bytes: property.add_disk_bytes,
             display_name: property.display_name,
             display_group: property.display_group,
+            value_type: property.value_type,
+            min_value: property.min_value,
+            max_value: property.max_value,
+            pattern: property.pattern,
+            allowed_values: property.allowed_values,
+            max_length: property.max_length,
+            is_required: property.is_required,
+            dependencies: property.dependencies,
         }
     }
 
     pub fn from(image_id: ImageId, property: api::AddImageProperty) -> Result<Self, Error> {
         let ui_type = property.ui_type().try_into()?;
+        let key = ImagePropertyKey::new(property.key)?;
+        let constraints = property
+            .constraints
+            .map(PropertyConstraints::try_from)
+            .transpose()?
+            .unwrap_or_default();
+        let dependencies = property
+            .dependencies
+            .into_iter()
+            .map(PropertyDependency::try_from)
+            .collect::<Result<_, _>>()
+            .map(Dependencies)?;
+
+        // an empty default is allowed so that required values must be set on create
+        if !property.default_value.is_empty() {
+            constraints
+                .check(&property.default_value)
+                .map_err(|violation| Error::InvalidDefault(key.clone(), violation))?;
+        }
 
         Ok(NewProperty {
             image_id,
-            key: ImagePropertyKey::new(property.key)?,
+            key,
             key_group: property
                 .key_group
                 .map(ImagePropertyGroup::new)
@@ -236,6 +365,14 @@ impl NewProperty {
             add_disk_bytes: property.add_disk_bytes,
             display_name: property.display_name,
             display_group: property.display_group,
+            value_type: constraints.value_type,
+            min_value: constraints.min_value,
+            max_value: constraints.max_value,
+            pattern: constraints.pattern,
+            allowed_values: constraints.allowed_values.into_iter().map(Some).collect(),
+            max_length: constraints.max_length,
+            is_required: constraints.is_required,
+            dependencies,
         })
     }
 
@@ -260,6 +397,20 @@ impl NewProperty {
             }
         }
 
+        let keys: HashSet<_> = properties.iter().map(|property| &property.key).collect();
+        for property in &properties {
+            for dependency in property.dependencies.iter() {
+                if dependency.key == property.key {
+                    return Err(Error::DependencySelf(property.key.clone()));
+                } else if !keys.contains(&dependency.key) {
+                    return Err(Error::DependencyTarget(
+                        property.key.clone(),
+                        dependency.key.clone(),
+                    ));
+                }
+            }
+        }
+
         diesel::insert_into(image_properties::table)
             .values(properties)
             .get_results(conn)
@@ -358,8 +509,9 @@ impl TryFrom<common::PropertyValueConfig> for PropertyValueConfig {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "sql_types::EnumUiType"]
+#[serde(rename_all = "snake_case")]
 pub enum UiType {
     Switch,
     Text,
@@ -392,10 +544,314 @@ impl TryFrom<common::UiType> for UiType {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
+#[ExistingTypePath = "sql_types::EnumValueType"]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    #[default]
+    Text,
+    Integer,
+    Float,
+    Boolean,
+}
+
+impl ValueType {
+    const fn is_numeric(self) -> bool {
+        matches!(self, ValueType::Integer | ValueType::Float)
+    }
+}
+
+impl From<ValueType> for common::ValueType {
+    fn from(value_type: ValueType) -> Self {
+        match value_type {
+            ValueType::Text => common::ValueType::Text,
+            ValueType::Integer => common::ValueType::Integer,
+            ValueType::Float => common::ValueType::Float,
+            ValueType::Boolean => common::ValueType::Boolean,
+        }
+    }
+}
+
+impl From<common::ValueType> for ValueType {
+    fn from(value_type: common::ValueType) -> Self {
+        match value_type {
+            common::ValueType::Unspecified | common::ValueType::Text => ValueType::Text,
+            common::ValueType::Integer => ValueType::Integer,
+            common::ValueType::Float => ValueType::Float,
+            common::ValueType::Boolean => ValueType::Boolean,
+        }
+    }
+}
+
+/// The reason a property value was rejected.
+#[derive(Clone, Debug, DisplayDoc, PartialEq)]
+pub enum Violation {
+    /// must be at most {0}
+    AboveMax(f64),
+    /// must be at least {0}
+    BelowMin(f64),
+    /// must be one of {0:?}
+    NotAllowed(Vec<String>),
+    /// must be `true` or `false`
+    NotBoolean,
+    /// must be an integer
+    NotInteger,
+    /// must be a number
+    NotNumber,
+    /// must match pattern `{0}`
+    Pattern(String),
+    /// is required
+    Required,
+    /// must be at most {0} characters
+    TooLong(i32),
+}
+
+/// Validation rules for the value of an image property.
+#[derive(Clone, Debug, Default)]
+pub struct PropertyConstraints {
+    pub value_type: ValueType,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub pattern: Option<String>,
+    pub allowed_values: Vec<String>,
+    pub max_length: Option<i32>,
+    pub is_required: bool,
+}
+
+impl PropertyConstraints {
+    /// Check that `value` satisfies these constraints.
+    ///
+    /// An empty value is only rejected for required properties, as optional
+    /// properties may be left unset.
+    pub fn check(&self, value: &str) -> Result<(), Violation> {
+        if value.is_empty() {
+            return if self.is_required {
+                Err(Violation::Required)
+            } else {
+                Ok(())
+            };
+        }
+
+        match self.value_type {
+            ValueType::Text => (),
+            ValueType::Integer => {
+                value.parse::<i64>().map_err(|_| Violation::NotInteger)?;
+            }
+            ValueType::Float => match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => (),
+                _ => return Err(Violation::NotNumber),
+            },
+            ValueType::Boolean => {
+                value.parse::<bool>().map_err(|_| Violation::NotBoolean)?;
+            }
+        }
+
+        if self.value_type.is_numeric() {
+            let number: f64 = value.parse().map_err(|_| Violation::NotNumber)?;
+            if let Some(min) = self.min_value.filter(|min| number < *min) {
+                return Err(Violation::BelowMin(min));
+            }
+            if let Some(max) = self.max_value.filter(|max| number > *max) {
+                return Err(Violation::AboveMax(max));
+            }
+        }
+
+        if let Some(max_length) = self.max_length {
+            let max = usize::try_from(max_length).unwrap_or_default();
+            if value.chars().count() > max {
+                return Err(Violation::TooLong(max_length));
+            }
+        }
+
+        if !self.allowed_values.is_empty() && !self.allowed_values.iter().any(|v| v == value) {
+            return Err(Violation::NotAllowed(self.allowed_values.clone()));
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let matches = anchored(pattern).is_ok_and(|regex| regex.is_match(value));
+            if !matches {
+                return Err(Violation::Pattern(pattern.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<common::PropertyConstraints> for PropertyConstraints {
+    type Error = Error;
+
+    fn try_from(constraints: common::PropertyConstraints) -> Result<Self, Self::Error> {
+        let value_type: ValueType = constraints.value_type().into();
+        let has_range = constraints.min_value.is_some() || constraints.max_value.is_some();
+        if has_range && !value_type.is_numeric() {
+            return Err(Error::ConstraintValueType);
+        }
+        if let (Some(min), Some(max)) = (constraints.min_value, constraints.max_value) {
+            if min > max {
+                return Err(Error::ConstraintRange(min, max));
+            }
+        }
+        if let Some(pattern) = &constraints.pattern {
+            anchored(pattern).map_err(Error::ConstraintPattern)?;
+        }
+        let max_length = constraints
+            .max_length
+            .map(|len| match i32::try_from(len) {
+                Ok(max) if max > 0 => Ok(max),
+                _ => Err(Error::ConstraintMaxLength(len)),
+            })
+            .transpose()?;
+
+        Ok(PropertyConstraints {
+            value_type,
+            min_value: constraints.min_value,
+            max_value: constraints.max_value,
+            pattern: constraints.pattern,
+            allowed_values: constraints.allowed_values,
+            max_length,
+            is_required: constraints.is_required,
+        })
+    }
+}
+
+impl From<PropertyConstraints> for common::PropertyConstraints {
+    fn from(constraints: PropertyConstraints) -> Self {
+        common::PropertyConstraints {
+            value_type: common::ValueType::from(constraints.value_type).into(),
+            min_value: constraints.min_value,
+            max_value: constraints.max_value,
+            pattern: constraints.pattern,
+            allowed_values: constraints.allowed_values,
+            max_length: constraints
+                .max_length
+                .and_then(|len| u32::try_from(len).ok()),
+            is_required: constraints.is_required,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyKind {
+    /// The property can only be set if the target is also set.
+    Requires,
+    /// The property can't be set if the target is also set.
+    ConflictsWith,
+    /// The property only applies, and is only shown, when the target is set.
+    VisibleWhen,
+}
+
+impl From<DependencyKind> for common::DependencyKind {
+    fn from(kind: DependencyKind) -> Self {
+        match kind {
+            DependencyKind::Requires => common::DependencyKind::Requires,
+            DependencyKind::ConflictsWith => common::DependencyKind::ConflictsWith,
+            DependencyKind::VisibleWhen => common::DependencyKind::VisibleWhen,
+        }
+    }
+}
+
+impl TryFrom<common::DependencyKind> for DependencyKind {
+    type Error = Error;
+
+    fn try_from(kind: common::DependencyKind) -> Result<Self, Self::Error> {
+        match kind {
+            common::DependencyKind::Unspecified => Err(Error::UnknownDependencyKind),
+            common::DependencyKind::Requires => Ok(DependencyKind::Requires),
+            common::DependencyKind::ConflictsWith => Ok(DependencyKind::ConflictsWith),
+            common::DependencyKind::VisibleWhen => Ok(DependencyKind::VisibleWhen),
+        }
+    }
+}
+
+/// A rule relating a property to the value of another property `key`.
+///
+/// The target is considered set when it has a value of `value`, or when
+/// `value` is None and the target has any value other than empty or `false`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PropertyDependency {
+    pub kind: DependencyKind,
+    pub key: ImagePropertyKey,
+    pub value: Option<String>,
+}
+
+impl PropertyDependency {
+    fn is_met(&self, target: Option<&str>) -> bool {
+        match (&self.value, target) {
+            (Some(expected), Some(actual)) => expected == actual,
+            (None, Some(actual)) => is_set(actual),
+            (_, None) => false,
+        }
+    }
+}
+
+impl TryFrom<common::PropertyDependency> for PropertyDependency {
+    type Error = Error;
+
+    fn try_from(dependency: common::PropertyDependency) -> Result<Self, Self::Error> {
+        Ok(PropertyDependency {
+            kind: dependency.kind().try_into()?,
+            key: ImagePropertyKey::new(dependency.target_key)?,
+            value: dependency.target_value,
+        })
+    }
+}
+
+impl From<PropertyDependency> for common::PropertyDependency {
+    fn from(dependency: PropertyDependency) -> Self {
+        common::PropertyDependency {
+            kind: common::DependencyKind::from(dependency.kind).into(),
+            target_key: dependency.key.0,
+            target_value: dependency.value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, AsExpression, Deref, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = Jsonb)]
+pub struct Dependencies(Vec<PropertyDependency>);
+
+impl FromSql<Jsonb, Pg> for Dependencies {
+    fn from_sql(value: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        serde_json::from_value(FromSql::<Jsonb, Pg>::from_sql(value)?).map_err(Into::into)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for Dependencies {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        let json = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&json, &mut out.reborrow())
+    }
+}
+
+/// The reason a property value breaks a dependency rule.
+#[derive(Clone, Debug, DisplayDoc, PartialEq, Eq)]
+pub enum DependencyViolation {
+    /// conflicts with `{0}`
+    ConflictsWith(ImagePropertyKey),
+    /// only applies when `{0}` is set
+    NotVisible(ImagePropertyKey),
+    /// requires `{0}`
+    Requires(ImagePropertyKey),
+}
+
+/// Whether a property value counts as set for dependency rules.
+fn is_set(value: &str) -> bool {
+    !value.is_empty() && value != "false"
+}
+
+/// Compile `pattern` so that it must match the whole value.
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{pattern})$"))
+}
+
 pub struct PropertyMap {
     pub key_to_value: HashMap<ImagePropertyKey, NewImagePropertyValue>,
     pub key_to_group: HashMap<ImagePropertyKey, ImagePropertyGroup>,
     pub group_to_keys: HashMap<ImagePropertyGroup, Vec<ImagePropertyKey>>,
+    pub key_to_constraints: HashMap<ImagePropertyKey, PropertyConstraints>,
+    pub key_to_dependencies: HashMap<ImagePropertyKey, (Dependencies, String)>,
 }
 
 impl PropertyMap {
@@ -403,8 +859,17 @@ impl PropertyMap {
         let mut key_to_value = HashMap::new();
         let mut key_to_group = HashMap::new();
         let mut group_to_keys = HashMap::<_, Vec<_>>::new();
+        let mut key_to_constraints = HashMap::new();
+        let mut key_to_dependencies = HashMap::new();
 
         for property in properties {
+            key_to_constraints.insert(property.key.clone(), property.constraints());
+            if !property.dependencies.is_empty() {
+                let default = property.default_value.clone();
+                let dependencies = property.dependencies.clone();
+                key_to_dependencies.insert(property.key.clone(), (dependencies, default));
+            }
+
             if let Some(group) = &property.key_group {
                 key_to_group.insert(property.key.clone(), group.clone());
                 group_to_keys
@@ -424,13 +889,48 @@ impl PropertyMap {
             key_to_value,
             key_to_group,
             group_to_keys,
+            key_to_constraints,
+            key_to_dependencies,
         }
     }
 
+    /// Apply `overrides` to the default property values.
+    ///
+    /// Returns an error naming the first key whose resulting value does not
+    /// satisfy the property's constraints or dependency rules.
     pub fn apply_overrides(
+        self,
+        overrides: Vec<NewImagePropertyValue>,
+    ) -> Result<Vec<PropertyValueConfig>, Error> {
+        self.apply(overrides, &HashSet::new())
+    }
+
+    /// Carry the `overrides` of an existing node over to the default property
+    /// values of a new image version.
+    ///
+    /// New defaults, and carried values of `newly_required` properties, are
+    /// checked as in `apply_overrides`. Any other carried value was accepted
+    /// by the previous version, so it is kept with a warning rather than
+    /// failing the upgrade if it breaks a rule of the new version.
+    pub fn carry_overrides(
+        self,
+        overrides: Vec<NewImagePropertyValue>,
+        newly_required: &HashSet<ImagePropertyKey>,
+    ) -> Result<Vec<PropertyValueConfig>, Error> {
+        let kept = overrides
+            .iter()
+            .filter(|value| !newly_required.contains(&value.key))
+            .map(|value| value.key.clone())
+            .collect();
+        self.apply(overrides, &kept)
+    }
+
+    /// Apply `overrides`, only warning about rules broken by the `kept` keys.
+    fn apply(
         mut self,
         overrides: Vec<NewImagePropertyValue>,
-    ) -> Vec<PropertyValueConfig> {
+        kept: &HashSet<ImagePropertyKey>,
+    ) -> Result<Vec<PropertyValueConfig>, Error> {
         for value in overrides {
             if let Some(group) = self.key_to_group.get(&value.key) {
                 if let Some(keys) = self.group_to_keys.get(group) {
@@ -443,17 +943,283 @@ impl PropertyMap {
             self.key_to_value.insert(value.key.clone(), value);
         }
 
+        self.check_dependencies(kept)?;
+
         self.key_to_value
             .into_values()
             .map(|new_value| {
+                if let Some(constraints) = self.key_to_constraints.get(&new_value.key) {
+                    match constraints.check(&new_value.value) {
+                        Ok(()) => (),
+                        Err(violation) if kept.contains(&new_value.key) => {
+                            warn!(
+                                "Keeping existing value for `{}` although it {violation}.",
+                                new_value.key
+                            );
+                        }
+                        Err(violation) => {
+                            return Err(Error::InvalidValue(new_value.key, violation));
+                        }
+                    }
+                }
+
                 let key_group = self.key_to_group.get(&new_value.key).cloned();
-                PropertyValueConfig {
+                Ok(PropertyValueConfig {
                     key: new_value.key,
                     key_group,
                     value: new_value.value,
                     has_changed: new_value.has_changed,
-                }
+                })
             })
             .collect()
     }
+
+    /// Check the dependency rules of each property against the final values.
+    ///
+    /// A `visible_when` property may keep its default while hidden, but can't
+    /// be given any other value.
+    fn check_dependencies(&self, kept: &HashSet<ImagePropertyKey>) -> Result<(), Error> {
+        for (key, (dependencies, default)) in &self.key_to_dependencies {
+            let Some(value) = self.key_to_value.get(key).map(|value| value.value.as_str()) else {
+                continue;
+            };
+
+            for dependency in dependencies.iter() {
+                let target = self
+                    .key_to_value
+                    .get(&dependency.key)
+                    .map(|value| value.value.as_str());
+                let is_met = dependency.is_met(target);
+                let violation = match dependency.kind {
+                    DependencyKind::Requires if is_set(value) && !is_met => {
+                        DependencyViolation::Requires(dependency.key.clone())
+                    }
+                    DependencyKind::ConflictsWith if is_set(value) && is_met => {
+                        DependencyViolation::ConflictsWith(dependency.key.clone())
+                    }
+                    DependencyKind::VisibleWhen if value != default && !is_met => {
+                        DependencyViolation::NotVisible(dependency.key.clone())
+                    }
+                    _ => continue,
+                };
+
+                if kept.contains(key) {
+                    warn!("Keeping existing value for `{key}` although it {violation}.");
+                    continue;
+                }
+                return Err(Error::DependencyViolation(key.clone(), violation));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(key: &str, value: &str) -> NewImagePropertyValue {
+        NewImagePropertyValue {
+            key: ImagePropertyKey::new(key.into()).unwrap(),
+            value: value.into(),
+            has_changed: true,
+        }
+    }
+
+    #[test]
+    fn constraints_reject_invalid_values() {
+        let port = PropertyConstraints {
+            value_type: ValueType::Integer,
+            min_value: Some(1024.0),
+            max_value: Some(65535.0),
+            ..Default::default()
+        };
+        assert_eq!(port.check("8545"), Ok(()));
+        assert_eq!(port.check(""), Ok(()));
+        assert_eq!(port.check("85.45"), Err(Violation::NotInteger));
+        assert_eq!(port.check("80"), Err(Violation::BelowMin(1024.0)));
+        assert_eq!(port.check("70000"), Err(Violation::AboveMax(65535.0)));
+
+        let network = PropertyConstraints {
+            allowed_values: vec!["mainnet".into(), "testnet".into()],
+            is_required: true,
+            ..Default::default()
+        };
+        assert_eq!(network.check("mainnet"), Ok(()));
+        assert_eq!(network.check(""), Err(Violation::Required));
+        assert!(matches!(
+            network.check("main"),
+            Err(Violation::NotAllowed(_))
+        ));
+
+        let name = PropertyConstraints {
+            pattern: Some("[a-z]+".into()),
+            max_length: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(name.check("node"), Ok(()));
+        assert_eq!(name.check("nodes1"), Err(Violation::TooLong(5)));
+        assert_eq!(
+            name.check("no de"),
+            Err(Violation::Pattern("[a-z]+".into()))
+        );
+    }
+
+    #[test]
+    fn apply_overrides_names_invalid_key() {
+        let key = ImagePropertyKey::new("rpc-port".into()).unwrap();
+        let mut map = PropertyMap {
+            key_to_value: HashMap::new(),
+            key_to_group: HashMap::new(),
+            group_to_keys: HashMap::new(),
+            key_to_constraints: HashMap::new(),
+            key_to_dependencies: HashMap::new(),
+        };
+        map.key_to_constraints.insert(
+            key.clone(),
+            PropertyConstraints {
+                value_type: ValueType::Integer,
+                ..Default::default()
+            },
+        );
+
+        let err = map
+            .apply_overrides(vec![value("rpc-port", "abc")])
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue(k, Violation::NotInteger) if k == key));
+    }
+
+    #[test]
+    fn apply_overrides_checks_dependencies() {
+        let key = |key: &str| ImagePropertyKey::new(key.into()).unwrap();
+        let dependency = |kind, target: &str, value: Option<&str>| PropertyDependency {
+            kind,
+            key: key(target),
+            value: value.map(Into::into),
+        };
+
+        let map = || {
+            let mut key_to_value = HashMap::new();
+            for (k, v) in [("mode", "full"), ("pruning-depth", "0"), ("tls", "false")] {
+                key_to_value.insert(key(k), value(k, v));
+            }
+            let mut key_to_dependencies = HashMap::new();
+            key_to_dependencies.insert(
+                key("pruning-depth"),
+                (
+                    Dependencies(vec![dependency(
+                        DependencyKind::VisibleWhen,
+                        "mode",
+                        Some("archive"),
+                    )]),
+                    "0".to_string(),
+                ),
+            );
+            key_to_dependencies.insert(
+                key("tls"),
+                (
+                    Dependencies(vec![
+                        dependency(DependencyKind::Requires, "mode", Some("archive")),
+                        dependency(DependencyKind::ConflictsWith, "pruning-depth", Some("1")),
+                    ]),
+                    "false".to_string(),
+                ),
+            );
+
+            PropertyMap {
+                key_to_value,
+                key_to_group: HashMap::new(),
+                group_to_keys: HashMap::new(),
+                key_to_constraints: HashMap::new(),
+                key_to_dependencies,
+            }
+        };
+
+        assert!(map().apply_overrides(vec![]).is_ok());
+
+        let err = map()
+            .apply_overrides(vec![value("pruning-depth", "100")])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DependencyViolation(k, DependencyViolation::NotVisible(_)) if k == key("pruning-depth")
+        ));
+        assert!(
+            map()
+                .apply_overrides(vec![
+                    value("mode", "archive"),
+                    value("pruning-depth", "100")
+                ])
+                .is_ok()
+        );
+
+        let err = map()
+            .apply_overrides(vec![value("tls", "true")])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DependencyViolation(k, DependencyViolation::Requires(_)) if k == key("tls")
+        ));
+
+        let overrides = vec![
+            value("mode", "archive"),
+            value("pruning-depth", "1"),
+            value("tls", "true"),
+        ];
+        let err = map().apply_overrides(overrides).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DependencyViolation(_, DependencyViolation::ConflictsWith(_))
+        ));
+    }
+
+    #[test]
+    fn carry_overrides_keeps_existing_values() {
+        let key = |key: &str| ImagePropertyKey::new(key.into()).unwrap();
+        let integer = |is_required| PropertyConstraints {
+            value_type: ValueType::Integer,
+            is_required,
+            ..Default::default()
+        };
+
+        let map = || {
+            let mut key_to_value = HashMap::new();
+            for (k, v) in [("rpc-port", "8545"), ("peers", "")] {
+                key_to_value.insert(key(k), value(k, v));
+            }
+            let mut key_to_constraints = HashMap::new();
+            key_to_constraints.insert(key("rpc-port"), integer(false));
+            key_to_constraints.insert(key("peers"), integer(true));
+
+            PropertyMap {
+                key_to_value,
+                key_to_group: HashMap::new(),
+                group_to_keys: HashMap::new(),
+                key_to_constraints,
+                key_to_dependencies: HashMap::new(),
+            }
+        };
+
+        // a value the node already had is kept even if it is now invalid
+        let values = map()
+            .carry_overrides(
+                vec![value("rpc-port", "abc"), value("peers", "8")],
+                &HashSet::new(),
+            )
+            .unwrap();
+        let port = values.iter().find(|v| v.key == key("rpc-port")).unwrap();
+        assert_eq!(port.value, "abc");
+
+        // but a newly required property must still be valid
+        let newly_required = HashSet::from([key("peers")]);
+        let err = map()
+            .carry_overrides(vec![value("peers", "")], &newly_required)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidValue(k, Violation::Required) if k == key("peers")));
+
+        // and so must a new default
+        let err = map().carry_overrides(vec![], &HashSet::new()).unwrap_err();
+        assert!(matches!(err, Error::InvalidValue(k, Violation::Required) if k == key("peers")));
+    }
 }
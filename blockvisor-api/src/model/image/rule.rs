@@ -46,6 +46,8 @@ impl From<Error> for Status {
         use Error::*;
         match err {
             ById(_, NotFound) => Status::not_found("Image rule not found."),
+            ParseIpCidr(_) => Status::invalid_argument("rules.ips"),
+            ParsePort(_) => Status::invalid_argument("rules.ports"),
             UnknownAction => Status::invalid_argument("action"),
             UnknownDirection => Status::invalid_argument("direction"),
             UnknownProtocol => Status::invalid_argument("protocol"),
@@ -350,8 +352,9 @@ impl TryFrom<common::PortName> for PortName {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "sql_types::EnumFirewallProtocol"]
+#[serde(rename_all = "snake_case")]
 pub enum FirewallProtocol {
     Tcp,
     Udp,
@@ -381,8 +384,9 @@ impl TryFrom<common::FirewallProtocol> for FirewallProtocol {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "sql_types::EnumFirewallDirection"]
+#[serde(rename_all = "snake_case")]
 pub enum FirewallDirection {
     Inbound,
     Outbound,
@@ -409,8 +413,9 @@ impl TryFrom<common::FirewallDirection> for FirewallDirection {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "sql_types::EnumFirewallAction"]
+#[serde(rename_all = "snake_case")]
 pub enum FirewallAction {
     Allow,
     Drop,
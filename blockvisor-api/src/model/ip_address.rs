@@ -1,6 +1,10 @@
 use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use derive_more::{Deref, From};
+use diesel::dsl;
 use diesel::prelude::*;
 use diesel::result::DatabaseErrorKind::UniqueViolation;
 use diesel::result::Error::{DatabaseError, NotFound};
@@ -10,27 +14,46 @@ use displaydoc::Display;
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::auth::resource::HostId;
+use crate::auth::resource::{HostId, NodeId};
 use crate::database::Conn;
 use crate::grpc::Status;
-use crate::model::sql::IpNetwork;
+use crate::model::sql::{self, IpNetwork};
 
 use super::schema::{ip_addresses, nodes};
 
+/// The most addresses that may be added from a single CIDR range.
+const MAX_RANGE_SIZE: usize = 65_536;
+
 #[derive(Debug, Display, Error)]
 pub enum Error {
     /// Failed to find assigned ip addresses for hosts `{0:?}`: {1}
     AssignedForHosts(HashSet<HostId>, diesel::result::Error),
     /// Failed to create new ip addresses: {0}
     BulkCreate(diesel::result::Error),
+    /// Failed to find ip addresses for host {0}: {1}
+    ByHost(HostId, diesel::result::Error),
+    /// Failed to find ip address `{0}`: {1}
+    ByIp(IpNetwork, diesel::result::Error),
+    /// Failed to delete ip address `{0}`: {1}
+    Delete(IpNetwork, diesel::result::Error),
     /// Failed to delete ip addresses for host {0}: {1}
     DeleteForHost(HostId, diesel::result::Error),
     /// Failed to find ip address for hosts `{0:?}`: {1}
     FindForHosts(HashSet<HostId>, diesel::result::Error),
     /// Failed to find ip addresses in use: {0}
     FindInUse(diesel::result::Error),
+    /// IP address `{0}` is held by node `{1}`.
+    HeldByNode(IpNetwork, NodeId),
     /// Failed to get next IP for host {0}: {1}
     NextForHost(HostId, diesel::result::Error),
+    /// Failed to quarantine ip addresses for host {0}: {1}
+    Quarantine(HostId, diesel::result::Error),
+    /// CIDR range `{0}` has more than {MAX_RANGE_SIZE} addresses.
+    RangeSize(ipnetwork::IpNetwork),
+    /// Failed to release ip address `{0}`: {1}
+    Release(IpNetwork, diesel::result::Error),
+    /// Failed to reserve ip address `{0}`: {1}
+    Reserve(IpNetwork, diesel::result::Error),
     /// Failed to update ip address range: {0}
     Update(diesel::result::Error),
 }
@@ -42,8 +65,14 @@ impl From<Error> for Status {
             BulkCreate(DatabaseError(UniqueViolation, _)) => {
                 Status::already_exists("Ip address already exists.")
             }
-            AssignedForHosts(_, NotFound) => Status::not_found("IP address not found."),
+            AssignedForHosts(_, NotFound)
+            | ByIp(_, NotFound)
+            | Delete(_, NotFound)
+            | Release(_, NotFound)
+            | Reserve(_, NotFound) => Status::not_found("IP address not found."),
+            HeldByNode(..) => Status::failed_precondition("IP address is held by a node."),
             NextForHost(_, NotFound) => Status::failed_precondition("host has no ips"),
+            RangeSize(_) => Status::out_of_range("cidrs"),
             _ => Status::internal("Internal error."),
         }
     }
@@ -52,11 +81,30 @@ impl From<Error> for Status {
 #[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
 pub struct IpAddressId(Uuid);
 
+/// The IP version of an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    const fn family(self) -> i32 {
+        match self {
+            IpFamily::V4 => 4,
+            IpFamily::V6 => 6,
+        }
+    }
+}
+
 #[derive(Debug, Queryable)]
 pub struct IpAddress {
     pub id: IpAddressId,
     pub ip: IpNetwork,
     pub host_id: HostId,
+    pub reserved_at: Option<DateTime<Utc>>,
+    pub reserved_reason: Option<String>,
+    pub quarantined_until: Option<DateTime<Utc>>,
 }
 
 impl IpAddress {
@@ -71,30 +119,62 @@ impl IpAddress {
             .map_err(|err| Error::FindForHosts(host_ids.clone(), err))
     }
 
+    /// All addresses of a host, along with the node holding each one.
+    pub async fn by_host(
+        host_id: HostId,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<(Self, Option<NodeId>)>, Error> {
+        ip_addresses::table
+            .left_join(nodes::table.on(held_by_node()))
+            .filter(ip_addresses::host_id.eq(host_id))
+            .order_by(ip_addresses::ip)
+            .select((ip_addresses::all_columns, nodes::id.nullable()))
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ByHost(host_id, err))
+    }
+
+    /// Find an address, along with the node holding it.
+    pub async fn by_ip(
+        ip: IpNetwork,
+        conn: &mut Conn<'_>,
+    ) -> Result<(Self, Option<NodeId>), Error> {
+        ip_addresses::table
+            .left_join(nodes::table.on(held_by_node()))
+            .filter(ip_addresses::ip.eq(ip))
+            .select((ip_addresses::all_columns, nodes::id.nullable()))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ByIp(ip, err))
+    }
+
     pub async fn assigned_for_hosts(
         host_ids: &HashSet<HostId>,
         conn: &mut Conn<'_>,
     ) -> Result<Vec<Self>, Error> {
         ip_addresses::table
-            .left_join(nodes::table.on(ip_addresses::ip.eq(nodes::ip_address)))
+            .left_join(nodes::table.on(held_by_node()))
             .filter(ip_addresses::host_id.eq_any(host_ids))
             .filter(nodes::id.is_not_null())
-            .filter(nodes::deleted_at.is_null())
             .select(ip_addresses::all_columns)
             .get_results(conn)
             .await
             .map_err(|err| Error::AssignedForHosts(host_ids.clone(), err))
     }
 
+    /// The next address of some family that is free for a new node.
+    ///
+    /// Reserved addresses and addresses that are still in quarantine after
+    /// being released by a node are skipped.
     pub async fn next_for_host(
         host_id: HostId,
+        family: IpFamily,
         conn: &mut Conn<'_>,
     ) -> Result<Option<Self>, Error> {
         let ids_in_use: Vec<Uuid> = ip_addresses::table
-            .left_join(nodes::table.on(ip_addresses::ip.eq(nodes::ip_address)))
+            .left_join(nodes::table.on(held_by_node()))
             .filter(ip_addresses::host_id.eq(host_id))
             .filter(nodes::id.is_not_null())
-            .filter(nodes::deleted_at.is_null())
             .select(ip_addresses::id)
             .load(conn)
             .await
@@ -103,6 +183,13 @@ impl IpAddress {
         let result = ip_addresses::table
             .filter(ip_addresses::host_id.eq(host_id))
             .filter(ip_addresses::id.ne_all(ids_in_use))
+            .filter(ip_addresses::reserved_at.is_null())
+            .filter(
+                ip_addresses::quarantined_until
+                    .is_null()
+                    .or(ip_addresses::quarantined_until.lt(Utc::now())),
+            )
+            .filter(sql::family(ip_addresses::ip).eq(family.family()))
             .select(ip_addresses::all_columns)
             .limit(1)
             .for_update()
@@ -117,6 +204,76 @@ impl IpAddress {
         }
     }
 
+    /// Hold back an address from new nodes until it is released.
+    pub async fn reserve(
+        ip: IpNetwork,
+        reason: Option<&str>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        if let (_, Some(node_id)) = Self::by_ip(ip, conn).await? {
+            return Err(Error::HeldByNode(ip, node_id));
+        }
+
+        diesel::update(ip_addresses::table.filter(ip_addresses::ip.eq(ip)))
+            .set((
+                ip_addresses::reserved_at.eq(Utc::now()),
+                ip_addresses::reserved_reason.eq(reason),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Reserve(ip, err))
+    }
+
+    /// Make an address available to new nodes, lifting any reservation or
+    /// quarantine.
+    pub async fn release(ip: IpNetwork, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        diesel::update(ip_addresses::table.filter(ip_addresses::ip.eq(ip)))
+            .set((
+                ip_addresses::reserved_at.eq(None::<DateTime<Utc>>),
+                ip_addresses::reserved_reason.eq(None::<String>),
+                ip_addresses::quarantined_until.eq(None::<DateTime<Utc>>),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Release(ip, err))
+    }
+
+    /// Hold back addresses freed by a node for `duration`, so that stale DNS
+    /// records or peers of the old node don't reach a new one.
+    pub async fn quarantine(
+        host_id: HostId,
+        ips: &[IpNetwork],
+        duration: Duration,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        let until = chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| Utc::now().checked_add_signed(duration))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let rows = ip_addresses::table
+            .filter(ip_addresses::host_id.eq(host_id))
+            .filter(ip_addresses::ip.eq_any(ips));
+
+        diesel::update(rows)
+            .set(ip_addresses::quarantined_until.eq(until))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Quarantine(host_id, err))
+    }
+
+    /// Remove an address that is not held by a node.
+    pub async fn delete(ip: IpNetwork, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        if let (_, Some(node_id)) = Self::by_ip(ip, conn).await? {
+            return Err(Error::HeldByNode(ip, node_id));
+        }
+
+        diesel::delete(ip_addresses::table.filter(ip_addresses::ip.eq(ip)))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Delete(ip, err))
+    }
+
     pub async fn delete_for_host(host_id: HostId, conn: &mut Conn<'_>) -> Result<(), Error> {
         diesel::delete(ip_addresses::table.filter(ip_addresses::host_id.eq(host_id)))
             .execute(conn)
@@ -126,6 +283,17 @@ impl IpAddress {
     }
 }
 
+/// Whether an undeleted node holds an address as its IPv4 or IPv6 address.
+#[dsl::auto_type(no_type_alias)]
+fn held_by_node() -> _ {
+    ip_addresses::ip
+        .eq(nodes::ip_address)
+        .or(ip_addresses::ip
+            .nullable()
+            .is_not_distinct_from(nodes::ipv6_address))
+        .and(nodes::deleted_at.is_null())
+}
+
 #[derive(Debug, Insertable)]
 #[diesel(table_name = ip_addresses)]
 pub struct NewIpAddress {
@@ -138,6 +306,33 @@ impl NewIpAddress {
         Self { ip, host_id }
     }
 
+    /// Expand a CIDR range into the addresses that nodes may use.
+    ///
+    /// The network and broadcast addresses of IPv4 ranges are skipped, along
+    /// with any `excluded` addresses such as the host's own IP and gateways.
+    pub fn from_range(
+        range: ipnetwork::IpNetwork,
+        excluded: &[IpAddr],
+        host_id: HostId,
+    ) -> Result<Vec<Self>, Error> {
+        let ips: Vec<IpAddr> = range.iter().take(MAX_RANGE_SIZE + 1).collect();
+        if ips.len() > MAX_RANGE_SIZE {
+            return Err(Error::RangeSize(range));
+        }
+
+        let skip_ends = match range {
+            ipnetwork::IpNetwork::V4(net) => net.prefix() < 31,
+            ipnetwork::IpNetwork::V6(_) => false,
+        };
+
+        Ok(ips
+            .into_iter()
+            .filter(|ip| !(skip_ends && (*ip == range.network() || *ip == range.broadcast())))
+            .filter(|ip| !excluded.contains(ip))
+            .map(|ip| Self::new(ipnetwork::IpNetwork::from(ip).into(), host_id))
+            .collect())
+    }
+
     pub async fn bulk_create(ips: Vec<Self>, conn: &mut Conn<'_>) -> Result<Vec<IpAddress>, Error> {
         diesel::insert_into(ip_addresses::table)
             .values(ips)
@@ -146,3 +341,32 @@ impl NewIpAddress {
             .map_err(Error::BulkCreate)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_cidr_ranges() {
+        let host_id = HostId::from(Uuid::new_v4());
+        let gateway: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let range = "10.0.0.0/29".parse().unwrap();
+        let ips = NewIpAddress::from_range(range, &[gateway], host_id).unwrap();
+        let ips: Vec<_> = ips.iter().map(|ip| ip.ip.to_string()).collect();
+        assert_eq!(
+            ips,
+            ["10.0.0.2", "10.0.0.3", "10.0.0.4", "10.0.0.5", "10.0.0.6"]
+        );
+
+        let range = "2001:db8::/126".parse().unwrap();
+        let ips = NewIpAddress::from_range(range, &[], host_id).unwrap();
+        assert_eq!(ips.len(), 4);
+
+        let range = "2001:db8::/64".parse().unwrap();
+        assert!(matches!(
+            NewIpAddress::from_range(range, &[], host_id),
+            Err(Error::RangeSize(_))
+        ));
+    }
+}
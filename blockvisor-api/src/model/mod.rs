@@ -6,12 +6,34 @@ pub use address::{Address, AddressId};
 pub mod api_key;
 pub use api_key::ApiKey;
 
+pub mod background;
+
+pub mod bundle;
+pub use bundle::{Bundle, BundleChannel};
+
+pub mod capacity_reservation;
+pub use capacity_reservation::CapacityReservation;
+
 pub mod command;
 pub use command::{Command, CommandId, CommandType};
 
+pub mod cost;
+pub use cost::CostReport;
+
 pub mod host;
 pub use host::Host;
 
+pub mod host_capacity;
+pub use host_capacity::HostCapacity;
+
+pub mod host_heartbeat;
+
+pub mod host_provision_token;
+pub use host_provision_token::HostProvisionToken;
+
+pub mod host_upgrade;
+pub use host_upgrade::{HostUpgrade, HostUpgradeCampaign};
+
 pub mod image;
 pub use image::{Image, ImageId};
 
@@ -40,6 +62,9 @@ pub mod schema;
 
 pub mod sql;
 
+pub mod sso;
+pub use sso::IdentityProvider;
+
 pub mod protocol;
 pub use protocol::{Protocol, ProtocolId, ProtocolVersion, VersionId};
 
@@ -48,3 +73,6 @@ pub use token::Token;
 
 pub mod user;
 pub use user::User;
+
+pub mod zone;
+pub use zone::{Zone, ZoneId};
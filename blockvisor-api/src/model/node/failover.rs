@@ -0,0 +1,273 @@
+//! Fail over nodes from hosts that have been offline for too long.
+//!
+//! Failover is opt-in, either for every node in an org or for a single node,
+//! where the node setting takes precedence over the org setting. Once a host
+//! has not been seen for longer than the configured failover threshold, each
+//! opted-in node on it is re-created on another host through the usual node
+//! scheduler, spreading similar nodes over many hosts unless the node was
+//! scheduled otherwise. The replacement node points back at the original via
+//! `old_node_id`.
+//!
+//! The original node is kept until its host comes back online, and is then
+//! deleted so that the host cleans up its resources. If the host has still not
+//! returned after the configured delete timeout, the original node is deleted
+//! anyway so that it is no longer billed and no longer blocks deleting the host.
+//!
+//! Each pass holds an advisory lock, so only one replica fails over nodes at a
+//! time.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel::dsl;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use futures_util::FutureExt;
+use thiserror::Error;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth::resource::{HostId, NodeId};
+use crate::config::Context;
+use crate::database::{AdvisoryLock, Conn, Database, WriteConn};
+use crate::grpc::{api, common};
+use crate::model::background::{self, cutoff};
+use crate::model::command::{CommandType, NewCommand};
+use crate::model::host::ConnectionStatus;
+use crate::model::schema::{hosts, node_failovers, nodes, orgs};
+use crate::mqtt::Message;
+
+use super::log::FailedOver;
+use super::relaunch::{authz, no_matching_host, relaunch, transaction};
+use super::{LogEvent, NewNodeLog, Node};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failover command error: {0}
+    Command(#[from] crate::model::command::Error),
+    /// Failover command API error: {0}
+    CommandApi(#[from] crate::grpc::command::Error),
+    /// Failover claims error: {0}
+    Claims(#[from] crate::auth::claims::Error),
+    /// Failed to complete failover `{0}`: {1}
+    Complete(Uuid, diesel::result::Error),
+    /// Failed to create failover for node `{0}`: {1}
+    Create(NodeId, diesel::result::Error),
+    /// Failover database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Failed to find nodes to fail over: {0}
+    FindCandidates(diesel::result::Error),
+    /// Failed to find pending failovers: {0}
+    FindPending(diesel::result::Error),
+    /// No visibility of the NodeDelete command for node `{0}`.
+    NoNodeDelete(NodeId),
+    /// Failover node error: {0}
+    Node(#[from] crate::model::node::Error),
+    /// Failover node log error: {0}
+    NodeLog(#[from] super::log::Error),
+    /// Failover relaunch error: {0}
+    Relaunch(#[from] super::relaunch::Error),
+    /// Failover transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+}
+
+/// A node that was re-created on another host after its host went offline.
+#[derive(Debug, Queryable)]
+#[diesel(table_name = node_failovers)]
+pub struct NodeFailover {
+    pub id: Uuid,
+    pub node_id: NodeId,
+    pub new_node_id: NodeId,
+    pub host_id: HostId,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl NodeFailover {
+    /// Incomplete failovers where the original host is back online, or that
+    /// were created before `expired`.
+    pub async fn pending(expired: DateTime<Utc>, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        node_failovers::table
+            .inner_join(hosts::table)
+            .filter(node_failovers::completed_at.is_null())
+            .filter(
+                hosts::connection_status
+                    .eq(ConnectionStatus::Online)
+                    .or(node_failovers::created_at.lt(expired)),
+            )
+            .select(node_failovers::all_columns)
+            .get_results(conn)
+            .await
+            .map_err(Error::FindPending)
+    }
+
+    pub async fn complete(&self, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        diesel::update(node_failovers::table.find(self.id))
+            .set(node_failovers::completed_at.eq(Utc::now()))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Complete(self.id, err))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = node_failovers)]
+pub struct NewNodeFailover {
+    pub node_id: NodeId,
+    pub new_node_id: NodeId,
+    pub host_id: HostId,
+}
+
+impl NewNodeFailover {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<NodeFailover, Error> {
+        let node_id = self.node_id;
+        diesel::insert_into(node_failovers::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Create(node_id, err))
+    }
+}
+
+/// Run `failover` and `complete` forever at the configured interval.
+pub async fn run(ctx: Arc<Context>) {
+    // each node fails over in its own transaction while the lock is held
+    let after = *ctx.config.host.failover_after;
+    let delete_after = *ctx.config.host.failover_delete_after;
+    let interval = *ctx.config.host.failover_interval;
+    let ctx = &*ctx;
+    background::run_exclusive(ctx, AdvisoryLock::NodeFailover, interval, move |_| {
+        async move {
+            if let Err(err) = failover(after, ctx).await {
+                error!("Failed to fail over nodes: {err}");
+            }
+            if let Err(err) = complete(delete_after, ctx).await {
+                error!("Failed to complete node failovers: {err}");
+            }
+            Ok::<_, Error>(Vec::<Message>::new())
+        }
+        .boxed()
+    })
+    .await;
+}
+
+/// Re-create the opted-in nodes of hosts that have been offline for `after`.
+pub async fn failover(after: Duration, ctx: &Context) -> Result<(), Error> {
+    let cutoff = cutoff(Utc::now(), after);
+    let nodes = candidates(cutoff, &mut ctx.conn().await?).await?;
+
+    for node in nodes {
+        let node_id = node.id;
+        let result = transaction(ctx, |mut write| {
+            async move { failover_node(node, &mut write).await }.scope_boxed()
+        })
+        .await;
+
+        match result {
+            Ok(new_node_id) => info!("Failed over node {node_id} to node {new_node_id}."),
+            Err(Error::Relaunch(err)) if no_matching_host(&err) => {
+                warn!("Failed to fail over node {node_id}: no matching host.");
+            }
+            Err(err) => error!("Failed to fail over node {node_id}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete the original nodes of failovers where the host is back online, or
+/// that have waited longer than `delete_after` for it.
+pub async fn complete(delete_after: Duration, ctx: &Context) -> Result<(), Error> {
+    let expired = cutoff(Utc::now(), delete_after);
+    let failovers = NodeFailover::pending(expired, &mut ctx.conn().await?).await?;
+
+    for failover in failovers {
+        let failover_id = failover.id;
+        let result = transaction(ctx, |mut write| {
+            async move { complete_failover(failover, &mut write).await }.scope_boxed()
+        })
+        .await;
+
+        if let Err(err) = result {
+            error!("Failed to complete node failover {failover_id}: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Live nodes on long-offline hosts that have opted in to failover.
+async fn candidates(cutoff: DateTime<Utc>, conn: &mut Conn<'_>) -> Result<Vec<Node>, Error> {
+    let opted_in = nodes::failover_enabled.eq(true).or(nodes::failover_enabled
+        .is_null()
+        .and(orgs::failover_enabled.eq(true)));
+    let failed_over = node_failovers::table.filter(node_failovers::node_id.eq(nodes::id));
+
+    nodes::table
+        .inner_join(hosts::table)
+        .inner_join(orgs::table)
+        .filter(nodes::deleted_at.is_null())
+        .filter(hosts::deleted_at.is_null())
+        .filter(hosts::connection_status.eq(ConnectionStatus::Offline))
+        .filter(hosts::last_seen_at.lt(cutoff))
+        .filter(opted_in)
+        .filter(dsl::not(dsl::exists(failed_over)))
+        .select(nodes::all_columns)
+        .get_results(conn)
+        .await
+        .map_err(Error::FindCandidates)
+}
+
+/// Re-create a node on another host, returning the id of the new node.
+async fn failover_node(node: Node, write: &mut WriteConn<'_, '_>) -> Result<NodeId, Error> {
+    let authz = authz(node.created_by(), write).await?;
+    let new = relaunch(&node, node.config_id, &authz, write).await?;
+
+    let event = LogEvent::FailedOver(FailedOver {
+        new_node: new.id,
+        new_host: new.host_id,
+    });
+    NewNodeLog::from(&node, &authz, event).create(write).await?;
+
+    NewNodeFailover {
+        node_id: node.id,
+        new_node_id: new.id,
+        host_id: node.host_id,
+    }
+    .create(write)
+    .await?;
+
+    Ok(new.id)
+}
+
+/// Delete the original node of a failover, then mark it as completed.
+async fn complete_failover(
+    failover: NodeFailover,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    match Node::delete(failover.node_id, write).await {
+        Ok(node) => {
+            let authz = authz(node.created_by(), write).await?;
+            let delete_cmd = NewCommand::node(&node, CommandType::NodeDelete)?
+                .create(write)
+                .await?;
+            let delete_cmd = api::Command::from(&delete_cmd, &authz, write)
+                .await?
+                .ok_or(Error::NoNodeDelete(node.id))?;
+
+            let deleted_by = common::Resource::from(&authz);
+            write.mqtt(delete_cmd);
+            write.mqtt(api::NodeMessage::deleted(&node, Some(deleted_by)));
+        }
+        // already deleted while the host was offline
+        Err(super::Error::AlreadyDeleted(_)) => (),
+        Err(err) => return Err(err.into()),
+    }
+
+    failover.complete(write).await?;
+
+    Ok(())
+}
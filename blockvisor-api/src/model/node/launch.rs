@@ -108,7 +108,9 @@ impl Launch {
                     };
 
                     for _ in 0..count.node_count {
-                        let candidate = node.find_host(&scheduler, authz, write).await?;
+                        let candidate = node
+                            .find_host(&scheduler, count.exclude_host_id, authz, write)
+                            .await?;
                         match node
                             .create_node(
                                 &candidate.host,
@@ -174,6 +176,8 @@ pub struct RegionCount {
     pub node_count: u32,
     pub resource: Option<ResourceAffinity>,
     pub similarity: Option<SimilarNodeAffinity>,
+    /// A host that the nodes may not be placed on.
+    pub exclude_host_id: Option<HostId>,
 }
 
 impl RegionCount {
@@ -183,6 +187,7 @@ impl RegionCount {
             node_count: 1,
             resource: None,
             similarity: None,
+            exclude_host_id: None,
         }
     }
 }
@@ -196,6 +201,7 @@ impl TryFrom<&common::RegionCount> for RegionCount {
             node_count: count.node_count,
             resource: count.resource().into(),
             similarity: count.similarity().into(),
+            exclude_host_id: None,
         })
     }
 }
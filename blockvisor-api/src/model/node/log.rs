@@ -124,8 +124,21 @@ pub enum LogEvent {
     CreateFailed,
     /// Node creation was cancelled because of some non-transient failure.
     CreateCancelled,
+    /// This node was re-created on another host after its host went offline.
+    ///
+    /// The node is deleted once its host comes back online.
+    FailedOver(FailedOver),
     /// This node was transferred to another org.
     OrgTransferred(OrgTransferred),
+    /// The node failed, and its restart policy restarted or re-created it.
+    RecoveryAttempted(RecoveryAttempted),
+    /// The node is still failed after the last attempt of its restart policy.
+    RecoveryGaveUp,
+    /// The CPU, memory or disk of this node was resized.
+    ///
+    /// If the host had no room for the new size, the node was re-created on
+    /// another host and is deleted.
+    Resized(Resized),
     /// A `NodeUpgrade` message has been sent to blockvisord.
     ///
     /// This should be followed by `UpgradeSucceeded` or `UpgradeFailed`.
@@ -143,10 +156,19 @@ impl LogEvent {
             LogEvent::CreateSucceeded => (NodeEvent::CreateSucceeded, None),
             LogEvent::CreateFailed => (NodeEvent::CreateFailed, None),
             LogEvent::CreateCancelled => (NodeEvent::CreateCancelled, None),
+            LogEvent::FailedOver(data) => {
+                (NodeEvent::FailedOver, Some(NodeEventData::FailedOver(data)))
+            }
             LogEvent::OrgTransferred(data) => (
                 NodeEvent::OrgTransferred,
                 Some(NodeEventData::OrgTransferred(data)),
             ),
+            LogEvent::RecoveryAttempted(data) => (
+                NodeEvent::RecoveryAttempted,
+                Some(NodeEventData::RecoveryAttempted(data)),
+            ),
+            LogEvent::RecoveryGaveUp => (NodeEvent::RecoveryGaveUp, None),
+            LogEvent::Resized(data) => (NodeEvent::Resized, Some(NodeEventData::Resized(data))),
             LogEvent::UpgradeStarted(data) => (
                 NodeEvent::UpgradeStarted,
                 Some(NodeEventData::UpgradeStarted(data)),
@@ -163,6 +185,28 @@ pub struct OrgTransferred {
     pub new: OrgId,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FailedOver {
+    pub new_node: NodeId,
+    pub new_host: HostId,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RecoveryAttempted {
+    pub attempt: i32,
+    /// The replacement node, if the node was re-created on another host.
+    pub new_node: Option<NodeId>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Resized {
+    pub cpu_cores: i64,
+    pub memory_bytes: i64,
+    pub disk_bytes: i64,
+    /// The replacement node, if the node was moved to another host.
+    pub new_node: Option<NodeId>,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct UpgradeStarted {
     pub old: ImageId,
@@ -176,7 +220,11 @@ pub enum NodeEvent {
     CreateSucceeded,
     CreateFailed,
     CreateCancelled,
+    FailedOver,
     OrgTransferred,
+    RecoveryAttempted,
+    RecoveryGaveUp,
+    Resized,
     UpgradeStarted,
     UpgradeSucceeded,
     UpgradeFailed,
@@ -185,7 +233,10 @@ pub enum NodeEvent {
 #[derive(Clone, Copy, Debug, AsExpression, FromSqlRow, Serialize, Deserialize)]
 #[diesel(sql_type = Jsonb)]
 pub enum NodeEventData {
+    FailedOver(FailedOver),
     OrgTransferred(OrgTransferred),
+    RecoveryAttempted(RecoveryAttempted),
+    Resized(Resized),
     UpgradeStarted(UpgradeStarted),
 }
 
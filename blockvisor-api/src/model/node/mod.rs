@@ -1,3 +1,6 @@
+pub mod failover;
+pub use failover::{NewNodeFailover, NodeFailover};
+
 pub mod job;
 pub use job::{NodeJob, NodeJobProgress, NodeJobStatus, NodeJobs};
 
@@ -7,15 +10,32 @@ pub use launch::{HostCount, Launch, RegionCount};
 pub mod log;
 pub use log::{LogEvent, NewNodeLog, NodeEvent, NodeEventData, NodeLog};
 
+pub mod placement;
+pub use placement::Placement;
+
+pub mod recovery;
+pub use recovery::{RestartAction, RestartPolicy};
+
+pub mod relaunch;
+
 pub mod report;
 pub use report::{NewNodeReport, NodeReport};
 
+pub mod resize;
+pub use resize::{NewNodeMove, NodeMove, NodeResize, Resize};
+
+pub mod rule;
+pub use rule::{NewNodeRule, NodeRule};
+
 pub mod scheduler;
 pub use scheduler::{NodeScheduler, ResourceAffinity, SimilarNodeAffinity};
 
 pub mod status;
 pub use status::{NextState, NodeHealth, NodeState, NodeStatus, ProtocolStatus};
 
+pub mod usage;
+pub use usage::{NodeUsage, UsageState, UsageSummary};
+
 use std::collections::{HashMap, HashSet, VecDeque};
 
 use chrono::{DateTime, Utc};
@@ -41,20 +61,29 @@ use crate::model::sql::{self, Amount, Currency, IpNetwork, Period, Tags, Version
 use crate::stripe::api::subscription::SubscriptionItemId;
 use crate::util::{SearchOperator, SortOrder};
 
+use super::capacity_reservation::CapacityReservationId;
 use super::command::NewCommand;
 use super::host::{Host, HostCandidate, HostRequirements};
 use super::image::config::{ConfigType, FirewallConfig, NewConfig};
 use super::image::property::NewImagePropertyValue;
-use super::image::{Config, ConfigId, Image, ImageId, NodeConfig};
+use super::image::{Config, ConfigId, Image, ImageId, ImageLifecycle, NodeConfig};
+use super::ip_address::IpFamily;
 use super::protocol::version::{ProtocolVersion, VersionId};
 use super::protocol::{Protocol, ProtocolId, VersionKey};
-use super::schema::{nodes, protocol_versions};
-use super::{Command, CommandType, IpAddress, Org, Paginate, Region, RegionId};
+use super::schema::{hosts, images, nodes, protocol_versions, regions};
+use super::{
+    CapacityReservation, Command, CommandType, HostCapacity, IpAddress, Org, Paginate, Region,
+    RegionId,
+};
 
 #[derive(Debug, Display, Error)]
 pub enum Error {
     /// Cannot delete node `{0}`, it is already deleted.
     AlreadyDeleted(NodeId),
+    /// Node capacity reservation error: {0}
+    CapacityReservation(#[from] crate::model::capacity_reservation::Error),
+    /// Failed to clear node health for host `{0}`: {1}
+    ClearHealth(HostId, diesel::result::Error),
     /// Node Cloudflare error: {0}
     Cloudflare(#[from] crate::cloudflare::Error),
     /// Node Command error: {0}
@@ -71,6 +100,10 @@ pub enum Error {
     FindById(NodeId, diesel::result::Error),
     /// Failed to find nodes by ids `{0:?}`: {1}
     FindByIds(HashSet<NodeId>, diesel::result::Error),
+    /// Failed to find nodes by image id `{0}`: {1}
+    FindByImageId(ImageId, diesel::result::Error),
+    /// Failed to find billable nodes for org `{0}`: {1}
+    FindBillable(OrgId, diesel::result::Error),
     /// Failed to find nodes by version ids `{0:?}`: {1}
     FindByVersionIds(HashSet<VersionId>, diesel::result::Error),
     /// Failed to find host id for possibly deleted node {0}: {1}
@@ -129,6 +162,10 @@ pub enum Error {
     Region(#[from] crate::model::region::Error),
     /// Node report error: {0}
     Report(#[from] self::report::Error),
+    /// Failed to reset recovery attempts for node {0}: {1}
+    ResetRecovery(NodeId, diesel::result::Error),
+    /// Node firewall rule error: {0}
+    Rule(#[from] self::rule::Error),
     /// Store error for node: {0}
     Store(#[from] crate::store::Error),
     /// Node stripe error: {0}
@@ -139,10 +176,14 @@ pub enum Error {
     UpdateStatus(diesel::result::Error),
     /// Failed to update metrics for node {0}: {1}
     UpdateMetrics(NodeId, diesel::result::Error),
+    /// Failed to update stripe item for node {0}: {1}
+    UpdateStripeItem(NodeId, diesel::result::Error),
     /// The updated org is the same as the current org.
     UpdateSameOrg,
     /// Failed to upgrade the node: {0}
     Upgrade(diesel::result::Error),
+    /// Node usage error: {0}
+    Usage(#[from] self::usage::Error),
     /// The node is already using the requested image_id.
     UpgradeSameImage,
     /// Failed to parse VM cpu count: {0}
@@ -171,11 +212,13 @@ impl From<Error> for Status {
             | FindOrgId(_, NotFound)
             | FindByVersionIds(_, NotFound) => Status::not_found("Node not found."),
             AlreadyDeleted(_)
+            | ClearHealth(_, _)
             | Cloudflare(_)
             | Create(_)
             | Delete(_, _)
             | FindById(_, _)
             | FindByIds(_, _)
+            | FindBillable(_, _)
             | FindDeletedById(_, _)
             | FindDeletedHostId(_, _)
             | FindDeletedOrgId(_, _)
@@ -187,10 +230,12 @@ impl From<Error> for Status {
             | HostHasNodes(_, _)
             | ItemWithoutPrice
             | PriceWithoutAmount
+            | ResetRecovery(_, _)
             | Stripe(_)
             | UpdateConfig(_)
             | UpdateMetrics(_, _)
             | UpdateStatus(_)
+            | UpdateStripeItem(_, _)
             | Upgrade(_)
             | VmCpu(_)
             | VmDisk(_)
@@ -204,6 +249,7 @@ impl From<Error> for Status {
             NoUpgradeCommand => Status::forbidden("Access denied."),
             UpdateSameOrg => Status::already_exists("new_org_id"),
             UpgradeSameImage => Status::already_exists("image_id"),
+            CapacityReservation(err) => err.into(),
             Command(err) => (*err).into(),
             Config(err) => err.into(),
             Grpc(err) => (*err).into(),
@@ -218,7 +264,9 @@ impl From<Error> for Status {
             ProtocolVersion(err) => err.into(),
             Region(err) => err.into(),
             Report(err) => err.into(),
+            Rule(err) => err.into(),
             Store(err) => err.into(),
+            Usage(err) => err.into(),
         }
     }
 }
@@ -266,6 +314,16 @@ pub struct Node {
     pub updated_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub cost: Option<Amount>,
+    pub failover_enabled: Option<bool>,
+    pub placement: Placement,
+    pub ipv6_address: Option<IpNetwork>,
+    pub ipv6_gateway: Option<IpNetwork>,
+    pub ipv6_dns_id: Option<String>,
+    /// The capacity reservation of the org consumed by this node.
+    pub capacity_reservation_id: Option<CapacityReservationId>,
+    pub restart_policy: RestartPolicy,
+    pub restart_attempts: i32,
+    pub restart_attempted_at: Option<DateTime<Utc>>,
 }
 
 impl Node {
@@ -278,6 +336,22 @@ impl Node {
             .map_err(|err| Error::FindById(id, err))
     }
 
+    /// Start recovery over once a node with recovery attempts runs again.
+    async fn reset_recovery(self, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        if self.node_state != NodeState::Running || self.restart_attempts == 0 {
+            return Ok(self);
+        }
+
+        diesel::update(nodes::table.find(self.id))
+            .set((
+                nodes::restart_attempts.eq(0),
+                nodes::restart_attempted_at.eq(None::<DateTime<Utc>>),
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ResetRecovery(self.id, err))
+    }
+
     pub async fn deleted_by_id(id: NodeId, conn: &mut Conn<'_>) -> Result<Self, Error> {
         nodes::table
             .find(id)
@@ -295,6 +369,15 @@ impl Node {
             .map_err(|err| Error::FindByIds(ids.clone(), err))
     }
 
+    pub async fn by_image_id(image_id: ImageId, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        nodes::table
+            .filter(nodes::image_id.eq(image_id))
+            .filter(nodes::deleted_at.is_null())
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::FindByImageId(image_id, err))
+    }
+
     pub async fn by_host_ids(
         host_ids: &HashSet<HostId>,
         org_ids: &HashSet<OrgId>,
@@ -309,6 +392,21 @@ impl Node {
             .map_err(|err| Error::FindHostIds(host_ids.clone(), err))
     }
 
+    /// Mark the health of every node on a host as unknown.
+    pub async fn clear_health(host_id: HostId, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        let rows = nodes::table
+            .filter(nodes::host_id.eq(host_id))
+            .filter(nodes::deleted_at.is_null());
+        diesel::update(rows)
+            .set((
+                nodes::protocol_health.eq(None::<NodeHealth>),
+                nodes::updated_at.eq(Utc::now()),
+            ))
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ClearHealth(host_id, err))
+    }
+
     pub async fn by_version_ids(
         version_ids: &HashSet<VersionId>,
         conn: &mut Conn<'_>,
@@ -321,6 +419,44 @@ impl Node {
             .map_err(|err| Error::FindByVersionIds(version_ids.clone(), err))
     }
 
+    /// All live nodes of an org that are billed through stripe, along with the
+    /// protocol version and host region that together determine their SKU.
+    ///
+    /// Nodes on one of the org's own hosts are billing exempt.
+    pub async fn billable(
+        org_id: OrgId,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<(Self, ProtocolVersion, Region)>, Error> {
+        nodes::table
+            .inner_join(protocol_versions::table)
+            .inner_join(hosts::table.inner_join(regions::table))
+            .filter(nodes::org_id.eq(org_id))
+            .filter(nodes::deleted_at.is_null())
+            .filter(nodes::stripe_item_id.is_not_null())
+            .filter(hosts::org_id.ne(org_id).or(hosts::org_id.is_null()))
+            .select((
+                Node::as_select(),
+                ProtocolVersion::as_select(),
+                regions::all_columns,
+            ))
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::FindBillable(org_id, err))
+    }
+
+    pub async fn set_stripe_item_id(
+        id: NodeId,
+        item_id: &SubscriptionItemId,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        diesel::update(nodes::table.find(id))
+            .set(nodes::stripe_item_id.eq(item_id))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::UpdateStripeItem(id, err))
+    }
+
     pub async fn org_id(id: NodeId, conn: &mut Conn<'_>) -> Result<OrgId, Error> {
         nodes::table
             .find(id)
@@ -378,6 +514,7 @@ impl Node {
 
         Org::remove_node(node.org_id, write).await?;
         Host::remove_node(&node, write).await?;
+        CapacityReservation::release(&node, write).await?;
         Command::delete_node_pending(node.id, write)
             .await
             .map_err(|err| Error::Command(Box::new(err)))?;
@@ -386,14 +523,27 @@ impl Node {
             .set((
                 nodes::next_state.eq(Some(NextState::Deleting)),
                 nodes::deleted_at.eq(Utc::now()),
+                nodes::capacity_reservation_id.eq(None::<CapacityReservationId>),
             ))
             .get_result(write)
             .await
             .map_err(|err| Error::Delete(id, err))?;
+        NodeUsage::record(&node, write).await?;
 
         if let Err(err) = write.ctx.dns.delete(&node.dns_id).await {
             warn!("Failed to remove node dns: {err}");
         }
+        if let Some(ref dns_id) = node.ipv6_dns_id {
+            if let Err(err) = write.ctx.dns.delete(dns_id).await {
+                warn!("Failed to remove node IPv6 dns: {err}");
+            }
+        }
+
+        let ips: Vec<_> = std::iter::once(node.ip_address)
+            .chain(node.ipv6_address)
+            .collect();
+        let quarantine = *write.ctx.config.host.ip_quarantine;
+        IpAddress::quarantine(node.host_id, &ips, quarantine, write).await?;
 
         // FIXME: secrets integration
         /*
@@ -430,10 +580,12 @@ impl Node {
         let requirements = HostRequirements {
             scheduler: &scheduler,
             protocol,
+            placement: &self.placement,
             org_id: Some(self.org_id),
             cpu_cores: self.cpu_cores,
             memory_bytes: self.memory_bytes,
             disk_bytes: self.disk_bytes,
+            exclude_host_id: None,
         };
         let candidates = Host::candidates(requirements, Some(2), write).await?;
 
@@ -597,6 +749,7 @@ pub struct NewNode {
     pub semantic_version: Version,
     pub auto_upgrade: bool,
     pub tags: Tags,
+    pub placement: Placement,
 }
 
 impl NewNode {
@@ -676,17 +829,33 @@ impl NewNode {
         let memory_bytes = i64::try_from(node_config.vm.memory_bytes).map_err(Error::VmMemory)?;
         let disk_bytes = i64::try_from(node_config.vm.disk_bytes).map_err(Error::VmDisk)?;
 
-        if cpu_cores + host.node_cpu_cores > host.cpu_cores {
+        let region = Region::by_id(host.region_id, write).await?;
+        let free = HostCapacity::new(host, &region).free;
+        if cpu_cores > free.cpu_cores {
             return Err(Error::HostFreeCpu(host.id));
-        } else if memory_bytes + host.node_memory_bytes > host.memory_bytes {
+        } else if memory_bytes > free.memory_bytes {
             return Err(Error::HostFreeMem(host.id));
-        } else if disk_bytes + host.node_disk_bytes > host.disk_bytes {
+        } else if disk_bytes > free.disk_bytes {
             return Err(Error::HostFreeDisk(host.id));
         }
 
-        let ip_address = IpAddress::next_for_host(host.id, write)
+        let ip_address = IpAddress::next_for_host(host.id, IpFamily::V4, write)
             .await?
             .ok_or_else(|| Error::HostFreeIp(host.id))?;
+        // hosts with an IPv6 gateway also give their nodes an IPv6 address
+        let ipv6_address = if host.ipv6_gateway.is_some() {
+            let ip = IpAddress::next_for_host(host.id, IpFamily::V6, write).await?;
+            if ip.is_none() {
+                warn!(
+                    "Host {} has no free IPv6 address for a dual-stack node.",
+                    host.id
+                );
+            }
+            ip
+        } else {
+            None
+        };
+        let ipv6_gateway = ipv6_address.as_ref().and(host.ipv6_gateway);
 
         // Users that have the billing-exempt permission or that are launching a node on their own
         // host do not need to be charged.
@@ -694,25 +863,21 @@ impl NewNode {
             authz.has_perm(BillingPerm::Exempt) || host.org_id == Some(self.org_id);
         let (stripe_item_id, price) = if billing_exempt {
             (None, None)
-        } else {
-            let region = Region::by_id(host.region_id, write).await?;
-
-            if let Some(sku) = version.sku(&region) {
-                if let Some(stripe) = write.ctx.stripe.as_ref() {
-                    let item = stripe.add_subscription(org, &sku).await?;
-                    let price = item
-                        .price
-                        .ok_or(Error::ItemWithoutPrice)?
-                        .unit_amount
-                        .ok_or(Error::PriceWithoutAmount)?;
-                    (Some(item.id), Some(price))
-                } else {
-                    warn!("Stripe not configured, cannot charge for node!");
-                    (None, None)
-                }
+        } else if let Some(sku) = version.sku(&region) {
+            if let Some(stripe) = write.ctx.stripe.as_ref() {
+                let item = stripe.add_subscription(org, &sku).await?;
+                let price = item
+                    .price
+                    .ok_or(Error::ItemWithoutPrice)?
+                    .unit_amount
+                    .ok_or(Error::PriceWithoutAmount)?;
+                (Some(item.id), Some(price))
             } else {
+                warn!("Stripe not configured, cannot charge for node!");
                 (None, None)
             }
+        } else {
+            (None, None)
         };
         let cost = price.map(|amount| Amount {
             amount,
@@ -725,6 +890,18 @@ impl NewNode {
                 .generate_one(3, "-")
                 .ok_or(Error::GenerateName)?;
             let dns_id = write.ctx.dns.create(&name, ip_address.ip.ip()).await?.id;
+            let ipv6_dns_id = match ipv6_address {
+                Some(ref ipv6) => match write.ctx.dns.create(&name, ipv6.ip.ip()).await {
+                    Ok(record) => Some(record.id),
+                    Err(err) => {
+                        if let Err(err) = write.ctx.dns.delete(&dns_id).await {
+                            warn!("Failed to delete DNS record {dns_id}: {err}");
+                        }
+                        return Err(err.into());
+                    }
+                },
+                None => None,
+            };
             let dns_name = if let Some(scheme) = &image.dns_scheme {
                 format!("{scheme}://{name}.{dns_base}")
             } else {
@@ -740,7 +917,10 @@ impl NewNode {
                     nodes::node_state.eq(NodeState::Starting),
                     nodes::ip_address.eq(&ip_address.ip),
                     nodes::ip_gateway.eq(&host.ip_gateway),
+                    nodes::ipv6_address.eq(ipv6_address.as_ref().map(|ip| ip.ip)),
+                    nodes::ipv6_gateway.eq(ipv6_gateway),
                     nodes::dns_id.eq(&dns_id),
+                    nodes::ipv6_dns_id.eq(&ipv6_dns_id),
                     nodes::dns_name.eq(&dns_name),
                     nodes::cpu_cores.eq(cpu_cores),
                     nodes::memory_bytes.eq(memory_bytes),
@@ -754,9 +934,16 @@ impl NewNode {
                 .get_result::<Node>(&mut write)
                 .await
             {
-                Ok(node) => {
+                Ok(mut node) => {
                     Org::add_node(self.org_id, write).await?;
                     Host::add_node(&node, write).await?;
+                    NodeUsage::record(&node, write).await?;
+                    if host.org_id.is_none() {
+                        node.capacity_reservation_id =
+                            CapacityReservation::consume(&node, host.region_id, write)
+                                .await?
+                                .map(|reservation| reservation.id);
+                    }
 
                     /*
                         if let Some(secrets) = secrets {
@@ -775,6 +962,11 @@ impl NewNode {
                     if let Err(err) = write.ctx.dns.delete(&dns_id).await {
                         warn!("Failed to delete DNS record {dns_id}: {err}");
                     }
+                    if let Some(ref dns_id) = ipv6_dns_id {
+                        if let Err(err) = write.ctx.dns.delete(dns_id).await {
+                            warn!("Failed to delete DNS record {dns_id}: {err}");
+                        }
+                    }
 
                     if let DatabaseError(UniqueViolation, ref info) = err {
                         if info.column_name() == Some("name") {
@@ -789,10 +981,12 @@ impl NewNode {
         }
     }
 
-    /// Finds the most suitable host to place the node on.
+    /// Finds the most suitable host to place the node on, other than
+    /// `exclude_host_id`.
     async fn find_host(
         &self,
         scheduler: &NodeScheduler,
+        exclude_host_id: Option<HostId>,
         authz: &AuthZ,
         conn: &mut Conn<'_>,
     ) -> Result<HostCandidate, Error> {
@@ -803,10 +997,12 @@ impl NewNode {
         let requirements = HostRequirements {
             scheduler,
             protocol: &protocol,
+            placement: &self.placement,
             org_id: Some(self.org_id),
             cpu_cores: i64::try_from(node_config.vm.cpu_cores).map_err(Error::VmCpu)?,
             memory_bytes: i64::try_from(node_config.vm.memory_bytes).map_err(Error::VmMemory)?,
             disk_bytes: i64::try_from(node_config.vm.disk_bytes).map_err(Error::VmDisk)?,
+            exclude_host_id,
         };
 
         let candidates = Host::candidates(requirements, Some(1), conn).await?;
@@ -823,9 +1019,14 @@ pub struct UpdateNode<'u> {
     pub auto_upgrade: Option<bool>,
     pub ip_address: Option<IpNetwork>,
     pub ip_gateway: Option<IpNetwork>,
+    pub ipv6_address: Option<Option<IpNetwork>>,
+    pub ipv6_gateway: Option<Option<IpNetwork>>,
     pub note: Option<&'u str>,
     pub tags: Option<Tags>,
     pub cost: Option<Amount>,
+    pub failover_enabled: Option<bool>,
+    pub restart_policy: Option<RestartPolicy>,
+    pub restart_attempts: Option<i32>,
 }
 
 impl UpdateNode<'_> {
@@ -851,11 +1052,14 @@ impl UpdateNode<'_> {
             NewNodeLog::from(&node, authz, event).create(conn).await?;
         }
 
-        diesel::update(nodes::table.find(id))
+        let node = diesel::update(nodes::table.find(id))
             .set((self, nodes::updated_at.eq(Utc::now())))
             .get_result(conn)
             .await
-            .map_err(Error::UpdateConfig)
+            .map_err(Error::UpdateConfig)?;
+        NodeUsage::record(&node, conn).await?;
+
+        Ok(node)
     }
 }
 
@@ -911,12 +1115,19 @@ pub struct UpdateNodeState<'u> {
 
 impl UpdateNodeState<'_> {
     pub async fn apply(self, id: NodeId, conn: &mut Conn<'_>) -> Result<Node, Error> {
+        // only a state change can open a new usage interval
+        let state_changed = self.node_state.is_some();
         let row = nodes::table.find(id);
-        diesel::update(row)
+        let node = diesel::update(row)
             .set((self, nodes::updated_at.eq(Utc::now())))
             .get_result(conn)
             .await
-            .map_err(Error::UpdateStatus)
+            .map_err(Error::UpdateStatus)?;
+        if state_changed {
+            NodeUsage::record(&node, conn).await?;
+        }
+
+        node.reset_recovery(conn).await
     }
 }
 
@@ -939,11 +1150,16 @@ impl UpdateNodeMetrics {
             .find(self.id)
             .filter(nodes::deleted_at.is_null());
 
-        diesel::update(row)
+        let node = diesel::update(row)
             .set(self)
             .get_result(conn)
             .await
-            .map_err(|err| Error::UpdateMetrics(self.id, err))
+            .map_err(|err| Error::UpdateMetrics(self.id, err))?;
+        if self.node_state.is_some() {
+            NodeUsage::record(&node, conn).await?;
+        }
+
+        node.reset_recovery(conn).await
     }
 
     pub async fn apply_all(updates: Vec<Self>, conn: &mut Conn<'_>) -> Result<Vec<Node>, Error> {
@@ -976,9 +1192,14 @@ impl UpgradeNode<'_> {
             return Err(Error::UpgradeSameImage);
         }
 
+        let node_rules = NodeRule::by_node_id(node.id, conn)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
         let old_config = config.node_config()?;
         let new_config = old_config
-            .upgrade(self.image.clone(), self.org_id, conn)
+            .upgrade(self.image.clone(), self.org_id, node_rules, conn)
             .await?;
 
         let new_config = NewConfig {
@@ -1097,6 +1318,7 @@ pub struct NodeFilter {
     pub ip_addresses: Vec<IpNetwork>,
     pub node_states: Vec<NodeState>,
     pub next_states: Vec<NextState>,
+    pub image_lifecycles: Vec<ImageLifecycle>,
     pub search: Option<NodeSearch>,
     pub sort: VecDeque<NodeSort>,
     pub limit: i64,
@@ -1174,6 +1396,13 @@ impl NodeFilter {
             query = query.filter(nodes::next_state.eq_any(self.next_states));
         }
 
+        if !self.image_lifecycles.is_empty() {
+            let image_ids = images::table
+                .filter(images::lifecycle.eq_any(self.image_lifecycles))
+                .select(images::id);
+            query = query.filter(nodes::image_id.eq_any(image_ids));
+        }
+
         if let Some(sort) = self.sort.pop_front() {
             query = query.order_by(sort.into_expr());
         } else {
@@ -1260,11 +1489,13 @@ mod tests {
         let (ctx, db) = Context::with_mocked().await.unwrap();
         let (meta_tx, _meta_rx) = mpsc::unbounded_channel();
         let (mqtt_tx, _mqtt_rx) = mpsc::unbounded_channel();
+        let (task_tx, _task_rx) = mpsc::unbounded_channel();
         let mut write = WriteConn {
             conn: &mut db.conn().await,
             ctx: &ctx,
             meta_tx,
             mqtt_tx,
+            task_tx,
         };
 
         let new_node = NewNode {
@@ -1277,6 +1508,7 @@ mod tests {
             semantic_version: "1.2.3".parse().unwrap(),
             auto_upgrade: false,
             tags: Default::default(),
+            placement: Placement::default(),
         };
 
         let launch = Launch::ByHost(vec![HostCount::one(db.seed.host1.id)]);
@@ -1300,6 +1532,7 @@ mod tests {
             ip_addresses: vec![],
             node_states: vec![NodeState::Running],
             next_states: vec![],
+            image_lifecycles: vec![],
             search: None,
             sort: VecDeque::new(),
             offset: 0,
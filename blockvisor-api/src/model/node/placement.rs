@@ -0,0 +1,161 @@
+//! Constraints on the hosts that a node may be placed on.
+//!
+//! Hosts may have key-value labels (e.g. `disk=nvme`) and taints. A node may
+//! require or prefer hosts with certain labels, and is only placed on a tainted
+//! host if it tolerates every taint on that host. A node may also avoid hosts
+//! running specific nodes, or running nodes with any of a set of tags.
+
+use diesel::deserialize::{FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::sql_types::Jsonb;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{Output, ToSql};
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::auth::resource::NodeId;
+use crate::grpc::{Status, common};
+use crate::model::sql::{Labels, Tag};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to parse anti-affinity NodeId: {0}
+    ParseNodeId(uuid::Error),
+    /// Failed to parse Placement `{0}`: {1}
+    ParsePlacement(serde_json::Value, serde_json::Error),
+    /// Placement sql error: {0}
+    Sql(#[from] crate::model::sql::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            ParseNodeId(_) => Status::invalid_argument("anti_affinity_node_ids"),
+            ParsePlacement(..) => Status::internal("Internal error."),
+            Sql(err) => err.into(),
+        }
+    }
+}
+
+/// Constraints on the hosts that a node may be placed on.
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[diesel(sql_type = Jsonb)]
+#[serde(default)]
+pub struct Placement {
+    /// Only place the node on hosts with all of these labels.
+    pub required: Labels,
+    /// Prefer hosts with all of these labels.
+    pub preferred: Labels,
+    /// Allow placing the node on hosts with these taints.
+    pub tolerations: Labels,
+    /// Avoid hosts running any of these nodes.
+    pub anti_affinity_nodes: Vec<NodeId>,
+    /// Avoid hosts running nodes with any of these tags.
+    pub anti_affinity_tags: Vec<String>,
+}
+
+impl Placement {
+    /// Whether the node avoids hosts running some other nodes.
+    pub fn has_anti_affinity(&self) -> bool {
+        !self.anti_affinity_nodes.is_empty() || !self.anti_affinity_tags.is_empty()
+    }
+}
+
+impl FromSql<Jsonb, Pg> for Placement {
+    fn from_sql(value: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(value)?;
+        Placement::deserialize(&value).map_err(|err| Error::ParsePlacement(value, err).into())
+    }
+}
+
+impl ToSql<Jsonb, Pg> for Placement {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+impl TryFrom<common::NodePlacement> for Placement {
+    type Error = Error;
+
+    fn try_from(placement: common::NodePlacement) -> Result<Self, Self::Error> {
+        let anti_affinity_nodes = placement
+            .anti_affinity_node_ids
+            .iter()
+            .map(|id| id.parse().map_err(Error::ParseNodeId))
+            .collect::<Result<_, _>>()?;
+        let anti_affinity_tags = placement
+            .anti_affinity_tags
+            .into_iter()
+            .map(|tag| Tag::new(tag).map(|tag| tag.to_string()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Placement {
+            required: Labels::new(placement.required_labels)?,
+            preferred: Labels::new(placement.preferred_labels)?,
+            tolerations: Labels::new(placement.tolerations)?,
+            anti_affinity_nodes,
+            anti_affinity_tags,
+        })
+    }
+}
+
+impl From<Placement> for common::NodePlacement {
+    fn from(placement: Placement) -> Self {
+        common::NodePlacement {
+            required_labels: placement.required.into(),
+            preferred_labels: placement.preferred.into(),
+            tolerations: placement.tolerations.into(),
+            anti_affinity_node_ids: placement
+                .anti_affinity_nodes
+                .into_iter()
+                .map(|id| id.to_string())
+                .collect(),
+            anti_affinity_tags: placement.anti_affinity_tags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn placement_from_api() {
+        let node_id = uuid::Uuid::new_v4();
+        let placement = common::NodePlacement {
+            required_labels: HashMap::from([("disk".to_string(), "nvme".to_string())]),
+            preferred_labels: HashMap::new(),
+            tolerations: HashMap::from([("tier".to_string(), "premium".to_string())]),
+            anti_affinity_node_ids: vec![node_id.to_string()],
+            anti_affinity_tags: vec!["validators".to_string()],
+        };
+
+        let placement = Placement::try_from(placement).unwrap();
+        assert!(placement.has_anti_affinity());
+        assert!(placement.preferred.is_empty());
+        assert_eq!(placement.anti_affinity_nodes, vec![NodeId::from(node_id)]);
+
+        let bad_label = common::NodePlacement {
+            required_labels: HashMap::from([("Disk".to_string(), "nvme".to_string())]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Placement::try_from(bad_label),
+            Err(Error::Sql(crate::model::sql::Error::LabelKey(_)))
+        ));
+
+        // missing fields default to no constraints
+        let value = serde_json::json!({});
+        assert_eq!(
+            Placement::deserialize(&value).unwrap(),
+            Placement::default()
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Recover nodes that have reported a `Failed` state.
+//!
+//! Each node has a restart policy, which defaults to never recovering the
+//! node. With `OnFailure` a failed node is restarted on its host, and with
+//! `Recreate` it is re-created on another host through the usual node
+//! scheduler (pointing back at the original via `old_node_id`) while the
+//! original node is deleted.
+//!
+//! The first attempt is made as soon as a failed node is found, and each later
+//! attempt waits for the backoff of the policy, doubling after every attempt.
+//! Once `max_attempts` have been made without the node running again, the API
+//! gives up and alerts the owners of the org. A node that reports `Running`
+//! again starts over with no attempts.
+//!
+//! Each pass holds an advisory lock, so only one replica recovers nodes at a
+//! time.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use diesel::deserialize::{FromSql, FromSqlRow};
+use diesel::dsl::sql;
+use diesel::expression::AsExpression;
+use diesel::pg::sql_types::Jsonb;
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Bool;
+use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::auth::AuthZ;
+use crate::auth::resource::NodeId;
+use crate::config::Context;
+use crate::database::{AdvisoryLock, Conn, Database, WriteConn};
+use crate::grpc::{Status, api, common};
+use crate::model::User;
+use crate::model::background;
+use crate::model::command::{CommandType, NewCommand};
+use crate::model::host::ConnectionStatus;
+use crate::model::rbac::RbacUser;
+use crate::model::schema::{hosts, nodes};
+use crate::mqtt::Message;
+
+use super::log::RecoveryAttempted;
+use super::relaunch::{authz, no_matching_host, relaunch, transaction};
+use super::{LogEvent, NewNodeLog, Node, NodeState};
+
+/// The longest wait between attempts, as a power of two of the backoff.
+const MAX_BACKOFF_DOUBLINGS: u32 = 10;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Recovery command error: {0}
+    Command(#[from] crate::model::command::Error),
+    /// Recovery command API error: {0}
+    CommandApi(#[from] crate::grpc::command::Error),
+    /// Recovery claims error: {0}
+    Claims(#[from] crate::auth::claims::Error),
+    /// Recovery database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Failed to find failed nodes: {0}
+    FindFailed(diesel::result::Error),
+    /// Unknown restart action.
+    MissingAction,
+    /// No visibility of the NodeDelete command for node `{0}`.
+    NoNodeDelete(NodeId),
+    /// No visibility of the NodeRestart command for node `{0}`.
+    NoNodeRestart(NodeId),
+    /// Recovery node error: {0}
+    Node(#[from] crate::model::node::Error),
+    /// Recovery node log error: {0}
+    NodeLog(#[from] super::log::Error),
+    /// Failed to parse RestartPolicy `{0}`: {1}
+    ParsePolicy(serde_json::Value, serde_json::Error),
+    /// Recovery org owner error: {0}
+    Rbac(#[from] crate::model::rbac::Error),
+    /// Failed to record recovery attempt for node `{0}`: {1}
+    Record(NodeId, diesel::result::Error),
+    /// Recovery relaunch error: {0}
+    Relaunch(#[from] super::relaunch::Error),
+    /// Recovery transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+    /// Recovery user error: {0}
+    User(#[from] crate::model::user::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            MissingAction => Status::invalid_argument("restart_policy.action"),
+            _ => Status::internal("Internal error."),
+        }
+    }
+}
+
+/// What to do with a node that has failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartAction {
+    /// Leave the node failed until someone intervenes.
+    #[default]
+    Never,
+    /// Restart the node on its current host.
+    OnFailure,
+    /// Re-create the node on another host.
+    Recreate,
+}
+
+/// How the API recovers a node that has failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Jsonb)]
+#[serde(default)]
+pub struct RestartPolicy {
+    pub action: RestartAction,
+    /// The number of attempts before giving up.
+    pub max_attempts: u32,
+    /// The wait after the first attempt, which doubles after each attempt.
+    pub backoff_secs: u32,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            action: RestartAction::Never,
+            max_attempts: 3,
+            backoff_secs: 60,
+        }
+    }
+}
+
+/// The next step in recovering a failed node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    Restart,
+    Recreate,
+    GiveUp,
+}
+
+impl RestartPolicy {
+    /// The next step for a failed node after `attempts`, if one is due.
+    ///
+    /// Giving up counts as a final attempt, so a node with more attempts than
+    /// `max_attempts` has already been given up on.
+    pub fn next(
+        &self,
+        attempts: i32,
+        attempted_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<Recovery> {
+        let attempts = u32::try_from(attempts).unwrap_or(0);
+        let action = match self.action {
+            RestartAction::Never => return None,
+            _ if attempts > self.max_attempts => return None,
+            _ if attempts == self.max_attempts => Recovery::GiveUp,
+            RestartAction::OnFailure => Recovery::Restart,
+            RestartAction::Recreate => Recovery::Recreate,
+        };
+
+        let due = attempted_at.is_none_or(|at| at + self.backoff(attempts) <= now);
+        due.then_some(action)
+    }
+
+    /// The wait after the attempt number `attempts`.
+    fn backoff(&self, attempts: u32) -> chrono::Duration {
+        let doublings = attempts.saturating_sub(1).min(MAX_BACKOFF_DOUBLINGS);
+        let secs = i64::from(self.backoff_secs) << doublings;
+        chrono::Duration::seconds(secs)
+    }
+}
+
+impl FromSql<Jsonb, Pg> for RestartPolicy {
+    fn from_sql(value: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(value)?;
+        RestartPolicy::deserialize(&value).map_err(|err| Error::ParsePolicy(value, err).into())
+    }
+}
+
+impl ToSql<Jsonb, Pg> for RestartPolicy {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+impl TryFrom<common::NodeRestartPolicy> for RestartPolicy {
+    type Error = Error;
+
+    fn try_from(policy: common::NodeRestartPolicy) -> Result<Self, Self::Error> {
+        let action = match policy.action() {
+            common::RestartAction::Unspecified => return Err(Error::MissingAction),
+            common::RestartAction::Never => RestartAction::Never,
+            common::RestartAction::OnFailure => RestartAction::OnFailure,
+            common::RestartAction::Recreate => RestartAction::Recreate,
+        };
+
+        Ok(RestartPolicy {
+            action,
+            max_attempts: policy.max_attempts,
+            backoff_secs: policy.backoff_seconds,
+        })
+    }
+}
+
+impl From<RestartPolicy> for common::NodeRestartPolicy {
+    fn from(policy: RestartPolicy) -> Self {
+        let action = match policy.action {
+            RestartAction::Never => common::RestartAction::Never,
+            RestartAction::OnFailure => common::RestartAction::OnFailure,
+            RestartAction::Recreate => common::RestartAction::Recreate,
+        };
+
+        common::NodeRestartPolicy {
+            action: action.into(),
+            max_attempts: policy.max_attempts,
+            backoff_seconds: policy.backoff_secs,
+        }
+    }
+}
+
+/// Run `recover` forever at the configured interval.
+pub async fn run(ctx: Arc<Context>) {
+    // each node recovers in its own transaction while the lock is held
+    let interval = *ctx.config.host.recovery_interval;
+    let ctx = &*ctx;
+    background::run_exclusive(ctx, AdvisoryLock::NodeRecovery, interval, move |_| {
+        async move {
+            recover(ctx).await?;
+            Ok::<_, Error>(Vec::<Message>::new())
+        }
+        .boxed()
+    })
+    .await;
+}
+
+/// Take the next step for each failed node with a restart policy.
+pub async fn recover(ctx: &Context) -> Result<(), Error> {
+    let now = Utc::now();
+    let nodes = candidates(&mut ctx.conn().await?).await?;
+
+    for node in nodes {
+        let node_id = node.id;
+        let attempt = node.restart_attempts.saturating_add(1);
+        let Some(recovery) =
+            node.restart_policy
+                .next(node.restart_attempts, node.restart_attempted_at, now)
+        else {
+            continue;
+        };
+
+        let result = transaction(ctx, |mut write| {
+            async move { recover_node(node, recovery, attempt, &mut write).await }.scope_boxed()
+        })
+        .await;
+
+        match result {
+            Ok(()) => info!("Recovery of node {node_id}: {recovery:?}"),
+            Err(Error::Relaunch(err)) if no_matching_host(&err) => {
+                warn!("Failed to recreate node {node_id}: no matching host.");
+
+                // count the attempt so that recovery eventually gives up
+                let mut conn = ctx.conn().await?;
+                if let Err(err) = record_attempt(node_id, attempt, &mut conn).await {
+                    error!("{err}");
+                }
+            }
+            Err(err) => error!("Failed to recover node {node_id}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Failed nodes on available hosts that have opted in to recovery.
+async fn candidates(conn: &mut Conn<'_>) -> Result<Vec<Node>, Error> {
+    let opted_in = sql::<Bool>("nodes.restart_policy ->> 'action' in ('on_failure', 'recreate')");
+
+    nodes::table
+        .inner_join(hosts::table)
+        .filter(nodes::deleted_at.is_null())
+        .filter(nodes::node_state.eq(NodeState::Failed))
+        .filter(nodes::next_state.is_null())
+        .filter(opted_in)
+        .filter(hosts::deleted_at.is_null())
+        .filter(hosts::connection_status.eq(ConnectionStatus::Online))
+        .select(nodes::all_columns)
+        .get_results(conn)
+        .await
+        .map_err(Error::FindFailed)
+}
+
+async fn recover_node(
+    node: Node,
+    recovery: Recovery,
+    attempt: i32,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    let authz = authz(node.created_by(), write).await?;
+
+    match recovery {
+        Recovery::Restart => {
+            restart_node(&node, &authz, write).await?;
+            record_attempt(node.id, attempt, write).await?;
+
+            let event = LogEvent::RecoveryAttempted(RecoveryAttempted {
+                attempt,
+                new_node: None,
+            });
+            NewNodeLog::from(&node, &authz, event).create(write).await?;
+        }
+
+        Recovery::Recreate => {
+            let new_id = recreate_node(&node, attempt, &authz, write).await?;
+
+            let event = LogEvent::RecoveryAttempted(RecoveryAttempted {
+                attempt,
+                new_node: Some(new_id),
+            });
+            NewNodeLog::from(&node, &authz, event).create(write).await?;
+        }
+
+        Recovery::GiveUp => {
+            record_attempt(node.id, attempt, write).await?;
+            NewNodeLog::from(&node, &authz, LogEvent::RecoveryGaveUp)
+                .create(write)
+                .await?;
+
+            warn!(
+                "Giving up on recovering node {} after {} attempts.",
+                node.id, node.restart_policy.max_attempts
+            );
+            alert_owners(&node, write).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn restart_node(
+    node: &Node,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<(), Error> {
+    let restart_cmd = NewCommand::node(node, CommandType::NodeRestart)?
+        .create(write)
+        .await?;
+    let restart_cmd = api::Command::from(&restart_cmd, authz, write)
+        .await?
+        .ok_or(Error::NoNodeRestart(node.id))?;
+    write.mqtt(restart_cmd);
+
+    Ok(())
+}
+
+/// Re-create a node on another host and delete the original, returning the id
+/// of the new node.
+async fn recreate_node(
+    node: &Node,
+    attempt: i32,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<NodeId, Error> {
+    // the replacement keeps the attempts of the original node
+    let new = relaunch(node, node.config_id, authz, write).await?;
+    record_attempt(new.id, attempt, write).await?;
+
+    let old = Node::delete(node.id, write).await?;
+    let delete_cmd = NewCommand::node(&old, CommandType::NodeDelete)?
+        .create(write)
+        .await?;
+    let delete_cmd = api::Command::from(&delete_cmd, authz, write)
+        .await?
+        .ok_or(Error::NoNodeDelete(old.id))?;
+
+    let recovered_by = common::Resource::from(authz);
+    write.mqtt(delete_cmd);
+    write.mqtt(api::NodeMessage::deleted(&old, Some(recovered_by)));
+
+    Ok(new.id)
+}
+
+async fn record_attempt(node_id: NodeId, attempt: i32, conn: &mut Conn<'_>) -> Result<(), Error> {
+    diesel::update(nodes::table.find(node_id))
+        .set((
+            nodes::restart_attempts.eq(attempt),
+            nodes::restart_attempted_at.eq(Utc::now()),
+        ))
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| Error::Record(node_id, err))
+}
+
+/// Email the owners of the node org that recovery has been given up, once the
+/// final attempt has been committed.
+async fn alert_owners(node: &Node, write: &mut WriteConn<'_, '_>) -> Result<(), Error> {
+    let ctx = write.ctx;
+    let Some(email) = ctx.email.clone() else {
+        return Ok(());
+    };
+
+    let owner_ids = RbacUser::org_owners(node.org_id, write).await?;
+    let owners = User::by_ids(&owner_ids.into_iter().collect(), write).await?;
+    let node_name = node.node_name.clone();
+    let max_attempts = node.restart_policy.max_attempts;
+    write.after_commit(async move {
+        for owner in owners {
+            if let Err(err) = email
+                .node_recovery_failed(&owner, &node_name, max_attempts)
+                .await
+            {
+                warn!("Failed to send node recovery email to {}: {err}", owner.id);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(action: RestartAction) -> RestartPolicy {
+        RestartPolicy {
+            action,
+            max_attempts: 3,
+            backoff_secs: 60,
+        }
+    }
+
+    #[test]
+    fn next_step_waits_for_backoff() {
+        let now = Utc::now();
+        let on_failure = policy(RestartAction::OnFailure);
+
+        // the first attempt is immediate
+        assert_eq!(on_failure.next(0, None, now), Some(Recovery::Restart));
+
+        // the second attempt waits 60s, the third 120s
+        let ago = |secs| Some(now - chrono::Duration::seconds(secs));
+        assert_eq!(on_failure.next(1, ago(30), now), None);
+        assert_eq!(on_failure.next(1, ago(60), now), Some(Recovery::Restart));
+        assert_eq!(on_failure.next(2, ago(90), now), None);
+        assert_eq!(on_failure.next(2, ago(120), now), Some(Recovery::Restart));
+
+        // give up once the last attempt had its chance
+        assert_eq!(on_failure.next(3, ago(200), now), None);
+        assert_eq!(on_failure.next(3, ago(240), now), Some(Recovery::GiveUp));
+        assert_eq!(on_failure.next(4, ago(10_000), now), None);
+
+        let recreate = policy(RestartAction::Recreate);
+        assert_eq!(recreate.next(0, None, now), Some(Recovery::Recreate));
+        assert_eq!(policy(RestartAction::Never).next(0, None, now), None);
+    }
+
+    #[test]
+    fn policy_defaults_to_never() {
+        let policy: RestartPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, RestartPolicy::default());
+        assert_eq!(policy.action, RestartAction::Never);
+
+        let policy: RestartPolicy = serde_json::from_str(r#"{"action":"recreate"}"#).unwrap();
+        assert_eq!(policy.action, RestartAction::Recreate);
+        assert_eq!(policy.max_attempts, 3);
+    }
+}
@@ -0,0 +1,298 @@
+//! Helpers for the background jobs that re-create nodes on another host.
+//!
+//! Failover, recovery and moving a resized node all act on behalf of the node
+//! creator, place the replacement like the original node, and run each node in
+//! its own transaction before sending its MQTT messages.
+//!
+//! The replacement keeps the rules and restart policy of the original node.
+
+use diesel::prelude::*;
+use diesel_async::scoped_futures::{ScopedBoxFuture, ScopedFutureExt};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use displaydoc::Display;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::auth::AuthZ;
+use crate::auth::claims::{Claims, Granted};
+use crate::auth::rbac::{Access, Perms, ProtocolAdminPerm, ProtocolPerm};
+use crate::auth::resource::{HostId, NodeId, Resource};
+use crate::config::Context;
+use crate::database::{Conn, WriteConn};
+use crate::grpc::{Status, api, common};
+use crate::model::command::{CommandType, NewCommand};
+use crate::model::host::Host;
+use crate::model::image::ConfigId;
+use crate::model::region::RegionId;
+use crate::model::schema::nodes;
+
+use super::{
+    Launch, NewNode, NewNodeRule, Node, NodeRule, RegionCount, ResourceAffinity,
+    SimilarNodeAffinity,
+};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Relaunch command error: {0}
+    Command(#[from] crate::model::command::Error),
+    /// Relaunch command API error: {0}
+    CommandApi(#[from] crate::grpc::command::Error),
+    /// Relaunch host error: {0}
+    Host(#[from] crate::model::host::Error),
+    /// No visibility of the NodeCreate command for node `{0}`.
+    NoNodeCreate(NodeId),
+    /// Node `{0}` was not re-created on another host.
+    NoNewNode(NodeId),
+    /// Relaunch node error: {0}
+    Node(#[from] super::Error),
+    /// Relaunch node API error: {0}
+    NodeApi(#[from] crate::grpc::node::Error),
+    /// Relaunch node rule error: {0}
+    NodeRule(#[from] super::rule::Error),
+    /// Failed to copy the restart policy to node `{0}`: {1}
+    RestartPolicy(NodeId, diesel::result::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            NoNodeCreate(_) | NoNewNode(_) | RestartPolicy(..) => {
+                Status::internal("Internal error.")
+            }
+            Command(err) => err.into(),
+            CommandApi(err) => err.into(),
+            Host(err) => err.into(),
+            Node(err) => err.into(),
+            NodeApi(err) => err.into(),
+            NodeRule(err) => err.into(),
+        }
+    }
+}
+
+/// Act on behalf of the creator of a node, with visibility of its protocol.
+pub(crate) async fn authz(
+    resource: Resource,
+    conn: &mut Conn<'_>,
+) -> Result<AuthZ, crate::auth::claims::Error> {
+    let perms = hashset! {
+        ProtocolAdminPerm::ViewPrivate.into(),
+        ProtocolPerm::ViewDevelopment.into(),
+        ProtocolPerm::ViewPublic.into(),
+    };
+    let access = Access::Perms(Perms::All(perms));
+    let claims = Claims::from_now(chrono::Duration::minutes(5), resource, access);
+    let granted = Granted::from_access(&claims.access, None, conn).await?;
+
+    Ok(AuthZ { claims, granted })
+}
+
+/// Run a closure in a database transaction, then send its MQTT messages and
+/// run its tasks.
+pub(super) async fn transaction<'a, F, T, E>(ctx: &'a Context, f: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(WriteConn<'c, 'a>) -> ScopedBoxFuture<'a, 'c, Result<T, E>> + Send + 'a,
+    T: Send + 'a,
+    E: From<diesel::result::Error> + From<crate::database::Error> + Send + 'a,
+{
+    let conn = &mut ctx.conn().await?;
+
+    let (meta_tx, _meta_rx) = mpsc::unbounded_channel();
+    let (mqtt_tx, mut mqtt_rx) = mpsc::unbounded_channel();
+    let (task_tx, mut task_rx) = mpsc::unbounded_channel();
+
+    let result = conn
+        .transaction(|conn| {
+            let write = WriteConn {
+                conn,
+                ctx,
+                meta_tx,
+                mqtt_tx,
+                task_tx,
+            };
+            f(write).scope_boxed()
+        })
+        .await?;
+
+    while let Some(msg) = mqtt_rx.recv().await {
+        if let Err(err) = ctx.notifier.send(msg).await {
+            warn!("Failed to send MQTT message: {err}");
+        }
+    }
+
+    while let Some(task) = task_rx.recv().await {
+        task.await;
+    }
+
+    Ok(result)
+}
+
+/// Re-create `node` with `config_id` on another host than its own, then send
+/// the NodeCreate command for the new node.
+pub(super) async fn relaunch(
+    node: &Node,
+    config_id: ConfigId,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<Node, Error> {
+    let host = Host::by_id(node.host_id, Some(node.org_id), write).await?;
+    let count = region_count(
+        node.scheduler_resource,
+        node.scheduler_similarity,
+        node.scheduler_region_id,
+        host.id,
+        host.region_id,
+    );
+    let new_node = NewNode {
+        org_id: node.org_id,
+        image_id: node.image_id,
+        config_id,
+        old_node_id: Some(node.id),
+        protocol_id: node.protocol_id,
+        protocol_version_id: node.protocol_version_id,
+        semantic_version: node.semantic_version.clone(),
+        auto_upgrade: node.auto_upgrade,
+        tags: node.tags.clone(),
+        placement: node.placement.clone(),
+    };
+
+    let dns_base = &write.ctx.config.cloudflare.dns.base;
+    let created = new_node
+        .create(Launch::ByRegion(vec![count]), dns_base, authz, write)
+        .await?;
+    let new = created
+        .into_iter()
+        .next()
+        .ok_or(Error::NoNewNode(node.id))?;
+
+    let rules = NodeRule::by_node_id(node.id, write)
+        .await?
+        .into_iter()
+        .map(|rule| NewNodeRule::new(new.id, rule.into()))
+        .collect();
+    NewNodeRule::bulk_create(rules, write).await?;
+
+    let new: Node = diesel::update(nodes::table.find(new.id))
+        .set(nodes::restart_policy.eq(node.restart_policy))
+        .get_result(write)
+        .await
+        .map_err(|err| Error::RestartPolicy(new.id, err))?;
+
+    let create_cmd = NewCommand::node(&new, CommandType::NodeCreate)?
+        .create(write)
+        .await?;
+    let create_cmd = api::Command::from(&create_cmd, authz, write)
+        .await?
+        .ok_or(Error::NoNodeCreate(new.id))?;
+
+    let created_by = common::Resource::from(authz);
+    let api_node = api::Node::from_model(new.clone(), authz, write).await?;
+    write.mqtt(create_cmd);
+    write.mqtt(api::NodeMessage::created(api_node, created_by));
+
+    Ok(new)
+}
+
+/// Where to place the replacement for a node on host `host_id` in
+/// `host_region`.
+///
+/// The replacement keeps the scheduling of the original node, but defaults to
+/// spreading similar nodes over many hosts in the region of the failed host.
+/// It is never placed on the host of the original node.
+pub(super) const fn region_count(
+    resource: Option<ResourceAffinity>,
+    similarity: Option<SimilarNodeAffinity>,
+    region_id: Option<RegionId>,
+    host_id: HostId,
+    host_region: RegionId,
+) -> RegionCount {
+    let similarity = match similarity {
+        Some(similarity) => similarity,
+        None => SimilarNodeAffinity::Spread,
+    };
+    let region_id = match region_id {
+        Some(region_id) => region_id,
+        None => host_region,
+    };
+
+    RegionCount {
+        region_id,
+        node_count: 1,
+        resource,
+        similarity: Some(similarity),
+        exclude_host_id: Some(host_id),
+    }
+}
+
+/// Whether relaunching failed because no other host could take the node.
+pub(super) fn no_matching_host(err: &Error) -> bool {
+    match err {
+        Error::Node(err) => node_no_matching_host(err),
+        _ => false,
+    }
+}
+
+fn node_no_matching_host(err: &super::Error) -> bool {
+    use super::launch::Error as LaunchError;
+
+    match err {
+        super::Error::NoMatchingHost => true,
+        super::Error::Launch(err) => match err.as_ref() {
+            LaunchError::Node(err) => node_no_matching_host(err),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn region_count_defaults_to_spread_in_host_region() {
+        let host_id = HostId::from(Uuid::new_v4());
+        let host_region = RegionId::from(Uuid::new_v4());
+        let count = region_count(None, None, None, host_id, host_region);
+        assert_eq!(count.region_id, host_region);
+        assert_eq!(count.exclude_host_id, Some(host_id));
+        assert!(matches!(
+            count.similarity,
+            Some(SimilarNodeAffinity::Spread)
+        ));
+
+        let node_region = RegionId::from(Uuid::new_v4());
+        let count = region_count(
+            Some(ResourceAffinity::MostResources),
+            Some(SimilarNodeAffinity::Cluster),
+            Some(node_region),
+            host_id,
+            host_region,
+        );
+        assert_eq!(count.region_id, node_region);
+        assert!(matches!(
+            count.similarity,
+            Some(SimilarNodeAffinity::Cluster)
+        ));
+        assert!(matches!(
+            count.resource,
+            Some(ResourceAffinity::MostResources)
+        ));
+    }
+
+    #[test]
+    fn no_matching_host_through_launch() {
+        use super::super::Error as NodeError;
+        use super::super::launch::Error as LaunchError;
+
+        let launch = NodeError::Launch(Box::new(LaunchError::Node(NodeError::NoMatchingHost)));
+        assert!(no_matching_host(&Error::Node(launch)));
+        assert!(!no_matching_host(&Error::Node(NodeError::GenerateName)));
+        assert!(!no_matching_host(&Error::NoNodeCreate(NodeId::from(
+            Uuid::new_v4()
+        ))));
+    }
+}
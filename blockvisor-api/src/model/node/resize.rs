@@ -0,0 +1,519 @@
+//! Resize the CPU, memory and disk of a node.
+//!
+//! A node is resized in place when its host has room for the extra resources,
+//! after holding back any public capacity that is reserved for other orgs. The
+//! host counters and billing usage then follow the new size.
+//!
+//! Otherwise the node is re-created with the new size on another host through
+//! the usual node scheduler, pointing back at the original via `old_node_id`.
+//! The original node keeps running until the new node reports that it is
+//! running, and is only then deleted. If the new node is deleted first, or is
+//! still not running after the configured delete timeout, the original node is
+//! kept and the new node is deleted. Each pass over the pending moves holds an
+//! advisory lock, so only one replica completes them at a time.
+//!
+//! Disks can only grow, and no resource may drop below the image minimum.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::UniqueViolation;
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use futures_util::FutureExt;
+use thiserror::Error;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthZ;
+use crate::auth::resource::NodeId;
+use crate::config::Context;
+use crate::database::{AdvisoryLock, Conn, Database, WriteConn};
+use crate::grpc::{Status, api, common};
+use crate::model::Region;
+use crate::model::background::{self, cutoff};
+use crate::model::capacity_reservation::CapacityReservation;
+use crate::model::command::{CommandType, NewCommand};
+use crate::model::host::Host;
+use crate::model::host_capacity::{HostCapacity, Resources};
+use crate::model::image::config::{ConfigType, NewConfig};
+use crate::model::image::{Config, ConfigId, Image};
+use crate::model::schema::{node_moves, nodes};
+use crate::mqtt::Message;
+
+use super::log::Resized;
+use super::relaunch::{authz, relaunch, transaction};
+use super::{LogEvent, NewNodeLog, Node, NodeState, NodeUsage};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Requested CPU cores {0} are below the image minimum of {1}.
+    BelowMinCpu(i64, i64),
+    /// Requested disk bytes {0} are below the image minimum of {1}.
+    BelowMinDisk(i64, i64),
+    /// Requested memory bytes {0} are below the image minimum of {1}.
+    BelowMinMemory(i64, i64),
+    /// Resize capacity reservation error: {0}
+    CapacityReservation(#[from] crate::model::capacity_reservation::Error),
+    /// Resize claims error: {0}
+    Claims(#[from] crate::auth::claims::Error),
+    /// Resize command error: {0}
+    Command(#[from] crate::model::command::Error),
+    /// Resize command API error: {0}
+    CommandApi(#[from] crate::grpc::command::Error),
+    /// Failed to complete node move `{0}`: {1}
+    CompleteMove(Uuid, diesel::result::Error),
+    /// Resize config error: {0}
+    Config(#[from] crate::model::image::config::Error),
+    /// Failed to create move for node `{0}`: {1}
+    CreateMove(NodeId, diesel::result::Error),
+    /// Resize database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Failed to find pending node moves: {0}
+    FindMoves(diesel::result::Error),
+    /// Resize host error: {0}
+    Host(#[from] crate::model::host::Error),
+    /// Resize image error: {0}
+    Image(#[from] crate::model::image::Error),
+    /// No visibility of the NodeDelete command for node `{0}`.
+    NoNodeDelete(NodeId),
+    /// Resize node error: {0}
+    Node(#[from] super::Error),
+    /// Resize node log error: {0}
+    NodeLog(#[from] super::log::Error),
+    /// Resize region error: {0}
+    Region(#[from] crate::model::region::Error),
+    /// Resize relaunch error: {0}
+    Relaunch(#[from] super::relaunch::Error),
+    /// Node disk can't shrink from {0} to {1} bytes.
+    ShrinkDisk(i64, i64),
+    /// Resize transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+    /// Node `{0}` already has the requested size.
+    Unchanged(NodeId),
+    /// Failed to update resources of node `{0}`: {1}
+    Update(NodeId, diesel::result::Error),
+    /// Resize node usage error: {0}
+    Usage(#[from] super::usage::Error),
+    /// Failed to parse VM cpu count: {0}
+    VmCpu(std::num::TryFromIntError),
+    /// Failed to parse VM disk bytes: {0}
+    VmDisk(std::num::TryFromIntError),
+    /// Failed to parse VM memory bytes: {0}
+    VmMemory(std::num::TryFromIntError),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            BelowMinCpu(..) | VmCpu(_) => Status::invalid_argument("cpu_cores"),
+            BelowMinDisk(..) | ShrinkDisk(..) | VmDisk(_) => Status::invalid_argument("disk_bytes"),
+            BelowMinMemory(..) | VmMemory(_) => Status::invalid_argument("memory_bytes"),
+            CreateMove(_, DatabaseError(UniqueViolation, _)) => {
+                Status::failed_precondition("The node is already being moved.")
+            }
+            Unchanged(_) => Status::already_exists("The node already has this size."),
+            Update(_, NotFound) => Status::not_found("Node not found."),
+            CompleteMove(..) | CreateMove(..) | Database(_) | FindMoves(_) | NoNodeDelete(_)
+            | Transaction(_) | Update(..) => Status::internal("Internal error."),
+            CapacityReservation(err) => err.into(),
+            Claims(err) => err.into(),
+            Command(err) => err.into(),
+            CommandApi(err) => err.into(),
+            Config(err) => err.into(),
+            Host(err) => err.into(),
+            Image(err) => err.into(),
+            Node(err) => err.into(),
+            NodeLog(err) => err.into(),
+            Region(err) => err.into(),
+            Relaunch(err) => err.into(),
+            Usage(err) => err.into(),
+        }
+    }
+}
+
+/// The new size of a node, where `None` keeps the current value.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NodeResize {
+    pub cpu_cores: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub disk_bytes: Option<u64>,
+}
+
+/// The outcome of resizing a node.
+#[derive(Debug)]
+pub enum Resize {
+    /// The node was resized on its current host.
+    InPlace(Node),
+    /// The node was re-created on another host, and the original is deleted
+    /// once the new node is running.
+    Moved(Node),
+}
+
+/// A resized node that was re-created on another host.
+#[derive(Debug, Queryable)]
+#[diesel(table_name = node_moves)]
+pub struct NodeMove {
+    pub id: Uuid,
+    pub node_id: NodeId,
+    pub new_node_id: NodeId,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl NodeMove {
+    /// Incomplete moves where the new node is running or has been deleted, or
+    /// that were created before `expired`.
+    pub async fn pending(expired: DateTime<Utc>, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        node_moves::table
+            .inner_join(nodes::table)
+            .filter(node_moves::completed_at.is_null())
+            .filter(
+                nodes::node_state
+                    .eq(NodeState::Running)
+                    .or(nodes::deleted_at.is_not_null())
+                    .or(node_moves::created_at.lt(expired)),
+            )
+            .select(node_moves::all_columns)
+            .get_results(conn)
+            .await
+            .map_err(Error::FindMoves)
+    }
+
+    pub async fn complete(&self, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        diesel::update(node_moves::table.find(self.id))
+            .set(node_moves::completed_at.eq(Utc::now()))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::CompleteMove(self.id, err))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = node_moves)]
+pub struct NewNodeMove {
+    pub node_id: NodeId,
+    pub new_node_id: NodeId,
+}
+
+impl NewNodeMove {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<NodeMove, Error> {
+        let node_id = self.node_id;
+        diesel::insert_into(node_moves::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::CreateMove(node_id, err))
+    }
+}
+
+impl NodeResize {
+    pub async fn apply(
+        self,
+        node: Node,
+        authz: &AuthZ,
+        write: &mut WriteConn<'_, '_>,
+    ) -> Result<Resize, Error> {
+        let config = Config::by_id(node.config_id, write).await?;
+        let mut node_config = config.node_config()?;
+        let image = Image::by_id(node.image_id, Some(node.org_id), authz, write).await?;
+
+        let vm = &mut node_config.vm;
+        vm.cpu_cores = self.cpu_cores.unwrap_or(vm.cpu_cores);
+        vm.memory_bytes = self.memory_bytes.unwrap_or(vm.memory_bytes);
+        vm.disk_bytes = self.disk_bytes.unwrap_or(vm.disk_bytes);
+
+        let size = Resources {
+            cpu_cores: i64::try_from(vm.cpu_cores).map_err(Error::VmCpu)?,
+            memory_bytes: i64::try_from(vm.memory_bytes).map_err(Error::VmMemory)?,
+            disk_bytes: i64::try_from(vm.disk_bytes).map_err(Error::VmDisk)?,
+        };
+        let current = Resources {
+            cpu_cores: node.cpu_cores,
+            memory_bytes: node.memory_bytes,
+            disk_bytes: node.disk_bytes,
+        };
+        let minimum = Resources {
+            cpu_cores: image.min_cpu_cores,
+            memory_bytes: image.min_memory_bytes,
+            disk_bytes: image.min_disk_bytes,
+        };
+        validate(node.id, current, size, minimum)?;
+
+        let new_config = NewConfig {
+            image_id: config.image_id,
+            archive_id: config.archive_id,
+            config_type: ConfigType::Node,
+            config: node_config.into(),
+        };
+        let config = new_config.create(authz, write).await?;
+
+        let host = Host::by_id(node.host_id, Some(node.org_id), write).await?;
+        let region = Region::by_id(host.region_id, write).await?;
+        let growth = growth(current, size);
+        let mut in_place = fits(growth, HostCapacity::new(&host, &region).free);
+        // growth on a public host may not eat into capacity reserved for other orgs
+        if in_place && host.org_id.is_none() {
+            let unreserved = Host::unreserved_public_free(Some(node.org_id), write).await?;
+            if let Some(free) = unreserved.get(&host.region_id) {
+                in_place = fits(growth, *free);
+            }
+        }
+
+        if in_place {
+            resize_in_place(node, &host, config.id, size, authz, write).await
+        } else {
+            move_node(node, config.id, size, authz, write).await
+        }
+    }
+}
+
+/// Update the node to its new size, keeping the host counters, billing and
+/// any consumed capacity reservation in step.
+async fn resize_in_place(
+    node: Node,
+    host: &Host,
+    config_id: ConfigId,
+    size: Resources,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<Resize, Error> {
+    let mut resized: Node = diesel::update(nodes::table.find(node.id))
+        .set((
+            nodes::config_id.eq(config_id),
+            nodes::cpu_cores.eq(size.cpu_cores),
+            nodes::memory_bytes.eq(size.memory_bytes),
+            nodes::disk_bytes.eq(size.disk_bytes),
+            nodes::updated_at.eq(Utc::now()),
+        ))
+        .get_result(write)
+        .await
+        .map_err(|err| Error::Update(node.id, err))?;
+
+    Host::remove_node(&node, write).await?;
+    Host::add_node(&resized, write).await?;
+    NodeUsage::record(&resized, write).await?;
+    if host.org_id.is_none() {
+        CapacityReservation::release(&node, write).await?;
+        resized.capacity_reservation_id =
+            CapacityReservation::consume(&resized, host.region_id, write)
+                .await?
+                .map(|reservation| reservation.id);
+    }
+
+    let event = LogEvent::Resized(Resized {
+        cpu_cores: size.cpu_cores,
+        memory_bytes: size.memory_bytes,
+        disk_bytes: size.disk_bytes,
+        new_node: None,
+    });
+    NewNodeLog::from(&resized, authz, event)
+        .create(write)
+        .await?;
+
+    Ok(Resize::InPlace(resized))
+}
+
+/// Re-create the node with its new size on another host, keeping the original
+/// until the new node is running.
+async fn move_node(
+    node: Node,
+    config_id: ConfigId,
+    size: Resources,
+    authz: &AuthZ,
+    write: &mut WriteConn<'_, '_>,
+) -> Result<Resize, Error> {
+    let new = relaunch(&node, config_id, authz, write).await?;
+
+    let event = LogEvent::Resized(Resized {
+        cpu_cores: size.cpu_cores,
+        memory_bytes: size.memory_bytes,
+        disk_bytes: size.disk_bytes,
+        new_node: Some(new.id),
+    });
+    NewNodeLog::from(&node, authz, event).create(write).await?;
+
+    NewNodeMove {
+        node_id: node.id,
+        new_node_id: new.id,
+    }
+    .create(write)
+    .await?;
+
+    Ok(Resize::Moved(new))
+}
+
+/// Run `complete` forever at the configured interval.
+pub async fn run(ctx: Arc<Context>) {
+    // each move completes in its own transaction while the lock is held
+    let delete_after = *ctx.config.host.move_delete_after;
+    let interval = *ctx.config.host.move_interval;
+    let ctx = &*ctx;
+    background::run_exclusive(ctx, AdvisoryLock::NodeMove, interval, move |_| {
+        async move {
+            complete(delete_after, ctx).await?;
+            Ok::<_, Error>(Vec::<Message>::new())
+        }
+        .boxed()
+    })
+    .await;
+}
+
+/// Delete the original nodes of moves where the new node is running, and the
+/// new nodes of moves that have not finished within `delete_after`.
+pub async fn complete(delete_after: Duration, ctx: &Context) -> Result<(), Error> {
+    let expired = cutoff(Utc::now(), delete_after);
+    let moves = NodeMove::pending(expired, &mut ctx.conn().await?).await?;
+
+    for node_move in moves {
+        let move_id = node_move.id;
+        let result = transaction(ctx, |mut write| {
+            async move { complete_move(node_move, &mut write).await }.scope_boxed()
+        })
+        .await;
+
+        if let Err(err) = result {
+            error!("Failed to complete node move {move_id}: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete the original node of a move once the new node is running, or the
+/// new node if it has not started in time, then mark the move as completed.
+async fn complete_move(node_move: NodeMove, write: &mut WriteConn<'_, '_>) -> Result<(), Error> {
+    let new = Node::deleted_by_id(node_move.new_node_id, write).await?;
+    // the original node is kept when the new node was deleted first
+    if new.deleted_at.is_none() {
+        if new.node_state == NodeState::Running {
+            delete_node(node_move.node_id, write).await?;
+        } else {
+            warn!(
+                "Deleting node {} after it did not start in time to replace node {}.",
+                new.id, node_move.node_id
+            );
+            delete_node(new.id, write).await?;
+        }
+    }
+
+    node_move.complete(write).await?;
+
+    Ok(())
+}
+
+/// Delete a node and tell its host to clean it up.
+async fn delete_node(node_id: NodeId, write: &mut WriteConn<'_, '_>) -> Result<(), Error> {
+    match Node::delete(node_id, write).await {
+        Ok(node) => {
+            let authz = authz(node.created_by(), write).await?;
+            let delete_cmd = NewCommand::node(&node, CommandType::NodeDelete)?
+                .create(write)
+                .await?;
+            let delete_cmd = api::Command::from(&delete_cmd, &authz, write)
+                .await?
+                .ok_or(Error::NoNodeDelete(node.id))?;
+
+            let deleted_by = common::Resource::from(&authz);
+            write.mqtt(delete_cmd);
+            write.mqtt(api::NodeMessage::deleted(&node, Some(deleted_by)));
+            Ok(())
+        }
+        // already deleted while the move was pending
+        Err(super::Error::AlreadyDeleted(_)) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Check a new node size against its current size and the image minimum.
+fn validate(
+    node_id: NodeId,
+    current: Resources,
+    size: Resources,
+    minimum: Resources,
+) -> Result<(), Error> {
+    if size.cpu_cores < minimum.cpu_cores {
+        Err(Error::BelowMinCpu(size.cpu_cores, minimum.cpu_cores))
+    } else if size.memory_bytes < minimum.memory_bytes {
+        Err(Error::BelowMinMemory(
+            size.memory_bytes,
+            minimum.memory_bytes,
+        ))
+    } else if size.disk_bytes < minimum.disk_bytes {
+        Err(Error::BelowMinDisk(size.disk_bytes, minimum.disk_bytes))
+    } else if size.disk_bytes < current.disk_bytes {
+        Err(Error::ShrinkDisk(current.disk_bytes, size.disk_bytes))
+    } else if size == current {
+        Err(Error::Unchanged(node_id))
+    } else {
+        Ok(())
+    }
+}
+
+/// The extra resources needed on a host, where shrinking needs none.
+fn growth(current: Resources, size: Resources) -> Resources {
+    Resources {
+        cpu_cores: (size.cpu_cores - current.cpu_cores).max(0),
+        memory_bytes: (size.memory_bytes - current.memory_bytes).max(0),
+        disk_bytes: (size.disk_bytes - current.disk_bytes).max(0),
+    }
+}
+
+/// Whether `growth` fits into the `free` capacity of a host.
+fn fits(growth: Resources, free: Resources) -> bool {
+    growth.cpu_cores <= free.cpu_cores.max(0)
+        && growth.memory_bytes <= free.memory_bytes.max(0)
+        && growth.disk_bytes <= free.disk_bytes.max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    const fn resources(cpu_cores: i64, memory_bytes: i64, disk_bytes: i64) -> Resources {
+        Resources {
+            cpu_cores,
+            memory_bytes,
+            disk_bytes,
+        }
+    }
+
+    #[test]
+    fn validate_against_image_and_current_size() {
+        let node_id = NodeId::from(Uuid::new_v4());
+        let current = resources(2, 4, 100);
+        let minimum = resources(2, 2, 50);
+
+        assert!(validate(node_id, current, resources(4, 4, 200), minimum).is_ok());
+        assert!(validate(node_id, current, resources(2, 2, 100), minimum).is_ok());
+        assert!(matches!(
+            validate(node_id, current, resources(1, 4, 100), minimum),
+            Err(Error::BelowMinCpu(1, 2))
+        ));
+        assert!(matches!(
+            validate(node_id, current, resources(2, 4, 80), minimum),
+            Err(Error::ShrinkDisk(100, 80))
+        ));
+        assert!(matches!(
+            validate(node_id, current, current, minimum),
+            Err(Error::Unchanged(_))
+        ));
+    }
+
+    #[test]
+    fn only_growth_needs_free_capacity() {
+        let growth = growth(resources(4, 8, 100), resources(2, 16, 150));
+        assert_eq!(growth, resources(0, 8, 50));
+
+        assert!(fits(growth, resources(0, 8, 50)));
+        assert!(fits(growth, resources(-1, 10, 60)));
+        assert!(!fits(growth, resources(4, 7, 60)));
+    }
+}
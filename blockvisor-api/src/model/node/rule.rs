@@ -0,0 +1,387 @@
+//! Node-level firewall rule overrides.
+//!
+//! A node starts with the `ImageRule`s of its image, and users may add their
+//! own rules on top (for example to allowlist an IP for an RPC port). Node
+//! rules are merged into the node's `FirewallConfig` and carried over to the
+//! new image when the node is upgraded.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::NodeId;
+use crate::database::Conn;
+use crate::grpc::Status;
+use crate::model::image::ImageRule;
+use crate::model::image::rule::{
+    FirewallAction, FirewallDirection, FirewallProtocol, FirewallRule, FirewallRuleKey, IpNames,
+    PortNames,
+};
+use crate::model::schema::node_rules;
+use crate::util::HashVec;
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to bulk create node firewall rules: {0}
+    BulkCreate(diesel::result::Error),
+    /// Failed to get firewall rules for node id {0}: {1}
+    ByNodeId(NodeId, diesel::result::Error),
+    /// Node rule `{0}` conflicts with image rule `{1}`.
+    Conflict(FirewallRuleKey, FirewallRuleKey),
+    /// Failed to delete firewall rules for node id {0}: {1}
+    Delete(NodeId, diesel::result::Error),
+    /// Node rule key `{0}` is used more than once.
+    DuplicateKey(FirewallRuleKey),
+    /// Node rule key `{0}` is already used by an image rule.
+    ImageKey(FirewallRuleKey),
+    /// Node rule `{0}` must match at least one ip or port.
+    MatchesAll(FirewallRuleKey),
+    /// Node rule `{0}` has an invalid port 0.
+    PortZero(FirewallRuleKey),
+    /// Node rule `{0}` to remove does not exist.
+    RemoveMissing(FirewallRuleKey),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            // safety: keys are from the client or image rules visible to them
+            Conflict(node_key, image_key) => Status::failed_precondition(format!(
+                "rule `{node_key}` conflicts with image rule `{image_key}`"
+            )),
+            DuplicateKey(key) => Status::invalid_argument(format!("duplicate rule key: {key}")),
+            ImageKey(key) => {
+                Status::already_exists(format!("rule key `{key}` is used by the image"))
+            }
+            MatchesAll(_) => Status::invalid_argument("rules.ips"),
+            PortZero(_) => Status::invalid_argument("rules.ports"),
+            RemoveMissing(key) => Status::not_found(format!("rule key: {key}")),
+            BulkCreate(_) | ByNodeId(_, _) | Delete(_, _) => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From)]
+pub struct NodeRuleId(Uuid);
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = node_rules)]
+pub struct NodeRule {
+    pub id: NodeRuleId,
+    pub node_id: NodeId,
+    pub key: FirewallRuleKey,
+    pub description: Option<String>,
+    pub protocol: FirewallProtocol,
+    pub direction: FirewallDirection,
+    pub action: FirewallAction,
+    pub ips: Option<IpNames>,
+    pub ports: Option<PortNames>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl NodeRule {
+    pub async fn by_node_id(node_id: NodeId, conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        node_rules::table
+            .filter(node_rules::node_id.eq(node_id))
+            .order_by(node_rules::created_at)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ByNodeId(node_id, err))
+    }
+
+    /// Apply added and removed rules to the existing rules of a node.
+    ///
+    /// An added rule with the key of an existing node rule replaces it. Added
+    /// rules are validated against the image rules first.
+    pub async fn replace(
+        node_id: NodeId,
+        add_rules: Vec<FirewallRule>,
+        remove_keys: Vec<FirewallRuleKey>,
+        image_rules: &[ImageRule],
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<Self>, Error> {
+        let existing = Self::by_node_id(node_id, conn).await?;
+        let existing_keys: HashSet<_> = existing.iter().map(|rule| &rule.key).collect();
+        if let Some(key) = remove_keys.iter().find(|key| !existing_keys.contains(key)) {
+            return Err(Error::RemoveMissing(key.clone()));
+        }
+
+        validate(&add_rules, image_rules)?;
+
+        let replaced: Vec<_> = remove_keys
+            .into_iter()
+            .chain(add_rules.iter().map(|rule| rule.key.clone()))
+            .collect();
+        diesel::delete(node_rules::table)
+            .filter(node_rules::node_id.eq(node_id))
+            .filter(node_rules::key.eq_any(&replaced))
+            .execute(conn)
+            .await
+            .map_err(|err| Error::Delete(node_id, err))?;
+
+        let new_rules = add_rules
+            .into_iter()
+            .map(|rule| NewNodeRule::new(node_id, rule))
+            .collect();
+        NewNodeRule::bulk_create(new_rules, conn).await?;
+
+        Self::by_node_id(node_id, conn).await
+    }
+}
+
+impl From<NodeRule> for FirewallRule {
+    fn from(rule: NodeRule) -> Self {
+        FirewallRule {
+            key: rule.key,
+            description: rule.description,
+            protocol: rule.protocol,
+            direction: rule.direction,
+            action: rule.action,
+            ips: rule.ips,
+            ports: rule.ports,
+        }
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = node_rules)]
+pub struct NewNodeRule {
+    pub node_id: NodeId,
+    pub key: FirewallRuleKey,
+    pub description: Option<String>,
+    pub protocol: FirewallProtocol,
+    pub direction: FirewallDirection,
+    pub action: FirewallAction,
+    pub ips: Option<IpNames>,
+    pub ports: Option<PortNames>,
+}
+
+impl NewNodeRule {
+    pub fn new(node_id: NodeId, rule: FirewallRule) -> Self {
+        NewNodeRule {
+            node_id,
+            key: rule.key,
+            description: rule.description,
+            protocol: rule.protocol,
+            direction: rule.direction,
+            action: rule.action,
+            ips: rule.ips,
+            ports: rule.ports,
+        }
+    }
+
+    pub async fn bulk_create(
+        rules: Vec<Self>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<NodeRule>, Error> {
+        if rules.is_empty() {
+            return Ok(vec![]);
+        }
+
+        diesel::insert_into(node_rules::table)
+            .values(rules)
+            .get_results(conn)
+            .await
+            .map_err(Error::BulkCreate)
+    }
+}
+
+/// Merge node rules over some base rules, where node rules take precedence.
+pub fn merge_rules<B, N>(base_rules: B, node_rules: N) -> Vec<FirewallRule>
+where
+    B: IntoIterator<Item = FirewallRule>,
+    N: IntoIterator<Item = FirewallRule>,
+{
+    let mut order = Vec::new();
+    let mut rules = HashMap::new();
+    for rule in base_rules.into_iter().chain(node_rules) {
+        if !rules.contains_key(&rule.key) {
+            order.push(rule.key.clone());
+        }
+        rules.insert(rule.key.clone(), rule);
+    }
+
+    order
+        .into_iter()
+        .filter_map(|key| rules.remove(&key))
+        .collect()
+}
+
+/// Validate new node rules against each other and the rules of the image.
+///
+/// IPs are already parsed as CIDRs when converting from the api type, so this
+/// checks that the ports are valid, that each rule matches some traffic, and
+/// that no rule overrides or contradicts an image rule.
+pub fn validate(rules: &[FirewallRule], image_rules: &[ImageRule]) -> Result<(), Error> {
+    let image_rules = image_rules
+        .iter()
+        .cloned()
+        .to_map_keep_last(|rule| (rule.key.clone(), FirewallRule::from(rule)));
+
+    let mut keys = HashSet::new();
+    for rule in rules {
+        if !keys.insert(&rule.key) {
+            return Err(Error::DuplicateKey(rule.key.clone()));
+        } else if image_rules.contains_key(&rule.key) {
+            return Err(Error::ImageKey(rule.key.clone()));
+        } else if rule.ips.is_none() && rule.ports.is_none() {
+            return Err(Error::MatchesAll(rule.key.clone()));
+        } else if ports(rule).any(|port| port == 0) {
+            return Err(Error::PortZero(rule.key.clone()));
+        }
+
+        if let Some(image_rule) = image_rules.values().find(|image| conflicts(rule, image)) {
+            return Err(Error::Conflict(rule.key.clone(), image_rule.key.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether two rules match some of the same traffic with different actions.
+fn conflicts(a: &FirewallRule, b: &FirewallRule) -> bool {
+    if a.action == b.action || a.direction != b.direction {
+        return false;
+    }
+
+    let protocols = match (a.protocol, b.protocol) {
+        (FirewallProtocol::Both, _) | (_, FirewallProtocol::Both) => true,
+        (a, b) => a == b,
+    };
+    let ports = match (&a.ports, &b.ports) {
+        (None, _) | (_, None) => true,
+        (Some(_), Some(_)) => ports(a).any(|port| ports(b).any(|other| port == other)),
+    };
+    let ips = match (&a.ips, &b.ips) {
+        (None, _) | (_, None) => true,
+        (Some(a), Some(b)) => a.0.iter().any(|a| {
+            b.0.iter().any(|b| {
+                a.ip.contains(&b.ip.first_address()) || b.ip.contains(&a.ip.first_address())
+            })
+        }),
+    };
+
+    protocols && ports && ips
+}
+
+fn ports(rule: &FirewallRule) -> impl Iterator<Item = u16> + '_ {
+    rule.ports
+        .iter()
+        .flat_map(|ports| ports.0.iter().map(|p| p.port))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::image::rule::{IpName, PortName};
+
+    use super::*;
+
+    fn rule(
+        key: &str,
+        action: FirewallAction,
+        ip: Option<&str>,
+        port: Option<u16>,
+    ) -> FirewallRule {
+        FirewallRule {
+            key: FirewallRuleKey(key.into()),
+            description: None,
+            protocol: FirewallProtocol::Tcp,
+            direction: FirewallDirection::Inbound,
+            action,
+            ips: ip.map(|ip| {
+                IpNames(vec![IpName {
+                    ip: ip.parse().unwrap(),
+                    name: None,
+                }])
+            }),
+            ports: port.map(|port| PortNames(vec![PortName { port, name: None }])),
+        }
+    }
+
+    fn image_rule(rule: FirewallRule) -> ImageRule {
+        ImageRule {
+            id: Uuid::new_v4().into(),
+            image_id: Uuid::new_v4().into(),
+            key: rule.key,
+            description: rule.description,
+            protocol: rule.protocol,
+            direction: rule.direction,
+            action: rule.action,
+            ips: rule.ips,
+            ports: rule.ports,
+        }
+    }
+
+    #[test]
+    fn validate_node_rules() {
+        use FirewallAction::*;
+
+        let image_rules = vec![
+            image_rule(rule("p2p", Allow, None, Some(30303))),
+            image_rule(rule("block-rpc", Drop, Some("0.0.0.0/0"), Some(8545))),
+        ];
+
+        let ok = rule("my-rpc", Allow, Some("10.0.0.1"), Some(8546));
+        validate(&[ok.clone()], &image_rules).unwrap();
+
+        let dupe = [ok.clone(), ok];
+        assert!(matches!(
+            validate(&dupe, &image_rules),
+            Err(Error::DuplicateKey(_))
+        ));
+
+        let image_key = rule("p2p", Allow, None, Some(30304));
+        assert!(matches!(
+            validate(&[image_key], &image_rules),
+            Err(Error::ImageKey(_))
+        ));
+
+        let match_all = rule("all", Drop, None, None);
+        assert!(matches!(
+            validate(&[match_all], &image_rules),
+            Err(Error::MatchesAll(_))
+        ));
+
+        let port_zero = rule("zero", Allow, None, Some(0));
+        assert!(matches!(
+            validate(&[port_zero], &image_rules),
+            Err(Error::PortZero(_))
+        ));
+
+        let conflict = rule("allow-rpc", Allow, Some("10.1.2.0/24"), Some(8545));
+        assert!(matches!(
+            validate(&[conflict], &image_rules),
+            Err(Error::Conflict(_, _))
+        ));
+
+        let same_action = rule("drop-rpc", Drop, Some("10.1.2.0/24"), Some(8545));
+        validate(&[same_action], &image_rules).unwrap();
+    }
+
+    #[test]
+    fn node_rules_are_merged_last() {
+        use FirewallAction::*;
+
+        let image_rules = vec![
+            rule("a", Allow, None, Some(1)),
+            rule("b", Allow, None, Some(2)),
+        ];
+        let node_rules = vec![
+            rule("c", Drop, None, Some(3)),
+            rule("b", Drop, None, Some(2)),
+        ];
+
+        let merged = merge_rules(image_rules, node_rules);
+        let keys: Vec<_> = merged.iter().map(|rule| rule.key.0.as_str()).collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+        assert_eq!(merged[1].action, Drop);
+    }
+}
@@ -0,0 +1,407 @@
+//! Node usage intervals for metered billing.
+//!
+//! Each row covers a period where a node stayed in the same `UsageState` for
+//! the same org with the same resources. A node has at most one open interval
+//! (with no `ended_at`) at a time, which is closed on the next change.
+//!
+//! Nodes stop accruing usage once they start deleting, so a `Deleting` node is
+//! not billed even though its resources are only freed when it is deleted.
+
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From, FromStr};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use diesel_derive_enum::DbEnum;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::resource::{NodeId, OrgId};
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+use crate::model::Region;
+use crate::model::protocol::ProtocolVersion;
+use crate::model::schema::{hosts, node_usage, nodes, protocol_versions, regions, sql_types};
+
+use super::{Node, NodeState};
+
+const SECONDS_PER_HOUR: i64 = 3600;
+const BYTES_PER_GIB: i128 = 1024 * 1024 * 1024;
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to find nodes without usage intervals: {0}
+    Backfill(diesel::result::Error),
+    /// Failed to close usage interval for node `{0}`: {1}
+    Close(NodeId, diesel::result::Error),
+    /// Failed to create usage interval for node `{0}`: {1}
+    Create(NodeId, diesel::result::Error),
+    /// Failed to find open usage interval for node `{0}`: {1}
+    FindOpen(NodeId, diesel::result::Error),
+    /// Failed to find usage intervals for org `{0}`: {1}
+    FindForOrg(OrgId, diesel::result::Error),
+    /// Usage period start is not before the end.
+    InvalidRange,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            InvalidRange => Status::invalid_argument("end"),
+            Backfill(_) | Close(..) | Create(..) | FindOpen(..) | FindForOrg(..) => {
+                Status::internal("Internal error.")
+            }
+        }
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Display,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    DieselNewType,
+    Deref,
+    From,
+    FromStr,
+)]
+pub struct NodeUsageId(Uuid);
+
+/// The billable state of a node, collapsed from its `NodeState`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[ExistingTypePath = "sql_types::EnumUsageState"]
+pub enum UsageState {
+    Starting,
+    Running,
+    Stopped,
+}
+
+impl UsageState {
+    /// Returns `None` for states where the node no longer accrues usage.
+    ///
+    /// A `Deleting` node is not billed, so its interval is closed as soon as
+    /// deletion starts rather than when the node is finally deleted.
+    pub const fn from_node(state: NodeState) -> Option<Self> {
+        match state {
+            NodeState::Starting => Some(UsageState::Starting),
+            NodeState::Running | NodeState::Upgrading => Some(UsageState::Running),
+            NodeState::Stopped | NodeState::Failed => Some(UsageState::Stopped),
+            NodeState::Deleting | NodeState::Deleted => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = node_usage)]
+pub struct NodeUsage {
+    pub id: NodeUsageId,
+    pub node_id: NodeId,
+    pub org_id: OrgId,
+    pub sku: Option<String>,
+    pub usage_state: UsageState,
+    pub cpu_cores: i64,
+    pub memory_bytes: i64,
+    pub disk_bytes: i64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+impl NodeUsage {
+    /// Record the current state of a node, closing the open interval if it no
+    /// longer matches and opening a new one if the node still accrues usage.
+    pub async fn record(node: &Node, conn: &mut Conn<'_>) -> Result<(), Error> {
+        let state = node
+            .deleted_at
+            .is_none()
+            .then(|| UsageState::from_node(node.node_state))
+            .flatten();
+
+        let now = Utc::now();
+        let (open, sku) = Self::open(node, conn).await?;
+        if let Some(open) = open {
+            if Some(open.usage_state) == state && open.matches(node, sku.as_deref()) {
+                return Ok(());
+            }
+
+            diesel::update(node_usage::table.find(open.id))
+                .set(node_usage::ended_at.eq(now))
+                .execute(conn)
+                .await
+                .map_err(|err| Error::Close(node.id, err))?;
+        }
+
+        let Some(state) = state else {
+            return Ok(());
+        };
+
+        diesel::insert_into(node_usage::table)
+            .values((
+                node_usage::node_id.eq(node.id),
+                node_usage::org_id.eq(node.org_id),
+                node_usage::sku.eq(sku),
+                node_usage::usage_state.eq(state),
+                node_usage::cpu_cores.eq(node.cpu_cores),
+                node_usage::memory_bytes.eq(node.memory_bytes),
+                node_usage::disk_bytes.eq(node.disk_bytes),
+                node_usage::started_at.eq(now),
+            ))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Create(node.id, err))
+    }
+
+    /// Open an interval for every live node without one, such as the nodes
+    /// created before usage was recorded.
+    ///
+    /// Returns the number of nodes that were checked.
+    pub async fn backfill(conn: &mut Conn<'_>) -> Result<usize, Error> {
+        let open_usage = node_usage::table.on(node_usage::node_id
+            .eq(nodes::id)
+            .and(node_usage::ended_at.is_null()));
+        let nodes: Vec<Node> = nodes::table
+            .left_join(open_usage)
+            .filter(nodes::deleted_at.is_null())
+            .filter(node_usage::id.is_null())
+            .select(Node::as_select())
+            .get_results(conn)
+            .await
+            .map_err(Error::Backfill)?;
+
+        for node in &nodes {
+            Self::record(node, conn).await?;
+        }
+
+        Ok(nodes.len())
+    }
+
+    /// Returns the intervals of an org that overlap with `[start, end)`.
+    pub async fn for_org(
+        org_id: OrgId,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<Self>, Error> {
+        node_usage::table
+            .filter(node_usage::org_id.eq(org_id))
+            .filter(node_usage::started_at.lt(end))
+            .filter(
+                node_usage::ended_at
+                    .is_null()
+                    .or(node_usage::ended_at.gt(start)),
+            )
+            .select(NodeUsage::as_select())
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::FindForOrg(org_id, err))
+    }
+
+    /// The open interval of a node, along with the SKU it is billed for now.
+    async fn open(
+        node: &Node,
+        conn: &mut Conn<'_>,
+    ) -> Result<(Option<Self>, Option<String>), Error> {
+        let open_usage = node_usage::table.on(node_usage::node_id
+            .eq(nodes::id)
+            .and(node_usage::ended_at.is_null()));
+        let (open, version, region): (Option<Self>, ProtocolVersion, Region) = nodes::table
+            .find(node.id)
+            .inner_join(protocol_versions::table)
+            .inner_join(hosts::table.inner_join(regions::table))
+            .left_join(open_usage)
+            .select((
+                NodeUsage::as_select().nullable(),
+                ProtocolVersion::as_select(),
+                regions::all_columns,
+            ))
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::FindOpen(node.id, err))?;
+
+        // Billing exempt nodes have no stripe item so are not charged for usage.
+        let sku = node
+            .stripe_item_id
+            .is_some()
+            .then(|| version.sku(&region))
+            .flatten();
+
+        Ok((open, sku))
+    }
+
+    fn matches(&self, node: &Node, sku: Option<&str>) -> bool {
+        self.org_id == node.org_id
+            && self.sku.as_deref() == sku
+            && self.cpu_cores == node.cpu_cores
+            && self.memory_bytes == node.memory_bytes
+            && self.disk_bytes == node.disk_bytes
+    }
+
+    /// The number of seconds of this interval that fall within `[start, end)`.
+    fn seconds_within(&self, start: DateTime<Utc>, end: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+        let from = self.started_at.max(start);
+        let to = self.ended_at.unwrap_or(now).min(end);
+        (to - from).num_seconds().max(0)
+    }
+}
+
+/// Aggregated usage for a single SKU over a period.
+///
+/// Resource usage is measured over the full lifetime of a node (including when
+/// it is stopped) since its resources remain allocated on the host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageSummary {
+    pub sku: Option<String>,
+    pub node_count: u64,
+    pub node_seconds: i64,
+    pub running_seconds: i64,
+    pub cpu_core_seconds: i64,
+    pub memory_gib_seconds: i64,
+    pub disk_gib_seconds: i64,
+}
+
+impl UsageSummary {
+    /// Sum the usage of each SKU for the intervals within `[start, end)`.
+    pub fn new(
+        usage: &[NodeUsage],
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Self>, Error> {
+        if start >= end {
+            return Err(Error::InvalidRange);
+        }
+
+        let mut by_sku: BTreeMap<Option<&str>, (HashSet<NodeId>, Totals)> = BTreeMap::new();
+        for interval in usage {
+            let seconds = interval.seconds_within(start, end, now);
+            if seconds == 0 {
+                continue;
+            }
+
+            let (nodes, totals) = by_sku.entry(interval.sku.as_deref()).or_default();
+            nodes.insert(interval.node_id);
+            totals.add(interval, seconds);
+        }
+
+        Ok(by_sku
+            .into_iter()
+            .map(|(sku, (nodes, totals))| UsageSummary {
+                sku: sku.map(ToString::to_string),
+                node_count: nodes.len() as u64,
+                node_seconds: saturate(totals.node),
+                running_seconds: saturate(totals.running),
+                cpu_core_seconds: saturate(totals.cpu),
+                memory_gib_seconds: saturate(totals.memory / BYTES_PER_GIB),
+                disk_gib_seconds: saturate(totals.disk / BYTES_PER_GIB),
+            })
+            .collect())
+    }
+
+    /// The node-hours for this SKU, rounded up to the nearest hour.
+    pub fn node_hours(&self) -> u64 {
+        let hours = (self.node_seconds + SECONDS_PER_HOUR - 1) / SECONDS_PER_HOUR;
+        u64::try_from(hours).unwrap_or_default()
+    }
+}
+
+impl From<UsageSummary> for api::SkuUsage {
+    fn from(summary: UsageSummary) -> Self {
+        api::SkuUsage {
+            sku: summary.sku,
+            node_count: summary.node_count,
+            node_seconds: summary.node_seconds,
+            running_seconds: summary.running_seconds,
+            cpu_core_seconds: summary.cpu_core_seconds,
+            memory_gib_seconds: summary.memory_gib_seconds,
+            disk_gib_seconds: summary.disk_gib_seconds,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    node: i128,
+    running: i128,
+    cpu: i128,
+    memory: i128,
+    disk: i128,
+}
+
+impl Totals {
+    fn add(&mut self, interval: &NodeUsage, seconds: i64) {
+        let seconds = i128::from(seconds);
+        self.node += seconds;
+        if interval.usage_state == UsageState::Running {
+            self.running += seconds;
+        }
+        self.cpu += i128::from(interval.cpu_cores) * seconds;
+        self.memory += i128::from(interval.memory_bytes) * seconds;
+        self.disk += i128::from(interval.disk_bytes) * seconds;
+    }
+}
+
+fn saturate(value: i128) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn interval(
+        sku: Option<&str>,
+        usage_state: UsageState,
+        started_at: DateTime<Utc>,
+        ended_at: Option<DateTime<Utc>>,
+    ) -> NodeUsage {
+        NodeUsage {
+            id: Uuid::new_v4().into(),
+            node_id: Uuid::nil().into(),
+            org_id: Uuid::nil().into(),
+            sku: sku.map(ToString::to_string),
+            usage_state,
+            cpu_cores: 2,
+            memory_bytes: 4 * 1024 * 1024 * 1024,
+            disk_bytes: 0,
+            started_at,
+            ended_at,
+        }
+    }
+
+    #[test]
+    fn summarizes_usage_within_period() {
+        let at = |hour| Utc.with_ymd_and_hms(2025, 3, 1, hour, 0, 0).unwrap();
+        let usage = [
+            interval(Some("sku"), UsageState::Starting, at(0), Some(at(2))),
+            interval(Some("sku"), UsageState::Running, at(2), Some(at(10))),
+            interval(Some("sku"), UsageState::Stopped, at(10), None),
+            interval(None, UsageState::Running, at(20), None),
+        ];
+
+        let summary = UsageSummary::new(&usage, at(1), at(12), at(11)).unwrap();
+        assert_eq!(summary.len(), 1);
+
+        let sku = &summary[0];
+        assert_eq!(sku.sku.as_deref(), Some("sku"));
+        assert_eq!(sku.node_count, 1);
+        assert_eq!(sku.node_seconds, 10 * 3600);
+        assert_eq!(sku.running_seconds, 8 * 3600);
+        assert_eq!(sku.cpu_core_seconds, 2 * 10 * 3600);
+        assert_eq!(sku.memory_gib_seconds, 4 * 10 * 3600);
+        assert_eq!(sku.node_hours(), 10);
+
+        let invalid = UsageSummary::new(&usage, at(12), at(1), at(11));
+        assert!(matches!(invalid, Err(Error::InvalidRange)));
+    }
+}
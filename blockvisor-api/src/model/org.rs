@@ -28,6 +28,16 @@ use super::{Paginate, Token};
 
 const PERSONAL_ORG_NAME: &str = "Personal";
 
+/// An org role along with the lesser roles that it includes.
+const fn implied_roles(role: OrgRole) -> &'static [OrgRole] {
+    match role {
+        OrgRole::Owner => &[OrgRole::Owner, OrgRole::Admin, OrgRole::Member],
+        OrgRole::Admin => &[OrgRole::Admin, OrgRole::Member],
+        OrgRole::Member => &[OrgRole::Member],
+        OrgRole::Personal => &[OrgRole::Personal],
+    }
+}
+
 #[derive(Debug, Display, Error)]
 pub enum Error {
     /// Failed to increment host count for org `{0}`: {1}
@@ -46,6 +56,8 @@ pub enum Error {
     FindByIds(HashSet<OrgId>, diesel::result::Error),
     /// Failed to find personal org for user `{0}`: {1}
     FindPersonal(UserId, diesel::result::Error),
+    /// Failed to find orgs with a stripe customer: {0}
+    FindWithCustomer(diesel::result::Error),
     /// Failed to check if org `{0}` has user `{1}`: {2}
     HasUser(OrgId, UserId, diesel::result::Error),
     /// Failed to get host counts for org: {0}
@@ -102,6 +114,7 @@ pub struct Org {
     pub member_count: i32,
     pub stripe_customer_id: Option<CustomerId>,
     pub address_id: Option<AddressId>,
+    pub failover_enabled: bool,
 }
 
 impl Org {
@@ -135,6 +148,16 @@ impl Org {
             .map_err(|err| Error::FindPersonal(user_id, err))
     }
 
+    /// All orgs that have a corresponding customer in stripe.
+    pub async fn with_customer(conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        orgs::table
+            .filter(orgs::stripe_customer_id.is_not_null())
+            .filter(orgs::deleted_at.is_null())
+            .get_results(conn)
+            .await
+            .map_err(Error::FindWithCustomer)
+    }
+
     pub async fn set_customer_id(
         self,
         customer_id: &str,
@@ -153,18 +176,25 @@ impl Org {
         role: OrgRole,
         conn: &mut Conn<'_>,
     ) -> Result<Self, Error> {
-        let roles = match role {
-            OrgRole::Owner => [OrgRole::Owner, OrgRole::Admin, OrgRole::Member].iter(),
-            OrgRole::Admin => [OrgRole::Admin, OrgRole::Member].iter(),
-            OrgRole::Member => [OrgRole::Member].iter(),
-            OrgRole::Personal => [OrgRole::Personal].iter(),
-        };
-
         Token::new_host_provision(user_id, org_id, conn).await?;
-        RbacUser::link_roles(user_id, org_id, roles.copied(), conn).await?;
+        RbacUser::link_roles(user_id, org_id, implied_roles(role).iter().copied(), conn).await?;
         Org::add_member(org_id, conn).await
     }
 
+    /// Replace the org roles of an existing org member with `role` and the roles it implies.
+    ///
+    /// Roles other than an `OrgRole` are left untouched.
+    pub async fn set_user_role(
+        user_id: UserId,
+        org_id: OrgId,
+        role: OrgRole,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        RbacUser::unlink_org_roles(user_id, org_id, conn).await?;
+        RbacUser::link_roles(user_id, org_id, implied_roles(role).iter().copied(), conn).await?;
+        Ok(())
+    }
+
     pub async fn has_user(
         org_id: OrgId,
         user_id: UserId,
@@ -444,6 +474,7 @@ pub struct UpdateOrg<'a> {
     pub id: OrgId,
     pub name: Option<&'a str>,
     pub address_id: Option<AddressId>,
+    pub failover_enabled: Option<bool>,
 }
 
 impl UpdateOrg<'_> {
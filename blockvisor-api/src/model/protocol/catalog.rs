@@ -0,0 +1,992 @@
+//! A declarative description of the public protocol catalog.
+//!
+//! A `Catalog` lists protocols, their variants and versions, and the image
+//! builds of each version. It can be exported from the database as TOML or
+//! YAML, kept under version control, and applied back to an environment.
+//!
+//! Applying a catalog is additive: `Catalog::plan` compares it against the
+//! current catalog and returns the changes needed to reconcile them. Entries
+//! missing from the catalog are left untouched, and since image builds are
+//! immutable, an existing build that differs (other than in visibility) is
+//! reported as a conflict rather than changed.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use displaydoc::Display;
+use prost_wkt_types::Empty;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::database::Conn;
+use crate::grpc::{Status, api, common};
+use crate::model::image::config::RamdiskConfig;
+use crate::model::image::property::{PropertyConstraints, PropertyDependency, UiType, ValueType};
+use crate::model::image::rule::{FirewallAction, FirewallDirection, FirewallProtocol};
+use crate::model::image::rule::{IpName, PortName};
+use crate::model::image::{Archive, Image, ImageId, ImageProperty, ImageRule};
+use crate::model::schema::{archives, image_properties, image_rules, images};
+use crate::model::schema::{protocol_versions, protocols};
+use crate::model::sql::Version;
+
+use super::version::{ProtocolKey, ProtocolVersion, VariantKey, VersionId, VersionMetadata};
+use super::{Protocol, ProtocolId, Visibility};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Catalog image build {1} of {0} is listed more than once.
+    DuplicateBuild(VersionPath, i64),
+    /// Catalog protocol `{0}` is listed more than once.
+    DuplicateProtocol(String),
+    /// Catalog variant `{0}/{1}` is listed more than once.
+    DuplicateVariant(String, String),
+    /// Catalog version {0} is listed more than once.
+    DuplicateVersion(VersionPath),
+    /// Failed to find catalog image build {1} of {0}: {2}
+    FindImage(VersionPath, i64, diesel::result::Error),
+    /// Failed to find catalog protocol `{0}`: {1}
+    FindProtocol(String, diesel::result::Error),
+    /// Failed to find catalog version {0}: {1}
+    FindVersion(VersionPath, diesel::result::Error),
+    /// Invalid protocol or variant key: {0}
+    Key(#[from] super::version::Error),
+    /// Failed to load archives: {0}
+    LoadArchives(diesel::result::Error),
+    /// Failed to load images: {0}
+    LoadImages(diesel::result::Error),
+    /// Failed to load image properties: {0}
+    LoadProperties(diesel::result::Error),
+    /// Failed to load protocols: {0}
+    LoadProtocols(diesel::result::Error),
+    /// Failed to load image firewall rules: {0}
+    LoadRules(diesel::result::Error),
+    /// Failed to load protocol versions: {0}
+    LoadVersions(diesel::result::Error),
+    /// Invalid `min_babel_version` for {0}: {1}
+    MinBabel(VersionPath, crate::model::sql::Error),
+    /// Failed to parse TOML catalog: {0}
+    ParseToml(toml::de::Error),
+    /// Failed to parse YAML catalog: {0}
+    ParseYaml(serde_yaml::Error),
+    /// Invalid `semantic_version` for `{0}/{1}`: {2}
+    SemanticVersion(String, String, crate::model::sql::Error),
+    /// Failed to serialize TOML catalog: {0}
+    SerializeToml(toml::ser::Error),
+    /// Failed to serialize YAML catalog: {0}
+    SerializeYaml(serde_yaml::Error),
+    /// Unknown catalog format.
+    UnknownFormat,
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            DuplicateBuild(..) | DuplicateProtocol(_) | DuplicateVariant(..)
+            | DuplicateVersion(_) | MinBabel(..) | Key(_) | SemanticVersion(..) => {
+                Status::invalid_argument(format!("catalog: {err}"))
+            }
+            ParseToml(_) | ParseYaml(_) => Status::invalid_argument(format!("catalog: {err}")),
+            UnknownFormat => Status::invalid_argument("format"),
+            _ => Status::internal("Internal error."),
+        }
+    }
+}
+
+/// The serialization format of a catalog document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CatalogFormat {
+    Toml,
+    Yaml,
+}
+
+impl TryFrom<api::CatalogFormat> for CatalogFormat {
+    type Error = Error;
+
+    fn try_from(format: api::CatalogFormat) -> Result<Self, Self::Error> {
+        match format {
+            api::CatalogFormat::Unspecified => Err(Error::UnknownFormat),
+            api::CatalogFormat::Toml => Ok(CatalogFormat::Toml),
+            api::CatalogFormat::Yaml => Ok(CatalogFormat::Yaml),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    #[serde(default)]
+    pub protocols: Vec<CatalogProtocol>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogProtocol {
+    pub key: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Only set when the protocol is created.
+    pub ticker: Option<String>,
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub variants: Vec<CatalogVariant>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogVariant {
+    pub key: String,
+    #[serde(default)]
+    pub versions: Vec<CatalogVersion>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogVersion {
+    pub semantic_version: String,
+    pub sku_code: String,
+    pub description: Option<String>,
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata: Vec<VersionMetadata>,
+    #[serde(default)]
+    pub images: Vec<CatalogImage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogImage {
+    pub build_version: i64,
+    pub image_uri: String,
+    pub description: Option<String>,
+    pub visibility: Visibility,
+    pub min_cpu_cores: i64,
+    pub min_memory_bytes: i64,
+    pub min_disk_bytes: i64,
+    pub min_babel_version: String,
+    pub dns_scheme: Option<String>,
+    pub default_firewall_in: FirewallAction,
+    pub default_firewall_out: FirewallAction,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ramdisks: Vec<RamdiskConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub firewall_rules: Vec<CatalogRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<CatalogProperty>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archives: Vec<CatalogArchive>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogRule {
+    pub key: String,
+    pub description: Option<String>,
+    pub protocol: FirewallProtocol,
+    pub direction: FirewallDirection,
+    pub action: FirewallAction,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ips: Vec<IpName>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortName>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogProperty {
+    pub key: String,
+    pub key_group: Option<String>,
+    pub is_group_default: Option<bool>,
+    #[serde(default)]
+    pub new_archive: bool,
+    #[serde(default)]
+    pub default_value: String,
+    #[serde(default)]
+    pub dynamic_value: bool,
+    pub description: Option<String>,
+    pub ui_type: UiType,
+    pub add_cpu_cores: Option<i64>,
+    pub add_memory_bytes: Option<i64>,
+    pub add_disk_bytes: Option<i64>,
+    pub display_name: Option<String>,
+    pub display_group: Option<String>,
+    #[serde(default)]
+    pub value_type: ValueType,
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_values: Vec<String>,
+    pub max_length: Option<i32>,
+    #[serde(default)]
+    pub is_required: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<PropertyDependency>,
+}
+
+/// The store key for one combination of `new_archive` properties.
+///
+/// A combination without a `store_key` may not be launched.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogArchive {
+    #[serde(default)]
+    pub new_archive_keys: BTreeSet<String>,
+    pub store_key: Option<String>,
+}
+
+/// The location of a protocol version within a catalog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionPath {
+    pub protocol_key: String,
+    pub variant_key: String,
+    pub semantic_version: String,
+}
+
+impl fmt::Display for VersionPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let VersionPath {
+            protocol_key,
+            variant_key,
+            semantic_version,
+        } = self;
+        write!(
+            f,
+            "`{protocol_key}/{variant_key}` version `{semantic_version}`"
+        )
+    }
+}
+
+/// A single step needed to reconcile the current catalog with a desired one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change<'c> {
+    CreateProtocol(&'c CatalogProtocol),
+    UpdateProtocol(&'c CatalogProtocol),
+    CreateVersion(VersionPath, &'c CatalogVersion),
+    UpdateVersion(VersionPath, &'c CatalogVersion),
+    CreateImage(VersionPath, &'c CatalogImage),
+    UpdateImage(VersionPath, &'c CatalogImage),
+    /// A difference that can't be applied, with a reason.
+    Conflict(VersionPath, Option<i64>, String),
+}
+
+impl Change<'_> {
+    pub const fn is_conflict(&self) -> bool {
+        matches!(self, Change::Conflict(..))
+    }
+}
+
+impl From<&Change<'_>> for api::CatalogChange {
+    fn from(change: &Change<'_>) -> Self {
+        let (action, protocol_key, path, build, detail) = match change {
+            Change::CreateProtocol(protocol) => {
+                (api::CatalogAction::Create, &protocol.key, None, None, None)
+            }
+            Change::UpdateProtocol(protocol) => {
+                (api::CatalogAction::Update, &protocol.key, None, None, None)
+            }
+            Change::CreateVersion(path, _) => (
+                api::CatalogAction::Create,
+                &path.protocol_key,
+                Some(path),
+                None,
+                None,
+            ),
+            Change::UpdateVersion(path, _) => (
+                api::CatalogAction::Update,
+                &path.protocol_key,
+                Some(path),
+                None,
+                None,
+            ),
+            Change::CreateImage(path, image) => (
+                api::CatalogAction::Create,
+                &path.protocol_key,
+                Some(path),
+                Some(image.build_version),
+                None,
+            ),
+            Change::UpdateImage(path, image) => (
+                api::CatalogAction::Update,
+                &path.protocol_key,
+                Some(path),
+                Some(image.build_version),
+                None,
+            ),
+            Change::Conflict(path, build, reason) => (
+                api::CatalogAction::Conflict,
+                &path.protocol_key,
+                Some(path),
+                *build,
+                Some(reason.clone()),
+            ),
+        };
+
+        api::CatalogChange {
+            action: action.into(),
+            protocol_key: protocol_key.clone(),
+            variant_key: path.map(|path| path.variant_key.clone()),
+            semantic_version: path.map(|path| path.semantic_version.clone()),
+            build_version: build.and_then(|build| u64::try_from(build).ok()),
+            detail,
+        }
+    }
+}
+
+impl Catalog {
+    /// Parse and validate a catalog document.
+    pub fn parse(document: &str, format: CatalogFormat) -> Result<Self, Error> {
+        let catalog: Self = match format {
+            CatalogFormat::Toml => toml::from_str(document).map_err(Error::ParseToml)?,
+            CatalogFormat::Yaml => serde_yaml::from_str(document).map_err(Error::ParseYaml)?,
+        };
+        catalog.validate()
+    }
+
+    pub fn serialize(&self, format: CatalogFormat) -> Result<String, Error> {
+        match format {
+            CatalogFormat::Toml => toml::to_string_pretty(self).map_err(Error::SerializeToml),
+            CatalogFormat::Yaml => serde_yaml::to_string(self).map_err(Error::SerializeYaml),
+        }
+    }
+
+    /// Check keys and versions, and normalize the catalog for comparison.
+    fn validate(mut self) -> Result<Self, Error> {
+        let mut protocol_keys = HashSet::new();
+        for protocol in &mut self.protocols {
+            ProtocolKey::new(protocol.key.clone())?;
+            if !protocol_keys.insert(protocol.key.clone()) {
+                return Err(Error::DuplicateProtocol(protocol.key.clone()));
+            }
+
+            let mut variant_keys = HashSet::new();
+            for variant in &mut protocol.variants {
+                VariantKey::new(variant.key.clone())?;
+                if !variant_keys.insert(variant.key.clone()) {
+                    let key = variant.key.clone();
+                    return Err(Error::DuplicateVariant(protocol.key.clone(), key));
+                }
+
+                let mut versions = HashSet::new();
+                for version in &mut variant.versions {
+                    let semver: Version = version.semantic_version.parse().map_err(|err| {
+                        Error::SemanticVersion(protocol.key.clone(), variant.key.clone(), err)
+                    })?;
+                    version.semantic_version = semver.to_string();
+
+                    let path = VersionPath {
+                        protocol_key: protocol.key.clone(),
+                        variant_key: variant.key.clone(),
+                        semantic_version: version.semantic_version.clone(),
+                    };
+                    if !versions.insert(semver) {
+                        return Err(Error::DuplicateVersion(path));
+                    }
+
+                    let mut builds = HashSet::new();
+                    for image in &mut version.images {
+                        let babel: Version = image
+                            .min_babel_version
+                            .parse()
+                            .map_err(|err| Error::MinBabel(path.clone(), err))?;
+                        image.min_babel_version = babel.to_string();
+                        if !builds.insert(image.build_version) {
+                            return Err(Error::DuplicateBuild(path, image.build_version));
+                        }
+                        image.normalize();
+                    }
+                    version.images.sort_by_key(|image| image.build_version);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Export the catalog of all public (non-org) protocols.
+    pub async fn load(conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let protocols: Vec<Protocol> = protocols::table
+            .filter(protocols::org_id.is_null())
+            .order_by(protocols::key)
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadProtocols)?;
+        let versions: Vec<ProtocolVersion> = protocol_versions::table
+            .filter(protocol_versions::org_id.is_null())
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadVersions)?;
+        let images: Vec<Image> = images::table
+            .filter(images::org_id.is_null())
+            .order_by(images::build_version)
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadImages)?;
+
+        let image_ids: HashSet<ImageId> = images.iter().map(|image| image.id).collect();
+        let rules: Vec<ImageRule> = image_rules::table
+            .filter(image_rules::image_id.eq_any(&image_ids))
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadRules)?;
+        let properties: Vec<ImageProperty> = image_properties::table
+            .filter(image_properties::image_id.eq_any(&image_ids))
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadProperties)?;
+        let archives: Vec<Archive> = archives::table
+            .filter(archives::image_id.eq_any(&image_ids))
+            .filter(archives::org_id.is_null())
+            .get_results(conn)
+            .await
+            .map_err(Error::LoadArchives)?;
+
+        Ok(Self::from_models(
+            protocols, versions, images, rules, properties, archives,
+        ))
+    }
+
+    fn from_models(
+        protocols: Vec<Protocol>,
+        versions: Vec<ProtocolVersion>,
+        images: Vec<Image>,
+        rules: Vec<ImageRule>,
+        properties: Vec<ImageProperty>,
+        archives: Vec<Archive>,
+    ) -> Self {
+        let mut image_rules: HashMap<ImageId, Vec<ImageRule>> = HashMap::new();
+        for rule in rules {
+            image_rules.entry(rule.image_id).or_default().push(rule);
+        }
+        let mut image_properties: HashMap<ImageId, Vec<ImageProperty>> = HashMap::new();
+        for property in properties {
+            image_properties
+                .entry(property.image_id)
+                .or_default()
+                .push(property);
+        }
+        let mut image_archives: HashMap<ImageId, Vec<Archive>> = HashMap::new();
+        for archive in archives {
+            image_archives
+                .entry(archive.image_id)
+                .or_default()
+                .push(archive);
+        }
+
+        let mut version_images = HashMap::<_, Vec<CatalogImage>>::new();
+        for image in images {
+            let rules = image_rules.remove(&image.id).unwrap_or_default();
+            let properties = image_properties.remove(&image.id).unwrap_or_default();
+            let archives = image_archives.remove(&image.id).unwrap_or_default();
+            let version_id = image.protocol_version_id;
+            let image = CatalogImage::from_models(image, rules, properties, &archives);
+            version_images.entry(version_id).or_default().push(image);
+        }
+
+        let mut protocol_variants = HashMap::<ProtocolId, BTreeMap<_, Vec<_>>>::new();
+        for version in versions {
+            protocol_variants
+                .entry(version.protocol_id)
+                .or_default()
+                .entry(version.variant_key.to_string())
+                .or_default()
+                .push(version);
+        }
+
+        let protocols = protocols
+            .into_iter()
+            .map(|protocol| {
+                let variants = protocol_variants
+                    .remove(&protocol.id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(key, mut versions)| {
+                        versions.sort_by(|a, b| a.semantic_version.cmp(&b.semantic_version));
+                        let versions = versions
+                            .into_iter()
+                            .map(|version| CatalogVersion {
+                                images: version_images.remove(&version.id).unwrap_or_default(),
+                                semantic_version: version.semantic_version.to_string(),
+                                sku_code: version.sku_code,
+                                description: version.description,
+                                visibility: version.visibility,
+                                metadata: version.metadata.into_iter().collect(),
+                            })
+                            .collect();
+                        CatalogVariant { key, versions }
+                    })
+                    .collect();
+
+                CatalogProtocol {
+                    key: protocol.key.to_string(),
+                    name: protocol.name,
+                    description: protocol.description,
+                    ticker: protocol.ticker,
+                    visibility: protocol.visibility,
+                    variants,
+                }
+            })
+            .collect();
+
+        Catalog { protocols }
+    }
+
+    /// Compute the changes needed to turn the `current` catalog into this one.
+    pub fn plan<'c>(&'c self, current: &Catalog) -> Vec<Change<'c>> {
+        let mut changes = Vec::new();
+
+        for protocol in &self.protocols {
+            let existing = current.protocols.iter().find(|p| p.key == protocol.key);
+            match existing {
+                None => changes.push(Change::CreateProtocol(protocol)),
+                Some(existing) => {
+                    if existing.name != protocol.name
+                        || changed(
+                            existing.description.as_deref(),
+                            protocol.description.as_deref(),
+                        )
+                        || existing.visibility != protocol.visibility
+                    {
+                        changes.push(Change::UpdateProtocol(protocol));
+                    }
+                }
+            }
+
+            for variant in &protocol.variants {
+                let versions = existing
+                    .and_then(|p| p.variants.iter().find(|v| v.key == variant.key))
+                    .map_or(&[][..], |v| &v.versions[..]);
+                for version in &variant.versions {
+                    let path = VersionPath {
+                        protocol_key: protocol.key.clone(),
+                        variant_key: variant.key.clone(),
+                        semantic_version: version.semantic_version.clone(),
+                    };
+                    let existing = versions
+                        .iter()
+                        .find(|v| v.semantic_version == version.semantic_version);
+                    plan_version(path, version, existing, &mut changes);
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+/// Find a public protocol by key, regardless of visibility.
+pub async fn find_protocol(key: &str, conn: &mut Conn<'_>) -> Result<Protocol, Error> {
+    protocols::table
+        .filter(protocols::key.eq(key))
+        .filter(protocols::org_id.is_null())
+        .get_result(conn)
+        .await
+        .map_err(|err| Error::FindProtocol(key.to_string(), err))
+}
+
+/// Find a public protocol version, regardless of visibility.
+pub async fn find_version(
+    path: &VersionPath,
+    conn: &mut Conn<'_>,
+) -> Result<ProtocolVersion, Error> {
+    protocol_versions::table
+        .filter(protocol_versions::protocol_key.eq(&path.protocol_key))
+        .filter(protocol_versions::variant_key.eq(&path.variant_key))
+        .filter(protocol_versions::semantic_version.eq(&path.semantic_version))
+        .filter(protocol_versions::org_id.is_null())
+        .get_result(conn)
+        .await
+        .map_err(|err| Error::FindVersion(path.clone(), err))
+}
+
+/// Find a public image build, regardless of visibility.
+pub async fn find_image(
+    path: &VersionPath,
+    version_id: VersionId,
+    build: i64,
+    conn: &mut Conn<'_>,
+) -> Result<Image, Error> {
+    images::table
+        .filter(images::protocol_version_id.eq(version_id))
+        .filter(images::build_version.eq(build))
+        .filter(images::org_id.is_null())
+        .get_result(conn)
+        .await
+        .map_err(|err| Error::FindImage(path.clone(), build, err))
+}
+
+/// An unset description in the catalog leaves the current one as is.
+fn changed(current: Option<&str>, desired: Option<&str>) -> bool {
+    desired.is_some() && desired != current
+}
+
+fn plan_version<'c>(
+    path: VersionPath,
+    version: &'c CatalogVersion,
+    existing: Option<&CatalogVersion>,
+    changes: &mut Vec<Change<'c>>,
+) {
+    let existing_images = match existing {
+        None => {
+            changes.push(Change::CreateVersion(path.clone(), version));
+            &[][..]
+        }
+        Some(existing) => {
+            if existing.sku_code != version.sku_code
+                || changed(
+                    existing.description.as_deref(),
+                    version.description.as_deref(),
+                )
+                || existing.visibility != version.visibility
+            {
+                changes.push(Change::UpdateVersion(path.clone(), version));
+            }
+            if existing.metadata != version.metadata {
+                let reason = "version metadata can't be changed".to_string();
+                changes.push(Change::Conflict(path.clone(), None, reason));
+            }
+            &existing.images[..]
+        }
+    };
+
+    let mut next_build = existing_images
+        .iter()
+        .map(|image| image.build_version)
+        .max()
+        .unwrap_or_default()
+        + 1;
+    for image in &version.images {
+        let build = image.build_version;
+        if let Some(existing) = existing_images.iter().find(|i| i.build_version == build) {
+            let unchanged = CatalogImage {
+                visibility: existing.visibility,
+                ..image.clone()
+            };
+            if unchanged != *existing {
+                let reason = "image builds are immutable; add a new build instead".to_string();
+                changes.push(Change::Conflict(path.clone(), Some(build), reason));
+            } else if existing.visibility != image.visibility {
+                changes.push(Change::UpdateImage(path.clone(), image));
+            }
+        } else if build == next_build {
+            changes.push(Change::CreateImage(path.clone(), image));
+            next_build += 1;
+        } else {
+            let reason = format!("expected the next image build to be {next_build}");
+            changes.push(Change::Conflict(path.clone(), Some(build), reason));
+        }
+    }
+}
+
+impl CatalogImage {
+    fn from_models(
+        image: Image,
+        rules: Vec<ImageRule>,
+        properties: Vec<ImageProperty>,
+        archives: &[Archive],
+    ) -> Self {
+        let property_keys: HashMap<_, _> = properties
+            .iter()
+            .map(|property| (property.id, property.key.to_string()))
+            .collect();
+        let archive_keys = |archive: &Archive| {
+            archive
+                .image_property_ids
+                .iter()
+                .flatten()
+                .filter_map(|id| property_keys.get(id).cloned())
+                .collect::<BTreeSet<_>>()
+        };
+
+        // list every new_archive combination, as launching is disallowed without an archive
+        let archives = new_archive_powerset(&properties)
+            .into_iter()
+            .map(|keys| CatalogArchive {
+                store_key: archives
+                    .iter()
+                    .find(|archive| archive_keys(archive) == keys)
+                    .map(|archive| archive.store_key.to_string()),
+                new_archive_keys: keys,
+            })
+            .collect();
+
+        let mut catalog = CatalogImage {
+            build_version: image.build_version,
+            image_uri: image.image_uri,
+            description: image.description,
+            visibility: image.visibility,
+            min_cpu_cores: image.min_cpu_cores,
+            min_memory_bytes: image.min_memory_bytes,
+            min_disk_bytes: image.min_disk_bytes,
+            min_babel_version: image.min_babel_version.to_string(),
+            dns_scheme: image.dns_scheme,
+            default_firewall_in: image.default_firewall_in,
+            default_firewall_out: image.default_firewall_out,
+            ramdisks: image.ramdisks.0,
+            firewall_rules: rules.into_iter().map(CatalogRule::from).collect(),
+            properties: properties.into_iter().map(CatalogProperty::from).collect(),
+            archives,
+        };
+        catalog.normalize();
+        catalog
+    }
+
+    /// Sort unordered collections so that images can be compared.
+    fn normalize(&mut self) {
+        self.firewall_rules.sort_by(|a, b| a.key.cmp(&b.key));
+        self.properties.sort_by(|a, b| a.key.cmp(&b.key));
+        self.archives
+            .sort_by(|a, b| a.new_archive_keys.cmp(&b.new_archive_keys));
+    }
+
+    /// Build the request to add this image as a new build of `version_id`.
+    pub fn to_request(&self, version_id: String) -> api::ImageServiceAddImageRequest {
+        api::ImageServiceAddImageRequest {
+            protocol_version_id: version_id,
+            org_id: None,
+            image_uri: self.image_uri.clone(),
+            description: self.description.clone(),
+            min_cpu_cores: u64::try_from(self.min_cpu_cores).unwrap_or_default(),
+            min_memory_bytes: u64::try_from(self.min_memory_bytes).unwrap_or_default(),
+            min_disk_bytes: u64::try_from(self.min_disk_bytes).unwrap_or_default(),
+            min_babel_version: self.min_babel_version.clone(),
+            ramdisks: self.ramdisks.iter().cloned().map(Into::into).collect(),
+            firewall: Some(common::FirewallConfig {
+                default_in: common::FirewallAction::from(self.default_firewall_in).into(),
+                default_out: common::FirewallAction::from(self.default_firewall_out).into(),
+                rules: self
+                    .firewall_rules
+                    .iter()
+                    .cloned()
+                    .map(Into::into)
+                    .collect(),
+            }),
+            properties: self.properties.iter().cloned().map(Into::into).collect(),
+            archive_pointers: self
+                .archives
+                .iter()
+                .map(|archive| api::ArchivePointer {
+                    new_archive_keys: archive.new_archive_keys.iter().cloned().collect(),
+                    pointer: Some(match archive.store_key {
+                        Some(ref key) => api::archive_pointer::Pointer::StoreKey(key.clone()),
+                        None => api::archive_pointer::Pointer::Disallowed(Empty {}),
+                    }),
+                })
+                .collect(),
+            dns_scheme: self.dns_scheme.clone(),
+        }
+    }
+}
+
+fn new_archive_powerset(properties: &[ImageProperty]) -> Vec<BTreeSet<String>> {
+    properties
+        .iter()
+        .filter(|property| property.new_archive)
+        .fold(vec![BTreeSet::new()], |sets, property| {
+            sets.iter()
+                .cloned()
+                .chain(sets.iter().map(|set| {
+                    let mut new_set = set.clone();
+                    new_set.insert(property.key.to_string());
+                    new_set
+                }))
+                .collect()
+        })
+}
+
+impl From<ImageRule> for CatalogRule {
+    fn from(rule: ImageRule) -> Self {
+        CatalogRule {
+            key: rule.key.0,
+            description: rule.description,
+            protocol: rule.protocol,
+            direction: rule.direction,
+            action: rule.action,
+            ips: rule.ips.map(|ips| ips.0).unwrap_or_default(),
+            ports: rule.ports.map(|ports| ports.0).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<CatalogRule> for common::FirewallRule {
+    fn from(rule: CatalogRule) -> Self {
+        common::FirewallRule {
+            key: rule.key,
+            description: rule.description,
+            protocol: common::FirewallProtocol::from(rule.protocol).into(),
+            direction: common::FirewallDirection::from(rule.direction).into(),
+            action: common::FirewallAction::from(rule.action).into(),
+            ips: rule.ips.into_iter().map(Into::into).collect(),
+            ports: rule.ports.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ImageProperty> for CatalogProperty {
+    fn from(property: ImageProperty) -> Self {
+        let constraints = property.constraints();
+        CatalogProperty {
+            key: property.key.to_string(),
+            key_group: property.key_group.map(|group| group.to_string()),
+            is_group_default: property.is_group_default,
+            new_archive: property.new_archive,
+            default_value: property.default_value,
+            dynamic_value: property.dynamic_value,
+            description: property.description,
+            ui_type: property.ui_type,
+            add_cpu_cores: property.add_cpu_cores,
+            add_memory_bytes: property.add_memory_bytes,
+            add_disk_bytes: property.add_disk_bytes,
+            display_name: property.display_name,
+            display_group: property.display_group,
+            value_type: constraints.value_type,
+            min_value: constraints.min_value,
+            max_value: constraints.max_value,
+            pattern: constraints.pattern,
+            allowed_values: constraints.allowed_values,
+            max_length: constraints.max_length,
+            is_required: constraints.is_required,
+            dependencies: property.dependencies.to_vec(),
+        }
+    }
+}
+
+impl From<CatalogProperty> for api::AddImageProperty {
+    fn from(property: CatalogProperty) -> Self {
+        let constraints = PropertyConstraints {
+            value_type: property.value_type,
+            min_value: property.min_value,
+            max_value: property.max_value,
+            pattern: property.pattern,
+            allowed_values: property.allowed_values,
+            max_length: property.max_length,
+            is_required: property.is_required,
+        };
+
+        api::AddImageProperty {
+            key: property.key,
+            key_group: property.key_group,
+            is_group_default: property.is_group_default,
+            new_archive: property.new_archive,
+            default_value: property.default_value,
+            dynamic_value: property.dynamic_value,
+            description: property.description,
+            ui_type: common::UiType::from(property.ui_type).into(),
+            add_cpu_cores: property.add_cpu_cores,
+            add_memory_bytes: property.add_memory_bytes,
+            add_disk_bytes: property.add_disk_bytes,
+            display_name: property.display_name,
+            display_group: property.display_group,
+            constraints: Some(constraints.into()),
+            dependencies: property.dependencies.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = r#"
+        [[protocols]]
+        key = "ethereum"
+        name = "Ethereum"
+        visibility = "public"
+
+        [[protocols.variants]]
+        key = "reth-mainnet"
+
+        [[protocols.variants.versions]]
+        semantic_version = "1.2.0"
+        sku_code = "ETH-RETH-MN"
+        visibility = "public"
+
+        [[protocols.variants.versions.images]]
+        build_version = 1
+        image_uri = "docker://reth:1.2.0"
+        visibility = "public"
+        min_cpu_cores = 4
+        min_memory_bytes = 8000000000
+        min_disk_bytes = 1000000000000
+        min_babel_version = "0.9.0"
+        default_firewall_in = "drop"
+        default_firewall_out = "allow"
+
+        [[protocols.variants.versions.images.properties]]
+        key = "archive"
+        new_archive = true
+        default_value = "false"
+        ui_type = "switch"
+        value_type = "boolean"
+
+        [[protocols.variants.versions.images.archives]]
+        new_archive_keys = []
+        store_key = "ethereum-reth-mainnet-v1"
+
+        [[protocols.variants.versions.images.archives]]
+        new_archive_keys = ["archive"]
+    "#;
+
+    #[test]
+    fn catalog_round_trips_between_formats() {
+        let catalog = Catalog::parse(CATALOG, CatalogFormat::Toml).unwrap();
+        let image = &catalog.protocols[0].variants[0].versions[0].images[0];
+        assert_eq!(image.properties[0].ui_type, UiType::Switch);
+        assert_eq!(image.archives.len(), 2);
+
+        let yaml = catalog.serialize(CatalogFormat::Yaml).unwrap();
+        assert_eq!(Catalog::parse(&yaml, CatalogFormat::Yaml).unwrap(), catalog);
+        let toml = catalog.serialize(CatalogFormat::Toml).unwrap();
+        assert_eq!(Catalog::parse(&toml, CatalogFormat::Toml).unwrap(), catalog);
+    }
+
+    #[test]
+    fn plan_is_empty_once_applied() {
+        let desired = Catalog::parse(CATALOG, CatalogFormat::Toml).unwrap();
+
+        let changes = desired.plan(&Catalog::default());
+        assert!(matches!(changes[0], Change::CreateProtocol(_)));
+        assert!(matches!(changes[1], Change::CreateVersion(..)));
+        assert!(matches!(changes[2], Change::CreateImage(..)));
+        assert_eq!(changes.len(), 3);
+
+        assert!(desired.plan(&desired).is_empty());
+    }
+
+    #[test]
+    fn plan_rejects_changed_builds() {
+        let current = Catalog::parse(CATALOG, CatalogFormat::Toml).unwrap();
+        let mut desired = current.clone();
+        let version = &mut desired.protocols[0].variants[0].versions[0];
+        version.visibility = Visibility::Private;
+        version.images[0].visibility = Visibility::Private;
+        let mut next = version.images[0].clone();
+        version.images[0].min_cpu_cores = 8;
+        next.build_version = 3;
+        version.images.push(next);
+
+        let changes = desired.plan(&current);
+        assert!(matches!(changes[0], Change::UpdateVersion(..)));
+        assert!(matches!(changes[1], Change::Conflict(_, Some(1), _)));
+        assert!(matches!(changes[2], Change::Conflict(_, Some(3), _)));
+        assert_eq!(changes.len(), 3);
+
+        desired.protocols[0].variants[0].versions[0].images[0].min_cpu_cores = 4;
+        desired.protocols[0].variants[0].versions[0].images[1].build_version = 2;
+        let changes = desired.plan(&current);
+        assert!(matches!(changes[1], Change::UpdateImage(..)));
+        assert!(matches!(changes[2], Change::CreateImage(..)));
+    }
+
+    #[test]
+    fn duplicate_versions_are_rejected() {
+        let toml = format!(
+            "{CATALOG}\n[[protocols.variants.versions]]\nsemantic_version = \"1.2.0\"\n\
+             sku_code = \"ETH-RETH-MN\"\nvisibility = \"public\"\n"
+        );
+        let err = Catalog::parse(&toml, CatalogFormat::Toml).unwrap_err();
+        assert!(matches!(err, Error::DuplicateVersion(_)));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod catalog;
+
 pub mod stats;
 
 pub mod version;
@@ -17,6 +19,7 @@ use diesel_async::RunQueryDsl;
 use diesel_derive_enum::DbEnum;
 use diesel_derive_newtype::DieselNewType;
 use displaydoc::Display as DisplayDoc;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -288,8 +291,9 @@ impl ProtocolSort {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "sql_types::EnumVisibility"]
+#[serde(rename_all = "snake_case")]
 pub enum Visibility {
     Public,
     Private,
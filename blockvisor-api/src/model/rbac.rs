@@ -7,6 +7,7 @@ use diesel::prelude::*;
 use diesel::result::Error::NotFound;
 use diesel_async::RunQueryDsl;
 use displaydoc::Display;
+use strum::IntoEnumIterator;
 use thiserror::Error;
 
 use crate::auth::rbac::BlockjoyRole;
@@ -255,7 +256,7 @@ impl RbacUser {
     pub async fn org_owners(org_id: OrgId, conn: &mut Conn<'_>) -> Result<Vec<UserId>, Error> {
         user_roles::table
             .filter(user_roles::org_id.eq(org_id))
-            .filter(user_roles::role.eq(OrgRole::Owner.to_string()))
+            .filter(user_roles::role.eq(Role::from(OrgRole::Owner).to_string()))
             .select(user_roles::user_id)
             .get_results(conn)
             .await
@@ -374,6 +375,28 @@ impl RbacUser {
                 _ => Ok(()),
             })
     }
+
+    /// Unlinks the user from all `OrgRole` roles within an org.
+    ///
+    /// Other roles linked to that org (such as `blockjoy-admin`) are kept.
+    pub async fn unlink_org_roles(
+        user_id: UserId,
+        org_id: OrgId,
+        conn: &mut Conn<'_>,
+    ) -> Result<(), Error> {
+        let org_roles: Vec<_> = OrgRole::iter()
+            .map(|role| Role::Org(role).to_string())
+            .collect();
+
+        diesel::delete(user_roles::table)
+            .filter(user_roles::user_id.eq(user_id))
+            .filter(user_roles::org_id.eq(org_id))
+            .filter(user_roles::role.eq_any(org_roles))
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::UnlinkUserRole(user_id, org_id, None, err))
+    }
 }
 
 #[derive(Debug, Queryable, Selectable)]
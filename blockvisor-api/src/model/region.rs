@@ -18,6 +18,8 @@ use super::schema::regions;
 
 #[derive(Debug, DisplayDoc, Error)]
 pub enum Error {
+    /// Failed to get all regions: {0}
+    All(diesel::result::Error),
     /// Failed to create region: {0}
     Create(diesel::result::Error),
     /// Failed to get regions for id `{0}`: {1}
@@ -74,6 +76,11 @@ pub struct Region {
     pub sku_code: Option<String>,
     pub key: RegionKey,
     pub display_name: String,
+    pub cpu_overcommit_percent: i32,
+    pub memory_overcommit_percent: i32,
+    pub disk_overcommit_percent: i32,
+    pub usage_scheduling: bool,
+    pub usage_headroom_percent: i32,
 }
 
 impl Region {
@@ -103,6 +110,14 @@ impl Region {
             .await
             .map_err(|err| Error::ByKey(key.clone(), err))
     }
+
+    pub async fn all(conn: &mut Conn<'_>) -> Result<Vec<Self>, Error> {
+        regions::table
+            .order_by(regions::key)
+            .get_results(conn)
+            .await
+            .map_err(Error::All)
+    }
 }
 
 #[derive(Clone, Debug, Insertable)]
@@ -130,6 +145,11 @@ impl From<Region> for api::Region {
             region_key: region.key.into(),
             display_name: region.display_name,
             sku_code: region.sku_code,
+            cpu_overcommit_percent: region.cpu_overcommit_percent.unsigned_abs(),
+            memory_overcommit_percent: region.memory_overcommit_percent.unsigned_abs(),
+            disk_overcommit_percent: region.disk_overcommit_percent.unsigned_abs(),
+            usage_scheduling: region.usage_scheduling,
+            usage_headroom_percent: region.usage_headroom_percent.unsigned_abs(),
         }
     }
 }
@@ -140,6 +160,11 @@ pub struct UpdateRegion<'u> {
     pub id: RegionId,
     pub display_name: Option<&'u str>,
     pub sku_code: Option<&'u str>,
+    pub cpu_overcommit_percent: Option<i32>,
+    pub memory_overcommit_percent: Option<i32>,
+    pub disk_overcommit_percent: Option<i32>,
+    pub usage_scheduling: Option<bool>,
+    pub usage_headroom_percent: Option<i32>,
 }
 
 impl UpdateRegion<'_> {
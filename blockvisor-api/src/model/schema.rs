@@ -5,6 +5,14 @@ pub mod sql_types {
     #[diesel(postgres_type(name = "blockchain_property_ui_type"))]
     pub struct BlockchainPropertyUiType;
 
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_archive_version_status"))]
+    pub struct EnumArchiveVersionStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_bundle_channel"))]
+    pub struct EnumBundleChannel;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "enum_command_exit_code"))]
     pub struct EnumCommandExitCode;
@@ -45,6 +53,18 @@ pub mod sql_types {
     #[diesel(postgres_type(name = "enum_host_type_old"))]
     pub struct EnumHostTypeOld;
 
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_host_upgrade_campaign_status"))]
+    pub struct EnumHostUpgradeCampaignStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_host_upgrade_status"))]
+    pub struct EnumHostUpgradeStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_image_lifecycle"))]
+    pub struct EnumImageLifecycle;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "enum_next_state"))]
     pub struct EnumNextState;
@@ -101,6 +121,14 @@ pub mod sql_types {
     #[diesel(postgres_type(name = "enum_ui_type"))]
     pub struct EnumUiType;
 
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_usage_state"))]
+    pub struct EnumUsageState;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "enum_value_type"))]
+    pub struct EnumValueType;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "enum_visibility"))]
     pub struct EnumVisibility;
@@ -141,6 +169,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    archive_storage (store_key) {
+        store_key -> Text,
+        data_versions -> Int8,
+        stored_bytes -> Int8,
+        measured_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumArchiveVersionStatus;
+
+    archive_versions (id) {
+        id -> Uuid,
+        store_key -> Text,
+        data_version -> Int8,
+        status -> EnumArchiveVersionStatus,
+        total_size -> Int8,
+        stored_bytes -> Int8,
+        chunks -> Int8,
+        missing_chunks -> Int8,
+        problem -> Nullable<Text>,
+        verified_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     archives (id) {
         id -> Uuid,
@@ -216,6 +271,52 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumBundleChannel;
+
+    bundle_pins (id) {
+        id -> Uuid,
+        host_id -> Nullable<Uuid>,
+        region_id -> Nullable<Uuid>,
+        channel -> EnumBundleChannel,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumBundleChannel;
+
+    bundles (id) {
+        id -> Uuid,
+        version -> Text,
+        channel -> EnumBundleChannel,
+        created_at -> Timestamptz,
+        promoted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    capacity_reservations (id) {
+        id -> Uuid,
+        org_id -> Uuid,
+        region_id -> Uuid,
+        protocol_id -> Nullable<Uuid>,
+        node_slots -> Nullable<Int4>,
+        cpu_cores -> Int8,
+        memory_bytes -> Int8,
+        disk_bytes -> Int8,
+        used_node_slots -> Int4,
+        used_cpu_cores -> Int8,
+        used_memory_bytes -> Int8,
+        used_disk_bytes -> Int8,
+        expires_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumCommandExitCode;
@@ -253,6 +354,64 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumResourceType;
+    use super::sql_types::EnumScheduleType;
+
+    host_provision_tokens (id) {
+        id -> Uuid,
+        org_id -> Uuid,
+        name -> Text,
+        token_hash -> Text,
+        created_by_type -> EnumResourceType,
+        created_by_id -> Uuid,
+        expires_at -> Nullable<Timestamptz>,
+        max_uses -> Nullable<Int8>,
+        use_count -> Int8,
+        region_ids -> Array<Nullable<Uuid>>,
+        schedule_type -> Nullable<EnumScheduleType>,
+        tags -> Array<Nullable<Text>>,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumHostUpgradeCampaignStatus;
+
+    host_upgrade_campaigns (id) {
+        id -> Uuid,
+        bundle_version -> Text,
+        status -> EnumHostUpgradeCampaignStatus,
+        batch_size -> Int8,
+        max_failures -> Int8,
+        paused_reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumHostUpgradeStatus;
+
+    host_upgrades (id) {
+        id -> Uuid,
+        campaign_id -> Uuid,
+        host_id -> Uuid,
+        region_id -> Uuid,
+        status -> EnumHostUpgradeStatus,
+        command_id -> Nullable<Uuid>,
+        message -> Nullable<Text>,
+        started_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumScheduleType;
@@ -295,6 +454,14 @@ diesel::table! {
         updated_at -> Nullable<Timestamptz>,
         deleted_at -> Nullable<Timestamptz>,
         cost -> Nullable<Jsonb>,
+        last_seen_at -> Nullable<Timestamptz>,
+        labels -> Jsonb,
+        taints -> Jsonb,
+        cpu_overcommit_percent -> Nullable<Int4>,
+        memory_overcommit_percent -> Nullable<Int4>,
+        disk_overcommit_percent -> Nullable<Int4>,
+        ipv6_gateway -> Nullable<Inet>,
+        zone_id -> Nullable<Uuid>,
     }
 }
 
@@ -343,6 +510,7 @@ diesel::table! {
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumUiType;
+    use super::sql_types::EnumValueType;
 
     image_properties (id) {
         id -> Uuid,
@@ -360,6 +528,14 @@ diesel::table! {
         add_disk_bytes -> Nullable<Int8>,
         display_name -> Nullable<Text>,
         display_group -> Nullable<Text>,
+        value_type -> EnumValueType,
+        min_value -> Nullable<Float8>,
+        max_value -> Nullable<Float8>,
+        pattern -> Nullable<Text>,
+        allowed_values -> Array<Nullable<Text>>,
+        max_length -> Nullable<Int4>,
+        is_required -> Bool,
+        dependencies -> Jsonb,
     }
 }
 
@@ -386,6 +562,7 @@ diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumFirewallAction;
     use super::sql_types::EnumVisibility;
+    use super::sql_types::EnumImageLifecycle;
 
     images (id) {
         id -> Uuid,
@@ -405,6 +582,9 @@ diesel::table! {
         updated_at -> Nullable<Timestamptz>,
         min_babel_version -> Text,
         dns_scheme -> Nullable<Text>,
+        lifecycle -> EnumImageLifecycle,
+        lifecycle_reason -> Nullable<Text>,
+        sunset_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -429,6 +609,20 @@ diesel::table! {
         id -> Uuid,
         ip -> Inet,
         host_id -> Uuid,
+        reserved_at -> Nullable<Timestamptz>,
+        reserved_reason -> Nullable<Text>,
+        quarantined_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    node_failovers (id) {
+        id -> Uuid,
+        node_id -> Uuid,
+        new_node_id -> Uuid,
+        host_id -> Uuid,
+        created_at -> Timestamptz,
+        completed_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -468,6 +662,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    node_moves (id) {
+        id -> Uuid,
+        node_id -> Uuid,
+        new_node_id -> Uuid,
+        created_at -> Timestamptz,
+        completed_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     node_properties_old (id) {
         id -> Uuid,
@@ -491,6 +695,44 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumFirewallProtocol;
+    use super::sql_types::EnumFirewallDirection;
+    use super::sql_types::EnumFirewallAction;
+
+    node_rules (id) {
+        id -> Uuid,
+        node_id -> Uuid,
+        key -> Text,
+        description -> Nullable<Text>,
+        protocol -> EnumFirewallProtocol,
+        direction -> EnumFirewallDirection,
+        action -> EnumFirewallAction,
+        ips -> Nullable<Jsonb>,
+        ports -> Nullable<Jsonb>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EnumUsageState;
+
+    node_usage (id) {
+        id -> Uuid,
+        node_id -> Uuid,
+        org_id -> Uuid,
+        sku -> Nullable<Text>,
+        usage_state -> EnumUsageState,
+        cpu_cores -> Int8,
+        memory_bytes -> Int8,
+        disk_bytes -> Int8,
+        started_at -> Timestamptz,
+        ended_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumNodeState;
@@ -542,6 +784,15 @@ diesel::table! {
         updated_at -> Nullable<Timestamptz>,
         deleted_at -> Nullable<Timestamptz>,
         cost -> Nullable<Jsonb>,
+        failover_enabled -> Nullable<Bool>,
+        placement -> Jsonb,
+        ipv6_address -> Nullable<Inet>,
+        ipv6_gateway -> Nullable<Inet>,
+        ipv6_dns_id -> Nullable<Text>,
+        capacity_reservation_id -> Nullable<Uuid>,
+        restart_policy -> Jsonb,
+        restart_attempts -> Int4,
+        restart_attempted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -606,6 +857,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    org_identity_providers (id) {
+        id -> Uuid,
+        org_id -> Uuid,
+        issuer -> Text,
+        client_id -> Text,
+        client_secret -> Text,
+        allowed_domains -> Array<Nullable<Text>>,
+        verified_domains -> Array<Nullable<Text>>,
+        group_roles -> Jsonb,
+        created_at -> Timestamptz,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     orgs (id) {
         id -> Uuid,
@@ -619,6 +885,7 @@ diesel::table! {
         member_count -> Int4,
         stripe_customer_id -> Nullable<Text>,
         address_id -> Nullable<Uuid>,
+        failover_enabled -> Bool,
     }
 }
 
@@ -672,6 +939,11 @@ diesel::table! {
         sku_code -> Nullable<Text>,
         key -> Text,
         display_name -> Text,
+        cpu_overcommit_percent -> Int4,
+        memory_overcommit_percent -> Int4,
+        disk_overcommit_percent -> Int4,
+        usage_scheduling -> Bool,
+        usage_headroom_percent -> Int4,
     }
 }
 
@@ -690,6 +962,30 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sso_identities (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        issuer -> Text,
+        subject -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sso_sessions (id) {
+        id -> Uuid,
+        org_id -> Uuid,
+        state -> Text,
+        nonce -> Text,
+        code_verifier -> Text,
+        redirect_uri -> Text,
+        link_user_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::EnumTokenType;
@@ -741,6 +1037,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    zones (id) {
+        id -> Uuid,
+        region_id -> Uuid,
+        key -> Text,
+        display_name -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::joinable!(api_keys -> users (user_id));
 diesel::joinable!(archives -> images (image_id));
 diesel::joinable!(archives -> orgs (org_id));
@@ -750,12 +1056,23 @@ diesel::joinable!(blockchain_properties_old -> blockchain_versions_old (blockcha
 diesel::joinable!(blockchain_properties_old -> blockchains_old (blockchain_id));
 diesel::joinable!(blockchain_versions_old -> blockchain_node_types_old (blockchain_node_type_id));
 diesel::joinable!(blockchain_versions_old -> blockchains_old (blockchain_id));
+diesel::joinable!(bundle_pins -> hosts (host_id));
+diesel::joinable!(bundle_pins -> regions (region_id));
+diesel::joinable!(capacity_reservations -> orgs (org_id));
+diesel::joinable!(capacity_reservations -> protocols (protocol_id));
+diesel::joinable!(capacity_reservations -> regions (region_id));
 diesel::joinable!(commands -> hosts (host_id));
 diesel::joinable!(commands -> nodes (node_id));
 diesel::joinable!(configs -> archives (archive_id));
 diesel::joinable!(configs -> images (image_id));
+diesel::joinable!(host_provision_tokens -> orgs (org_id));
+diesel::joinable!(host_upgrades -> commands (command_id));
+diesel::joinable!(host_upgrades -> host_upgrade_campaigns (campaign_id));
+diesel::joinable!(host_upgrades -> hosts (host_id));
+diesel::joinable!(host_upgrades -> regions (region_id));
 diesel::joinable!(hosts -> orgs (org_id));
 diesel::joinable!(hosts -> regions (region_id));
+diesel::joinable!(hosts -> zones (zone_id));
 diesel::joinable!(hosts_old -> orgs (org_id));
 diesel::joinable!(hosts_old -> regions (region_id));
 diesel::joinable!(hosts_old -> users (created_by));
@@ -766,13 +1083,19 @@ diesel::joinable!(images -> protocol_versions (protocol_version_id));
 diesel::joinable!(invitations -> orgs (org_id));
 diesel::joinable!(invitations -> users (invited_by));
 diesel::joinable!(ip_addresses -> hosts (host_id));
+diesel::joinable!(node_failovers -> hosts (host_id));
 diesel::joinable!(node_logs -> hosts (host_id));
 diesel::joinable!(node_logs -> nodes (node_id));
 diesel::joinable!(node_logs_old -> blockchains_old (blockchain_id));
 diesel::joinable!(node_logs_old -> orgs (org_id));
+diesel::joinable!(node_moves -> nodes (new_node_id));
 diesel::joinable!(node_properties_old -> blockchain_properties_old (blockchain_property_id));
 diesel::joinable!(node_properties_old -> nodes_old (node_id));
 diesel::joinable!(node_reports -> nodes (node_id));
+diesel::joinable!(node_rules -> nodes (node_id));
+diesel::joinable!(node_usage -> nodes (node_id));
+diesel::joinable!(node_usage -> orgs (org_id));
+diesel::joinable!(nodes -> capacity_reservations (capacity_reservation_id));
 diesel::joinable!(nodes -> configs (config_id));
 diesel::joinable!(nodes -> hosts (host_id));
 diesel::joinable!(nodes -> images (image_id));
@@ -784,26 +1107,39 @@ diesel::joinable!(nodes_old -> blockchains_old (blockchain_id));
 diesel::joinable!(nodes_old -> hosts_old (host_id));
 diesel::joinable!(nodes_old -> orgs (org_id));
 diesel::joinable!(nodes_old -> regions (scheduler_region));
+diesel::joinable!(org_identity_providers -> orgs (org_id));
 diesel::joinable!(orgs -> addresses (address_id));
 diesel::joinable!(protocol_versions -> orgs (org_id));
 diesel::joinable!(protocols -> orgs (org_id));
 diesel::joinable!(role_permissions -> permissions (permission));
 diesel::joinable!(role_permissions -> roles (role));
+diesel::joinable!(sso_identities -> users (user_id));
+diesel::joinable!(sso_sessions -> orgs (org_id));
+diesel::joinable!(sso_sessions -> users (link_user_id));
 diesel::joinable!(user_roles -> orgs (org_id));
 diesel::joinable!(user_roles -> roles (role));
 diesel::joinable!(user_roles -> users (user_id));
 diesel::joinable!(user_settings -> users (user_id));
+diesel::joinable!(zones -> regions (region_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     addresses,
     api_keys,
+    archive_storage,
+    archive_versions,
     archives,
     blockchain_node_types_old,
     blockchain_properties_old,
     blockchain_versions_old,
     blockchains_old,
+    bundle_pins,
+    bundles,
+    capacity_reservations,
     commands,
     configs,
+    host_provision_tokens,
+    host_upgrade_campaigns,
+    host_upgrades,
     hosts,
     hosts_old,
     image_properties,
@@ -811,12 +1147,17 @@ diesel::allow_tables_to_appear_in_same_query!(
     images,
     invitations,
     ip_addresses,
+    node_failovers,
     node_logs,
     node_logs_old,
+    node_moves,
     node_properties_old,
     node_reports,
+    node_rules,
+    node_usage,
     nodes,
     nodes_old,
+    org_identity_providers,
     orgs,
     permissions,
     protocol_versions,
@@ -824,8 +1165,11 @@ diesel::allow_tables_to_appear_in_same_query!(
     regions,
     role_permissions,
     roles,
+    sso_identities,
+    sso_sessions,
     tokens,
     user_roles,
     user_settings,
     users,
+    zones,
 );
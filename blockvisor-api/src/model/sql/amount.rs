@@ -56,6 +56,15 @@ impl TryFrom<common::BillingAmount> for Amount {
     }
 }
 
+impl Amount {
+    /// The amount normalised to a monthly billing period.
+    pub const fn monthly_amount(&self) -> i64 {
+        match self.period {
+            Period::Monthly => self.amount,
+        }
+    }
+}
+
 impl FromSql<Jsonb, Pg> for Amount {
     fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
         let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(value)?;
@@ -1,6 +1,7 @@
 pub mod amount;
 pub use amount::{Amount, Currency, Period};
 
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::str::FromStr;
 
@@ -9,7 +10,7 @@ use diesel::deserialize::{FromSql, FromSqlRow};
 use diesel::expression::AsExpression;
 use diesel::pg::{Pg, PgValue};
 use diesel::serialize::{Output, ToSql};
-use diesel::sql_types::{Array, Inet, Jsonb, Nullable, SingleValue, Text};
+use diesel::sql_types::{Array, BigInt, Bool, Inet, Integer, Jsonb, Nullable, SingleValue, Text};
 use diesel::{define_sql_function, deserialize, serialize};
 use displaydoc::Display as DisplayDoc;
 use serde::{Deserialize, Serialize};
@@ -22,13 +23,19 @@ use crate::model::protocol::VersionMetadata;
 use crate::util::LOWER_KEBAB_CASE;
 
 define_sql_function!(fn coalesce(x: Nullable<Text>, y: Text) -> Text);
+define_sql_function!(fn family(x: Inet) -> Integer);
 define_sql_function!(fn greatest<T: SingleValue>(x: T, y: T) -> T);
 define_sql_function!(fn lower(x: Text) -> Text);
+define_sql_function!(fn pg_try_advisory_xact_lock(key: BigInt) -> Bool);
 define_sql_function!(fn string_to_array(text: Text, split: Text) -> Array<Text>);
 define_sql_function!(fn text<T: SingleValue>(x: T) -> Text);
 
 #[derive(Debug, DisplayDoc, Error)]
 pub enum Error {
+    /// Label key is not lower-kebab-case: {0}
+    LabelKey(String),
+    /// Label value for `{0}` must be at most 63 characters.
+    LabelValue(String),
     /// Failed to parse IP `{0}`: {1}
     ParseIp(String, ipnetwork::IpNetworkError),
     /// Failed to parse Perm `{0}`: {1}
@@ -37,6 +44,8 @@ pub enum Error {
     ParseUrl(String, url::ParseError),
     /// Failed to parse Version `{0}`: {1}
     ParseVersion(String, semver::Error),
+    /// Failed to parse Labels `{0}`: {1}
+    ParseLabels(serde_json::Value, serde_json::Error),
     /// Failed to parse ProtocolVersionMetadata `{0}`: {1}
     ParseVersionMetadata(serde_json::Value, serde_json::Error),
     /// Tag is not lower-kebab-case: {0}
@@ -49,6 +58,7 @@ impl From<Error> for Status {
     fn from(err: Error) -> Self {
         use Error::*;
         match err {
+            LabelKey(_) | LabelValue(_) => Status::invalid_argument("labels"),
             TagChars(_) | TagLen(_) => Status::invalid_argument("tag"),
             _ => Status::internal("Internal error."),
         }
@@ -214,6 +224,74 @@ impl From<Tags> for common::Tags {
     }
 }
 
+/// Key-value labels, such as host labels or taints.
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+    IntoIterator,
+)]
+#[diesel(sql_type = Jsonb)]
+pub struct Labels(BTreeMap<String, String>);
+
+impl Labels {
+    const MAX_VALUE_LEN: usize = 63;
+
+    pub fn new(labels: HashMap<String, String>) -> Result<Self, Error> {
+        labels
+            .into_iter()
+            .map(|(key, value)| {
+                if key.is_empty() || !key.chars().all(|c| LOWER_KEBAB_CASE.contains(c)) {
+                    Err(Error::LabelKey(key))
+                } else if value.len() > Self::MAX_VALUE_LEN {
+                    Err(Error::LabelValue(key))
+                } else {
+                    Ok((key, value))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Labels)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromSql<Jsonb, Pg> for Labels {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(value)?;
+        Labels::deserialize(&value).map_err(|err| Error::ParseLabels(value, err).into())
+    }
+}
+
+impl ToSql<Jsonb, Pg> for Labels {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+impl TryFrom<common::Labels> for Labels {
+    type Error = Error;
+
+    fn try_from(labels: common::Labels) -> Result<Self, Self::Error> {
+        Labels::new(labels.labels)
+    }
+}
+
+impl From<Labels> for HashMap<String, String> {
+    fn from(labels: Labels) -> Self {
+        labels.0.into_iter().collect()
+    }
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq, AsExpression, FromSqlRow, IntoIterator)]
 #[diesel(sql_type = Array<Nullable<Text>>)]
 pub struct Permissions(Vec<Perm>);
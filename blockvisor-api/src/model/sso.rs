@@ -0,0 +1,492 @@
+//! Per-org OpenID Connect identity providers, their pending logins, and the
+//! provider identities linked to users.
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From, FromStr};
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::result::Error::NotFound;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Jsonb;
+use diesel::upsert::excluded;
+use diesel_async::RunQueryDsl;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::auth::rbac::{OrgRole, Role};
+use crate::auth::resource::{OrgId, UserId};
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+
+use super::schema::{org_identity_providers, sso_identities, sso_sessions};
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to link SSO identity to user `{0}`: {1}
+    CreateIdentity(UserId, diesel::result::Error),
+    /// Failed to create SSO session for org `{0}`: {1}
+    CreateSession(OrgId, diesel::result::Error),
+    /// Failed to delete identity provider for org `{0}`: {1}
+    Delete(OrgId, diesel::result::Error),
+    /// Failed to delete expired SSO sessions: {0}
+    DeleteExpired(diesel::result::Error),
+    /// Domain `{0}` is not an allowed domain of the identity provider.
+    DomainNotAllowed(String),
+    /// Failed to find identity provider for org `{0}`: {1}
+    FindByOrg(OrgId, diesel::result::Error),
+    /// Failed to find SSO identity: {0}
+    FindIdentity(diesel::result::Error),
+    /// Failed to find SSO session: {0}
+    FindSession(diesel::result::Error),
+    /// Allowed domain `{0}` is not a valid domain.
+    InvalidDomain(String),
+    /// Group `{0}` can only map to org-admin or org-member.
+    InvalidGroupRole(String),
+    /// An identity provider must allow at least one email domain.
+    NoDomains,
+    /// Failed to parse group roles from `{0}`: {1}
+    ParseGroupRoles(serde_json::Value, serde_json::Error),
+    /// Failed to parse role `{0}`: {1}
+    ParseRole(String, String),
+    /// Failed to save identity provider for org `{0}`: {1}
+    Upsert(OrgId, diesel::result::Error),
+    /// Failed to verify domain for org `{0}`: {1}
+    VerifyDomain(OrgId, diesel::result::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            Delete(_, NotFound) | FindByOrg(_, NotFound) => {
+                Status::not_found("Identity provider not found.")
+            }
+            FindSession(NotFound) => Status::unauthorized("SSO session expired."),
+            DomainNotAllowed(_) => Status::invalid_argument("domain"),
+            InvalidDomain(_) | NoDomains => Status::invalid_argument("allowed_domains"),
+            InvalidGroupRole(_) | ParseRole(..) => Status::invalid_argument("group_roles"),
+            CreateIdentity(..) | CreateSession(..) | Delete(..) | DeleteExpired(_)
+            | FindByOrg(..) | FindIdentity(_) | FindSession(_) | ParseGroupRoles(..)
+            | Upsert(..) | VerifyDomain(..) => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Display,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    DieselNewType,
+    Deref,
+    From,
+    FromStr,
+)]
+pub struct IdentityProviderId(Uuid);
+
+/// Maps an IdP group claim to the org role its members receive.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupRole {
+    pub group: String,
+    pub role: Role,
+}
+
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[diesel(sql_type = Jsonb)]
+pub struct GroupRoles(Vec<GroupRole>);
+
+impl GroupRoles {
+    pub fn from_api(group_roles: Vec<api::SsoGroupRole>) -> Result<Self, Error> {
+        group_roles
+            .into_iter()
+            .map(|group_role| {
+                let role: Role = group_role
+                    .role
+                    .parse()
+                    .map_err(|err| Error::ParseRole(group_role.role.clone(), err))?;
+                match role {
+                    Role::Org(OrgRole::Admin | OrgRole::Member) => Ok(GroupRole {
+                        group: group_role.group,
+                        role,
+                    }),
+                    _ => Err(Error::InvalidGroupRole(group_role.group)),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(GroupRoles)
+    }
+
+    /// The highest org role granted by any of `groups`, defaulting to member.
+    pub fn role_for(&self, groups: &[String]) -> OrgRole {
+        let is_admin = self.0.iter().any(|group_role| {
+            group_role.role == Role::Org(OrgRole::Admin) && groups.contains(&group_role.group)
+        });
+
+        if is_admin {
+            OrgRole::Admin
+        } else {
+            OrgRole::Member
+        }
+    }
+}
+
+impl FromSql<Jsonb, Pg> for GroupRoles {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(value)?;
+        GroupRoles::deserialize(&value).map_err(|err| Error::ParseGroupRoles(value, err).into())
+    }
+}
+
+impl ToSql<Jsonb, Pg> for GroupRoles {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = org_identity_providers)]
+pub struct IdentityProvider {
+    pub id: IdentityProviderId,
+    pub org_id: OrgId,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub allowed_domains: Vec<Option<String>>,
+    pub verified_domains: Vec<Option<String>>,
+    pub group_roles: GroupRoles,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl IdentityProvider {
+    pub async fn by_org(org_id: OrgId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        org_identity_providers::table
+            .filter(org_identity_providers::org_id.eq(org_id))
+            .select(IdentityProvider::as_select())
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::FindByOrg(org_id, err))
+    }
+
+    pub async fn delete(org_id: OrgId, conn: &mut Conn<'_>) -> Result<(), Error> {
+        let row = org_identity_providers::table.filter(org_identity_providers::org_id.eq(org_id));
+        let deleted = diesel::delete(row)
+            .execute(conn)
+            .await
+            .map_err(|err| Error::Delete(org_id, err))?;
+
+        if deleted == 0 {
+            Err(Error::Delete(org_id, NotFound))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Mark one of the allowed domains as verified to be owned by the org.
+    pub async fn verify_domain(
+        org_id: OrgId,
+        domain: &str,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        let provider = Self::by_org(org_id, conn).await?;
+        let domain = domain.trim().to_lowercase();
+        if !provider.allowed_domains.contains(&Some(domain.clone())) {
+            return Err(Error::DomainNotAllowed(domain));
+        } else if provider.verified_domains.contains(&Some(domain.clone())) {
+            return Ok(provider);
+        }
+
+        let mut verified_domains = provider.verified_domains;
+        verified_domains.push(Some(domain));
+
+        diesel::update(org_identity_providers::table.find(provider.id))
+            .set((
+                org_identity_providers::verified_domains.eq(verified_domains),
+                org_identity_providers::updated_at.eq(Utc::now()),
+            ))
+            .returning(IdentityProvider::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::VerifyDomain(org_id, err))
+    }
+
+    /// Whether `email` belongs to one of the verified domains.
+    ///
+    /// An allowed domain is only trusted once it has been verified, otherwise
+    /// an org could claim the users of any domain.
+    pub fn allows_email(&self, email: &str) -> bool {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+
+        self.verified_domains
+            .iter()
+            .flatten()
+            .any(|verified| verified.eq_ignore_ascii_case(domain))
+    }
+}
+
+impl From<IdentityProvider> for api::OrgIdentityProvider {
+    fn from(provider: IdentityProvider) -> Self {
+        api::OrgIdentityProvider {
+            org_id: provider.org_id.to_string(),
+            issuer: provider.issuer,
+            client_id: provider.client_id,
+            allowed_domains: provider.allowed_domains.into_iter().flatten().collect(),
+            verified_domains: provider.verified_domains.into_iter().flatten().collect(),
+            group_roles: provider
+                .group_roles
+                .0
+                .into_iter()
+                .map(|group_role| api::SsoGroupRole {
+                    group: group_role.group,
+                    role: group_role.role.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = org_identity_providers)]
+pub struct NewIdentityProvider {
+    pub org_id: OrgId,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub allowed_domains: Vec<Option<String>>,
+    pub verified_domains: Vec<Option<String>>,
+    pub group_roles: GroupRoles,
+}
+
+impl NewIdentityProvider {
+    pub fn new(
+        org_id: OrgId,
+        issuer: String,
+        client_id: String,
+        client_secret: String,
+        allowed_domains: Vec<String>,
+        group_roles: GroupRoles,
+    ) -> Result<Self, Error> {
+        if allowed_domains.is_empty() {
+            return Err(Error::NoDomains);
+        }
+
+        let allowed_domains = allowed_domains
+            .into_iter()
+            .map(|domain| {
+                let domain = domain.trim().to_lowercase();
+                if domain.is_empty() || domain.contains(['@', '/', ' ']) || !domain.contains('.') {
+                    Err(Error::InvalidDomain(domain))
+                } else {
+                    Ok(Some(domain))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(NewIdentityProvider {
+            org_id,
+            issuer,
+            client_id,
+            client_secret,
+            allowed_domains,
+            verified_domains: vec![],
+            group_roles,
+        })
+    }
+
+    /// Keep the verified domains of `existing` that are still allowed.
+    #[must_use]
+    pub fn keep_verified(mut self, existing: &IdentityProvider) -> Self {
+        self.verified_domains = existing
+            .verified_domains
+            .iter()
+            .filter(|domain| self.allowed_domains.contains(domain))
+            .cloned()
+            .collect();
+        self
+    }
+
+    /// Create or replace the identity provider for an org.
+    pub async fn upsert(self, conn: &mut Conn<'_>) -> Result<IdentityProvider, Error> {
+        let org_id = self.org_id;
+        diesel::insert_into(org_identity_providers::table)
+            .values(&self)
+            .on_conflict(org_identity_providers::org_id)
+            .do_update()
+            .set((
+                org_identity_providers::issuer.eq(excluded(org_identity_providers::issuer)),
+                org_identity_providers::client_id.eq(excluded(org_identity_providers::client_id)),
+                org_identity_providers::client_secret
+                    .eq(excluded(org_identity_providers::client_secret)),
+                org_identity_providers::allowed_domains
+                    .eq(excluded(org_identity_providers::allowed_domains)),
+                org_identity_providers::verified_domains
+                    .eq(excluded(org_identity_providers::verified_domains)),
+                org_identity_providers::group_roles
+                    .eq(excluded(org_identity_providers::group_roles)),
+                org_identity_providers::updated_at.eq(Utc::now()),
+            ))
+            .returning(IdentityProvider::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::Upsert(org_id, err))
+    }
+}
+
+/// An authorization request awaiting its callback from the identity provider.
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = sso_sessions)]
+pub struct SsoSession {
+    pub id: Uuid,
+    pub org_id: OrgId,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub redirect_uri: String,
+    pub link_user_id: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SsoSession {
+    /// Consume the unexpired session for `state` so it can only be used once.
+    pub async fn take(state: &str, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        let row = sso_sessions::table
+            .filter(sso_sessions::state.eq(state))
+            .filter(sso_sessions::expires_at.gt(Utc::now()));
+
+        diesel::delete(row)
+            .returning(SsoSession::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(Error::FindSession)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = sso_sessions)]
+pub struct NewSsoSession<'a> {
+    pub org_id: OrgId,
+    pub state: &'a str,
+    pub nonce: &'a str,
+    pub code_verifier: &'a str,
+    pub redirect_uri: &'a str,
+    pub link_user_id: Option<UserId>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl NewSsoSession<'_> {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<SsoSession, Error> {
+        diesel::delete(sso_sessions::table.filter(sso_sessions::expires_at.lt(Utc::now())))
+            .execute(conn)
+            .await
+            .map_err(Error::DeleteExpired)?;
+
+        let org_id = self.org_id;
+        diesel::insert_into(sso_sessions::table)
+            .values(self)
+            .returning(SsoSession::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::CreateSession(org_id, err))
+    }
+}
+
+/// An identity provider subject linked to a user.
+///
+/// Logins are matched on the issuer and subject, never on the email address,
+/// so a provider can't sign in to an account that was not linked to it.
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = sso_identities)]
+pub struct SsoIdentity {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SsoIdentity {
+    pub async fn find(
+        issuer: &str,
+        subject: &str,
+        conn: &mut Conn<'_>,
+    ) -> Result<Option<Self>, Error> {
+        sso_identities::table
+            .filter(sso_identities::issuer.eq(issuer))
+            .filter(sso_identities::subject.eq(subject))
+            .select(SsoIdentity::as_select())
+            .get_result(conn)
+            .await
+            .optional()
+            .map_err(Error::FindIdentity)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = sso_identities)]
+pub struct NewSsoIdentity<'a> {
+    pub user_id: UserId,
+    pub issuer: &'a str,
+    pub subject: &'a str,
+}
+
+impl NewSsoIdentity<'_> {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<SsoIdentity, Error> {
+        let user_id = self.user_id;
+        diesel::insert_into(sso_identities::table)
+            .values(self)
+            .returning(SsoIdentity::as_returning())
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::CreateIdentity(user_id, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_groups_to_highest_role() {
+        let group_roles = GroupRoles::from_api(vec![
+            api::SsoGroupRole {
+                group: "ops".into(),
+                role: "org-admin".into(),
+            },
+            api::SsoGroupRole {
+                group: "dev".into(),
+                role: "org-member".into(),
+            },
+        ])
+        .unwrap();
+
+        let groups = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            group_roles.role_for(&groups(&["dev", "ops"])),
+            OrgRole::Admin
+        );
+        assert_eq!(group_roles.role_for(&groups(&["dev"])), OrgRole::Member);
+        assert_eq!(group_roles.role_for(&[]), OrgRole::Member);
+
+        let owner = GroupRoles::from_api(vec![api::SsoGroupRole {
+            group: "root".into(),
+            role: "org-owner".into(),
+        }]);
+        assert!(matches!(owner, Err(Error::InvalidGroupRole(_))));
+    }
+}
@@ -0,0 +1,149 @@
+//! Availability zones within a region.
+//!
+//! Hosts may be assigned to a zone of their region, so that nodes can be
+//! spread over independent failure domains.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, Display, From, FromStr, Into};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind::{ForeignKeyViolation, UniqueViolation};
+use diesel::result::Error::{DatabaseError, NotFound};
+use diesel_async::RunQueryDsl;
+use diesel_derive_newtype::DieselNewType;
+use displaydoc::Display as DisplayDoc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::database::Conn;
+use crate::grpc::{Status, api};
+use crate::util::{LOWER_KEBAB_CASE, NanosUtc};
+
+use super::RegionId;
+use super::schema::zones;
+
+#[derive(Debug, DisplayDoc, Error)]
+pub enum Error {
+    /// Failed to get zone for id `{0}`: {1}
+    ById(ZoneId, diesel::result::Error),
+    /// Failed to get zones for regions `{0:?}`: {1}
+    ByRegions(HashSet<RegionId>, diesel::result::Error),
+    /// Failed to create zone: {0}
+    Create(diesel::result::Error),
+    /// Zone key is not lower-kebab-case: {0}
+    KeyChars(String),
+    /// Zone key must be at least 2 characters: {0}
+    KeyLen(String),
+    /// Zone `{0}` is not in region `{1}`.
+    WrongRegion(ZoneId, RegionId),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            Create(DatabaseError(UniqueViolation, _)) => {
+                Status::already_exists("Zone already exists.")
+            }
+            Create(DatabaseError(ForeignKeyViolation, _)) => Status::not_found("Region not found."),
+            ById(_, NotFound) => Status::not_found("Zone not found."),
+            KeyChars(_) | KeyLen(_) => Status::invalid_argument("zone_key"),
+            WrongRegion(..) => Status::failed_precondition("Zone is not in the host region."),
+            _ => Status::internal("Internal error."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, From, FromStr)]
+pub struct ZoneId(Uuid);
+
+#[derive(Clone, Debug, Display, PartialEq, Eq, DieselNewType, Deref, Into)]
+pub struct ZoneKey(String);
+
+impl ZoneKey {
+    pub fn new(key: String) -> Result<Self, Error> {
+        if key.len() < 2 {
+            Err(Error::KeyLen(key))
+        } else if !key.chars().all(|c| LOWER_KEBAB_CASE.contains(c)) {
+            Err(Error::KeyChars(key))
+        } else {
+            Ok(ZoneKey(key))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+pub struct Zone {
+    pub id: ZoneId,
+    pub region_id: RegionId,
+    pub key: ZoneKey,
+    pub display_name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Zone {
+    pub async fn by_id(id: ZoneId, conn: &mut Conn<'_>) -> Result<Self, Error> {
+        zones::table
+            .find(id)
+            .get_result(conn)
+            .await
+            .map_err(|err| Error::ById(id, err))
+    }
+
+    /// Find a zone that a host in `region_id` may be assigned to.
+    pub async fn in_region(
+        id: ZoneId,
+        region_id: RegionId,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self, Error> {
+        let zone = Self::by_id(id, conn).await?;
+        if zone.region_id == region_id {
+            Ok(zone)
+        } else {
+            Err(Error::WrongRegion(id, region_id))
+        }
+    }
+
+    pub async fn by_regions(
+        region_ids: &HashSet<RegionId>,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<Self>, Error> {
+        zones::table
+            .filter(zones::region_id.eq_any(region_ids))
+            .order_by(zones::key)
+            .get_results(conn)
+            .await
+            .map_err(|err| Error::ByRegions(region_ids.clone(), err))
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[diesel(table_name = zones)]
+pub struct NewZone<'a> {
+    pub region_id: RegionId,
+    pub key: ZoneKey,
+    pub display_name: &'a str,
+}
+
+impl NewZone<'_> {
+    pub async fn create(self, conn: &mut Conn<'_>) -> Result<Zone, Error> {
+        diesel::insert_into(zones::table)
+            .values(self)
+            .get_result(conn)
+            .await
+            .map_err(Error::Create)
+    }
+}
+
+impl From<Zone> for api::Zone {
+    fn from(zone: Zone) -> Self {
+        api::Zone {
+            zone_id: zone.id.to_string(),
+            region_id: zone.region_id.to_string(),
+            zone_key: zone.key.into(),
+            display_name: zone.display_name,
+            created_at: Some(NanosUtc::from(zone.created_at).into()),
+        }
+    }
+}
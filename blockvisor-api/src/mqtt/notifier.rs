@@ -12,7 +12,7 @@ use crate::database::{Database, Pool};
 use crate::grpc::command::host_pending;
 use crate::grpc::common;
 use crate::model::command::NewCommand;
-use crate::model::host::{ConnectionStatus, UpdateHost};
+use crate::model::host::{ConnectionStatus, Host, UpdateHost};
 use crate::model::{Command, CommandType};
 
 use super::{CLIENT_CAPACITY, CLIENT_QOS, Client, Message};
@@ -108,11 +108,17 @@ impl Notifier {
         let host_id = status.host_id.parse().map_err(Error::ParseHostId)?;
         let conn_status = status.connection_status().try_into()?;
 
-        UpdateHost::default()
-            .with_connection_status(conn_status)
-            .apply(host_id, &mut conn)
-            .await
-            .map_err(Error::UpdateHostStatus)?;
+        if conn_status == ConnectionStatus::Online {
+            Host::heartbeat(host_id, &mut conn)
+                .await
+                .map_err(Error::UpdateHostStatus)?;
+        } else {
+            UpdateHost::default()
+                .with_connection_status(conn_status)
+                .apply(host_id, &mut conn)
+                .await
+                .map_err(Error::UpdateHostStatus)?;
+        }
 
         if conn_status == ConnectionStatus::Online
             && Command::has_host_pending(host_id, &mut conn).await?
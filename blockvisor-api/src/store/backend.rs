@@ -0,0 +1,88 @@
+//! The object storage behind a `Store`.
+//!
+//! Objects are addressed by a bucket and a key. Keys are lowercased by every
+//! backend so that reads always find what was written.
+
+use std::time::Duration;
+
+use aws_sdk_s3::error::{BuildError, SdkError};
+use aws_sdk_s3::operation::delete_objects::DeleteObjectsError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_sdk_s3::operation::put_object::PutObjectError;
+use aws_sdk_s3::presigning::PresigningConfigError;
+use aws_sdk_s3::primitives::ByteStreamError;
+use displaydoc::Display;
+use thiserror::Error;
+use url::Url;
+
+use super::local::{self, LocalDir};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Failed to build delete request: {0}
+    BuildDelete(BuildError),
+    /// Failed to delete keys from bucket `{0}`: {1:?}
+    DeleteKeys(String, SdkError<DeleteObjectsError>),
+    /// Failed to create presigned download URL for key `{0}`: {1:?}
+    DownloadUrl(String, SdkError<GetObjectError>),
+    /// Failed to head key `{0}:{1}`: {2:?}
+    HeadKey(String, String, SdkError<HeadObjectError>),
+    /// Failed to list path `{0}`: {1:?}
+    ListPath(String, SdkError<ListObjectsV2Error>),
+    /// Local store error: {0}
+    Local(#[from] local::Error),
+    /// Bucket `{0}` does not contain key `{1}`
+    MissingKey(String, String),
+    /// Negative content length for key `{0}:{1}`: {2}
+    ObjectSize(String, String, std::num::TryFromIntError),
+    /// Failed to parse URL from PresignedRequest: {0}
+    ParseRequestUrl(url::ParseError),
+    /// Failed to create presigned config: {0}
+    PresigningConfig(PresigningConfigError),
+    /// Failed to query key `{0}:{1}`: {2}
+    QueryKey(String, String, ByteStreamError),
+    /// Failed to read key `{0}:{1}`: {2:?}
+    ReadKey(String, String, SdkError<GetObjectError>),
+    /// Failed to create presigned download URL for key `{0}`: {1:?}
+    UploadUrl(String, SdkError<PutObjectError>),
+    /// Failed to write key `{0}:{1}`: {2:?}
+    WriteKey(String, String, SdkError<PutObjectError>),
+}
+
+#[tonic::async_trait]
+pub trait Backend: Send + Sync {
+    /// Returns the keys of the objects under `path`.
+    async fn list(&self, bucket: &str, path: &str) -> Result<Vec<String>, Error>;
+
+    /// Returns the key and size in bytes of every object under `path`.
+    ///
+    /// Unlike `list`, this is not limited to a single page of results.
+    async fn list_sizes(&self, bucket: &str, path: &str) -> Result<Vec<(String, u64)>, Error>;
+
+    /// Returns the contents of the object at `key`.
+    async fn read_key(&self, bucket: &str, key: &str) -> Result<Vec<u8>, Error>;
+
+    /// Returns the size in bytes of the object at `key`.
+    async fn head_key(&self, bucket: &str, key: &str) -> Result<u64, Error>;
+
+    async fn write_key(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<(), Error>;
+
+    /// Deletes the objects at `keys`, ignoring any that do not exist.
+    async fn delete_keys(&self, bucket: &str, keys: &[String]) -> Result<(), Error>;
+
+    /// Returns a URL that allows anyone to download `key` until it expires.
+    async fn download_url(&self, bucket: &str, key: &str, expires: Duration) -> Result<Url, Error>;
+
+    /// Returns a URL that allows anyone to upload `key` until it expires.
+    async fn upload_url(&self, bucket: &str, key: &str, expires: Duration) -> Result<Url, Error>;
+
+    /// Returns the local directory backend, if this is one.
+    ///
+    /// The local backend serves its own URLs, so the HTTP routes need access
+    /// to it for signature checks and file access.
+    fn as_local(&self) -> Option<&LocalDir> {
+        None
+    }
+}
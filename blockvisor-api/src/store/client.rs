@@ -2,37 +2,18 @@ use std::time::Duration;
 
 use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::operation::get_object::GetObjectError;
-use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
-use aws_sdk_s3::operation::put_object::PutObjectError;
-use aws_sdk_s3::presigning::{PresigningConfig, PresigningConfigError};
-use aws_sdk_s3::primitives::ByteStreamError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
 use derive_more::Deref;
-use displaydoc::Display;
-use thiserror::Error;
 use url::Url;
 
-#[derive(Debug, Display, Error)]
-pub enum Error {
-    /// Failed to create presigned download URL for key `{0}`: {1:?}
-    DownloadUrl(String, SdkError<GetObjectError>),
-    /// Failed to list path `{0}`: {1:?}
-    ListPath(String, SdkError<ListObjectsV2Error>),
-    /// Bucket `{0}` does not contain key `{1}`
-    MissingKey(String, String),
-    /// Failed to parse URL from PresignedRequest: {0}
-    ParseRequestUrl(url::ParseError),
-    /// Failed to create presigned config: {0}
-    PresigningConfig(PresigningConfigError),
-    /// Failed to query key `{0}:{1}`: {2}
-    QueryKey(String, String, ByteStreamError),
-    /// Failed to read key `{0}:{1}`: {2:?}
-    ReadKey(String, String, SdkError<GetObjectError>),
-    /// Failed to create presigned download URL for key `{0}`: {1:?}
-    UploadUrl(String, SdkError<PutObjectError>),
-    /// Failed to write key `{0}:{1}`: {2:?}
-    WriteKey(String, String, SdkError<PutObjectError>),
-}
+use super::backend::{Backend, Error};
+
+/// The maximum number of keys in a single `DeleteObjects` request.
+const MAX_DELETE_KEYS: usize = 1000;
 
+/// A store backend for S3 compatible object storage.
 #[derive(Deref)]
 pub struct Client {
     #[deref]
@@ -43,8 +24,11 @@ impl Client {
     pub const fn new(inner: aws_sdk_s3::Client) -> Self {
         Client { inner }
     }
+}
 
-    pub(super) async fn list(&self, bucket: &str, path: &str) -> Result<Vec<String>, Error> {
+#[tonic::async_trait]
+impl Backend for Client {
+    async fn list(&self, bucket: &str, path: &str) -> Result<Vec<String>, Error> {
         let path = path.to_lowercase();
         let resp = self
             .list_objects_v2()
@@ -63,7 +47,37 @@ impl Client {
         Ok(files)
     }
 
-    pub(super) async fn read_key(&self, bucket: &str, key: &str) -> Result<Vec<u8>, Error> {
+    async fn list_sizes(&self, bucket: &str, path: &str) -> Result<Vec<(String, u64)>, Error> {
+        let path = path.to_lowercase();
+        let mut objects = Vec::new();
+        let mut token = None;
+
+        loop {
+            let resp = self
+                .list_objects_v2()
+                .bucket(bucket)
+                .prefix(&path)
+                .set_continuation_token(token)
+                .send()
+                .await
+                .map_err(|err| Error::ListPath(path.clone(), err))?;
+
+            for object in resp.contents() {
+                if let Some(key) = object.key() {
+                    let size = u64::try_from(object.size().unwrap_or_default())
+                        .map_err(|err| Error::ObjectSize(bucket.into(), key.into(), err))?;
+                    objects.push((key.to_string(), size));
+                }
+            }
+
+            token = resp.next_continuation_token().map(ToString::to_string);
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    async fn read_key(&self, bucket: &str, key: &str) -> Result<Vec<u8>, Error> {
         let key = key.to_lowercase();
         let response = self
             .get_object()
@@ -86,12 +100,26 @@ impl Client {
             .map_err(|err| Error::QueryKey(bucket.into(), key, err))
     }
 
-    pub(super) async fn write_key(
-        &self,
-        bucket: &str,
-        key: &str,
-        data: Vec<u8>,
-    ) -> Result<(), Error> {
+    async fn head_key(&self, bucket: &str, key: &str) -> Result<u64, Error> {
+        let key = key.to_lowercase();
+        let response = self
+            .head_object()
+            .bucket(bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|err| match err {
+                SdkError::ServiceError(e) if matches!(e.err(), HeadObjectError::NotFound(_)) => {
+                    Error::MissingKey(bucket.into(), key.clone())
+                }
+                _ => Error::HeadKey(bucket.into(), key.clone(), err),
+            })?;
+
+        u64::try_from(response.content_length().unwrap_or_default())
+            .map_err(|err| Error::ObjectSize(bucket.into(), key, err))
+    }
+
+    async fn write_key(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<(), Error> {
         let key = key.to_lowercase();
         self.put_object()
             .bucket(bucket)
@@ -103,12 +131,35 @@ impl Client {
             .map_err(|err| Error::WriteKey(bucket.into(), key.clone(), err))
     }
 
-    pub(super) async fn download_url(
-        &self,
-        bucket: &str,
-        key: &str,
-        expires: Duration,
-    ) -> Result<Url, Error> {
+    async fn delete_keys(&self, bucket: &str, keys: &[String]) -> Result<(), Error> {
+        for batch in keys.chunks(MAX_DELETE_KEYS) {
+            let objects = batch
+                .iter()
+                .map(|key| {
+                    ObjectIdentifier::builder()
+                        .key(key.to_lowercase())
+                        .build()
+                        .map_err(Error::BuildDelete)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let delete = Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .map_err(Error::BuildDelete)?;
+
+            self.delete_objects()
+                .bucket(bucket)
+                .delete(delete)
+                .send()
+                .await
+                .map_err(|err| Error::DeleteKeys(bucket.into(), err))?;
+        }
+
+        Ok(())
+    }
+
+    async fn download_url(&self, bucket: &str, key: &str, expires: Duration) -> Result<Url, Error> {
         let key = key.to_lowercase();
         let config = PresigningConfig::expires_in(expires).map_err(Error::PresigningConfig)?;
 
@@ -121,12 +172,7 @@ impl Client {
             .and_then(|url| url.uri().parse().map_err(Error::ParseRequestUrl))
     }
 
-    pub(super) async fn upload_url(
-        &self,
-        bucket: &str,
-        key: &str,
-        expires: Duration,
-    ) -> Result<Url, Error> {
+    async fn upload_url(&self, bucket: &str, key: &str, expires: Duration) -> Result<Url, Error> {
         let key = key.to_lowercase();
         let config = PresigningConfig::expires_in(expires).map_err(Error::PresigningConfig)?;
 
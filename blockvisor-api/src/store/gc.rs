@@ -0,0 +1,457 @@
+//! Garbage collection of archive data versions.
+//!
+//! Every upload reserves a new data version under its `StoreKey`, so without
+//! collection the archive bucket only ever grows. Each pass keeps the newest
+//! verified data versions of every `StoreKey`, along with anything newer that
+//! may still be uploading, and deletes the objects of all older versions. The
+//! objects of a `StoreKey` that no archive still needs are deleted entirely.
+//! Each `StoreKey` is collected in its own savepoint, so a failure only rolls
+//! back the changes for that key. Objects are only deleted once the database
+//! changes of a pass are committed, so a failed pass never leaves archive
+//! versions without their data. Every replica runs the pass, so it holds an
+//! advisory lock and is skipped while another replica is collecting.
+//!
+//! Each pass also records the bytes stored under every `StoreKey`, which is
+//! the source of the storage accounting reported to admins.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::config::Context;
+use crate::database::{self, AdvisoryLock, Conn, Database};
+use crate::grpc::Status;
+use crate::model::image::{Archive, ArchiveStorage, ArchiveVersion, NewArchiveStorage};
+
+use super::{MANIFEST_HEADER, Store, StoreKey};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// GC archive error: {0}
+    Archive(#[from] crate::model::image::archive::Error),
+    /// GC archive storage error: {0}
+    ArchiveStorage(#[from] crate::model::image::archive_storage::Error),
+    /// GC archive version error: {0}
+    ArchiveVersion(#[from] crate::model::image::archive_version::Error),
+    /// GC database error: {0}
+    Database(#[from] crate::database::Error),
+    /// GC diesel failure: {0}
+    Diesel(#[from] diesel::result::Error),
+    /// Garbage collection is already running.
+    Running,
+    /// GC store error: {0}
+    Store(#[from] super::Error),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        use Error::*;
+        match err {
+            Archive(err) => err.into(),
+            ArchiveStorage(err) => err.into(),
+            ArchiveVersion(err) => err.into(),
+            Database(err) => err.into(),
+            Diesel(_) => Status::internal("Internal error."),
+            Running => Status::failed_precondition("Garbage collection is already running."),
+            Store(err) => err.into(),
+        }
+    }
+}
+
+/// The outcome of a single garbage collection pass.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Whether objects were left in place.
+    pub dry_run: bool,
+    /// The number of store keys that were checked.
+    pub store_keys: usize,
+    /// Data versions that were (or would be) deleted.
+    pub deleted_versions: Vec<(StoreKey, u64)>,
+    /// Unreferenced store keys that were (or would be) deleted entirely.
+    pub deleted_store_keys: Vec<StoreKey>,
+    /// The number of objects that were (or would be) deleted.
+    pub deleted_objects: usize,
+    /// The bytes that were (or would be) freed.
+    pub deleted_bytes: u64,
+    /// The object keys to delete once the pass is committed.
+    object_keys: Vec<String>,
+}
+
+impl Report {
+    /// Delete the objects of a pass after its database changes are committed.
+    pub async fn delete_objects(&mut self, store: &Store) -> Result<(), Error> {
+        let keys = std::mem::take(&mut self.object_keys);
+        if !keys.is_empty() {
+            store.delete_objects(&keys).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `collect` forever at the configured interval.
+///
+/// Does nothing if the GC interval is not configured.
+pub async fn run(ctx: Arc<Context>) {
+    let config = &ctx.config.store.gc;
+    let Some(gc_interval) = config.interval else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(*gc_interval);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match ctx.conn().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("Failed to collect archive garbage: {err}");
+                continue;
+            }
+        };
+
+        let result = database::exclusive(AdvisoryLock::StoreGc, &mut conn, |conn| {
+            collect(&ctx.store, config.keep_versions, config.dry_run, conn).scope_boxed()
+        })
+        .await;
+        let result = match result {
+            Ok(Some(mut report)) => report
+                .delete_objects(&ctx.store)
+                .await
+                .map(|()| Some(report)),
+            Ok(None) => Ok(None),
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(None) => (),
+            Ok(Some(report)) => info!(
+                "Collected archive garbage for {} store keys (dry run: {}): {} versions and {} store keys with {} objects and {} bytes.",
+                report.store_keys,
+                report.dry_run,
+                report.deleted_versions.len(),
+                report.deleted_store_keys.len(),
+                report.deleted_objects,
+                report.deleted_bytes,
+            ),
+            Err(err) => error!("Failed to collect archive garbage: {err}"),
+        }
+    }
+}
+
+/// Delete old data versions and unreferenced store keys from the store.
+///
+/// Keeps the newest `keep_versions` verified data versions of each `StoreKey`
+/// (at least one). If `dry_run` is set then nothing is deleted, but the
+/// report describes what would have been.
+///
+/// Each `StoreKey` runs in a savepoint of `conn`, and its objects are only
+/// added to the report once that savepoint is released. Objects are left in
+/// the store until `Report::delete_objects` is called after `conn` commits.
+pub async fn collect(
+    store: &Store,
+    keep_versions: usize,
+    dry_run: bool,
+    conn: &mut Conn<'_>,
+) -> Result<Report, Error> {
+    let (live, unreferenced) = Archive::store_keys(conn).await?;
+
+    let mut report = Report {
+        dry_run,
+        ..Default::default()
+    };
+
+    for store_key in &live {
+        let result = conn
+            .transaction(|conn| {
+                collect_versions(store, store_key, keep_versions, dry_run, conn).scope_boxed()
+            })
+            .await;
+        match result {
+            Ok(plan) => {
+                report.store_keys += 1;
+                report.deleted_objects += plan.deleted_keys.len();
+                report.deleted_bytes += plan.deleted_bytes;
+                if !dry_run {
+                    report.object_keys.extend(plan.deleted_keys);
+                }
+                report.deleted_versions.extend(
+                    plan.deleted_versions
+                        .into_iter()
+                        .map(|version| (store_key.clone(), version)),
+                );
+            }
+            // one misbehaving store key should not block the others
+            Err(err) => error!("Failed to collect garbage for `{store_key}`: {err}"),
+        }
+    }
+
+    for store_key in unreferenced {
+        let result = conn
+            .transaction(|conn| collect_store_key(store, &store_key, dry_run, conn).scope_boxed())
+            .await;
+        match result {
+            Ok((keys, bytes)) => {
+                report.store_keys += 1;
+                report.deleted_objects += keys.len();
+                report.deleted_bytes += bytes;
+                if !dry_run {
+                    report.object_keys.extend(keys);
+                }
+                report.deleted_store_keys.push(store_key);
+            }
+            Err(err) => error!("Failed to collect garbage for `{store_key}`: {err}"),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Delete the data versions of a `StoreKey` older than those it keeps.
+async fn collect_versions(
+    store: &Store,
+    store_key: &StoreKey,
+    keep_versions: usize,
+    dry_run: bool,
+    conn: &mut Conn<'_>,
+) -> Result<Plan, Error> {
+    let objects = store.stored_objects(store_key).await?;
+    let incomplete = ArchiveVersion::incomplete(store_key, conn).await?;
+    let plan = Plan::new(store_key, objects, &incomplete, keep_versions);
+
+    let (data_versions, stored_bytes) = if dry_run {
+        (
+            plan.kept_versions + plan.deleted_versions.len(),
+            plan.kept_bytes + plan.deleted_bytes,
+        )
+    } else {
+        if !plan.deleted_versions.is_empty() {
+            ArchiveVersion::delete(store_key, Some(&plan.deleted_versions), conn).await?;
+        }
+        (plan.kept_versions, plan.kept_bytes)
+    };
+
+    NewArchiveStorage::new(store_key.clone(), data_versions, stored_bytes)?
+        .upsert(conn)
+        .await?;
+
+    Ok(plan)
+}
+
+/// Delete the records of an unreferenced `StoreKey`.
+///
+/// Returns the keys and bytes of its objects.
+async fn collect_store_key(
+    store: &Store,
+    store_key: &StoreKey,
+    dry_run: bool,
+    conn: &mut Conn<'_>,
+) -> Result<(Vec<String>, u64), Error> {
+    let objects = store.stored_objects(store_key).await?;
+    let bytes = objects.iter().map(|(_, size)| size).sum();
+    let keys: Vec<_> = objects.into_iter().map(|(key, _)| key).collect();
+
+    if !dry_run {
+        ArchiveVersion::delete(store_key, None, conn).await?;
+        ArchiveStorage::delete(store_key, conn).await?;
+    }
+
+    Ok((keys, bytes))
+}
+
+/// The objects of a `StoreKey` to keep and to delete.
+#[derive(Debug, Default)]
+struct Plan {
+    kept_versions: usize,
+    kept_bytes: u64,
+    deleted_versions: Vec<u64>,
+    deleted_keys: Vec<String>,
+    deleted_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct VersionObjects {
+    keys: Vec<String>,
+    bytes: u64,
+    has_manifest: bool,
+}
+
+impl Plan {
+    /// Plan which objects to delete from `objects` under `store_key`.
+    ///
+    /// A data version is verified if it has a manifest and did not fail
+    /// verification. Every version older than the oldest of the newest
+    /// `keep_versions` verified versions is deleted. Nothing is deleted if
+    /// there are not yet enough verified versions.
+    fn new(
+        store_key: &StoreKey,
+        objects: Vec<(String, u64)>,
+        incomplete: &HashSet<u64>,
+        keep_versions: usize,
+    ) -> Self {
+        let prefix = format!("{store_key}/");
+        let mut plan = Plan::default();
+        let mut versions: BTreeMap<u64, VersionObjects> = BTreeMap::new();
+
+        for (key, size) in objects {
+            let version = key
+                .strip_prefix(&prefix)
+                .and_then(|path| path.split_once('/'))
+                .and_then(|(version, _)| version.parse().ok());
+            let Some(version) = version else {
+                // leave anything outside a data version alone
+                plan.kept_bytes += size;
+                continue;
+            };
+
+            let objects = versions.entry(version).or_default();
+            objects.has_manifest |= key.ends_with(MANIFEST_HEADER);
+            objects.bytes += size;
+            objects.keys.push(key);
+        }
+
+        let oldest_kept = versions
+            .iter()
+            .rev()
+            .filter(|(version, objects)| objects.has_manifest && !incomplete.contains(version))
+            .map(|(version, _)| *version)
+            .nth(keep_versions.max(1) - 1);
+
+        for (version, objects) in versions {
+            if oldest_kept.is_some_and(|oldest| version < oldest) {
+                plan.deleted_versions.push(version);
+                plan.deleted_keys.extend(objects.keys);
+                plan.deleted_bytes += objects.bytes;
+            } else {
+                plan.kept_versions += 1;
+                plan.kept_bytes += objects.bytes;
+            }
+        }
+
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel_async::RunQueryDsl;
+
+    use crate::config::store::BucketConfig;
+    use crate::database::seed::{STORE_KEY_1, STORE_KEY_2};
+    use crate::store::local::LocalDir;
+
+    use super::*;
+
+    fn objects(versions: &[u64]) -> Vec<(String, u64)> {
+        versions
+            .iter()
+            .flat_map(|version| {
+                [
+                    (format!("store-key/{version}/data.part_0"), 100),
+                    (format!("store-key/{version}/{MANIFEST_HEADER}"), 1),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plan_keeps_newest_verified_versions() {
+        let store_key = StoreKey::new("store-key".into()).unwrap();
+        let mut objects = objects(&[1, 2, 3, 4]);
+        // an upload in progress has no manifest yet
+        objects.push(("store-key/5/data.part_0".into(), 100));
+        objects.push(("store-key/5/.lock".into(), 0));
+
+        let incomplete = HashSet::from([4]);
+        let plan = Plan::new(&store_key, objects.clone(), &incomplete, 2);
+        assert_eq!(plan.deleted_versions, vec![1]);
+        assert_eq!(plan.deleted_keys.len(), 2);
+        assert_eq!(plan.deleted_bytes, 101);
+        assert_eq!(plan.kept_versions, 4);
+        assert_eq!(plan.kept_bytes, 403);
+
+        // not enough verified versions to delete anything
+        let plan = Plan::new(&store_key, objects.clone(), &incomplete, 4);
+        assert!(plan.deleted_versions.is_empty());
+        assert_eq!(plan.kept_versions, 5);
+
+        // always keep at least one verified version
+        let plan = Plan::new(&store_key, objects, &incomplete, 0);
+        assert_eq!(plan.deleted_versions, vec![1, 2]);
+        assert_eq!(plan.kept_versions, 3);
+    }
+
+    #[tokio::test]
+    async fn collect_rolls_back_one_store_key_at_a_time() {
+        let (_ctx, db) = Context::with_mocked().await.unwrap();
+        let dir = std::env::temp_dir().join(format!("gc-{}", uuid::Uuid::new_v4()));
+        let url = "http://localhost:8080/".parse().unwrap();
+        let store = Store {
+            backend: Box::new(LocalDir::new(dir, url, "secret")),
+            bucket: BucketConfig {
+                archive: "archive".into(),
+                bundle: "bundle".into(),
+            },
+            prefix: String::new(),
+            expiration: std::time::Duration::from_secs(60),
+        };
+        for store_key in [STORE_KEY_1, STORE_KEY_2] {
+            for (key, size) in objects(&[1, 2]) {
+                let key = key.replacen("store-key", store_key, 1);
+                let data = vec![0; usize::try_from(size).unwrap()];
+                store
+                    .backend
+                    .write_key("archive", &key, data)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // recording the storage of the second store key fails
+        let mut conn = db.conn().await;
+        diesel::sql_query(
+            "create function fail_gc() returns trigger as $$
+             begin raise exception 'gc failure'; end; $$ language plpgsql",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        diesel::sql_query(format!(
+            "create trigger fail_gc before insert on archive_storage for each row
+             when (new.store_key = '{STORE_KEY_2}') execute function fail_gc()"
+        ))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        let mut report = conn
+            .transaction(|conn| collect(&store, 1, false, conn).scope_boxed())
+            .await
+            .unwrap();
+        let store_key_1 = StoreKey::new(STORE_KEY_1.into()).unwrap();
+        assert_eq!(report.store_keys, 1);
+        assert_eq!(report.deleted_versions, vec![(store_key_1.clone(), 1)]);
+        assert_eq!(report.object_keys.len(), 2);
+        assert!(
+            report
+                .object_keys
+                .iter()
+                .all(|key| key.starts_with("store-1/1/"))
+        );
+
+        let storage = ArchiveStorage::all(&mut conn).await.unwrap();
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage[0].store_key, store_key_1);
+
+        report.delete_objects(&store).await.unwrap();
+        let key_1 = store.stored_objects(&store_key_1).await.unwrap();
+        assert_eq!(key_1.len(), 2);
+        let store_key_2 = StoreKey::new(STORE_KEY_2.into()).unwrap();
+        let key_2 = store.stored_objects(&store_key_2).await.unwrap();
+        assert_eq!(key_2.len(), 4);
+    }
+}
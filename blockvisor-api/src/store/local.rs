@@ -0,0 +1,471 @@
+//! A store backend that keeps objects in a local directory.
+//!
+//! This is for development environments and test rigs without an S3 service.
+//! Each bucket is a subdirectory of `dir`. Download and upload URLs point back
+//! at the `/v1/store` routes of this API and carry an expiry time and a
+//! signature, so clients can use them just like S3 presigned URLs.
+
+use std::ffi::OsString;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::Utc;
+use displaydoc::Display;
+use futures::{Stream, StreamExt, stream};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
+
+use super::backend::{self, Backend};
+
+/// The URL path segments that local store routes are nested under.
+pub const URL_PATH: [&str; 2] = ["v1", "store"];
+
+const SIGNING_CONTEXT: &str = "blockvisor-api local store url signature";
+const PARTIAL_SUFFIX: &str = ".partial";
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Base URL `{0}` cannot have path segments.
+    BaseUrl(Url),
+    /// Failed to create directory `{0:?}`: {1}
+    CreateDir(PathBuf, io::Error),
+    /// Failed to delete `{0:?}`: {1}
+    Delete(PathBuf, io::Error),
+    /// Signed URL expired.
+    Expired,
+    /// URL expiry is out of range.
+    ExpiresRange,
+    /// Invalid path segment: {0}
+    InvalidPath(String),
+    /// Invalid URL signature.
+    InvalidSignature,
+    /// Failed to list `{0:?}`: {1}
+    List(PathBuf, io::Error),
+    /// No file at `{0:?}`.
+    NotFound(PathBuf),
+    /// Failed to read `{0:?}`: {1}
+    Read(PathBuf, io::Error),
+    /// Failed to write `{0:?}`: {1}
+    Write(PathBuf, io::Error),
+}
+
+/// What a signed URL allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Download,
+    Upload,
+}
+
+/// The query parameters of a signed URL.
+#[derive(Debug, Deserialize)]
+pub struct Signature {
+    pub expires: i64,
+    pub signature: String,
+}
+
+pub struct LocalDir {
+    dir: PathBuf,
+    url: Url,
+    key: [u8; 32],
+}
+
+impl LocalDir {
+    /// Store objects under `dir`, with signed URLs relative to `url`.
+    pub fn new(dir: PathBuf, url: Url, secret: &str) -> Self {
+        let key = blake3::derive_key(SIGNING_CONTEXT, secret.as_bytes());
+        LocalDir { dir, url, key }
+    }
+
+    /// The file path of `key` in `bucket`.
+    ///
+    /// Rejects any segment that could escape the store directory.
+    pub fn path(&self, bucket: &str, key: &str) -> Result<PathBuf, Error> {
+        let mut path = self.dir.join(segment(bucket)?);
+        for part in key.split('/') {
+            path.push(segment(part)?);
+        }
+        Ok(path)
+    }
+
+    /// Create a URL that grants `access` to `key` until `expires` has passed.
+    pub fn signed_url(
+        &self,
+        access: Access,
+        bucket: &str,
+        key: &str,
+        expires: Duration,
+    ) -> Result<Url, Error> {
+        let key = key.to_lowercase();
+        self.path(bucket, &key)?;
+
+        let expires = i64::try_from(expires.as_secs())
+            .ok()
+            .and_then(|secs| Utc::now().timestamp().checked_add(secs))
+            .ok_or(Error::ExpiresRange)?;
+        let signature = self.mac(access, bucket, &key, expires);
+
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|()| Error::BaseUrl(self.url.clone()))?
+            .pop_if_empty()
+            .extend(URL_PATH)
+            .push(bucket)
+            .extend(key.split('/'));
+        url.query_pairs_mut()
+            .append_pair("expires", &expires.to_string())
+            .append_pair("signature", &signature.to_hex());
+
+        Ok(url)
+    }
+
+    /// Check the signature of a URL and return the file path it grants.
+    pub fn verify(
+        &self,
+        access: Access,
+        bucket: &str,
+        key: &str,
+        signature: &Signature,
+    ) -> Result<PathBuf, Error> {
+        if signature.expires < Utc::now().timestamp() {
+            return Err(Error::Expired);
+        }
+
+        let actual =
+            blake3::Hash::from_hex(&signature.signature).map_err(|_| Error::InvalidSignature)?;
+        // `blake3::Hash` equality is constant time
+        if actual != self.mac(access, bucket, key, signature.expires) {
+            return Err(Error::InvalidSignature);
+        }
+
+        self.path(bucket, key)
+    }
+
+    /// Open a file for download, returning its size and contents.
+    pub async fn read_stream(
+        &self,
+        path: &Path,
+    ) -> Result<(u64, impl Stream<Item = io::Result<Bytes>> + use<>), Error> {
+        let file = File::open(path).await.map_err(|err| match err.kind() {
+            ErrorKind::NotFound => Error::NotFound(path.into()),
+            _ => Error::Read(path.into(), err),
+        })?;
+        let metadata = file
+            .metadata()
+            .await
+            .map_err(|err| Error::Read(path.into(), err))?;
+
+        let contents = stream::try_unfold(file, |mut file| async move {
+            let mut buf = vec![0; READ_BUFFER_SIZE];
+            let len = file.read(&mut buf).await?;
+            if len == 0 {
+                return Ok(None);
+            }
+            buf.truncate(len);
+            Ok(Some((Bytes::from(buf), file)))
+        });
+
+        Ok((metadata.len(), contents))
+    }
+
+    /// Write an uploaded file.
+    ///
+    /// The upload is written beside `path` and only renamed into place once
+    /// complete, so a failed upload never leaves a truncated object behind.
+    pub async fn write_stream<S, E>(&self, path: &Path, mut contents: S) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        create_parent(path).await?;
+
+        let partial = partial_path(path);
+        let mut file = File::create(&partial)
+            .await
+            .map_err(|err| Error::Write(partial.clone(), err))?;
+
+        while let Some(bytes) = contents.next().await {
+            let bytes =
+                bytes.map_err(|err| Error::Write(partial.clone(), io::Error::other(err)))?;
+            file.write_all(&bytes)
+                .await
+                .map_err(|err| Error::Write(partial.clone(), err))?;
+        }
+
+        file.flush()
+            .await
+            .map_err(|err| Error::Write(partial.clone(), err))?;
+        fs::rename(&partial, path)
+            .await
+            .map_err(|err| Error::Write(path.into(), err))
+    }
+
+    fn mac(&self, access: Access, bucket: &str, key: &str, expires: i64) -> blake3::Hash {
+        let message = format!("{access:?}\n{bucket}\n{key}\n{expires}");
+        blake3::keyed_hash(&self.key, message.as_bytes())
+    }
+}
+
+#[tonic::async_trait]
+impl Backend for LocalDir {
+    async fn list(&self, bucket: &str, path: &str) -> Result<Vec<String>, backend::Error> {
+        let objects = self.list_sizes(bucket, path).await?;
+        Ok(objects.into_iter().map(|(key, _)| key).collect())
+    }
+
+    async fn list_sizes(
+        &self,
+        bucket: &str,
+        path: &str,
+    ) -> Result<Vec<(String, u64)>, backend::Error> {
+        let path = path.to_lowercase();
+        let root = self.dir.join(segment(bucket)?);
+
+        let mut objects = Vec::new();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::List(dir, err).into()),
+            };
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|err| Error::List(dir.clone(), err))?
+            {
+                let entry_path = entry.path();
+                let metadata = entry
+                    .metadata()
+                    .await
+                    .map_err(|err| Error::List(entry_path.clone(), err))?;
+                if metadata.is_dir() {
+                    dirs.push(entry_path);
+                    continue;
+                }
+
+                let Some(key) = relative_key(&root, &entry_path) else {
+                    continue;
+                };
+                if key.starts_with(&path) && !key.ends_with(PARTIAL_SUFFIX) {
+                    objects.push((key, metadata.len()));
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn read_key(&self, bucket: &str, key: &str) -> Result<Vec<u8>, backend::Error> {
+        let key = key.to_lowercase();
+        let path = self.path(bucket, &key)?;
+        fs::read(&path).await.map_err(|err| match err.kind() {
+            ErrorKind::NotFound => backend::Error::MissingKey(bucket.into(), key),
+            _ => Error::Read(path, err).into(),
+        })
+    }
+
+    async fn head_key(&self, bucket: &str, key: &str) -> Result<u64, backend::Error> {
+        let key = key.to_lowercase();
+        let path = self.path(bucket, &key)?;
+        match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Ok(metadata.len()),
+            Ok(_) => Err(backend::Error::MissingKey(bucket.into(), key)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Err(backend::Error::MissingKey(bucket.into(), key))
+            }
+            Err(err) => Err(Error::Read(path, err).into()),
+        }
+    }
+
+    async fn write_key(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+    ) -> Result<(), backend::Error> {
+        let path = self.path(bucket, &key.to_lowercase())?;
+        create_parent(&path).await?;
+        fs::write(&path, data)
+            .await
+            .map_err(|err| Error::Write(path, err).into())
+    }
+
+    async fn delete_keys(&self, bucket: &str, keys: &[String]) -> Result<(), backend::Error> {
+        for key in keys {
+            let path = self.path(bucket, &key.to_lowercase())?;
+            match fs::remove_file(&path).await {
+                Ok(()) => (),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => return Err(Error::Delete(path, err).into()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn download_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires: Duration,
+    ) -> Result<Url, backend::Error> {
+        self.signed_url(Access::Download, bucket, key, expires)
+            .map_err(Into::into)
+    }
+
+    async fn upload_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires: Duration,
+    ) -> Result<Url, backend::Error> {
+        self.signed_url(Access::Upload, bucket, key, expires)
+            .map_err(Into::into)
+    }
+
+    fn as_local(&self) -> Option<&LocalDir> {
+        Some(self)
+    }
+}
+
+/// Check that a path segment stays within its parent directory.
+fn segment(part: &str) -> Result<&str, Error> {
+    let mut components = Path::new(part).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !part.contains('\\') => Ok(part),
+        _ => Err(Error::InvalidPath(part.into())),
+    }
+}
+
+/// The store key of a file path under a bucket directory.
+fn relative_key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = OsString::from(path.as_os_str());
+    partial.push(PARTIAL_SUFFIX);
+    PathBuf::from(partial)
+}
+
+async fn create_parent(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|err| Error::CreateDir(parent.into(), err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_dir() -> LocalDir {
+        let dir = std::env::temp_dir().join(format!("store-{}", uuid::Uuid::new_v4()));
+        let url = "http://localhost:8080/".parse().unwrap();
+        LocalDir::new(dir, url, "secret")
+    }
+
+    #[test]
+    fn signed_urls_are_checked() {
+        let local = local_dir();
+        let expires = Duration::from_secs(60);
+        let url = local
+            .signed_url(Access::Download, "archive", "Key/1/data.part_0", expires)
+            .unwrap();
+        assert_eq!(url.path(), "/v1/store/archive/key/1/data.part_0");
+
+        let signature: Signature = serde_urlencoded::from_str(url.query().unwrap()).unwrap();
+        let key = "key/1/data.part_0";
+        assert!(
+            local
+                .verify(Access::Download, "archive", key, &signature)
+                .is_ok()
+        );
+        assert!(matches!(
+            local.verify(Access::Upload, "archive", key, &signature),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            local.verify(Access::Download, "archive", "key/2/data.part_0", &signature),
+            Err(Error::InvalidSignature)
+        ));
+
+        let expired = Signature {
+            expires: signature.expires - 120,
+            signature: signature.signature,
+        };
+        assert!(matches!(
+            local.verify(Access::Download, "archive", key, &expired),
+            Err(Error::Expired)
+        ));
+    }
+
+    #[test]
+    fn paths_stay_in_the_store_dir() {
+        let local = local_dir();
+        assert!(local.path("archive", "key/1/data.part_0").is_ok());
+        assert!(local.path("archive", "../key").is_err());
+        assert!(local.path("archive", "key//data").is_err());
+        assert!(local.path("..", "key").is_err());
+        assert!(local.path("archive", "/etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn objects_round_trip() {
+        let local = local_dir();
+        local
+            .write_key("archive", "key/1/manifest", b"manifest".to_vec())
+            .await
+            .unwrap();
+        local
+            .write_key("archive", "key/1/data.part_0", vec![0; 10])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            local.read_key("archive", "key/1/manifest").await.unwrap(),
+            b"manifest"
+        );
+        assert_eq!(
+            local
+                .head_key("archive", "key/1/data.part_0")
+                .await
+                .unwrap(),
+            10
+        );
+
+        let mut objects = local.list_sizes("archive", "key/").await.unwrap();
+        objects.sort();
+        assert_eq!(
+            objects,
+            vec![
+                ("key/1/data.part_0".to_string(), 10),
+                ("key/1/manifest".to_string(), 8)
+            ]
+        );
+
+        local
+            .delete_keys("archive", &["key/1/data.part_0".into(), "missing".into()])
+            .await
+            .unwrap();
+        assert!(matches!(
+            local.head_key("archive", "key/1/data.part_0").await,
+            Err(backend::Error::MissingKey(_, _))
+        ));
+
+        fs::remove_dir_all(&local.dir).await.unwrap();
+    }
+}
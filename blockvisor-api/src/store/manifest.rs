@@ -1,3 +1,4 @@
+use std::mem;
 use std::path::PathBuf;
 
 use displaydoc::Display;
@@ -17,20 +18,34 @@ pub enum Error {
     ChecksumSha256(Vec<u8>),
     /// Failed to parse chunk index: {0}
     ChunkIndex(std::num::TryFromIntError),
+    /// Chunk key `{0}` is not under the data version path.
+    ChunkKey(String),
+    /// Chunk at position {0} has index {1}.
+    ChunkOrder(usize, usize),
+    /// Chunk {0} has a size of zero.
+    ChunkSize(usize),
     /// Failed to parse ChunksLen: {0}
     ChunksLen(std::num::TryFromIntError),
+    /// Chunk {0} has an empty checksum.
+    EmptyChecksum(usize),
+    /// Manifest has no chunks.
+    EmptyManifest,
     /// Missing Checksum.
     MissingChecksum,
     /// Missing chunk index. This should not happen.
     MissingChunkIndex,
     /// Missing Compression type.
     MissingCompression,
+    /// Chunks use more than one checksum type.
+    MixedChecksums,
     /// Failed to parse ArchiveChunk URL: {0}
     ParseArchiveUrl(url::ParseError),
     /// Failed to parse upload URL: {0}
     ParseUploadUrl(url::ParseError),
     /// Failed to parse slot index: {0}
     SlotIndex(std::num::TryFromIntError),
+    /// Manifest total_size is {0} but chunk destinations add up to {1}.
+    TotalSize(u64, u64),
 }
 
 /// `DownloadManifest` is the legacy storage format.
@@ -43,6 +58,39 @@ pub struct DownloadManifest {
     pub chunks: Vec<ArchiveChunk>,
 }
 
+impl DownloadManifest {
+    /// Check that the manifest is internally consistent.
+    ///
+    /// Chunks must be in index order under `path`, share a single non-empty
+    /// checksum type, and have destinations that add up to `total_size`.
+    pub fn validate(&self, path: &str) -> Result<(), Error> {
+        let first = self.chunks.first().ok_or(Error::EmptyManifest)?;
+
+        let mut total_size = 0;
+        for (position, chunk) in self.chunks.iter().enumerate() {
+            if let Some(index) = chunk.index.filter(|index| *index != position) {
+                return Err(Error::ChunkOrder(position, index));
+            } else if !chunk.key.starts_with(path) {
+                return Err(Error::ChunkKey(chunk.key.clone()));
+            } else if chunk.size == 0 {
+                return Err(Error::ChunkSize(position));
+            } else if mem::discriminant(&chunk.checksum) != mem::discriminant(&first.checksum) {
+                return Err(Error::MixedChecksums);
+            } else if chunk.checksum.is_empty() {
+                return Err(Error::EmptyChecksum(position));
+            }
+
+            total_size += chunk.destinations.iter().map(|dest| dest.size).sum::<u64>();
+        }
+
+        if total_size == self.total_size {
+            Ok(())
+        } else {
+            Err(Error::TotalSize(self.total_size, total_size))
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ManifestHeader {
     pub total_size: u64,
@@ -156,6 +204,17 @@ pub enum Checksum {
     Blake3([u8; 32]),
 }
 
+impl Checksum {
+    /// Whether the checksum bytes are all zero.
+    pub fn is_empty(&self) -> bool {
+        let bytes: &[u8] = match self {
+            Checksum::Sha1(bytes) => bytes,
+            Checksum::Sha256(bytes) | Checksum::Blake3(bytes) => bytes,
+        };
+        bytes.iter().all(|byte| *byte == 0)
+    }
+}
+
 impl From<Checksum> for api::Checksum {
     fn from(checksum: Checksum) -> Self {
         api::Checksum {
@@ -288,4 +347,61 @@ mod tests {
 
         let _: ArchiveChunk = serde_json::from_str(json).unwrap();
     }
+
+    fn chunk(index: usize, checksum: Checksum, size: u64) -> ArchiveChunk {
+        ArchiveChunk {
+            index: Some(index),
+            key: format!("store-key/1/data.part_{index}"),
+            url: None,
+            checksum,
+            size: 10,
+            destinations: vec![ChunkTarget {
+                path: "data".into(),
+                position: 0,
+                size,
+            }],
+        }
+    }
+
+    #[test]
+    fn validate_manifest() {
+        let path = "store-key/1/";
+        let manifest = |chunks| DownloadManifest {
+            total_size: 300,
+            compression: None,
+            chunks,
+        };
+
+        let chunks = vec![
+            chunk(0, Checksum::Sha256([1; 32]), 100),
+            chunk(1, Checksum::Sha256([2; 32]), 200),
+        ];
+        manifest(chunks.clone()).validate(path).unwrap();
+
+        let result = manifest(vec![]).validate(path);
+        assert!(matches!(result, Err(Error::EmptyManifest)));
+
+        let result = manifest(chunks.clone()).validate("other-key/1/");
+        assert!(matches!(result, Err(Error::ChunkKey(_))));
+
+        let mut swapped = chunks.clone();
+        swapped.swap(0, 1);
+        let result = manifest(swapped).validate(path);
+        assert!(matches!(result, Err(Error::ChunkOrder(0, 1))));
+
+        let mut mixed = chunks.clone();
+        mixed[1].checksum = Checksum::Blake3([2; 32]);
+        let result = manifest(mixed).validate(path);
+        assert!(matches!(result, Err(Error::MixedChecksums)));
+
+        let mut empty = chunks.clone();
+        empty[1].checksum = Checksum::Sha256([0; 32]);
+        let result = manifest(empty).validate(path);
+        assert!(matches!(result, Err(Error::EmptyChecksum(1))));
+
+        let mut short = chunks;
+        short[1].destinations[0].size = 100;
+        let result = manifest(short).validate(path);
+        assert!(matches!(result, Err(Error::TotalSize(300, 200))));
+    }
 }
@@ -1,6 +1,14 @@
+pub mod backend;
+pub use backend::Backend;
+
 pub mod client;
 pub use client::Client;
 
+pub mod gc;
+
+pub mod local;
+pub use local::LocalDir;
+
 pub mod manifest;
 
 pub mod secret;
@@ -14,11 +22,12 @@ use aws_sdk_s3::config::{
 use derive_more::{Deref, Display, Into};
 use diesel_derive_newtype::DieselNewType;
 use displaydoc::Display as DisplayDoc;
+use futures::{StreamExt, TryStreamExt, stream};
 use thiserror::Error;
 use tracing::warn;
 use url::Url;
 
-use crate::config::store::{BucketConfig, Config};
+use crate::config::store::{BackendConfig, BucketConfig, Config, S3Config};
 use crate::grpc::{Status, api};
 use crate::util::LOWER_KEBAB_CASE;
 
@@ -29,12 +38,19 @@ pub const BUNDLE_FILE: &str = "bvd-bundle.tgz";
 pub const MANIFEST_BODY: &str = "manifest-body.json";
 pub const MANIFEST_HEADER: &str = "manifest-header.json";
 
+/// The number of chunk objects to check concurrently.
+const VERIFY_CONCURRENCY: usize = 16;
+
 #[derive(Debug, DisplayDoc, Error)]
 pub enum Error {
     /// Data Version {1} already reserved for `StoreKey` {0}
     AlreadyReserved(StoreKey, u64),
-    /// Store client error: {0}
-    Client(#[from] client::Error),
+    /// Store backend error: {0}
+    Client(#[from] backend::Error),
+    /// Failed to delete objects: {0}
+    DeleteObjects(backend::Error),
+    /// Failed to list objects for `StoreKey` {0}: {1}
+    ListObjects(StoreKey, backend::Error),
     /// Storage manifest error: {0}
     Manifest(#[from] manifest::Error),
     /// Missing chunk index: {0}
@@ -49,12 +65,14 @@ pub enum Error {
     ParseManifestBody(StoreKey, serde_json::Error),
     /// Failed to parse `ManifestHeader` for `StoreKey` {0}: {1}
     ParseManifestHeader(StoreKey, serde_json::Error),
+    /// Failed to check chunk `{0}`: {1}
+    ReadChunk(String, backend::Error),
     /// Failed to read `ManifestBody` for `StoreKey` {0}: {1}
-    ReadManifestBody(StoreKey, client::Error),
+    ReadManifestBody(StoreKey, backend::Error),
     /// Failed to read `ManifestHeader` for `StoreKey` {0}: {1}
-    ReadManifestHeader(StoreKey, client::Error),
+    ReadManifestHeader(StoreKey, backend::Error),
     /// Failed to reserve the next data version for `StoreKey` {0}: {1}
-    ReserveNextVersion(StoreKey, client::Error),
+    ReserveNextVersion(StoreKey, backend::Error),
     /// Failed to serialize ManifestBody: {0}
     SerializeBody(serde_json::Error),
     /// Failed to serialize ManifestHeader: {0}
@@ -72,13 +90,16 @@ impl From<Error> for Status {
             AlreadyReserved(_, data_version) => {
                 Status::already_exists(format!("Data version: {data_version}"))
             }
-            Client(client::Error::MissingKey(_, _)) | NoDataVersion => {
+            Client(backend::Error::MissingKey(_, _)) | NoDataVersion => {
                 Status::not_found("Store not found.")
             }
             Client(_)
+            | DeleteObjects(_)
+            | ListObjects(_, _)
             | Manifest(_)
             | ParseManifestHeader(_, _)
             | ParseManifestBody(_, _)
+            | ReadChunk(_, _)
             | ReadManifestHeader(_, _)
             | ReadManifestBody(_, _)
             | ReserveNextVersion(_, _)
@@ -93,7 +114,7 @@ impl From<Error> for Status {
     }
 }
 
-#[derive(Clone, Debug, Display, PartialEq, Eq, DieselNewType, Deref, Into)]
+#[derive(Clone, Debug, Display, Hash, PartialEq, Eq, DieselNewType, Deref, Into)]
 pub struct StoreKey(String);
 
 impl StoreKey {
@@ -109,7 +130,7 @@ impl StoreKey {
 }
 
 pub struct Store {
-    pub client: Client,
+    pub backend: Box<dyn Backend>,
     pub bucket: BucketConfig,
     pub prefix: String,
     pub expiration: Duration,
@@ -117,6 +138,24 @@ pub struct Store {
 
 impl Store {
     pub fn new(config: &Config) -> Self {
+        let backend: Box<dyn Backend> = match config.backend {
+            BackendConfig::S3(ref s3) => Box::new(Self::s3_client(s3)),
+            BackendConfig::Local(ref local) => Box::new(LocalDir::new(
+                local.dir.clone(),
+                local.url.clone(),
+                &local.secret,
+            )),
+        };
+
+        Store {
+            backend,
+            bucket: config.bucket.clone(),
+            prefix: config.dir_chains_prefix.clone(),
+            expiration: *config.presigned_url_expiration,
+        }
+    }
+
+    fn s3_client(config: &S3Config) -> Client {
         let credentials = Credentials::new(&*config.key_id, &*config.key, None, None, CREDENTIALS);
         let s3_config = aws_sdk_s3::Config::builder()
             .endpoint_url(config.store_url.to_string())
@@ -124,18 +163,11 @@ impl Store {
             .credentials_provider(credentials)
             .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
             .response_checksum_validation(ResponseChecksumValidation::WhenRequired);
-        let client = Client::new(aws_sdk_s3::Client::from_conf(s3_config.build()));
-
-        Store {
-            client,
-            bucket: config.bucket.clone(),
-            prefix: config.dir_chains_prefix.clone(),
-            expiration: *config.presigned_url_expiration,
-        }
+        Client::new(aws_sdk_s3::Client::from_conf(s3_config.build()))
     }
 
     pub async fn list_bundles(&self) -> Result<Vec<api::BundleIdentifier>, Error> {
-        let keys = self.client.list(&self.bucket.bundle, "").await?;
+        let keys = self.backend.list(&self.bucket.bundle, "").await?;
         Ok(keys
             .iter()
             .filter_map(api::BundleIdentifier::maybe_from_key)
@@ -144,12 +176,20 @@ impl Store {
 
     pub async fn download_bundle(&self, version: &str) -> Result<Url, Error> {
         let key = format!("{version}/{BUNDLE_FILE}");
-        self.client
+        self.backend
             .download_url(&self.bucket.bundle, &key, self.expiration)
             .await
             .map_err(Into::into)
     }
 
+    pub async fn upload_bundle(&self, version: &str) -> Result<Url, Error> {
+        let key = format!("{version}/{BUNDLE_FILE}");
+        self.backend
+            .upload_url(&self.bucket.bundle, &key, self.expiration)
+            .await
+            .map_err(Into::into)
+    }
+
     /// Fetch and parse a download manifest header.
     ///
     /// If `data_version` is None then it uses the latest data version.
@@ -161,17 +201,17 @@ impl Store {
         let data_version = if let Some(version) = data_version {
             version
         } else {
-            let mut versions = self.data_versions(store_key).await?;
-            versions.pop().ok_or(Error::NoDataVersion)?
+            let versions = self.data_versions(store_key).await?;
+            versions.first().copied().ok_or(Error::NoDataVersion)?
         };
 
         let key = format!("{store_key}/{data_version}/{MANIFEST_HEADER}");
-        match self.client.read_key(&self.bucket.archive, &key).await {
+        match self.backend.read_key(&self.bucket.archive, &key).await {
             Ok(bytes) => match serde_json::from_slice(&bytes) {
                 Ok(header) => Ok((header, data_version)),
                 Err(err) => Err(Error::ParseManifestHeader(store_key.clone(), err)),
             },
-            Err(client::Error::MissingKey(_, _)) => {
+            Err(backend::Error::MissingKey(_, _)) => {
                 Err(Error::MissingManifestHeader(store_key.clone()))
             }
             Err(err) => Err(Error::ReadManifestHeader(store_key.clone(), err)),
@@ -189,17 +229,17 @@ impl Store {
         let data_version = if let Some(version) = data_version {
             version
         } else {
-            let mut versions = self.data_versions(store_key).await?;
-            versions.pop().ok_or(Error::NoDataVersion)?
+            let versions = self.data_versions(store_key).await?;
+            versions.first().copied().ok_or(Error::NoDataVersion)?
         };
 
         let key = format!("{store_key}/{data_version}/{MANIFEST_BODY}");
-        match self.client.read_key(&self.bucket.archive, &key).await {
+        match self.backend.read_key(&self.bucket.archive, &key).await {
             Ok(bytes) => match serde_json::from_slice(&bytes) {
                 Ok(body) => Ok((body, data_version)),
                 Err(err) => Err(Error::ParseManifestBody(store_key.clone(), err)),
             },
-            Err(client::Error::MissingKey(_, _)) => {
+            Err(backend::Error::MissingKey(_, _)) => {
                 Err(Error::MissingManifestBody(store_key.clone()))
             }
             Err(err) => Err(Error::ReadManifestBody(store_key.clone(), err)),
@@ -227,7 +267,7 @@ impl Store {
                 .clone();
             chunk.index = Some(index);
             chunk.url = self
-                .client
+                .backend
                 .download_url(&self.bucket.archive, &chunk.key, expires)
                 .await
                 .map(Some)?;
@@ -246,19 +286,56 @@ impl Store {
         let header_key = format!("{store_key}/{data_version}/{MANIFEST_HEADER}");
         let header: ManifestHeader = (&manifest).try_into()?;
         let header_data = serde_json::to_vec(&header).map_err(Error::SerializeHeader)?;
-        self.client
+        self.backend
             .write_key(&self.bucket.archive, &header_key, header_data)
             .await?;
 
         let body_key = format!("{store_key}/{data_version}/{MANIFEST_BODY}");
         let body: ManifestBody = manifest.into();
         let body_data = serde_json::to_vec(&body).map_err(Error::SerializeBody)?;
-        self.client
+        self.backend
             .write_key(&self.bucket.archive, &body_key, body_data)
             .await
             .map_err(Into::into)
     }
 
+    /// Check that every chunk of a manifest exists in the archive bucket with
+    /// the expected size.
+    pub async fn verify_manifest(
+        &self,
+        manifest: &DownloadManifest,
+    ) -> Result<Verification, Error> {
+        let sizes: Vec<_> = stream::iter(&manifest.chunks)
+            .map(|chunk| async move {
+                match self
+                    .backend
+                    .head_key(&self.bucket.archive, &chunk.key)
+                    .await
+                {
+                    Ok(size) => Ok((chunk, Some(size))),
+                    Err(backend::Error::MissingKey(_, _)) => Ok((chunk, None)),
+                    Err(err) => Err(Error::ReadChunk(chunk.key.clone(), err)),
+                }
+            })
+            .buffered(VERIFY_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let mut verification = Verification::default();
+        for (chunk, size) in sizes {
+            match size {
+                Some(size) if size == chunk.size => verification.stored_bytes += size,
+                Some(size) => {
+                    verification.stored_bytes += size;
+                    verification.mismatched.push(chunk.key.clone());
+                }
+                None => verification.missing.push(chunk.key.clone()),
+            }
+        }
+
+        Ok(verification)
+    }
+
     pub async fn upload_slots(
         &self,
         store_key: &StoreKey,
@@ -276,7 +353,7 @@ impl Store {
         for &index in slot_indexes {
             let key = format!("{store_key}/{data_version}/data.part_{index}");
             let url = self
-                .client
+                .backend
                 .upload_url(&self.bucket.archive, &key, expires)
                 .await?;
             slots.push(UploadSlot { index, key, url });
@@ -286,9 +363,9 @@ impl Store {
     }
 
     /// Return a descending order list of data versions for a `StoreKey`.
-    async fn data_versions(&self, store_key: &StoreKey) -> Result<Vec<u64>, Error> {
+    pub async fn data_versions(&self, store_key: &StoreKey) -> Result<Vec<u64>, Error> {
         let path = format!("{store_key}/");
-        let paths = self.client.list(&self.bucket.archive, &path).await?;
+        let paths = self.backend.list(&self.bucket.archive, &path).await?;
 
         let mut versions: Vec<u64> = paths
             .iter()
@@ -309,22 +386,71 @@ impl Store {
         Ok(versions)
     }
 
+    /// Return the key and size of every object stored under a `StoreKey`.
+    pub async fn stored_objects(&self, store_key: &StoreKey) -> Result<Vec<(String, u64)>, Error> {
+        let path = format!("{store_key}/");
+        self.backend
+            .list_sizes(&self.bucket.archive, &path)
+            .await
+            .map_err(|err| Error::ListObjects(store_key.clone(), err))
+    }
+
+    /// Delete objects from the archive bucket.
+    pub async fn delete_objects(&self, keys: &[String]) -> Result<(), Error> {
+        self.backend
+            .delete_keys(&self.bucket.archive, keys)
+            .await
+            .map_err(Error::DeleteObjects)
+    }
+
     /// Reserve the next data version.
     async fn reserve_next_version(&self, store_key: &StoreKey) -> Result<u64, Error> {
-        let mut versions = self.data_versions(store_key).await?;
-        let next_version = versions.pop().unwrap_or_default() + 1;
+        let versions = self.data_versions(store_key).await?;
+        let next_version = versions.first().copied().unwrap_or_default() + 1;
 
         let lock_key = format!("{store_key}/{next_version}/.lock");
-        match self.client.read_key(&self.bucket.archive, &lock_key).await {
+        match self.backend.read_key(&self.bucket.archive, &lock_key).await {
             Ok(_) => Err(Error::AlreadyReserved(store_key.clone(), next_version)),
-            Err(client::Error::MissingKey(_, _)) => Ok(()),
+            Err(backend::Error::MissingKey(_, _)) => Ok(()),
             Err(err) => Err(Error::ReserveNextVersion(store_key.clone(), err)),
         }?;
 
-        self.client
+        self.backend
             .write_key(&self.bucket.archive, &lock_key, Vec::new())
             .await
             .map(|()| next_version)
             .map_err(|err| Error::ReserveNextVersion(store_key.clone(), err))
     }
 }
+
+/// The result of checking manifest chunks against the archive bucket.
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// Total bytes of the chunk objects found.
+    pub stored_bytes: u64,
+    /// Keys of chunks with no object in the bucket.
+    pub missing: Vec<String>,
+    /// Keys of chunks where the object size differs from the manifest.
+    pub mismatched: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+
+    /// A description of the first problem found, if any.
+    pub fn problem(&self) -> Option<String> {
+        if let Some(key) = self.missing.first() {
+            Some(format!(
+                "{} chunks missing, including `{key}`",
+                self.missing.len()
+            ))
+        } else {
+            self.mismatched.first().map(|key| {
+                let count = self.mismatched.len();
+                format!("{count} chunks have the wrong size, including `{key}`")
+            })
+        }
+    }
+}
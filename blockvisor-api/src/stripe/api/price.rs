@@ -53,6 +53,8 @@ pub struct Price {
     pub metadata: Option<super::Metadata>,
     /// A brief description of the price, hidden from customers.
     pub nickname: Option<String>,
+    /// The recurring components of a price such as `interval` and `usage_type`.
+    pub recurring: Option<Recurring>,
     /// The unit amount in cents (or local equivalent) to be charged, represented as a whole integer
     /// if possible.
     ///
@@ -65,6 +67,45 @@ pub struct Price {
     pub unit_amount_decimal: Option<String>,
 }
 
+impl Price {
+    /// Whether this price is billed from reported usage rather than a quantity.
+    pub fn is_metered(&self) -> bool {
+        self.recurring
+            .as_ref()
+            .is_some_and(|recurring| recurring.usage_type == Some(UsageType::Metered))
+    }
+
+    /// The SKU this price was created for, if any.
+    pub fn sku(&self) -> Option<&str> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("sku"))
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Recurring {
+    /// How usage records are combined for a metered price over a period. One of `sum`,
+    /// `last_during_period`, `last_ever` or `max`.
+    pub aggregate_usage: Option<String>,
+    /// The frequency at which a subscription is billed. One of `day`, `week`, `month` or `year`.
+    pub interval: Option<String>,
+    /// The number of intervals between subscription billings.
+    pub interval_count: Option<u64>,
+    /// Configures how the quantity per period should be determined.
+    pub usage_type: Option<UsageType>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageType {
+    /// Bills a quantity set when adding the price to a subscription.
+    Licensed,
+    /// Aggregates usage records reported for the subscription item.
+    Metered,
+}
+
 impl TryFrom<&Price> for common::BillingAmount {
     type Error = Error;
 
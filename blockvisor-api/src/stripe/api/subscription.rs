@@ -405,11 +405,27 @@ impl super::StripeEndpoint for GetSubscriptionItem<'_> {
 #[derive(Debug, serde::Serialize)]
 pub struct ListSubscriptionItems<'a> {
     subscription: &'a SubscriptionId,
+    limit: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starting_after: Option<&'a SubscriptionItemId>,
 }
 
 impl<'a> ListSubscriptionItems<'a> {
+    /// The largest page size that stripe allows.
+    const PAGE_LIMIT: u8 = 100;
+
     pub const fn new(id: &'a SubscriptionId) -> Self {
-        Self { subscription: id }
+        Self {
+            subscription: id,
+            limit: Self::PAGE_LIMIT,
+            starting_after: None,
+        }
+    }
+
+    /// Continue listing after the last item of the previous page.
+    pub const fn starting_after(mut self, item_id: Option<&'a SubscriptionItemId>) -> Self {
+        self.starting_after = item_id;
+        self
     }
 }
 
@@ -441,6 +457,7 @@ pub struct UpdateSubscriptionItem<'a> {
 pub enum QuantityModification {
     Increment { current_quantity: u64 },
     Decrement { current_quantity: u64 },
+    Set { quantity: u64 },
 }
 
 impl<'a> UpdateSubscriptionItem<'a> {
@@ -450,10 +467,11 @@ impl<'a> UpdateSubscriptionItem<'a> {
             quantity: match modification {
                 QuantityModification::Increment { current_quantity } => current_quantity + 1,
                 QuantityModification::Decrement { current_quantity } => current_quantity - 1,
+                QuantityModification::Set { quantity } => quantity,
             },
             proration_behavior: match modification {
                 QuantityModification::Increment { .. } => "always_invoice",
-                QuantityModification::Decrement { .. } => "none",
+                QuantityModification::Decrement { .. } | QuantityModification::Set { .. } => "none",
             },
         }
     }
@@ -497,3 +515,58 @@ impl super::StripeEndpoint for DeleteSubscriptionItem<'_> {
         format!("subscription_items/{}", self.item_id)
     }
 }
+
+/// A usage record reported against a metered subscription item.
+///
+/// For more details see <https://stripe.com/docs/api/usage_records/object>
+#[derive(Debug, serde::Deserialize)]
+pub struct UsageRecord {
+    /// Unique identifier for the object.
+    pub id: String,
+    /// The usage quantity for the specified date.
+    pub quantity: u64,
+    /// The ID of the subscription item this usage record contains data for.
+    pub subscription_item: SubscriptionItemId,
+    /// The timestamp when this usage occurred.
+    pub timestamp: super::Timestamp,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CreateUsageRecord<'a> {
+    #[serde(skip_serializing)]
+    item_id: &'a SubscriptionItemId,
+    quantity: u64,
+    timestamp: super::Timestamp,
+    action: &'static str,
+}
+
+impl<'a> CreateUsageRecord<'a> {
+    pub const fn new(
+        item_id: &'a SubscriptionItemId,
+        quantity: u64,
+        timestamp: super::Timestamp,
+    ) -> Self {
+        Self {
+            item_id,
+            quantity,
+            timestamp,
+            action: "set",
+        }
+    }
+}
+
+impl super::StripeEndpoint for CreateUsageRecord<'_> {
+    type Result = UsageRecord;
+
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("subscription_items/{}/usage_records", self.item_id)
+    }
+
+    fn body(&self) -> Option<&Self> {
+        Some(self)
+    }
+}
@@ -1,5 +1,7 @@
 pub mod api;
 mod client;
+pub mod reconcile;
+pub mod usage;
 
 use std::sync::Arc;
 
@@ -83,6 +85,12 @@ pub trait Payment {
         price_id: &price::PriceId,
     ) -> Result<Option<subscription::SubscriptionItem>, Error>;
 
+    /// List all items within a subscription.
+    async fn list_subscription_items(
+        &self,
+        subscription_id: &subscription::SubscriptionId,
+    ) -> Result<Vec<subscription::SubscriptionItem>, Error>;
+
     async fn update_subscription_item(
         &self,
         item_id: &subscription::SubscriptionItemId,
@@ -94,6 +102,16 @@ pub trait Payment {
         item_id: &subscription::SubscriptionItemId,
     ) -> Result<(), Error>;
 
+    /// Report usage for a metered subscription item.
+    ///
+    /// The quantity replaces any usage previously reported at the same timestamp.
+    async fn create_usage_record(
+        &self,
+        item_id: &subscription::SubscriptionItemId,
+        quantity: u64,
+        timestamp: api::Timestamp,
+    ) -> Result<subscription::UsageRecord, Error>;
+
     async fn get_price(&self, sku: &str) -> Result<price::Price, Error>;
 
     async fn get_address(
@@ -206,6 +224,8 @@ pub enum Error {
     CreateSubscription(client::Error),
     /// Failed to create stripe subscription item: {0}
     CreateSubscriptionItem(client::Error),
+    /// Failed to create stripe usage record: {0}
+    CreateUsageRecord(client::Error),
     /// Failed to delete address: {0}
     DeleteAddress(client::Error),
     /// Failed to delete stripe subscription item: {0}
@@ -224,6 +244,8 @@ pub enum Error {
     ItemWithoutSubscription,
     /// Failed to list stripe payment methods: {0}
     ListPaymentMethods(client::Error),
+    /// Failed to list subscription items: {0}
+    ListSubscriptionItems(client::Error),
     /// Failed to list stripe subscriptions: {0}
     ListSubscriptions(client::Error),
     /// No address found for the current customer.
@@ -429,6 +451,30 @@ impl Payment for Stripe {
             .find(|item| item.price.as_ref().map(|price| &price.id) == Some(price_id)))
     }
 
+    async fn list_subscription_items(
+        &self,
+        subscription_id: &subscription::SubscriptionId,
+    ) -> Result<Vec<subscription::SubscriptionItem>, Error> {
+        let mut items = Vec::new();
+        loop {
+            let last = items
+                .last()
+                .map(|item: &subscription::SubscriptionItem| item.id.clone());
+            let req = subscription::ListSubscriptionItems::new(subscription_id)
+                .starting_after(last.as_ref());
+            let page = self
+                .client
+                .request(&req)
+                .await
+                .map_err(Error::ListSubscriptionItems)?;
+
+            items.extend(page.data);
+            if !page.has_more {
+                return Ok(items);
+            }
+        }
+    }
+
     async fn update_subscription_item(
         &self,
         item_id: &subscription::SubscriptionItemId,
@@ -453,6 +499,19 @@ impl Payment for Stripe {
         Ok(())
     }
 
+    async fn create_usage_record(
+        &self,
+        item_id: &subscription::SubscriptionItemId,
+        quantity: u64,
+        timestamp: api::Timestamp,
+    ) -> Result<subscription::UsageRecord, Error> {
+        let req = subscription::CreateUsageRecord::new(item_id, quantity, timestamp);
+        self.client
+            .request(&req)
+            .await
+            .map_err(Error::CreateUsageRecord)
+    }
+
     async fn get_price(&self, sku: &str) -> Result<price::Price, Error> {
         let req = price::SearchPrice::new(sku);
         let mut prices = self
@@ -461,6 +520,8 @@ impl Payment for Stripe {
             .await
             .map_err(Error::SearchPrices)?
             .data;
+        // metered prices share the sku of the licensed price for the same node
+        prices.retain(|price| !price.is_metered());
         if let Some(price) = prices.pop() {
             if !prices.is_empty() {
                 tracing::warn!("More than one price returned for sku `{sku}`.");
@@ -637,6 +698,13 @@ pub mod tests {
                 .await
         }
 
+        async fn list_subscription_items(
+            &self,
+            subscription_id: &subscription::SubscriptionId,
+        ) -> Result<Vec<subscription::SubscriptionItem>, Error> {
+            self.stripe.list_subscription_items(subscription_id).await
+        }
+
         async fn update_subscription_item(
             &self,
             item_id: &subscription::SubscriptionItemId,
@@ -654,6 +722,17 @@ pub mod tests {
             self.stripe.delete_subscription_item(item_id).await
         }
 
+        async fn create_usage_record(
+            &self,
+            item_id: &subscription::SubscriptionItemId,
+            quantity: u64,
+            timestamp: api::Timestamp,
+        ) -> Result<subscription::UsageRecord, Error> {
+            self.stripe
+                .create_usage_record(item_id, quantity, timestamp)
+                .await
+        }
+
         async fn get_price(&self, sku: &str) -> Result<price::Price, Error> {
             self.stripe.get_price(sku).await
         }
@@ -684,7 +763,11 @@ pub mod tests {
 
     impl MockStripe {
         pub async fn new() -> Self {
-            let server = mock_server().await;
+            Self::with_server(mock_server().await)
+        }
+
+        /// Use a mock server with custom responses.
+        pub fn with_server(server: ServerGuard) -> Self {
             let server_url = format!("{}/v1/", server.url()).parse().unwrap();
             let config = Arc::new(mock_config(&server));
             let stripe = Stripe::new_mock(config, server_url).unwrap();
@@ -786,6 +869,9 @@ pub mod tests {
         Config {
             secret: Some("stripe_fake_secret".to_owned().into()),
             base_url: format!("{}/v1/", server.url()),
+            reconcile_interval: "1h".parse().unwrap(),
+            reconcile_dry_run: false,
+            usage_interval: None,
         }
     }
 
@@ -858,7 +944,7 @@ pub mod tests {
         }"#
     }
 
-    const fn mock_prices() -> &'static str {
+    pub const fn mock_prices() -> &'static str {
         r#"{
           "object": "search_result",
           "url": "/v1/prices/search",
@@ -1180,7 +1266,7 @@ pub mod tests {
         }"#
     }
 
-    const fn mock_subscriptions() -> &'static str {
+    pub const fn mock_subscriptions() -> &'static str {
         r#"{
           "object": "list",
           "url": "/v1/subscriptions",
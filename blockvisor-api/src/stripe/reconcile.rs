@@ -0,0 +1,270 @@
+//! Periodic reconciliation of stripe subscriptions against billable nodes.
+//!
+//! Each org has at most one subscription, with one subscription item per SKU
+//! and a quantity equal to the number of live nodes billed for that SKU. Node
+//! creation and deletion keep these in step, but a failed request can leave
+//! the two out of sync. This job finds and repairs such drift, or only reports
+//! it when `stripe.reconcile_dry_run` is set.
+//!
+//! Every replica runs the job, so each pass holds an advisory lock and is
+//! skipped while another replica is reconciling.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::auth::resource::{NodeId, OrgId};
+use crate::config::Context;
+use crate::database::{self, AdvisoryLock, Conn, Database};
+use crate::model::{Node, Org};
+
+use super::Subscription;
+use super::api::price::PriceId;
+use super::api::subscription::{QuantityModification, SubscriptionId, SubscriptionItemId};
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Reconcile database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Reconcile node error: {0}
+    Node(#[from] crate::model::node::Error),
+    /// Reconcile org error: {0}
+    Org(#[from] crate::model::org::Error),
+    /// Reconcile stripe error: {0}
+    Stripe(#[from] super::Error),
+    /// Reconcile transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+}
+
+/// A difference found between stripe and the database.
+#[derive(Debug, Display)]
+pub enum Discrepancy {
+    /// Org `{0}` has {1} billable nodes but no subscription.
+    NoSubscription(OrgId, usize),
+    /// Node `{0}` has no SKU for its region.
+    NoSku(NodeId),
+    /// Node `{0}` has no stripe price for SKU `{1}`.
+    NoPrice(NodeId, String),
+    /// Node `{0}` was billed against item `{1}` instead of `{2}`.
+    WrongItem(NodeId, SubscriptionItemId, SubscriptionItemId),
+    /// Subscription `{0}` has no item for price `{1}`.
+    MissingItem(SubscriptionId, String),
+    /// Item `{0}` has quantity {1} but {2} billable nodes.
+    Quantity(SubscriptionItemId, u64, u64),
+    /// Item `{0}` has no billable nodes.
+    StaleItem(SubscriptionItemId),
+    /// Subscription `{0}` has no billable nodes.
+    StaleSubscription(SubscriptionId),
+}
+
+/// The outcome of a single reconciliation pass.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub orgs: usize,
+    pub discrepancies: Vec<(OrgId, Discrepancy)>,
+}
+
+/// Run `reconcile` forever at the configured interval.
+///
+/// Does nothing if stripe is not configured.
+pub async fn run(ctx: Arc<Context>) {
+    let Some(stripe) = ctx.stripe.clone() else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(*ctx.config.stripe.reconcile_interval);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match ctx.conn().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("Failed to reconcile stripe subscriptions: {err}");
+                continue;
+            }
+        };
+
+        let dry_run = ctx.config.stripe.reconcile_dry_run;
+        let result = database::exclusive(AdvisoryLock::StripeReconcile, &mut conn, |_| {
+            reconcile(&ctx, &**stripe, dry_run).scope_boxed()
+        })
+        .await;
+        match result {
+            Ok(None) => (),
+            Ok(Some(report)) => {
+                for (org_id, discrepancy) in &report.discrepancies {
+                    warn!("Stripe drift for org {org_id}: {discrepancy}");
+                }
+                info!(
+                    "Reconciled stripe subscriptions for {} orgs with {} discrepancies.",
+                    report.orgs,
+                    report.discrepancies.len()
+                );
+            }
+            Err(err) => error!("Failed to reconcile stripe subscriptions: {err}"),
+        }
+    }
+}
+
+/// Reconcile the subscription of every org that is a stripe customer.
+///
+/// With `dry_run` the discrepancies are only reported, and neither stripe nor
+/// the database is changed.
+pub async fn reconcile(
+    ctx: &Context,
+    stripe: &(dyn Subscription + Send + Sync),
+    dry_run: bool,
+) -> Result<Report, Error> {
+    let mut conn = ctx.conn().await?;
+    let orgs = Org::with_customer(&mut conn).await?;
+
+    let mut report = Report::default();
+    for org in orgs {
+        match reconcile_org(&org, stripe, dry_run, &mut conn).await {
+            Ok(discrepancies) => {
+                report.orgs += 1;
+                report
+                    .discrepancies
+                    .extend(discrepancies.into_iter().map(|d| (org.id, d)));
+            }
+            // one misbehaving org should not block the others
+            Err(err) => error!("Failed to reconcile stripe for org {}: {err}", org.id),
+        }
+    }
+
+    Ok(report)
+}
+
+async fn reconcile_org(
+    org: &Org,
+    stripe: &(dyn Subscription + Send + Sync),
+    dry_run: bool,
+    conn: &mut Conn<'_>,
+) -> Result<Vec<Discrepancy>, Error> {
+    let mut discrepancies = Vec::new();
+    let Some(customer_id) = org.stripe_customer_id.as_ref() else {
+        return Ok(discrepancies);
+    };
+
+    let nodes = Node::billable(org.id, conn).await?;
+    let Some(subscription) = stripe.get_subscription_by_customer(customer_id).await? else {
+        if !nodes.is_empty() {
+            discrepancies.push(Discrepancy::NoSubscription(org.id, nodes.len()));
+        }
+        return Ok(discrepancies);
+    };
+
+    let items = stripe.list_subscription_items(&subscription.id).await?;
+    let mut by_price: HashMap<String, SubscriptionItemId> = HashMap::new();
+    // item id to (current quantity, billable node count)
+    let mut counts: HashMap<SubscriptionItemId, (u64, u64)> = HashMap::new();
+    // metered items are billed from usage records rather than node counts
+    let mut metered = false;
+    for item in items {
+        match item.price {
+            Some(price) if price.is_metered() => {
+                metered = true;
+                continue;
+            }
+            Some(price) => {
+                by_price.insert(price.id.0, item.id.clone());
+            }
+            None => (),
+        }
+        counts.insert(item.id, (item.quantity, 0));
+    }
+
+    let mut prices: HashMap<String, Option<PriceId>> = HashMap::new();
+    for (node, version, region) in nodes {
+        let current = node.stripe_item_id.filter(|id| counts.contains_key(id));
+
+        let Some(sku) = version.sku(&region) else {
+            discrepancies.push(Discrepancy::NoSku(node.id));
+            if let Some(id) = current {
+                counts.entry(id).and_modify(|(_, count)| *count += 1);
+            }
+            continue;
+        };
+
+        if !prices.contains_key(&sku) {
+            let price = match stripe.get_price(&sku).await {
+                Ok(price) => Some(price.id),
+                Err(super::Error::NoPrice(_)) => None,
+                Err(err) => return Err(err.into()),
+            };
+            prices.insert(sku.clone(), price);
+        }
+        let Some(price_id) = prices.get(&sku).and_then(Option::as_ref) else {
+            discrepancies.push(Discrepancy::NoPrice(node.id, sku));
+            if let Some(id) = current {
+                counts.entry(id).and_modify(|(_, count)| *count += 1);
+            }
+            continue;
+        };
+
+        let expected = if let Some(id) = by_price.get(&price_id.0) {
+            id.clone()
+        } else {
+            discrepancies.push(Discrepancy::MissingItem(
+                subscription.id.clone(),
+                price_id.0.clone(),
+            ));
+            if dry_run {
+                if let Some(id) = current {
+                    counts.entry(id).and_modify(|(_, count)| *count += 1);
+                }
+                continue;
+            }
+
+            // New items are created with a quantity of 1.
+            let item = stripe
+                .create_subscription_item(&subscription.id, price_id)
+                .await?;
+            by_price.insert(price_id.0.clone(), item.id.clone());
+            counts.insert(item.id.clone(), (1, 0));
+            item.id
+        };
+
+        if current.as_ref() != Some(&expected) {
+            if let Some(current) = current {
+                discrepancies.push(Discrepancy::WrongItem(node.id, current, expected.clone()));
+            }
+            if !dry_run {
+                Node::set_stripe_item_id(node.id, &expected, conn).await?;
+            }
+        }
+        counts.entry(expected).and_modify(|(_, count)| *count += 1);
+    }
+
+    // A subscription must contain at least one item, so cancel it instead.
+    if !metered && counts.values().all(|(_, count)| *count == 0) {
+        if !dry_run {
+            stripe.cancel_subscription(&subscription.id).await?;
+        }
+        discrepancies.push(Discrepancy::StaleSubscription(subscription.id));
+        return Ok(discrepancies);
+    }
+
+    for (item_id, (quantity, count)) in counts {
+        if count == 0 {
+            if !dry_run {
+                stripe.delete_subscription_item(&item_id).await?;
+            }
+            discrepancies.push(Discrepancy::StaleItem(item_id));
+        } else if quantity != count {
+            if !dry_run {
+                let modification = QuantityModification::Set { quantity: count };
+                stripe
+                    .update_subscription_item(&item_id, modification)
+                    .await?;
+            }
+            discrepancies.push(Discrepancy::Quantity(item_id, quantity, count));
+        }
+    }
+
+    Ok(discrepancies)
+}
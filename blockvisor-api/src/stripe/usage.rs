@@ -0,0 +1,165 @@
+//! Periodic reporting of node usage to metered stripe prices.
+//!
+//! Any subscription item whose price is metered and has a `sku` in its
+//! metadata is sent the node-hours recorded for that SKU since the start of the
+//! current billing period. Each report is a running total for the period, so
+//! the price must aggregate usage with `last_during_period`.
+//!
+//! Every replica runs the job, so each pass holds an advisory lock and is
+//! skipped while another replica is pushing usage.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use displaydoc::Display;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::config::Context;
+use crate::database::{self, AdvisoryLock, Conn, Database};
+use crate::model::Org;
+use crate::model::node::{NodeUsage, UsageSummary};
+
+use super::Subscription;
+
+const AGGREGATE_LAST_DURING_PERIOD: &str = "last_during_period";
+
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// Usage database error: {0}
+    Database(#[from] crate::database::Error),
+    /// Invalid billing period timestamp: {0}
+    InvalidPeriod(i64),
+    /// Usage node error: {0}
+    Node(#[from] crate::model::node::usage::Error),
+    /// Usage org error: {0}
+    Org(#[from] crate::model::org::Error),
+    /// Usage stripe error: {0}
+    Stripe(#[from] super::Error),
+    /// Usage transaction error: {0}
+    Transaction(#[from] diesel::result::Error),
+}
+
+/// Run `push` forever at the configured interval.
+///
+/// Does nothing if stripe or the usage interval are not configured.
+pub async fn run(ctx: Arc<Context>) {
+    let Some(stripe) = ctx.stripe.clone() else {
+        return;
+    };
+    let Some(usage_interval) = ctx.config.stripe.usage_interval else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(*usage_interval);
+    loop {
+        interval.tick().await;
+
+        let mut conn = match ctx.conn().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("Failed to push stripe usage records: {err}");
+                continue;
+            }
+        };
+
+        let result = database::exclusive(AdvisoryLock::StripeUsage, &mut conn, |_| {
+            push(&ctx, &**stripe).scope_boxed()
+        })
+        .await;
+        match result {
+            Ok(None) => (),
+            Ok(Some(records)) => info!("Pushed {records} stripe usage records."),
+            Err(err) => error!("Failed to push stripe usage records: {err}"),
+        }
+    }
+}
+
+/// Push the usage of every org that is a stripe customer.
+///
+/// Returns the number of usage records created.
+pub async fn push(
+    ctx: &Context,
+    stripe: &(dyn Subscription + Send + Sync),
+) -> Result<usize, Error> {
+    let mut conn = ctx.conn().await?;
+    let orgs = Org::with_customer(&mut conn).await?;
+
+    let mut records = 0;
+    for org in orgs {
+        match push_org(&org, stripe, &mut conn).await {
+            Ok(count) => records += count,
+            Err(err) => error!("Failed to push stripe usage for org {}: {err}", org.id),
+        }
+    }
+
+    Ok(records)
+}
+
+async fn push_org(
+    org: &Org,
+    stripe: &(dyn Subscription + Send + Sync),
+    conn: &mut Conn<'_>,
+) -> Result<usize, Error> {
+    let Some(customer_id) = org.stripe_customer_id.as_ref() else {
+        return Ok(0);
+    };
+    let Some(subscription) = stripe.get_subscription_by_customer(customer_id).await? else {
+        return Ok(0);
+    };
+
+    let mut metered = Vec::new();
+    for item in stripe.list_subscription_items(&subscription.id).await? {
+        let Some(price) = item.price.as_ref().filter(|price| price.is_metered()) else {
+            continue;
+        };
+        let Some(sku) = price.sku() else {
+            continue;
+        };
+
+        let aggregate = price
+            .recurring
+            .as_ref()
+            .and_then(|recurring| recurring.aggregate_usage.as_deref());
+        if aggregate != Some(AGGREGATE_LAST_DURING_PERIOD) {
+            warn!(
+                "Skipping metered price {} without last_during_period",
+                price.id.0
+            );
+            continue;
+        }
+
+        metered.push((item.id.clone(), sku.to_string()));
+    }
+    if metered.is_empty() {
+        return Ok(0);
+    }
+
+    let now = Utc::now();
+    let period_start = subscription.current_period_start.0;
+    let period_end = subscription.current_period_end.0;
+    let start =
+        DateTime::from_timestamp(period_start, 0).ok_or(Error::InvalidPeriod(period_start))?;
+    let end = DateTime::from_timestamp(period_end, 0)
+        .ok_or(Error::InvalidPeriod(period_end))?
+        .min(now);
+    if start >= end {
+        return Ok(0);
+    }
+
+    let usage = NodeUsage::for_org(org.id, start, end, conn).await?;
+    let summary = UsageSummary::new(&usage, start, end, now)?;
+
+    for (item_id, sku) in &metered {
+        let hours = summary
+            .iter()
+            .find(|usage| usage.sku.as_deref() == Some(sku))
+            .map_or(0, UsageSummary::node_hours);
+        stripe
+            .create_usage_record(item_id, hours, end.into())
+            .await?;
+    }
+
+    Ok(metered.len())
+}
@@ -65,9 +65,14 @@ async fn responds_ok_for_pending() {
         auto_upgrade: None,
         ip_address: Some("123.123.123.123".parse().unwrap()),
         ip_gateway: None,
+        ipv6_address: None,
+        ipv6_gateway: None,
         note: None,
         tags: None,
         cost: None,
+        failover_enabled: None,
+        restart_policy: None,
+        restart_attempts: None,
     };
     update.apply(node_id, &authz, &mut conn).await.unwrap();
     create_command(&test, node_id, CommandType::NodeCreate).await;
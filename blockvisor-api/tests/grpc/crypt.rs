@@ -220,5 +220,6 @@ pub fn create_node(
         new_values: vec![],
         add_rules: vec![],
         tags: None,
+        placement: None,
     }
 }
@@ -1,6 +1,16 @@
+use std::time::Duration;
+
 use blockvisor_api::auth::resource::HostId;
 use blockvisor_api::grpc::{api, common};
+use blockvisor_api::model::capacity_reservation::{CapacityReservation, NewCapacityReservation};
+use blockvisor_api::model::host::{ConnectionStatus, Host};
+use blockvisor_api::model::{Node, host_heartbeat};
+use blockvisor_api::mqtt::Message;
+use blockvisor_api::util::NanosUtc;
+use chrono::Utc;
+use diesel_async::RunQueryDsl;
 use tonic::Code;
+use uuid::Uuid;
 
 use crate::setup::TestServer;
 use crate::setup::helper::traits::{HostService, NodeService, OrgService, SocketRpc};
@@ -21,6 +31,8 @@ async fn create_a_new_host() {
         bv_version: "0.1.2".to_string(),
         ip_address: "172.168.0.1".to_string(),
         ip_gateway: "72.168.0.100".to_string(),
+        ipv6_gateway: None,
+        zone_id: None,
         ips: vec!["172.168.0.2".to_string()],
         cpu_cores: 2,
         memory_bytes: 2,
@@ -55,6 +67,86 @@ async fn create_a_new_host() {
     assert_eq!(resp.host.unwrap().network_name, "new-host");
 }
 
+#[tokio::test]
+async fn provision_tokens_restrict_new_hosts() {
+    let test = TestServer::new().await;
+
+    let token_req = |name: &str| api::OrgServiceCreateProvisionTokenRequest {
+        org_id: test.seed().org.id.to_string(),
+        name: name.to_string(),
+        expires_at: None,
+        max_uses: None,
+        region_ids: vec![],
+        schedule_type: None,
+        tags: None,
+    };
+    let create_token = async |req| {
+        test.send_admin(OrgService::create_provision_token, req)
+            .await
+            .unwrap()
+            .secret
+    };
+    let create_host = async |provision_token| {
+        let req = api::HostServiceCreateHostRequest {
+            provision_token,
+            is_private: true,
+            network_name: "new-host".to_string(),
+            display_name: None,
+            region_id: test.seed().region.id.to_string(),
+            schedule_type: common::ScheduleType::Automatic.into(),
+            os: "LuukOS".to_string(),
+            os_version: "4".to_string(),
+            bv_version: "0.1.2".to_string(),
+            ip_address: "172.168.0.1".to_string(),
+            ip_gateway: "72.168.0.100".to_string(),
+            ipv6_gateway: None,
+            zone_id: None,
+            ips: vec!["172.168.0.2".to_string()],
+            cpu_cores: 2,
+            memory_bytes: 2,
+            disk_bytes: 2,
+            tags: None,
+        };
+        test.send_unauthenticated(HostService::create_host, req)
+            .await
+    };
+
+    let expired = create_token(api::OrgServiceCreateProvisionTokenRequest {
+        expires_at: Some(NanosUtc::from(Utc::now() - chrono::Duration::minutes(1)).into()),
+        ..token_req("expired")
+    })
+    .await;
+    let status = create_host(expired).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let other_region = create_token(api::OrgServiceCreateProvisionTokenRequest {
+        region_ids: vec![Uuid::new_v4().to_string()],
+        ..token_req("other-region")
+    })
+    .await;
+    let status = create_host(other_region).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let manual = create_token(api::OrgServiceCreateProvisionTokenRequest {
+        schedule_type: Some(common::ScheduleType::Manual.into()),
+        ..token_req("manual")
+    })
+    .await;
+    let status = create_host(manual).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let once = create_token(api::OrgServiceCreateProvisionTokenRequest {
+        max_uses: Some(1),
+        region_ids: vec![test.seed().region.id.to_string()],
+        ..token_req("once")
+    })
+    .await;
+    let resp = create_host(once.clone()).await.unwrap();
+    assert_eq!(resp.provision_org_id, test.seed().org.id.to_string());
+    let status = create_host(once).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
+
 #[tokio::test]
 async fn update_an_existing_host() {
     let test = TestServer::new().await;
@@ -73,6 +165,13 @@ async fn update_an_existing_host() {
         disk_bytes: None,
         update_tags: None,
         cost: None,
+        labels: None,
+        taints: None,
+        cpu_overcommit_percent: None,
+        memory_overcommit_percent: None,
+        disk_overcommit_percent: None,
+        ipv6_gateway: None,
+        zone_id: None,
     };
 
     // fails without token
@@ -108,6 +207,59 @@ async fn update_an_existing_host() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn schedule_with_host_overcommit() {
+    let test = TestServer::new().await;
+    let host_id = test.seed().host1.id.to_string();
+
+    let list_req = api::HostServiceListRegionsRequest {
+        org_id: None,
+        image_id: test.seed().image.id.to_string(),
+    };
+    let listed = async || {
+        let resp = test
+            .send_super(HostService::list_regions, list_req.clone())
+            .await
+            .unwrap();
+        !resp.regions.is_empty()
+    };
+    let update_host = async |cpu_overcommit_percent| {
+        let req = api::HostServiceUpdateHostRequest {
+            host_id: host_id.clone(),
+            cpu_overcommit_percent: Some(cpu_overcommit_percent),
+            ..Default::default()
+        };
+        test.send_super(HostService::update_host, req)
+            .await
+            .unwrap()
+            .host
+            .unwrap()
+    };
+    assert!(listed().await);
+
+    // the seeded node already uses all of the overcommitted cpu
+    let host = update_host(1).await;
+    assert_eq!(host.cpu_overcommit_percent, Some(1));
+    assert!(!listed().await);
+
+    let host = update_host(200).await;
+    assert_eq!(host.cpu_overcommit_percent, Some(200));
+    assert!(listed().await);
+
+    // clearing the override falls back to the region default
+    let host = update_host(0).await;
+    assert_eq!(host.cpu_overcommit_percent, None);
+    let req = api::HostServiceUpdateRegionRequest {
+        region_id: test.seed().region.id.to_string(),
+        cpu_overcommit_percent: Some(1),
+        ..Default::default()
+    };
+    test.send_super(HostService::update_region, req)
+        .await
+        .unwrap();
+    assert!(!listed().await);
+}
+
 #[tokio::test]
 async fn delete_an_existing_host() {
     let test = TestServer::new().await;
@@ -174,3 +326,354 @@ async fn start_and_stop_a_host() {
     };
     test.send_admin(HostService::restart, req).await.unwrap();
 }
+
+#[tokio::test]
+async fn manage_host_ip_pool() {
+    let test = TestServer::new().await;
+    let host_id = test.seed().host2.id;
+
+    let add_req = api::HostServiceAddIpAddressesRequest {
+        host_id: host_id.to_string(),
+        cidrs: vec!["10.10.0.0/30".to_string()],
+    };
+
+    // only admins may change the pool
+    let status = test
+        .send_admin(HostService::add_ip_addresses, add_req.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // network and broadcast addresses are skipped
+    let resp = test
+        .send_super(HostService::add_ip_addresses, add_req)
+        .await
+        .unwrap();
+    let ips: Vec<_> = resp.ip_addresses.iter().map(|ip| ip.ip.as_str()).collect();
+    assert_eq!(ips, ["10.10.0.1", "10.10.0.2"]);
+
+    // the seeded node holds an address of host1
+    let node = &test.seed().node;
+    let list_req = api::HostServiceListIpAddressesRequest {
+        host_id: test.seed().host1.id.to_string(),
+    };
+    let resp = test
+        .send_super(HostService::list_ip_addresses, list_req)
+        .await
+        .unwrap();
+    let held = resp
+        .ip_addresses
+        .iter()
+        .find(|ip| ip.ip == node.ip_address.to_string())
+        .unwrap();
+    assert_eq!(held.node_id, Some(node.id.to_string()));
+
+    // addresses held by a node can't be reserved or removed
+    let req = api::HostServiceReserveIpAddressRequest {
+        ip: node.ip_address.to_string(),
+        reason: None,
+    };
+    let status = test
+        .send_super(HostService::reserve_ip_address, req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let req = api::HostServiceReserveIpAddressRequest {
+        ip: "10.10.0.1".to_string(),
+        reason: Some("customer allowlist".to_string()),
+    };
+    let resp = test
+        .send_super(HostService::reserve_ip_address, req)
+        .await
+        .unwrap();
+    assert!(resp.ip_address.unwrap().reserved_at.is_some());
+
+    let req = api::HostServiceReleaseIpAddressRequest {
+        ip: "10.10.0.1".to_string(),
+    };
+    let resp = test
+        .send_super(HostService::release_ip_address, req)
+        .await
+        .unwrap();
+    assert!(resp.ip_address.unwrap().reserved_at.is_none());
+
+    let remove_req = api::HostServiceRemoveIpAddressRequest {
+        ip: "10.10.0.2".to_string(),
+    };
+    test.send_super(HostService::remove_ip_address, remove_req.clone())
+        .await
+        .unwrap();
+    let status = test
+        .send_super(HostService::remove_ip_address, remove_req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn report_region_and_zone_capacity() {
+    let test = TestServer::new().await;
+    let region_id = test.seed().region.id.to_string();
+
+    let zone_req = |zone_key: &str| api::HostServiceCreateZoneRequest {
+        region_id: region_id.clone(),
+        zone_key: zone_key.to_string(),
+        display_name: "Zone A".to_string(),
+    };
+
+    // only admins may create zones
+    let status = test
+        .send_admin(HostService::create_zone, zone_req("zone-a"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let status = test
+        .send_super(HostService::create_zone, zone_req("Zone A"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let zone = test
+        .send_super(HostService::create_zone, zone_req("zone-a"))
+        .await
+        .unwrap()
+        .zone
+        .unwrap();
+    let status = test
+        .send_super(HostService::create_zone, zone_req("zone-a"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+
+    // assign the public host to the zone
+    let req = api::HostServiceUpdateHostRequest {
+        host_id: test.seed().host1.id.to_string(),
+        zone_id: Some(zone.zone_id.clone()),
+        ..Default::default()
+    };
+    let host = test
+        .send_super(HostService::update_host, req)
+        .await
+        .unwrap()
+        .host
+        .unwrap();
+    assert_eq!(host.zone_id, Some(zone.zone_id.clone()));
+
+    let req = api::HostServiceGetRegionCapacityRequest {
+        region_id: Some(region_id.clone()),
+    };
+    let status = test
+        .send_admin(HostService::get_region_capacity, req.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let resp = test
+        .send_super(HostService::get_region_capacity, req)
+        .await
+        .unwrap();
+    let region = &resp.regions[0];
+    let public = region.public.as_ref().unwrap();
+    let private = region.private.as_ref().unwrap();
+    assert_eq!(public.hosts, 1);
+    assert_eq!(private.hosts, 1);
+    // the seeded node holds one address of the public host
+    assert_eq!(public.allocated_ips, 1);
+    assert_eq!(public.free_ips, public.total_ips - 1);
+
+    let zone_capacity = &region.zones[0];
+    assert_eq!(zone_capacity.zone.as_ref().unwrap().zone_key, "zone-a");
+    assert_eq!(zone_capacity.public.as_ref().unwrap().hosts, 1);
+    assert_eq!(zone_capacity.private.as_ref().unwrap().hosts, 0);
+}
+
+#[tokio::test]
+async fn reserved_capacity_is_held_for_its_org() {
+    let test = TestServer::new().await;
+    let org_id = test.seed().org.id.to_string();
+
+    let list_req = |org_id: Option<String>| api::HostServiceListRegionsRequest {
+        org_id,
+        image_id: test.seed().image.id.to_string(),
+    };
+    let listed = |resp: api::HostServiceListRegionsResponse| {
+        resp.regions.iter().any(|info| {
+            info.region.as_ref().unwrap().region_id == test.seed().region.id.to_string()
+        })
+    };
+
+    // the public host is available to everyone
+    let resp = test
+        .send_super(HostService::list_regions, list_req(None))
+        .await
+        .unwrap();
+    assert!(listed(resp));
+
+    let create_req = api::HostServiceCreateCapacityReservationRequest {
+        org_id: org_id.clone(),
+        region_id: test.seed().region.id.to_string(),
+        cpu_cores: 10_000,
+        memory_bytes: 0,
+        disk_bytes: 0,
+        slots: None,
+        expires_at: None,
+    };
+    let status = test
+        .send_admin(HostService::create_capacity_reservation, create_req.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let reservation = test
+        .send_super(HostService::create_capacity_reservation, create_req)
+        .await
+        .unwrap()
+        .reservation
+        .unwrap();
+    assert!(reservation.active);
+
+    // other orgs no longer fit into the region, but the reserving org does
+    let resp = test
+        .send_super(HostService::list_regions, list_req(None))
+        .await
+        .unwrap();
+    assert!(!listed(resp));
+    let resp = test
+        .send_super(HostService::list_regions, list_req(Some(org_id.clone())))
+        .await
+        .unwrap();
+    assert!(listed(resp));
+
+    // a resized reservation that fits frees the region again
+    let update_req = api::HostServiceUpdateCapacityReservationRequest {
+        reservation_id: reservation.reservation_id.clone(),
+        cpu_cores: Some(1),
+        ..Default::default()
+    };
+    test.send_super(HostService::update_capacity_reservation, update_req)
+        .await
+        .unwrap();
+    let resp = test
+        .send_super(HostService::list_regions, list_req(None))
+        .await
+        .unwrap();
+    assert!(listed(resp));
+
+    let expire_req = api::HostServiceExpireCapacityReservationRequest {
+        reservation_id: reservation.reservation_id,
+    };
+    let expired = test
+        .send_super(HostService::expire_capacity_reservation, expire_req)
+        .await
+        .unwrap()
+        .reservation
+        .unwrap();
+    assert!(!expired.active);
+
+    let list_req = api::HostServiceListCapacityReservationsRequest {
+        org_ids: vec![org_id],
+        ..Default::default()
+    };
+    let resp = test
+        .send_super(HostService::list_capacity_reservations, list_req)
+        .await
+        .unwrap();
+    assert!(resp.reservations.is_empty());
+}
+
+#[tokio::test]
+async fn deleted_nodes_hand_back_reserved_capacity() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    let region_id = test.seed().region.id;
+    let mut conn = test.conn().await;
+
+    let new_reservation = |node_slots: Option<i32>, cpu_cores| NewCapacityReservation {
+        org_id: node.org_id,
+        region_id,
+        protocol_id: node_slots.map(|_| node.protocol_id),
+        node_slots,
+        cpu_cores,
+        memory_bytes: node.memory_bytes,
+        disk_bytes: node.disk_bytes,
+        expires_at: None,
+    };
+
+    // a slot that is smaller than the node is not consumed
+    let slot = new_reservation(Some(2), 0).create(&mut conn).await.unwrap();
+    let resources = new_reservation(None, 10).create(&mut conn).await.unwrap();
+    let consumed = CapacityReservation::consume(node, region_id, &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(consumed.id, resources.id);
+    assert_eq!(consumed.used_cpu_cores, node.cpu_cores);
+    let slot = CapacityReservation::by_id(slot.id, &mut conn)
+        .await
+        .unwrap();
+    assert_eq!(slot.used_node_slots, 0);
+
+    let req = api::NodeServiceDeleteRequest {
+        node_id: node.id.to_string(),
+    };
+    test.send_admin(NodeService::delete, req).await.unwrap();
+
+    let released = CapacityReservation::by_id(resources.id, &mut conn)
+        .await
+        .unwrap();
+    assert_eq!(released.used_cpu_cores, 0);
+    assert_eq!(released.used_memory_bytes, 0);
+    assert_eq!(released.used_disk_bytes, 0);
+}
+
+#[tokio::test]
+async fn stale_hosts_are_no_longer_scheduled() {
+    let test = TestServer::new().await;
+    let host_id = test.seed().host1.id;
+    let node_id = test.seed().node.id;
+
+    let list_req = api::HostServiceListRegionsRequest {
+        org_id: None,
+        image_id: test.seed().image.id.to_string(),
+    };
+    let resp = test
+        .send_super(HostService::list_regions, list_req.clone())
+        .await
+        .unwrap();
+    assert_eq!(resp.regions.len(), 1);
+
+    // the public host was last seen online an hour ago
+    let mut conn = test.conn().await;
+    let query = format!(
+        "UPDATE hosts SET connection_status = 'online', last_seen_at = now() - interval '1 hour' WHERE id = '{host_id}'"
+    );
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+
+    let messages = host_heartbeat::sweep(Duration::from_secs(60), &mut conn)
+        .await
+        .unwrap();
+    assert!(
+        messages
+            .iter()
+            .any(|msg| matches!(msg, Message::HostMessage(_)))
+    );
+    assert!(
+        messages
+            .iter()
+            .any(|msg| matches!(msg, Message::NodeMessage(_)))
+    );
+
+    let host = Host::by_id(host_id, None, &mut conn).await.unwrap();
+    assert_eq!(host.connection_status, ConnectionStatus::Offline);
+    let node = Node::by_id(node_id, &mut conn).await.unwrap();
+    assert!(node.protocol_health.is_none());
+
+    // the private host is not available to every org
+    let resp = test
+        .send_super(HostService::list_regions, list_req)
+        .await
+        .unwrap();
+    assert!(resp.regions.is_empty());
+}
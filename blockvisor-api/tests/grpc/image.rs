@@ -105,6 +105,8 @@ fn add_image_property<S: Into<String>>(key: S, new_archive: bool) -> api::AddIma
         add_cpu_cores: None,
         add_memory_bytes: None,
         add_disk_bytes: None,
+        constraints: None,
+        dependencies: vec![],
     }
 }
 
@@ -350,6 +352,67 @@ async fn update_existing_image() {
     assert_eq!(result.unwrap_err().code(), Code::NotFound);
 }
 
+#[tokio::test]
+async fn deprecate_an_image() {
+    let test = TestServer::new().await;
+
+    let get_latest = api::ImageServiceGetImageRequest {
+        version_key: version_key(PROTOCOL_KEY, VARIANT_KEY),
+        org_id: Some(ORG_ID.into()),
+        semantic_version: None,
+        build_version: None,
+    };
+    let deprecate = api::ImageServiceSetLifecycleRequest {
+        image_id: IMAGE_ID.into(),
+        lifecycle: common::ImageLifecycle::Deprecated.into(),
+        reason: Some("security fix in next build".into()),
+        sunset_at: None,
+    };
+
+    // org admin can't change the image lifecycle
+    let result = test
+        .send_admin(ImageService::set_lifecycle, deprecate.clone())
+        .await;
+    assert_eq!(result.unwrap_err().code(), Code::PermissionDenied);
+
+    // super user can deprecate an image
+    let result = test
+        .send_super(ImageService::set_lifecycle, deprecate)
+        .await;
+    let image = result.unwrap().image.unwrap();
+    assert_eq!(image.lifecycle(), common::ImageLifecycle::Deprecated);
+    assert_eq!(
+        image.lifecycle_reason.as_deref(),
+        Some("security fix in next build")
+    );
+
+    // a deprecated build is no longer the latest image
+    let result = test
+        .send_member(ImageService::get_image, get_latest.clone())
+        .await;
+    assert_eq!(result.unwrap_err().code(), Code::NotFound);
+
+    // but can still be found by build version
+    let mut req = get_latest.clone();
+    req.build_version = Some(1);
+    let result = test.send_member(ImageService::get_image, req).await;
+    assert_eq!(result.unwrap().image.unwrap().image_id, IMAGE_ID);
+
+    // super user can reactivate an image
+    let activate = api::ImageServiceSetLifecycleRequest {
+        image_id: IMAGE_ID.into(),
+        lifecycle: common::ImageLifecycle::Active.into(),
+        reason: None,
+        sunset_at: None,
+    };
+    let result = test.send_super(ImageService::set_lifecycle, activate).await;
+    let image = result.unwrap().image.unwrap();
+    assert_eq!(image.lifecycle(), common::ImageLifecycle::Active);
+
+    let result = test.send_member(ImageService::get_image, get_latest).await;
+    assert_eq!(result.unwrap().image.unwrap().image_id, IMAGE_ID);
+}
+
 fn version_key(protocol_key: &str, variant_key: &str) -> Option<common::ProtocolVersionKey> {
     Some(common::ProtocolVersionKey {
         protocol_key: protocol_key.into(),
@@ -10,4 +10,6 @@ mod metrics;
 mod node;
 mod org;
 mod protocol;
+mod sso;
+mod stripe;
 mod user;
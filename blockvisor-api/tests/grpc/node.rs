@@ -1,19 +1,26 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use blockvisor_api::auth::rbac::{NodePerm, Perms, ProtocolPerm};
+use blockvisor_api::auth::resource::{HostId, NodeId};
 use blockvisor_api::database::seed::{
-    ARCHIVE_ID_1, ARCHIVE_ID_2, DISK_BYTES, IMAGE_ID, MEMORY_BYTES, MORE_RESOURCES_KEY, ORG_ID,
+    ARCHIVE_ID_1, ARCHIVE_ID_2, DISK_BYTES, IMAGE_ID, MEMORY_BYTES, MORE_RESOURCES_KEY,
+    NETWORK_KEY, ORG_ID,
 };
 use blockvisor_api::grpc::{api, common};
-use blockvisor_api::model::Node;
-use blockvisor_api::model::command::Command;
-use blockvisor_api::model::schema::commands;
+use blockvisor_api::model::command::{Command, CommandType};
+use blockvisor_api::model::node::{failover, recovery, resize};
+use blockvisor_api::model::schema::{commands, node_failovers, node_moves, nodes};
 use blockvisor_api::model::sql::Tag;
+use blockvisor_api::model::{Host, Node};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use tonic::Code;
 use uuid::Uuid;
 
 use crate::setup::TestServer;
-use crate::setup::helper::traits::{NodeService, SocketRpc};
+use crate::setup::helper::traits::{ImageService, NodeService, SocketRpc};
 
 #[tokio::test]
 async fn create_a_new_node() {
@@ -27,6 +34,7 @@ async fn create_a_new_node() {
         new_values,
         add_rules,
         tags: None,
+        placement: None,
     };
 
     // an org admin can't create a node with an invalid org_id
@@ -84,6 +92,233 @@ async fn create_a_new_node() {
     assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
 }
 
+#[tokio::test]
+async fn create_a_node_from_a_deprecated_image() {
+    let test = TestServer::new().await;
+
+    let req = api::ImageServiceSetLifecycleRequest {
+        image_id: IMAGE_ID.into(),
+        lifecycle: common::ImageLifecycle::Deprecated.into(),
+        reason: None,
+        sunset_at: None,
+    };
+    test.send_super(ImageService::set_lifecycle, req)
+        .await
+        .unwrap();
+
+    // new nodes can't be created from a deprecated image
+    let req = api::NodeServiceCreateRequest {
+        org_id: ORG_ID.into(),
+        image_id: IMAGE_ID.into(),
+        old_node_id: None,
+        launcher: Some(launch_region(test.seed().region.id, 1)),
+        new_values: vec![],
+        add_rules: vec![],
+        tags: None,
+        placement: None,
+    };
+    let result = test.send_admin(NodeService::create, req).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn create_a_node_with_invalid_properties() {
+    let test = TestServer::new().await;
+
+    // the network must be a known value, and more resources are only for mainnet
+    let mut conn = test.conn().await;
+    let queries = [
+        format!(
+            "UPDATE image_properties SET allowed_values = '{{testnet,mainnet}}'
+            WHERE image_id = '{IMAGE_ID}' AND key = '{NETWORK_KEY}';"
+        ),
+        format!(
+            r#"UPDATE image_properties
+            SET dependencies = '[{{"kind": "requires", "key": "{NETWORK_KEY}", "value": "mainnet"}}]'
+            WHERE image_id = '{IMAGE_ID}' AND key = '{MORE_RESOURCES_KEY}';"#
+        ),
+    ];
+    for query in queries {
+        diesel::sql_query(query).execute(&mut conn).await.unwrap();
+    }
+
+    let create_req = |new_values| api::NodeServiceCreateRequest {
+        org_id: ORG_ID.into(),
+        image_id: IMAGE_ID.into(),
+        old_node_id: None,
+        launcher: Some(launch_region(test.seed().region.id, 1)),
+        new_values,
+        add_rules: vec![],
+        tags: None,
+        placement: None,
+    };
+
+    // a value outside the allowed values is rejected
+    let req = create_req(vec![property(NETWORK_KEY, "devnet")]);
+    let status = test.send_admin(NodeService::create, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status.message().contains(NETWORK_KEY), "{status:?}");
+
+    // as is a value that breaks a dependency rule
+    let req = create_req(vec![property(MORE_RESOURCES_KEY, "moar")]);
+    let status = test.send_admin(NodeService::create, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status.message().contains(MORE_RESOURCES_KEY), "{status:?}");
+
+    // but both are fine once the dependency is met
+    let new_values = vec![
+        property(NETWORK_KEY, "mainnet"),
+        property(MORE_RESOURCES_KEY, "moar"),
+    ];
+    test.send_admin(NodeService::create, create_req(new_values))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn create_a_node_with_required_labels() {
+    let test = TestServer::new().await;
+    let host1 = test.seed().host1.id;
+    let host2 = test.seed().host2.id;
+    make_room(&test).await;
+    update_host(&test, host2, r#"labels = '{"disk": "nvme"}'"#).await;
+
+    // only the labelled host is a candidate
+    let placement = common::NodePlacement {
+        required_labels: HashMap::from([("disk".to_string(), "nvme".to_string())]),
+        ..Default::default()
+    };
+    let node = create_placed(&test, placement.clone()).await.unwrap();
+    assert_eq!(node.host_id, host2.to_string());
+
+    // a label with a different value does not match
+    let mismatch = common::NodePlacement {
+        required_labels: HashMap::from([("disk".to_string(), "hdd".to_string())]),
+        ..Default::default()
+    };
+    let result = create_placed(&test, mismatch).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+
+    // the labelled host has no addresses left
+    let result = create_placed(&test, placement).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+
+    // but a node without the requirement still lands on the unlabelled host
+    let node = create_placed(&test, Default::default()).await.unwrap();
+    assert_eq!(node.host_id, host1.to_string());
+}
+
+#[tokio::test]
+async fn create_a_node_on_a_tainted_host() {
+    let test = TestServer::new().await;
+    let host1 = test.seed().host1.id;
+    let host2 = test.seed().host2.id;
+    make_room(&test).await;
+    update_host(&test, host1, r#"taints = '{"dedicated": "archive"}'"#).await;
+
+    // a node without the toleration avoids the tainted host
+    let node = create_placed(&test, Default::default()).await.unwrap();
+    assert_eq!(node.host_id, host2.to_string());
+
+    // so it has nowhere to go once the untainted host is full
+    let result = create_placed(&test, Default::default()).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+
+    // a node that tolerates the taint may still land on it
+    let placement = common::NodePlacement {
+        tolerations: HashMap::from([("dedicated".to_string(), "archive".to_string())]),
+        ..Default::default()
+    };
+    let node = create_placed(&test, placement).await.unwrap();
+    assert_eq!(node.host_id, host1.to_string());
+
+    // unless the host has another taint
+    update_host(
+        &test,
+        host1,
+        r#"taints = '{"dedicated": "archive", "gpu": "true"}'"#,
+    )
+    .await;
+    let placement = common::NodePlacement {
+        tolerations: HashMap::from([("dedicated".to_string(), "archive".to_string())]),
+        ..Default::default()
+    };
+    let result = create_placed(&test, placement).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn create_a_node_with_anti_affinity() {
+    let test = TestServer::new().await;
+    let host1 = test.seed().host1.id;
+    let host2 = test.seed().host2.id;
+    let seed_node = test.seed().node.id;
+    make_room(&test).await;
+
+    // the seed node runs on host1, so avoiding its tag leaves host2
+    let mut conn = test.conn().await;
+    let query = format!("UPDATE nodes SET tags = '{{validators}}' WHERE id = '{seed_node}'");
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+
+    let placement = common::NodePlacement {
+        anti_affinity_tags: vec!["validators".to_string()],
+        ..Default::default()
+    };
+    let node = create_placed(&test, placement).await.unwrap();
+    assert_eq!(node.host_id, host2.to_string());
+
+    // avoiding a node on each host leaves no candidates
+    let placement = common::NodePlacement {
+        anti_affinity_node_ids: vec![seed_node.to_string(), node.id.clone()],
+        ..Default::default()
+    };
+    let result = create_placed(&test, placement).await;
+    assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+
+    // avoiding the node on host2 leaves host1
+    let placement = common::NodePlacement {
+        anti_affinity_node_ids: vec![node.id],
+        ..Default::default()
+    };
+    let node = create_placed(&test, placement).await.unwrap();
+    assert_eq!(node.host_id, host1.to_string());
+}
+
+/// Give the private host as much room as the public one.
+async fn make_room(test: &TestServer) {
+    let set = format!(
+        "cpu_cores = 100, memory_bytes = {}, disk_bytes = {}",
+        100 * MEMORY_BYTES,
+        100 * DISK_BYTES
+    );
+    update_host(test, test.seed().host2.id, &set).await;
+}
+
+async fn update_host(test: &TestServer, host_id: HostId, set: &str) {
+    let mut conn = test.conn().await;
+    let query = format!("UPDATE hosts SET {set} WHERE id = '{host_id}'");
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+}
+
+/// Create a node somewhere in the seeded region with `placement`.
+async fn create_placed(
+    test: &TestServer,
+    placement: common::NodePlacement,
+) -> Result<api::Node, tonic::Status> {
+    let req = api::NodeServiceCreateRequest {
+        org_id: ORG_ID.into(),
+        image_id: IMAGE_ID.into(),
+        old_node_id: None,
+        launcher: Some(launch_region(test.seed().region.id, 1)),
+        new_values: vec![],
+        add_rules: vec![],
+        tags: None,
+        placement: Some(placement),
+    };
+    let mut resp = test.send_admin(NodeService::create, req).await?;
+    Ok(resp.nodes.pop().unwrap())
+}
+
 fn launch_host<S: ToString>(host_id: S, node_count: u32) -> common::NodeLauncher {
     common::NodeLauncher {
         launch: Some(common::node_launcher::Launch::ByHost(common::ByHost {
@@ -128,6 +363,8 @@ async fn update_a_node_config() {
         new_note: Some("milk, eggs, bread and copious snacks".to_string()),
         new_values: vec![],
         new_firewall: None,
+        add_rules: vec![],
+        remove_rules: vec![],
         update_tags: Some(common::UpdateTags {
             update: Some(common::update_tags::Update::OverwriteTags(common::Tags {
                 tags: vec![common::Tag {
@@ -136,6 +373,8 @@ async fn update_a_node_config() {
             })),
         }),
         cost: None,
+        failover_enabled: None,
+        restart_policy: None,
     };
 
     // fails for unknown id
@@ -166,6 +405,266 @@ async fn update_a_node_config() {
     validate_commands(&test).await;
 }
 
+#[tokio::test]
+async fn update_node_firewall_rules() {
+    let test = TestServer::new().await;
+    let node_id = test.seed().node.id.to_string();
+
+    let rule = |key: &str, port| common::FirewallRule {
+        key: key.into(),
+        description: None,
+        protocol: common::FirewallProtocol::Tcp.into(),
+        direction: common::FirewallDirection::Inbound.into(),
+        action: common::FirewallAction::Allow.into(),
+        ips: vec![common::IpName {
+            ip: "10.0.0.1".into(),
+            name: None,
+        }],
+        ports: vec![common::PortName { port, name: None }],
+    };
+    let update = |add_rules, remove_rules| api::NodeServiceUpdateConfigRequest {
+        node_id: node_id.clone(),
+        add_rules,
+        remove_rules,
+        ..Default::default()
+    };
+    let rule_keys = |node: api::Node| -> Vec<String> {
+        let firewall = node.config.unwrap().firewall.unwrap();
+        firewall.rules.into_iter().map(|rule| rule.key).collect()
+    };
+    let get = api::NodeServiceGetRequest {
+        node_id: node_id.clone(),
+    };
+
+    // a firewall set directly is kept when node rules change later
+    let req = api::NodeServiceUpdateConfigRequest {
+        node_id: node_id.clone(),
+        new_firewall: Some(common::FirewallConfig {
+            default_in: common::FirewallAction::Drop.into(),
+            default_out: common::FirewallAction::Allow.into(),
+            rules: vec![rule("custom", 9000)],
+        }),
+        ..Default::default()
+    };
+    test.send_admin(NodeService::update_config, req)
+        .await
+        .unwrap();
+
+    // an org admin can allowlist an ip for the rpc port
+    let req = update(vec![rule("my-rpc", 8545)], vec![]);
+    test.send_admin(NodeService::update_config, req)
+        .await
+        .unwrap();
+    let node = test.send_admin(NodeService::get, get.clone()).await;
+    assert_eq!(
+        rule_keys(node.unwrap().node.unwrap()),
+        vec!["custom", "my-rpc"]
+    );
+
+    // port 0 is not a valid port
+    let req = update(vec![rule("zero", 0)], vec![]);
+    let result = test.send_admin(NodeService::update_config, req).await;
+    assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+
+    // an invalid cidr is rejected
+    let mut invalid = rule("invalid", 8546);
+    invalid.ips[0].ip = "10.0.0.1/8".into();
+    let req = update(vec![invalid], vec![]);
+    let result = test.send_admin(NodeService::update_config, req).await;
+    assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+
+    // can't remove an unknown rule
+    let req = update(vec![], vec!["unknown".into()]);
+    let result = test.send_admin(NodeService::update_config, req).await;
+    assert_eq!(result.unwrap_err().code(), Code::NotFound);
+
+    // can remove an existing rule
+    let req = update(vec![], vec!["my-rpc".into()]);
+    test.send_admin(NodeService::update_config, req)
+        .await
+        .unwrap();
+    let node = test.send_admin(NodeService::get, get).await;
+    assert_eq!(rule_keys(node.unwrap().node.unwrap()), vec!["custom"]);
+}
+
+#[tokio::test]
+async fn resize_a_node() {
+    let test = TestServer::new().await;
+    let node_id = test.seed().node.id;
+
+    let resize_req = |cpu_cores, disk_bytes| api::NodeServiceResizeRequest {
+        node_id: node_id.to_string(),
+        cpu_cores,
+        memory_bytes: None,
+        disk_bytes,
+    };
+    let disk = |n: i64| Some(u64::try_from(n * DISK_BYTES).unwrap());
+
+    // fails below the image minimum
+    let req = resize_req(Some(0), None);
+    let status = test.send_admin(NodeService::resize, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // fails without any change
+    let req = resize_req(None, disk(1));
+    let status = test.send_admin(NodeService::resize, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+
+    // fails when no host can take the new size
+    let req = resize_req(Some(1000), None);
+    let status = test.send_admin(NodeService::resize, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let mut conn = test.conn().await;
+    let node = Node::by_id(node_id, &mut conn).await.unwrap();
+    let host = Host::by_id(node.host_id, None, &mut conn).await.unwrap();
+
+    // grows the disk on the current host
+    let req = resize_req(Some(2), disk(2));
+    let resp = test.send_admin(NodeService::resize, req).await.unwrap();
+    assert_eq!(resp.node.unwrap().node_id, node_id.to_string());
+
+    let resized = Node::by_id(node_id, &mut conn).await.unwrap();
+    assert_eq!(resized.host_id, node.host_id);
+    assert_eq!(resized.cpu_cores, 2);
+    assert_eq!(resized.disk_bytes, 2 * DISK_BYTES);
+    assert_ne!(resized.config_id, node.config_id);
+
+    // the seeded node is not counted on its host, so counters floor at zero
+    let updated = Host::by_id(node.host_id, None, &mut conn).await.unwrap();
+    assert_eq!(updated.node_cpu_cores, (host.node_cpu_cores - 1).max(0) + 2);
+    assert_eq!(
+        updated.node_disk_bytes,
+        (host.node_disk_bytes - DISK_BYTES).max(0) + 2 * DISK_BYTES
+    );
+
+    // the disk can't shrink again
+    let req = resize_req(None, disk(1));
+    let status = test.send_admin(NodeService::resize, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    validate_commands(&test).await;
+}
+
+#[tokio::test]
+async fn move_a_resized_node_to_another_host() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    let new_node_id = move_seed_node(&test).await;
+
+    let mut conn = test.conn().await;
+    let new_node = Node::by_id(new_node_id, &mut conn).await.unwrap();
+    assert_eq!(new_node.old_node_id, Some(node.id));
+    assert_eq!(new_node.host_id, test.seed().host2.id);
+    assert_eq!(new_node.cpu_cores, 3);
+
+    // a node is only moved once at a time
+    let req = api::NodeServiceResizeRequest {
+        node_id: node.id.to_string(),
+        cpu_cores: Some(4),
+        memory_bytes: None,
+        disk_bytes: None,
+    };
+    let status = test.send_admin(NodeService::resize, req).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    // the original node is kept while the new node is starting
+    resize::complete(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap();
+
+    // until the new node is running
+    let query = format!("UPDATE nodes SET node_state = 'running' WHERE id = '{new_node_id}'");
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+    resize::complete(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap_err();
+    Node::by_id(new_node_id, &mut conn).await.unwrap();
+
+    let completed: Option<DateTime<Utc>> = node_moves::table
+        .filter(node_moves::node_id.eq(node.id))
+        .select(node_moves::completed_at)
+        .get_result(&mut conn)
+        .await
+        .unwrap();
+    assert!(completed.is_some());
+
+    validate_commands(&test).await;
+}
+
+#[tokio::test]
+async fn expire_a_node_move_that_never_runs() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    let new_node_id = move_seed_node(&test).await;
+    let mut conn = test.conn().await;
+
+    // the new node is given time to start
+    resize::complete(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(new_node_id, &mut conn).await.unwrap();
+
+    // until the move has waited too long
+    let query = format!(
+        "UPDATE node_moves SET created_at = now() - interval '1 hour' WHERE node_id = '{}'",
+        node.id
+    );
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+    resize::complete(Duration::from_secs(60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(new_node_id, &mut conn).await.unwrap_err();
+    Node::by_id(node.id, &mut conn).await.unwrap();
+
+    let completed: Option<DateTime<Utc>> = node_moves::table
+        .filter(node_moves::node_id.eq(node.id))
+        .select(node_moves::completed_at)
+        .get_result(&mut conn)
+        .await
+        .unwrap();
+    assert!(completed.is_some());
+
+    validate_commands(&test).await;
+}
+
+/// Grow the seeded node beyond its host so that it moves to the other host.
+///
+/// Returns the id of the new node.
+async fn move_seed_node(test: &TestServer) -> NodeId {
+    let node = &test.seed().node;
+    let mut conn = test.conn().await;
+
+    // the current host has no room to grow, but the other host does
+    let queries = [
+        format!(
+            "UPDATE hosts SET cpu_cores = 1 WHERE id = '{}'",
+            node.host_id
+        ),
+        format!(
+            "UPDATE hosts SET cpu_cores = 10, memory_bytes = 10 * memory_bytes, disk_bytes = 10 * disk_bytes WHERE id = '{}'",
+            test.seed().host2.id
+        ),
+    ];
+    for query in queries {
+        diesel::sql_query(query).execute(&mut conn).await.unwrap();
+    }
+
+    let req = api::NodeServiceResizeRequest {
+        node_id: node.id.to_string(),
+        cpu_cores: Some(3),
+        memory_bytes: None,
+        disk_bytes: None,
+    };
+    let resp = test.send_admin(NodeService::resize, req).await.unwrap();
+    let new_node_id: NodeId = resp.node.unwrap().node_id.parse().unwrap();
+    assert_ne!(new_node_id, node.id);
+
+    new_node_id
+}
+
 #[tokio::test]
 async fn get_an_existing_node() {
     let test = TestServer::new().await;
@@ -243,6 +742,243 @@ async fn report_a_node_status() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn recover_a_failed_node() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+
+    // restart once, without waiting between attempts
+    set_restart_policy(&test, common::RestartAction::OnFailure, 1).await;
+    report_failed(&test).await;
+
+    let mut conn = test.conn().await;
+
+    // the first attempt restarts the node
+    recovery::recover(test.context()).await.unwrap();
+    let failed = Node::by_id(node.id, &mut conn).await.unwrap();
+    assert_eq!(failed.restart_attempts, 1);
+    assert_eq!(count_restarts(&test).await, 1);
+
+    // then it gives up without another restart
+    recovery::recover(test.context()).await.unwrap();
+    let failed = Node::by_id(node.id, &mut conn).await.unwrap();
+    assert_eq!(failed.restart_attempts, 2);
+    assert_eq!(count_restarts(&test).await, 1);
+
+    // and leaves the node alone after that
+    recovery::recover(test.context()).await.unwrap();
+    let failed = Node::by_id(node.id, &mut conn).await.unwrap();
+    assert_eq!(failed.restart_attempts, 2);
+
+    validate_commands(&test).await;
+}
+
+#[tokio::test]
+async fn recreate_a_failed_node_on_another_host() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    let host2 = &test.seed().host2;
+
+    // recreate twice, without waiting between attempts
+    set_restart_policy(&test, common::RestartAction::Recreate, 2).await;
+    report_failed(&test).await;
+
+    let mut conn = test.conn().await;
+    let set_cpu_cores = |cpu_cores: i64| {
+        format!(
+            "UPDATE hosts SET cpu_cores = {cpu_cores} WHERE id = '{}'",
+            host2.id
+        )
+    };
+
+    // the failing host is never a candidate, even with room for the node
+    diesel::sql_query(set_cpu_cores(0))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    recovery::recover(test.context()).await.unwrap();
+    let failed = Node::by_id(node.id, &mut conn).await.unwrap();
+    assert_eq!(failed.restart_attempts, 1);
+    assert!(recreated(&test, node.id).await.is_none());
+
+    // so the node is recreated on the other host once it has room
+    diesel::sql_query(set_cpu_cores(host2.cpu_cores))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    recovery::recover(test.context()).await.unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap_err();
+
+    let new_node_id = recreated(&test, node.id).await.unwrap();
+    let new_node = Node::by_id(new_node_id, &mut conn).await.unwrap();
+    assert_eq!(new_node.host_id, host2.id);
+    assert_eq!(new_node.restart_attempts, 2);
+
+    validate_commands(&test).await;
+}
+
+/// The node that replaced `node_id`, if any.
+async fn recreated(test: &TestServer, node_id: NodeId) -> Option<NodeId> {
+    let mut conn = test.conn().await;
+    nodes::table
+        .filter(nodes::old_node_id.eq(node_id))
+        .select(nodes::id)
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .unwrap()
+}
+
+async fn set_restart_policy(test: &TestServer, action: common::RestartAction, max_attempts: u32) {
+    let req = api::NodeServiceUpdateConfigRequest {
+        node_id: test.seed().node.id.to_string(),
+        restart_policy: Some(common::NodeRestartPolicy {
+            action: action.into(),
+            max_attempts,
+            backoff_seconds: 0,
+        }),
+        ..Default::default()
+    };
+    test.send_admin(NodeService::update_config, req)
+        .await
+        .unwrap();
+}
+
+/// Report the seeded node as failed.
+async fn report_failed(test: &TestServer) {
+    let node = &test.seed().node;
+    let perms = Perms::All(hashset! {
+        NodePerm::ReportStatus.into(),
+        ProtocolPerm::ViewPublic.into()
+    });
+    let jwt = test.org_jwt(perms);
+    let req = api::NodeServiceReportStatusRequest {
+        node_id: node.id.to_string(),
+        config_id: node.config_id.to_string(),
+        status: Some(common::NodeStatus {
+            state: common::NodeState::Failed.into(),
+            next: None,
+            protocol: None,
+        }),
+        p2p_address: None,
+    };
+    test.send_with(NodeService::report_status, req, &jwt)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn fail_over_a_node_from_an_offline_host() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    let (new_node_id, completed) = fail_over(&test).await;
+    assert!(completed.is_none());
+
+    let mut conn = test.conn().await;
+    let new_node = Node::by_id(new_node_id, &mut conn).await.unwrap();
+    assert_eq!(new_node.old_node_id, Some(node.id));
+    assert_eq!(new_node.host_id, test.seed().host2.id);
+
+    // the original node is kept while its host is offline
+    failover::complete(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap();
+
+    // until it has waited too long for the host to return
+    failover::complete(Duration::from_secs(60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap_err();
+    let (_, completed) = failed_over(&test, node.id).await.unwrap();
+    assert!(completed.is_some());
+}
+
+#[tokio::test]
+async fn complete_a_failover_once_the_host_returns() {
+    let test = TestServer::new().await;
+    let node = &test.seed().node;
+    fail_over(&test).await;
+
+    let mut conn = test.conn().await;
+    let query = format!(
+        "UPDATE hosts SET connection_status = 'online', last_seen_at = now() WHERE id = '{}'",
+        node.host_id
+    );
+    diesel::sql_query(query).execute(&mut conn).await.unwrap();
+
+    failover::complete(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    Node::by_id(node.id, &mut conn).await.unwrap_err();
+    let (_, completed) = failed_over(&test, node.id).await.unwrap();
+    assert!(completed.is_some());
+
+    validate_commands(&test).await;
+}
+
+/// Take the host of the seeded node offline and fail the node over.
+///
+/// Returns the new node id, and when the failover was completed.
+async fn fail_over(test: &TestServer) -> (NodeId, Option<DateTime<Utc>>) {
+    let node = &test.seed().node;
+    let mut conn = test.conn().await;
+
+    // the org opts in, and the host was last seen an hour ago
+    let queries = [
+        format!(
+            "UPDATE orgs SET failover_enabled = true WHERE id = '{}'",
+            node.org_id
+        ),
+        format!(
+            "UPDATE hosts SET connection_status = 'offline', last_seen_at = now() - interval '1 hour' WHERE id = '{}'",
+            node.host_id
+        ),
+    ];
+    for query in queries {
+        diesel::sql_query(query).execute(&mut conn).await.unwrap();
+    }
+
+    // the host has not been offline for long enough
+    failover::failover(Duration::from_secs(2 * 60 * 60), test.context())
+        .await
+        .unwrap();
+    assert!(failed_over(test, node.id).await.is_none());
+
+    failover::failover(Duration::from_secs(60), test.context())
+        .await
+        .unwrap();
+    let failed = failed_over(test, node.id).await.unwrap();
+
+    // a node is only failed over once
+    failover::failover(Duration::from_secs(60), test.context())
+        .await
+        .unwrap();
+    let count: i64 = node_failovers::table
+        .filter(node_failovers::node_id.eq(node.id))
+        .count()
+        .get_result(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+
+    failed
+}
+
+async fn failed_over(
+    test: &TestServer,
+    node_id: NodeId,
+) -> Option<(NodeId, Option<DateTime<Utc>>)> {
+    let mut conn = test.conn().await;
+    node_failovers::table
+        .filter(node_failovers::node_id.eq(node_id))
+        .select((node_failovers::new_node_id, node_failovers::completed_at))
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .unwrap()
+}
+
 #[tokio::test]
 async fn delete_an_existing_node() {
     let test = TestServer::new().await;
@@ -266,6 +1002,17 @@ async fn delete_an_existing_node() {
     validate_commands(&test).await;
 }
 
+async fn count_restarts(test: &TestServer) -> i64 {
+    let mut conn = test.conn().await;
+    commands::table
+        .filter(commands::node_id.eq(test.seed().node.id))
+        .filter(commands::command_type.eq(CommandType::NodeRestart))
+        .count()
+        .get_result(&mut conn)
+        .await
+        .unwrap()
+}
+
 async fn validate_commands(test: &TestServer) {
     let mut conn = test.conn().await;
     let commands: Vec<Command> = commands::table
@@ -1,10 +1,11 @@
 use blockvisor_api::auth::claims::{Claims, Expirable};
-use blockvisor_api::auth::rbac::InvitationPerm;
+use blockvisor_api::auth::rbac::{InvitationPerm, OrgRole, Role, ViewRole};
 use blockvisor_api::auth::resource::Resource;
 use blockvisor_api::database::seed;
 use blockvisor_api::grpc::api;
 use blockvisor_api::model::invitation::NewInvitation;
 use blockvisor_api::model::org::Org;
+use blockvisor_api::model::rbac::RbacUser;
 
 use crate::setup::TestServer;
 use crate::setup::helper::traits::{InvitationService, OrgService, SocketRpc};
@@ -123,3 +124,92 @@ async fn member_count_works() {
         .unwrap();
     assert_eq!(org_resp.member_count, members + 1);
 }
+
+#[tokio::test]
+async fn member_roles_can_be_changed_and_ownership_transferred() {
+    let test = TestServer::new().await;
+    let org_id = test.seed().org.id;
+    let admin_id = test.seed().admin.id;
+    let member_id = test.seed().member.id;
+
+    let update_req = || api::OrgServiceUpdateRequest {
+        org_id: org_id.to_string(),
+        name: Some("renamed-by-member".to_string()),
+    };
+
+    // members can't update the org or change roles
+    let status = test
+        .send_member(OrgService::update, update_req())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let req = api::OrgServiceUpdateMemberRoleRequest {
+        org_id: org_id.to_string(),
+        user_id: member_id.to_string(),
+        role: "org-admin".to_string(),
+    };
+    let status = test
+        .send_member(OrgService::update_member_role, req.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    // an admin can promote them, and their existing token picks up the change
+    test.send_admin(OrgService::update_member_role, req)
+        .await
+        .unwrap();
+    test.send_member(OrgService::update, update_req())
+        .await
+        .unwrap();
+
+    // ownership can't be granted through a role change
+    let req = api::OrgServiceUpdateMemberRoleRequest {
+        org_id: org_id.to_string(),
+        user_id: member_id.to_string(),
+        role: "org-owner".to_string(),
+    };
+    let status = test
+        .send_admin(OrgService::update_member_role, req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    // the seed org has no owner, so make the admin one to hand it over
+    let mut conn = test.conn().await;
+    RbacUser::link_role(admin_id, org_id, ViewRole::DeveloperPreview, &mut conn)
+        .await
+        .unwrap();
+    Org::set_user_role(admin_id, org_id, OrgRole::Owner, &mut conn)
+        .await
+        .unwrap();
+
+    let req = api::OrgServiceTransferOwnershipRequest {
+        org_id: org_id.to_string(),
+        user_id: member_id.to_string(),
+    };
+    test.send_admin(OrgService::transfer_ownership, req)
+        .await
+        .unwrap();
+
+    let owners = RbacUser::org_owners(org_id, &mut conn).await.unwrap();
+    assert_eq!(owners, vec![member_id]);
+    let admin_roles = RbacUser::org_roles(admin_id, org_id, true, &mut conn)
+        .await
+        .unwrap();
+    assert!(admin_roles.contains(&Role::Org(OrgRole::Admin)));
+    assert!(!admin_roles.contains(&Role::Org(OrgRole::Owner)));
+    // roles that aren't org roles survive the role changes
+    assert!(admin_roles.contains(&Role::View(ViewRole::DeveloperPreview)));
+
+    // the last owner can't be demoted
+    let req = api::OrgServiceUpdateMemberRoleRequest {
+        org_id: org_id.to_string(),
+        user_id: member_id.to_string(),
+        role: "org-member".to_string(),
+    };
+    let status = test
+        .send_member(OrgService::update_member_role, req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+}
@@ -337,3 +337,98 @@ async fn update_existing_version() {
     let result = test.send_member(ProtocolService::get_latest, req).await;
     assert_eq!(result.unwrap_err().code(), Code::NotFound);
 }
+
+#[tokio::test]
+async fn import_an_exported_catalog() {
+    let test = TestServer::new().await;
+
+    let req = api::ProtocolServiceExportCatalogRequest {
+        format: api::CatalogFormat::Toml.into(),
+    };
+    let result = test
+        .send_admin(ProtocolService::export_catalog, req.clone())
+        .await;
+    assert_eq!(result.unwrap_err().code(), Code::PermissionDenied);
+    let exported = test
+        .send_super(ProtocolService::export_catalog, req)
+        .await
+        .unwrap()
+        .catalog;
+
+    // re-importing the current catalog is a no-op
+    let req = api::ProtocolServiceImportCatalogRequest {
+        catalog: exported,
+        format: api::CatalogFormat::Toml.into(),
+        dry_run: false,
+    };
+    let resp = test
+        .send_super(ProtocolService::import_catalog, req)
+        .await
+        .unwrap();
+    assert!(resp.changes.is_empty());
+
+    let catalog = r"
+protocols:
+  - key: sui
+    name: Sui
+    ticker: SUI
+    visibility: public
+    variants:
+      - key: sui-mainnet
+        versions:
+          - semantic_version: 1.0.0
+            sku_code: SUI-MN
+            visibility: public
+            images:
+              - build_version: 1
+                image_uri: docker://sui:1.0.0
+                visibility: public
+                min_cpu_cores: 2
+                min_memory_bytes: 4000000000
+                min_disk_bytes: 100000000000
+                min_babel_version: 0.9.0
+                default_firewall_in: drop
+                default_firewall_out: allow
+                archives:
+                  - new_archive_keys: []
+                    store_key: sui-mainnet-v1
+";
+    let mut req = api::ProtocolServiceImportCatalogRequest {
+        catalog: catalog.to_string(),
+        format: api::CatalogFormat::Yaml.into(),
+        dry_run: true,
+    };
+    let resp = test
+        .send_super(ProtocolService::import_catalog, req.clone())
+        .await
+        .unwrap();
+    assert_eq!(resp.changes.len(), 3);
+    assert!(!resp.applied);
+
+    req.dry_run = false;
+    let resp = test
+        .send_super(ProtocolService::import_catalog, req.clone())
+        .await
+        .unwrap();
+    assert!(resp.applied);
+
+    let get = api::ProtocolServiceGetProtocolRequest {
+        protocol: Some(ApiProtocol::ProtocolKey("sui".to_string())),
+        org_id: None,
+    };
+    let protocol = test
+        .send_member(ProtocolService::get_protocol, get)
+        .await
+        .unwrap()
+        .protocol
+        .unwrap();
+    assert_eq!(protocol.versions.len(), 1);
+
+    // applying the catalog again changes nothing
+    req.dry_run = true;
+    let resp = test
+        .send_super(ProtocolService::import_catalog, req)
+        .await
+        .unwrap();
+    assert!(resp.changes.is_empty());
+}
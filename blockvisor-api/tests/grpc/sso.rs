@@ -0,0 +1,294 @@
+use blockvisor_api::auth::rbac::{OrgRole, Role};
+use blockvisor_api::auth::resource::{OrgId, Resource};
+use blockvisor_api::grpc::api;
+use blockvisor_api::model::rbac::RbacUser;
+use blockvisor_api::model::sso::SsoIdentity;
+use blockvisor_api::model::user::User;
+use jsonwebtoken::{EncodingKey, Header};
+use mockito::{Matcher, Mock, ServerGuard};
+use tonic::{Code, Status};
+use url::Url;
+
+use crate::setup::TestServer;
+use crate::setup::helper::traits::{AuthService, OrgService, SocketRpc};
+
+const CLIENT_ID: &str = "blockvisor";
+const CLIENT_SECRET: &str = "sso-secret";
+const REDIRECT_URI: &str = "https://app.example.com/sso/callback";
+
+struct Idp {
+    server: ServerGuard,
+    _discovery: Mock,
+}
+
+impl Idp {
+    async fn new() -> Self {
+        let mut server = mockito::Server::new_async().await;
+        let issuer = server.url();
+        let discovery = serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+        });
+        let discovery = server
+            .mock("GET", "/.well-known/openid-configuration")
+            .with_header("content-type", "application/json")
+            .with_body(discovery.to_string())
+            .create_async()
+            .await;
+
+        Idp {
+            server,
+            _discovery: discovery,
+        }
+    }
+
+    fn url(&self) -> String {
+        self.server.url()
+    }
+
+    /// Complete a login started with `state` and `nonce` as the IdP user.
+    async fn callback(
+        &mut self,
+        test: &TestServer,
+        (state, nonce): (String, String),
+        claims: IdpUser<'_>,
+    ) -> Result<api::AuthServiceSsoCallbackResponse, Status> {
+        let token = claims.id_token(&self.url(), &nonce);
+        let token_endpoint = self
+            .server
+            .mock("POST", "/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("code".into(), "auth-code".into()),
+                Matcher::Regex("code_verifier=".into()),
+            ]))
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::json!({ "id_token": token }).to_string())
+            .create_async()
+            .await;
+
+        let req = api::AuthServiceSsoCallbackRequest {
+            state,
+            code: "auth-code".into(),
+        };
+        let result = test
+            .send_unauthenticated(AuthService::sso_callback, req)
+            .await;
+        token_endpoint.remove_async().await;
+
+        result
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IdpUser<'a> {
+    sub: &'a str,
+    email: &'a str,
+    email_verified: bool,
+    groups: &'a [&'a str],
+}
+
+impl IdpUser<'_> {
+    fn id_token(&self, issuer: &str, nonce: &str) -> String {
+        let claims = serde_json::json!({
+            "iss": issuer,
+            "aud": CLIENT_ID,
+            "sub": self.sub,
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "email": self.email,
+            "email_verified": self.email_verified,
+            "given_name": "Single",
+            "family_name": "Sign",
+            "nonce": nonce,
+            "groups": self.groups,
+        });
+        let key = EncodingKey::from_secret(CLIENT_SECRET.as_bytes());
+        jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap()
+    }
+}
+
+async fn set_provider(test: &TestServer, idp: &Idp, domain: &str) {
+    let req = api::OrgServiceSetIdentityProviderRequest {
+        org_id: test.seed().org.id.to_string(),
+        issuer: idp.url(),
+        client_id: CLIENT_ID.into(),
+        client_secret: Some(CLIENT_SECRET.into()),
+        allowed_domains: vec![domain.into()],
+        group_roles: vec![api::SsoGroupRole {
+            group: "ops".into(),
+            role: "org-admin".into(),
+        }],
+    };
+    test.send_super(OrgService::set_identity_provider, req)
+        .await
+        .unwrap();
+}
+
+async fn verify_domain(test: &TestServer, domain: &str) {
+    let req = api::OrgServiceVerifySsoDomainRequest {
+        org_id: test.seed().org.id.to_string(),
+        domain: domain.into(),
+    };
+    let resp = test
+        .send_super(OrgService::verify_sso_domain, req)
+        .await
+        .unwrap();
+    assert_eq!(resp.provider.unwrap().verified_domains, vec![domain]);
+}
+
+/// Returns the `state` and `nonce` of the authorization url.
+fn session_params(authorization_url: &str) -> (String, String) {
+    let url = Url::parse(authorization_url).unwrap();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap()
+    };
+    assert_eq!(param("code_challenge_method"), "S256");
+
+    (param("state"), param("nonce"))
+}
+
+async fn authorize(test: &TestServer, org_id: OrgId) -> (String, String) {
+    let req = api::AuthServiceSsoAuthorizeRequest {
+        org_id: org_id.to_string(),
+        redirect_uri: REDIRECT_URI.into(),
+    };
+    let resp = test
+        .send_unauthenticated(AuthService::sso_authorize, req)
+        .await
+        .unwrap();
+
+    session_params(&resp.authorization_url)
+}
+
+#[tokio::test]
+async fn sso_login_creates_user_with_mapped_role() {
+    let test = TestServer::new().await;
+    let mut idp = Idp::new().await;
+    let org_id = test.seed().org.id;
+    set_provider(&test, &idp, "example.com").await;
+
+    // org members can't see the provider config
+    let req = api::OrgServiceGetIdentityProviderRequest {
+        org_id: org_id.to_string(),
+    };
+    let status = test
+        .send_member(OrgService::get_identity_provider, req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let new_user = IdpUser {
+        sub: "idp-user-1",
+        email: "new.user@example.com",
+        email_verified: true,
+        groups: &["ops"],
+    };
+
+    // an unverified domain can't be used to sign in
+    let session = authorize(&test, org_id).await;
+    let status = idp.callback(&test, session, new_user).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // and org admins can't verify their own domains
+    let req = api::OrgServiceVerifySsoDomainRequest {
+        org_id: org_id.to_string(),
+        domain: "example.com".into(),
+    };
+    let status = test
+        .send_admin(OrgService::verify_sso_domain, req)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    verify_domain(&test, "example.com").await;
+
+    // the provider must have verified the email
+    let unverified = IdpUser {
+        email_verified: false,
+        ..new_user
+    };
+    let session = authorize(&test, org_id).await;
+    let status = idp.callback(&test, session, unverified).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let session = authorize(&test, org_id).await;
+    let resp = idp
+        .callback(&test, session.clone(), new_user)
+        .await
+        .unwrap();
+    assert!(!resp.token.is_empty());
+
+    let mut conn = test.conn().await;
+    let user = User::by_email("new.user@example.com", &mut conn)
+        .await
+        .unwrap();
+    assert!(User::is_confirmed(user.id, &mut conn).await.unwrap());
+    let roles = RbacUser::org_roles(user.id, org_id, true, &mut conn)
+        .await
+        .unwrap();
+    assert!(roles.contains(&Role::Org(OrgRole::Admin)));
+
+    let identity = SsoIdentity::find(&idp.url(), "idp-user-1", &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(identity.user_id, user.id);
+
+    // the state can only be used once
+    let status = idp.callback(&test, session, new_user).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn sso_login_requires_linking_an_existing_user() {
+    let test = TestServer::new().await;
+    let mut idp = Idp::new().await;
+    let org_id = test.seed().org.id;
+    let member = &test.seed().member;
+    set_provider(&test, &idp, "org.com").await;
+    verify_domain(&test, "org.com").await;
+
+    let idp_member = IdpUser {
+        sub: "idp-member",
+        email: &member.email,
+        email_verified: true,
+        groups: &[],
+    };
+
+    // the provider can't sign in to an existing account by email
+    let session = authorize(&test, org_id).await;
+    let status = idp.callback(&test, session, idp_member).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    // linking requires signing in as the user
+    let req = api::AuthServiceSsoLinkRequest {
+        org_id: org_id.to_string(),
+        user_id: member.id.to_string(),
+        redirect_uri: REDIRECT_URI.into(),
+    };
+    let status = test
+        .send_admin(AuthService::sso_link, req.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let resp = test.send_member(AuthService::sso_link, req).await.unwrap();
+    let session = session_params(&resp.authorization_url);
+    idp.callback(&test, session, idp_member).await.unwrap();
+
+    let mut conn = test.conn().await;
+    let identity = SsoIdentity::find(&idp.url(), "idp-member", &mut conn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(identity.user_id, member.id);
+
+    // once linked the provider signs in as the existing user
+    let session = authorize(&test, org_id).await;
+    let resp = idp.callback(&test, session, idp_member).await.unwrap();
+    let claims = test.cipher().jwt.decode(&resp.token.into()).unwrap();
+    assert_eq!(claims.resource(), Resource::User(member.id));
+}
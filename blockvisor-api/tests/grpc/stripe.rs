@@ -0,0 +1,191 @@
+use blockvisor_api::auth::resource::OrgId;
+use blockvisor_api::model::Node;
+use blockvisor_api::model::schema::regions;
+use blockvisor_api::stripe::api::subscription::SubscriptionItemId;
+use blockvisor_api::stripe::reconcile::{self, Discrepancy};
+use blockvisor_api::stripe::tests::{MockStripe, mock_prices, mock_subscriptions};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use mockito::{Matcher, Mock, ServerGuard};
+
+use crate::setup::TestServer;
+
+const SUBSCRIPTION_ID: &str = "sub_1MowQVLkdIwHu7ixeRlqHVzs";
+const BILLED_PRICE: &str = "price_1MoBy5LkdIwHu7ixZhnattbh";
+const BILLED_ITEM: &str = "si_billed";
+const STALE_ITEM: &str = "si_stale";
+const METERED_ITEM: &str = "si_metered";
+
+#[tokio::test]
+async fn reconcile_pages_through_subscription_items() {
+    let test = TestServer::new().await;
+    let org_id = test.seed().org.id;
+    let node = &test.seed().node;
+
+    let mut conn = test.conn().await;
+    diesel::update(regions::table)
+        .set(regions::sku_code.eq("EU1"))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let billed_item = SubscriptionItemId::from(BILLED_ITEM.to_string());
+    Node::set_stripe_item_id(node.id, &billed_item, &mut conn)
+        .await
+        .unwrap();
+
+    // the billed item is only on the second page
+    let mut server = mockito::Server::new_async().await;
+    let first_page = items_page(true, STALE_ITEM, "price_stale", false);
+    let _mocks = mock_stripe(&mut server, first_page, STALE_ITEM).await;
+    let delete = server
+        .mock("DELETE", Matcher::Regex("^/v1/subscription_items/".into()))
+        .with_status(200)
+        .with_body(format!(
+            r#"{{"id": "{STALE_ITEM}", "object": "subscription_item", "deleted": true}}"#
+        ))
+        .expect(1)
+        .create_async()
+        .await;
+    let create = server
+        .mock("POST", "/v1/subscription_items")
+        .expect(0)
+        .create_async()
+        .await;
+    let stripe = MockStripe::with_server(server);
+
+    // a dry run only reports the stale item on the first page
+    let report = reconcile::reconcile(test.context(), &stripe, true)
+        .await
+        .unwrap();
+    let discrepancies = org_discrepancies(&report, org_id);
+    assert_eq!(discrepancies.len(), 1, "{discrepancies:?}");
+    assert!(matches!(
+        discrepancies[0],
+        Discrepancy::StaleItem(id) if id.to_string() == STALE_ITEM
+    ));
+
+    // a real run removes it, without creating an item for the billed node
+    let report = reconcile::reconcile(test.context(), &stripe, false)
+        .await
+        .unwrap();
+    assert_eq!(org_discrepancies(&report, org_id).len(), 1);
+
+    delete.assert_async().await;
+    create.assert_async().await;
+}
+
+#[tokio::test]
+async fn reconcile_leaves_metered_items_alone() {
+    let test = TestServer::new().await;
+    let org_id = test.seed().org.id;
+    let node = &test.seed().node;
+
+    let mut conn = test.conn().await;
+    diesel::update(regions::table)
+        .set(regions::sku_code.eq("EU1"))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let billed_item = SubscriptionItemId::from(BILLED_ITEM.to_string());
+    Node::set_stripe_item_id(node.id, &billed_item, &mut conn)
+        .await
+        .unwrap();
+
+    // the metered item has no billable nodes but is billed from usage instead
+    let mut server = mockito::Server::new_async().await;
+    let first_page = items_page(true, METERED_ITEM, "price_metered", true);
+    let _mocks = mock_stripe(&mut server, first_page, METERED_ITEM).await;
+    let update = server
+        .mock("POST", Matcher::Regex("^/v1/subscription_items/".into()))
+        .expect(0)
+        .create_async()
+        .await;
+    let delete = server
+        .mock("DELETE", Matcher::Regex("^/v1/subscription_items/".into()))
+        .expect(0)
+        .create_async()
+        .await;
+    let stripe = MockStripe::with_server(server);
+
+    let report = reconcile::reconcile(test.context(), &stripe, false)
+        .await
+        .unwrap();
+    let discrepancies = org_discrepancies(&report, org_id);
+    assert!(discrepancies.is_empty(), "{discrepancies:?}");
+
+    update.assert_async().await;
+    delete.assert_async().await;
+}
+
+fn org_discrepancies(report: &reconcile::Report, org_id: OrgId) -> Vec<&Discrepancy> {
+    report
+        .discrepancies
+        .iter()
+        .filter(|(id, _)| *id == org_id)
+        .map(|(_, discrepancy)| discrepancy)
+        .collect()
+}
+
+async fn mock_stripe(server: &mut ServerGuard, first_page: String, last_item: &str) -> Vec<Mock> {
+    let second_page = items_page(false, BILLED_ITEM, BILLED_PRICE, false);
+
+    vec![
+        server
+            .mock("GET", Matcher::Regex("^/v1/subscriptions".into()))
+            .with_status(200)
+            .with_body(mock_subscriptions())
+            .create_async()
+            .await,
+        server
+            .mock("GET", "/v1/subscription_items")
+            .match_query(Matcher::Exact(format!(
+                "subscription={SUBSCRIPTION_ID}&limit=100"
+            )))
+            .with_status(200)
+            .with_body(first_page)
+            .create_async()
+            .await,
+        server
+            .mock("GET", "/v1/subscription_items")
+            .match_query(Matcher::UrlEncoded(
+                "starting_after".into(),
+                last_item.into(),
+            ))
+            .with_status(200)
+            .with_body(second_page)
+            .create_async()
+            .await,
+        server
+            .mock("GET", Matcher::Regex("^/v1/prices/search".into()))
+            .with_status(200)
+            .with_body(mock_prices())
+            .create_async()
+            .await,
+    ]
+}
+
+fn items_page(has_more: bool, item_id: &str, price_id: &str, metered: bool) -> String {
+    let usage_type = if metered { "metered" } else { "licensed" };
+    format!(
+        r#"{{
+          "object": "list",
+          "url": "/v1/subscription_items",
+          "has_more": {has_more},
+          "data": [
+            {{
+              "id": "{item_id}",
+              "object": "subscription_item",
+              "created": 1688507587,
+              "metadata": {{}},
+              "price": {{
+                "id": "{price_id}",
+                "object": "price",
+                "recurring": {{ "usage_type": "{usage_type}" }}
+              }},
+              "quantity": 1,
+              "subscription": "{SUBSCRIPTION_ID}"
+            }}
+          ]
+        }}"#
+    )
+}